@@ -0,0 +1,175 @@
+//! Throughput of `chunk_bytes_cdc` on inputs that stress the gear hash loop
+//! differently, as a baseline for catching performance regressions; across average
+//! chunk sizes (with `min = avg / 4` and `max = avg * 4`); and of the boundary scan
+//! with and without skipping the bytes below `min_chunk_size`. Inputs come from fixed
+//! seeds, so runs are comparable.
+//!
+//! Run with `cargo bench --bench cdc_bench`.
+
+use std::{fs, hint::black_box};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use rbckp::backup::cdc_chunker;
+
+const INPUT_LEN: usize = 8 << 20;
+
+const SIZES: [(usize, usize, usize); 2] = [(512, 2048, 8192), (4096, 16384, 65536)];
+
+/// Input of the average size sweep.
+const SWEEP_LEN: usize = 16 << 20;
+
+const AVG_SIZES: [usize; 4] = [1024, 4096, 16384, 65536];
+
+/// Input of the skip-min comparison: large enough that setup noise does not matter.
+const SKIP_MIN_LEN: usize = 64 << 20;
+
+/// Sizes of the skip-min comparison: the larger `min` is relative to `avg`, the more
+/// bytes the scan skips.
+const SKIP_MIN_SIZES: [(usize, usize, usize); 2] = [(2048, 8192, 32768), (16384, 32768, 131072)];
+
+/// Pseudo-random bytes from a xorshift generator: nothing to deduplicate.
+fn random_bytes(len: usize) -> Vec<u8> {
+    seeded_random_bytes(len, 0x9e37_79b9_7f4a_7c15)
+}
+
+/// Pseudo-random bytes from a xorshift generator started at `seed`.
+fn seeded_random_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+/// Text with a lot of repetition, like logs or source code.
+fn compressible_text(len: usize) -> Vec<u8> {
+    let mut text = Vec::with_capacity(len);
+    let mut line = 0u64;
+    while text.len() < len {
+        text.extend_from_slice(
+            format!(
+                "2026-03-01T12:{:02}:{:02}Z INFO request {} served in {} ms\n",
+                line / 60 % 60,
+                line % 60,
+                line,
+                line * 7 % 300
+            )
+            .as_bytes(),
+        );
+        line += 1;
+    }
+    text.truncate(len);
+    text
+}
+
+/// A 64 KiB random block repeated: long stretches of duplicate content.
+fn repetitive_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let block = seeded_random_bytes(64 << 10, seed);
+    block.iter().copied().cycle().take(len).collect()
+}
+
+/// A real binary: this benchmark's own executable, repeated if it is short.
+fn binary_blob(len: usize) -> Vec<u8> {
+    let exe = fs::read(std::env::current_exe().unwrap()).unwrap();
+    exe.iter().copied().cycle().take(len).collect()
+}
+
+fn bench_chunk_bytes_cdc(c: &mut Criterion) {
+    let inputs = [
+        ("random", random_bytes(INPUT_LEN)),
+        ("text", compressible_text(INPUT_LEN)),
+        ("binary", binary_blob(INPUT_LEN)),
+    ];
+
+    let mut group = c.benchmark_group("chunk_bytes_cdc");
+    group.throughput(Throughput::Bytes(INPUT_LEN as u64));
+    group.sample_size(20);
+    for (name, data) in &inputs {
+        for (min, avg, max) in SIZES {
+            group.bench_with_input(
+                BenchmarkId::new(*name, format!("{}/{}/{}", min, avg, max)),
+                data,
+                |b, data| b.iter(|| cdc_chunker::chunk_bytes_cdc(black_box(data), min, avg, max)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_avg_sizes(c: &mut Criterion) {
+    let inputs = [
+        ("random", seeded_random_bytes(SWEEP_LEN, 0x5eed_0001)),
+        ("repetitive", repetitive_bytes(SWEEP_LEN, 0x5eed_0002)),
+    ];
+
+    let mut group = c.benchmark_group("chunker");
+    group.throughput(Throughput::Bytes(SWEEP_LEN as u64));
+    group.sample_size(10);
+    for (name, data) in &inputs {
+        for avg in AVG_SIZES {
+            group.bench_with_input(BenchmarkId::new(*name, avg), data, |b, data| {
+                b.iter(|| cdc_chunker::chunk_bytes_cdc(black_box(data), avg / 4, avg, avg * 4))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Chunk ends as the loop before the skip-min optimization found them: the gear hash
+/// runs over every byte from the chunk start. `avg` must be a power of two.
+fn naive_boundaries(data: &[u8], min: usize, avg: usize, max: usize) -> Vec<usize> {
+    let table = cdc_chunker::make_gear_table();
+    let mask = avg as u32 - 1;
+    let mut ends = Vec::new();
+    let mut start = 0;
+    let mut rolling_hash = 0u32;
+    for (i, &byte) in data.iter().enumerate() {
+        rolling_hash = cdc_chunker::gear_hash_step(rolling_hash, byte, &table);
+        let len = i + 1 - start;
+        if len >= max || (len >= min && rolling_hash & mask == 0) {
+            ends.push(i + 1);
+            start = i + 1;
+            rolling_hash = 0;
+        }
+    }
+    if start < data.len() {
+        ends.push(data.len());
+    }
+    ends
+}
+
+fn bench_skip_min(c: &mut Criterion) {
+    let data = random_bytes(SKIP_MIN_LEN);
+
+    let mut group = c.benchmark_group("skip_min");
+    group.throughput(Throughput::Bytes(SKIP_MIN_LEN as u64));
+    group.sample_size(10);
+    for (min, avg, max) in SKIP_MIN_SIZES {
+        // Skipping must not move a single boundary.
+        assert_eq!(
+            cdc_chunker::chunk_boundaries_cdc(&data, min, avg, max),
+            naive_boundaries(&data, min, avg, max)
+        );
+
+        let sizes = format!("{}/{}/{}", min, avg, max);
+        group.bench_with_input(BenchmarkId::new("skip_min", &sizes), &data, |b, data| {
+            b.iter(|| cdc_chunker::chunk_boundaries_cdc(black_box(data), min, avg, max))
+        });
+        group.bench_with_input(BenchmarkId::new("naive", &sizes), &data, |b, data| {
+            b.iter(|| naive_boundaries(black_box(data), min, avg, max))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_chunk_bytes_cdc,
+    bench_avg_sizes,
+    bench_skip_min
+);
+criterion_main!(benches);
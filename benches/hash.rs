@@ -0,0 +1,55 @@
+//! Chunk id throughput of each `HashAlgorithm`, hashing 16 MB of random data in
+//! chunks of typical sizes on one thread.
+//!
+//! Run with `cargo bench --bench hash`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use rbckp::backup::hash::HashAlgorithm;
+
+const INPUT_LEN: usize = 16 << 20;
+
+const CHUNK_SIZES: [usize; 3] = [4096, 65536, 1 << 20];
+
+/// Pseudo-random bytes from a xorshift generator started at `seed`.
+fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+fn bench_hash_algorithms(c: &mut Criterion) {
+    let data = random_bytes(INPUT_LEN, 0x5eed_0003);
+
+    let mut group = c.benchmark_group("hash");
+    group.throughput(Throughput::Bytes(INPUT_LEN as u64));
+    group.sample_size(10);
+    for algorithm in [
+        HashAlgorithm::Blake3,
+        HashAlgorithm::XxHash3,
+        HashAlgorithm::SipHash,
+    ] {
+        let hasher = algorithm.keyed_hasher(Some([0x5e; 16]));
+        for chunk_size in CHUNK_SIZES {
+            let id = BenchmarkId::new(algorithm.to_string(), chunk_size);
+            group.bench_with_input(id, &data, |b, data| {
+                b.iter(|| {
+                    for chunk in black_box(data).chunks(chunk_size) {
+                        black_box(hasher.hash(chunk));
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_hash_algorithms);
+criterion_main!(benches);
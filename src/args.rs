@@ -1,9 +1,535 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// Allow invalid UTF-8 paths
-    #[arg(short = 'F', value_name = "file", value_hint = clap::ValueHint::DirPath)]
-    pub target_file: std::path::PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Allow invalid UTF-8 paths, `-` reads from stdin
+    #[arg(short = 'F', value_name = "file", value_hint = clap::ValueHint::DirPath, required = true)]
+    pub target_file: Option<std::path::PathBuf>,
+
+    /// Settings file to use instead of `./settings.ini`
+    #[arg(long, global = true, value_name = "path", value_hint = clap::ValueHint::FilePath)]
+    pub config: Option<std::path::PathBuf>,
+
+    /// Only print errors and the data a command exists to show
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print debug messages; twice for even more detail
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Worker threads used for chunk hashing (0 = one per CPU), overrides the `threads` setting
+    #[arg(long, value_name = "N")]
+    pub threads: Option<usize>,
+
+    /// Memory-map the target file instead of reading it into a buffer
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Also print every chunk of the target file as `offset len reason hash`: why it
+    /// was cut there (`boundary`, `forced`, `end` or `zero`) and the rolling hash at
+    /// the cut
+    #[arg(long)]
+    pub debug_boundaries: bool,
+
+    /// Bytes of every chunk shown in the preview written to `output.txt`
+    #[arg(long, value_name = "bytes", default_value_t = crate::backup::preview::DEFAULT_PREVIEW_LEN)]
+    pub preview_len: usize,
+
+    /// Where to cut chunks, overrides the `strategy` chunk setting; `fixed-size` cuts
+    /// every `avg` bytes, to compare the dedup ratio with that of CDC
+    #[arg(long, value_enum, value_name = "strategy")]
+    pub strategy: Option<crate::backup::cdc_chunker::ChunkingStrategy>,
+
+    /// Print the summary as JSON instead of text, and nothing else on stdout
+    #[arg(long, conflicts_with = "debug_boundaries")]
+    pub json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create a new, empty repository
+    Init(InitArgs),
+    /// Back up files and directories into a repository as a new snapshot
+    Backup(BackupArgs),
+    /// Restore the files of a snapshot into a directory
+    Restore(RestoreArgs),
+    /// Write the content of one file of a snapshot to stdout
+    Cat(CatArgs),
+    /// Write a snapshot as a tar archive
+    Export(ExportArgs),
+    /// Read a tar archive from stdin into a repository as a new snapshot
+    Import(ImportArgs),
+    /// Check whether a directory still matches a snapshot, without restoring it
+    VerifyTree(VerifyTreeArgs),
+    /// Check the integrity of a repository, optionally re-reading its data
+    Verify(VerifyArgs),
+    /// Re-hash every chunk in a repository and compare it with its id, without
+    /// looking at any snapshot
+    Check(CheckArgs),
+    /// Delete the snapshots a retention policy does not keep
+    Forget(ForgetArgs),
+    /// Show a snapshot as a read-only filesystem (needs the `fuse` feature)
+    Mount(MountArgs),
+    /// List the snapshots in a repository
+    #[command(visible_alias = "list")]
+    ListSnapshots(ListSnapshotsArgs),
+    /// Add a tag to a snapshot
+    Tag(TagArgs),
+    /// Remove a tag from a snapshot
+    Untag(TagArgs),
+    /// Regenerate the chunk index of a repository from its pack files
+    RebuildIndex(RebuildIndexArgs),
+    /// Remove a stale repository lock left by a hung or crashed process
+    Unlock(UnlockArgs),
+    /// Upgrade a repository written by an older rbckp to the current format
+    Migrate(MigrateArgs),
+    /// Measure chunking throughput on a file
+    Bench(BenchArgs),
+    /// Show how many chunks of one file another file already has
+    Compare(CompareArgs),
+    /// Show how much new data a backup of some paths would write, without writing it
+    Estimate(EstimateArgs),
+    /// Show which files were added, removed or changed between two snapshots
+    Diff(DiffArgs),
+    /// Copy snapshots to another repository, transferring only the chunks it lacks
+    Copy(CopyArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct InitArgs {
+    /// Directory (or `sftp://user@host/path` URL) to create the repository in
+    #[arg(value_name = "path", value_hint = clap::ValueHint::DirPath)]
+    pub path: std::path::PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BackupArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Files and directories to back up. Paths inside others, or given twice, are only
+    /// backed up once
+    #[arg(
+        value_name = "path",
+        required_unless_present_any = ["stdin", "targets"],
+        conflicts_with = "stdin",
+        value_hint = clap::ValueHint::AnyPath
+    )]
+    pub paths: Vec<std::path::PathBuf>,
+
+    /// A file or directory to back up, like the positional paths; can be repeated
+    #[arg(
+        short = 'F',
+        value_name = "path",
+        conflicts_with = "stdin",
+        value_hint = clap::ValueHint::AnyPath
+    )]
+    pub targets: Vec<std::path::PathBuf>,
+
+    /// Back up what is piped to stdin as a single file instead of paths, e.g.
+    /// `pg_dump db | rbckp backup --stdin --stdin-name db.sql --repo R`
+    #[arg(long)]
+    pub stdin: bool,
+
+    /// Name to record the data read with `--stdin` under
+    #[arg(long, value_name = "name", default_value = "stdin", requires = "stdin")]
+    pub stdin_name: String,
+
+    /// Leave out paths matching this glob (`.gitignore` syntax, relative to each path
+    /// given); can be repeated. `.rbckpignore` files and the `exclude` setting are
+    /// honored as well
+    #[arg(long, value_name = "glob")]
+    pub exclude: Vec<String>,
+
+    /// Only back up files matching this glob (same syntax as `--exclude`), or below a
+    /// directory matching it; can be repeated. Excludes still apply to what is included
+    #[arg(long, value_name = "glob")]
+    pub include: Vec<String>,
+
+    /// Back up what symlinks point to instead of the links themselves
+    #[arg(long)]
+    pub follow_symlinks: bool,
+
+    /// Tag the snapshot; can be repeated
+    #[arg(long, value_name = "name")]
+    pub tag: Vec<String>,
+
+    /// Continue an interrupted backup of the same paths without asking, keeping the
+    /// files it finished and the chunks it stored. This is also what happens when
+    /// stdin is not a terminal, e.g. under cron
+    #[arg(long, conflicts_with = "stdin")]
+    pub resume: bool,
+
+    /// Answer yes instead of asking, e.g. whether to resume an interrupted backup
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Start over instead of resuming an interrupted backup of the same paths,
+    /// discarding its journal
+    #[arg(long, conflicts_with_all = ["resume", "yes", "stdin"])]
+    pub no_resume: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RestoreArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Snapshot id (or a unique prefix of it)
+    #[arg(value_name = "snapshot")]
+    pub snapshot: String,
+
+    /// Directory to restore into; recorded paths are recreated below it
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub target: std::path::PathBuf,
+
+    /// Leave modification times, permissions and ownership as the restore creates them
+    #[arg(long)]
+    pub no_metadata: bool,
+
+    /// Only restore files matching this glob (same syntax as backup `--exclude`, matched
+    /// against the recorded path), or below a directory matching it; can be repeated
+    #[arg(long, value_name = "glob")]
+    pub include: Vec<String>,
+
+    /// Leave out files matching this glob, or below a directory matching it; can be
+    /// repeated
+    #[arg(long, value_name = "glob")]
+    pub exclude: Vec<String>,
+    /// What to do about files that already exist in the target: fail (`never`),
+    /// replace them (`always`), or replace those whose content differs (`if-changed`)
+    #[arg(long, value_enum, value_name = "policy", default_value_t)]
+    pub overwrite: crate::backup::restore::OverwritePolicy,
+
+    /// Only print which files would be created, overwritten or skipped
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CatArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Snapshot id (or a unique prefix of it)
+    #[arg(value_name = "snapshot")]
+    pub snapshot: String,
+
+    /// Path of the file as recorded in the snapshot
+    #[arg(value_name = "path")]
+    pub path: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Snapshot id (or a unique prefix of it)
+    #[arg(long, value_name = "id")]
+    pub snapshot: String,
+
+    /// Archive format
+    #[arg(long, value_enum, value_name = "format", default_value_t)]
+    pub format: crate::backup::export::ExportFormat,
+
+    /// File to write the archive to instead of stdout
+    #[arg(long, short, value_name = "file", value_hint = clap::ValueHint::FilePath)]
+    pub output: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Tag the snapshot; can be repeated
+    #[arg(long, value_name = "name")]
+    pub tag: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyTreeArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Snapshot id (or a unique prefix of it)
+    #[arg(long, value_name = "id")]
+    pub snapshot: String,
+
+    /// Directory (or file) to check, as it was given to the backup
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::AnyPath)]
+    pub path: std::path::PathBuf,
+
+    /// Also read and chunk every file, to find changes that kept size and modification time
+    #[arg(long)]
+    pub read_data: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ForgetArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Keep the N newest snapshots
+    #[arg(long, value_name = "N")]
+    pub keep_last: Option<u32>,
+
+    /// Keep the newest snapshot of each of the last N days with snapshots
+    #[arg(long, value_name = "N")]
+    pub keep_daily: Option<u32>,
+
+    /// Keep the newest snapshot of each of the last N weeks with snapshots
+    #[arg(long, value_name = "N")]
+    pub keep_weekly: Option<u32>,
+
+    /// Keep the newest snapshot of each of the last N months with snapshots
+    #[arg(long, value_name = "N")]
+    pub keep_monthly: Option<u32>,
+
+    /// Only print what would be kept and forgotten, and why
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Delete the chunks no remaining snapshot uses afterwards
+    #[arg(long)]
+    pub prune: bool,
+
+    #[command(flatten)]
+    pub filter: SnapshotFilterArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MountArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Snapshot id (or a unique prefix of it)
+    #[arg(value_name = "snapshot")]
+    pub snapshot: String,
+
+    /// Empty directory to mount the snapshot on
+    #[arg(value_name = "mountpoint", value_hint = clap::ValueHint::DirPath)]
+    pub mountpoint: std::path::PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ListSnapshotsArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Also show what the backup that took each snapshot did
+    #[arg(long, short)]
+    pub long: bool,
+
+    /// Print the snapshots as JSON, run statistics included, instead of text
+    #[arg(long, conflicts_with = "long")]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub filter: SnapshotFilterArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SnapshotFilterArgs {
+    /// Only consider snapshots with this tag; can be repeated to require several
+    #[arg(long, value_name = "name")]
+    pub tag: Vec<String>,
+
+    /// Only consider snapshots taken on this host; can be repeated
+    #[arg(long, value_name = "name")]
+    pub host: Vec<String>,
+}
+
+impl SnapshotFilterArgs {
+    pub fn snapshot_filter(&self) -> crate::backup::snapshot::SnapshotFilter {
+        crate::backup::snapshot::SnapshotFilter {
+            tags: self.tag.clone(),
+            hosts: self.host.clone(),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TagArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Snapshot id (or a unique prefix of it)
+    #[arg(value_name = "snapshot")]
+    pub snapshot: String,
+
+    /// Tag name
+    #[arg(value_name = "tag")]
+    pub tag: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Also re-read and re-hash every chunk
+    #[arg(long, conflicts_with = "read_data_subset")]
+    pub read_data: bool,
+
+    /// Also re-read and re-hash a subset of the chunks: about `P%` of them, or bucket
+    /// `K/N`; the same subset every time, and buckets 1/N to N/N cover every chunk once
+    #[arg(long, value_name = "P%|K/N")]
+    pub read_data_subset: Option<crate::backup::verify::DataSubset>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CheckArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Restore the packs of corrupt chunks from the secondary side of a `type = tee`
+    /// store, and list the chunks that cannot be restored in `corrupt.txt`
+    #[arg(long)]
+    pub repair: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RebuildIndexArgs {
+    /// Repository directory or `sftp://user@host/path` URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Re-read and re-hash every chunk instead of trusting the pack footers
+    #[arg(long)]
+    pub read_data: bool,
+
+    /// Break a stale repository lock (asks for confirmation first)
+    #[arg(long)]
+    pub force_unlock: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct UnlockArgs {
+    /// Repository directory
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Only remove the lock if every holder took it at least this many minutes ago
+    #[arg(long, value_name = "minutes", default_value_t = 60)]
+    pub older_than: u64,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MigrateArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// File to chunk; it is read into memory once, before timing starts
+    #[arg(short = 'F', value_name = "file", value_hint = clap::ValueHint::FilePath)]
+    pub file: std::path::PathBuf,
+
+    /// How many times to chunk the file
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub iterations: u32,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+    /// The two files, the old version first: reports how much of the second one is
+    /// already in the chunks of the first
+    #[arg(short = 'F', value_name = "file", value_hint = clap::ValueHint::FilePath, required = true)]
+    pub files: Vec<std::path::PathBuf>,
+
+    /// Print the result as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct EstimateArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// Files and directories a backup would be given
+    #[arg(value_name = "path", required_unless_present = "targets", value_hint = clap::ValueHint::AnyPath)]
+    pub paths: Vec<std::path::PathBuf>,
+
+    /// A file or directory, like the positional paths; can be repeated
+    #[arg(short = 'F', value_name = "path", value_hint = clap::ValueHint::AnyPath)]
+    pub targets: Vec<std::path::PathBuf>,
+
+    /// Leave out paths matching this glob, as for `backup`
+    #[arg(long, value_name = "glob")]
+    pub exclude: Vec<String>,
+
+    /// Only consider files matching this glob, as for `backup`
+    #[arg(long, value_name = "glob")]
+    pub include: Vec<String>,
+
+    /// Read what symlinks point to, as `backup --follow-symlinks` would
+    #[arg(long)]
+    pub follow_symlinks: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CopyArgs {
+    /// Repository to copy from (directory or URL)
+    #[arg(long, value_name = "repo", value_hint = clap::ValueHint::DirPath)]
+    pub from: std::path::PathBuf,
+
+    /// Repository to copy to (directory or URL)
+    #[arg(long, value_name = "repo", value_hint = clap::ValueHint::DirPath)]
+    pub to: std::path::PathBuf,
+
+    /// Snapshot to copy: id (or a unique prefix of it)
+    #[arg(
+        long,
+        value_name = "id",
+        required_unless_present = "all",
+        conflicts_with = "all"
+    )]
+    pub snapshot: Option<String>,
+
+    /// Copy every snapshot the destination does not have yet
+    #[arg(long)]
+    pub all: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Repository directory or URL
+    #[arg(long, value_name = "dir", value_hint = clap::ValueHint::DirPath)]
+    pub repo: std::path::PathBuf,
+
+    /// The older snapshot: id (or a unique prefix of it)
+    #[arg(value_name = "old")]
+    pub old: String,
+
+    /// The newer snapshot: id (or a unique prefix of it)
+    #[arg(value_name = "new")]
+    pub new: String,
+
+    /// Print the result as JSON
+    #[arg(long)]
+    pub json: bool,
 }
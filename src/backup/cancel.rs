@@ -0,0 +1,41 @@
+//! Stopping a running backup cleanly, e.g. on Ctrl-C.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::backup::store::StoreError;
+
+/// Asks a [`BackupSession`](crate::backup::session::BackupSession) to stop.
+///
+/// Clones share the flag, so one can be handed to a signal handler while the
+/// session checks another. The session stops before storing its next chunk,
+/// failing with [`StoreError::Interrupted`]; the chunks stored until then are
+/// kept and made durable by [`checkpoint`](crate::backup::session::BackupSession::checkpoint).
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`StoreError::Interrupted`] once cancelled.
+    pub fn check(&self) -> Result<(), StoreError> {
+        if self.is_cancelled() {
+            return Err(StoreError::Interrupted);
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,459 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read},
+    ops::Range,
+};
 
-use blake3;
+pub mod test_vectors;
 
-fn chunk_id_hash(chunk: &[u8]) -> String {
-    blake3::hash(chunk).to_hex().to_string()
+use bytes::Bytes;
+use rayon::prelude::*;
+
+use crate::backup::{
+    hash::{ChunkHasher, ChunkId, HashAlgorithm},
+    sink::{ChunkSink, MemorySink},
+    store::StoreError,
+};
+
+/// Chunks grouped by their content id.
+pub type ChunkMap = HashMap<ChunkId, Vec<Vec<u8>>>;
+
+/// Borrowed counterpart of [`ChunkMap`], pointing into the chunked input.
+pub type ChunkRefMap<'a> = HashMap<ChunkId, Vec<&'a [u8]>>;
+
+/// Number of trailing bytes that still influence the 32-bit gear hash at a shift of 1.
+///
+/// Every step shifts the hash left by one bit, so after 32 more bytes the
+/// contribution of a byte has been shifted out of the register entirely.
+/// With a larger shift the window shrinks accordingly, see [`gear_window`].
+const GEAR_WINDOW: usize = u32::BITS as usize;
+
+/// Version of the chunk boundary algorithm, recorded in the config of every new
+/// repository.
+///
+/// Stores deduplicate against chunks cut by older versions, so the boundaries for given
+/// parameters must never change silently. Bump this whenever they change on purpose;
+/// `tests/golden.rs` and [`test_vectors`] check the boundaries recorded for this
+/// version.
+///
+/// Version 2 cuts runs of at least [`ZERO_RUN_MIN`] zero bytes out as zero chunks.
+pub const CHUNKER_FORMAT_VERSION: u32 = 2;
+
+/// Shift applied to the gear hash per byte unless configured otherwise.
+pub const DEFAULT_GEAR_SHIFT: u32 = 1;
+
+/// Seed of the gear table unless configured otherwise; the boundaries in
+/// `tests/golden_chunks.txt` are cut with it.
+pub const DEFAULT_GEAR_SEED: u32 = 0x1234_5678;
+
+/// Fewest boundary bits unless configured otherwise; a mask needs at least one.
+pub const DEFAULT_MIN_BOUNDARY_BITS: u32 = 1;
+
+/// Most boundary bits unless configured otherwise, and the most allowed at all: the
+/// rolling hash is 32 bits wide.
+pub const DEFAULT_MAX_BOUNDARY_BITS: u32 = 31;
+
+/// Shortest run of zero bytes that [`chunk_refs_cdc`] turns into a zero chunk instead of
+/// chunking and hashing it, e.g. the unallocated regions of a VM image.
+pub const ZERO_RUN_MIN: usize = 64 * 1024;
+
+/// Longest zero chunk; longer runs are split into several.
+pub const ZERO_CHUNK_MAX: usize = 64 * 1024 * 1024;
+
+// Zero chunk ids are `zero:<len>`, which can never be a hex hash.
+const ZERO_CHUNK_PREFIX: &str = "zero:";
+
+// Zero runs are found by testing aligned blocks of this size; every run of at least
+// `ZERO_RUN_MIN` bytes contains a whole one.
+const ZERO_SCAN_BLOCK: usize = 4096;
+
+/// Id of a chunk with this content under the default [`HashAlgorithm`], see
+/// [`ChunkId::of`].
+pub fn chunk_id_hash(chunk: &[u8]) -> ChunkId {
+    ChunkId::of(chunk)
+}
+
+/// [`chunk_id_hash`] as the raw 32 hash bytes, for indexes that would otherwise spend
+/// 64 bytes on the hex form of every id.
+pub fn chunk_id_hash_bytes(chunk: &[u8]) -> [u8; 32] {
+    *ChunkId::of(chunk).as_bytes()
+}
+
+/// Id of a chunk of `len` zero bytes. Zero chunks are recorded by length only; stores
+/// never write them and read them back as zeros.
+pub fn zero_chunk_id(len: usize) -> String {
+    format!("{}{}", ZERO_CHUNK_PREFIX, len)
+}
+
+/// The length of the zero chunk `id`, or `None` if it is a regular chunk hash.
+///
+/// No chunker makes a zero chunk of more than [`ZERO_CHUNK_MAX`] bytes, so a longer
+/// (or empty) one is not taken for a zero chunk: materialising it could exhaust
+/// memory, and a store does not have it either.
+pub fn zero_chunk_len(id: &str) -> Option<usize> {
+    let len = id.strip_prefix(ZERO_CHUNK_PREFIX)?.parse().ok()?;
+    (1..=ZERO_CHUNK_MAX).contains(&len).then_some(len)
+}
+
+/// A chunk described by its position in the input instead of a copy of its bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkRef {
+    /// [`ChunkId`] of the chunk bytes in hex, as stores and manifests record chunks, or
+    /// a [`zero_chunk_id`].
+    pub hash: String,
+    /// Start offset of the chunk inside the input.
+    pub offset: usize,
+    /// Chunk length in bytes.
+    pub len: usize,
+}
+
+/// A chunk with its bytes, as a zero-copy slice of the buffer it was cut from.
+///
+/// Cloning a chunk (or its `data`) only bumps a reference count, so chunks can be
+/// handed to other threads for compression or upload without copying them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    /// [`ChunkId`] of `data` in hex.
+    pub hash: String,
+    pub data: Bytes,
+}
+
+/// Where chunks are cut.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChunkingStrategy {
+    /// Content-defined boundaries from the rolling hash, see [`chunk_bytes_cdc`].
+    #[default]
+    Cdc,
+    /// A cut every `target_avg_chunk_size` bytes, whatever the content.
+    ///
+    /// Only a baseline to compare CDC against: inserting or removing a single byte
+    /// moves every later cut, so nothing after an edit deduplicates with the version
+    /// before it. Runs of zeros still become zero chunks.
+    FixedSize,
+}
+
+impl fmt::Display for ChunkingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChunkingStrategy::Cdc => "cdc",
+            ChunkingStrategy::FixedSize => "fixed-size",
+        })
+    }
+}
+
+/// Chunking parameters.
+///
+/// `min_chunk_size <= target_avg_chunk_size <= max_chunk_size` must hold, and
+/// `min_chunk_size` must be > 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CdcParams {
+    pub min_chunk_size: usize,
+    pub target_avg_chunk_size: usize,
+    pub max_chunk_size: usize,
+    /// Number of low hash bits that must be zero for a cut.
+    /// When `None`, it is derived from `target_avg_chunk_size` (about `log2(avg)`).
+    pub boundary_bits: Option<u32>,
+    /// Range the boundary bits are clamped to, whether derived or set explicitly;
+    /// `1 <= min_boundary_bits <= max_boundary_bits <= 31` must hold. See
+    /// [`CdcParams::with_boundary_bits_range`].
+    pub min_boundary_bits: u32,
+    pub max_boundary_bits: u32,
+    /// Bits the rolling hash is shifted left per byte, 1 or 2.
+    /// Larger shifts make old bytes fade out of the hash faster (a window of
+    /// `32 / gear_shift` bytes). Changing it changes all boundaries.
+    pub gear_shift: u32,
+    /// Seed of the gear table (see [`make_gear_table_seeded`]); `None` means
+    /// [`DEFAULT_GEAR_SEED`]. Changing it changes all boundaries.
+    pub gear_seed: Option<u32>,
+    /// Aim at `target_avg_chunk_size` itself instead of the nearest power of two, by
+    /// requiring one more zero bit at a share of the positions (see
+    /// [`CdcParams::with_fractional_bits`]). Ignored when `boundary_bits` is set.
+    pub fractional_bits: bool,
+    /// How chunk ids are computed; does not affect boundaries.
+    pub hash_algorithm: HashAlgorithm,
+    /// Secret key of a keyed `hash_algorithm`, ignored by the others.
+    pub hash_key: Option<[u8; 16]>,
+    /// Content-defined cuts, or fixed-size ones for comparison. With
+    /// [`ChunkingStrategy::FixedSize`] every setting of the rolling hash is ignored.
+    pub strategy: ChunkingStrategy,
+}
+
+/// A finding of [`CdcParams::validate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParamWarning {
+    /// `max` is below twice the average distance between content-defined boundaries,
+    /// so many chunks are cut at `max` instead. Those forced cuts move with every
+    /// insertion, which undoes the point of content-defined chunking: edits no longer
+    /// leave the chunks around them intact, and deduplication suffers.
+    MaxClipsBoundaries { avg: usize, max: usize },
+}
+
+impl fmt::Display for ParamWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamWarning::MaxClipsBoundaries { avg, max } => write!(
+                f,
+                "max chunk size {} is less than twice the average of {}; many chunks will \
+                 be cut at the maximum, which hurts deduplication",
+                max, avg
+            ),
+        }
+    }
+}
+
+impl CdcParams {
+    pub fn new(min_chunk_size: usize, target_avg_chunk_size: usize, max_chunk_size: usize) -> Self {
+        CdcParams {
+            min_chunk_size,
+            target_avg_chunk_size,
+            max_chunk_size,
+            boundary_bits: None,
+            min_boundary_bits: DEFAULT_MIN_BOUNDARY_BITS,
+            max_boundary_bits: DEFAULT_MAX_BOUNDARY_BITS,
+            gear_shift: DEFAULT_GEAR_SHIFT,
+            gear_seed: None,
+            fractional_bits: false,
+            hash_algorithm: HashAlgorithm::default(),
+            hash_key: None,
+            strategy: ChunkingStrategy::Cdc,
+        }
+    }
+
+    /// Use exactly `boundary_bits` bits for the boundary mask instead of deriving them
+    /// from the target average, e.g. to experiment with the cut probability alone.
+    pub fn with_boundary_bits(mut self, boundary_bits: u32) -> Self {
+        self.boundary_bits = Some(boundary_bits);
+        self
+    }
+
+    /// Clamp the boundary bits to `min..=max` instead of `1..=31`, e.g. to keep a tiny
+    /// `avg` from cutting chunks so small that the index outweighs the data.
+    pub fn with_boundary_bits_range(mut self, min: u32, max: u32) -> Self {
+        self.min_boundary_bits = min;
+        self.max_boundary_bits = max;
+        self
+    }
+
+    /// Use a different per-byte shift for the rolling hash (1 or 2).
+    pub fn with_gear_shift(mut self, gear_shift: u32) -> Self {
+        self.gear_shift = gear_shift;
+        self
+    }
+
+    /// Build the gear table from `seed` instead of [`DEFAULT_GEAR_SEED`].
+    pub fn with_gear_seed(mut self, seed: u32) -> Self {
+        self.gear_seed = Some(seed);
+        self
+    }
+
+    /// Derive the cut probability from `log2(avg)` exactly instead of rounding it to
+    /// whole bits, so that e.g. an average of 3000 does not behave like 2048 or 4096.
+    ///
+    /// With `N = floor(log2(avg))`, a position whose low N hash bits are zero is a cut
+    /// unless a coin from the high hash bits asks for bit N to be zero as well; the
+    /// coin's odds are set so that cuts happen at a rate of `1 / avg`. Changes all
+    /// boundaries unless `avg` is a power of two.
+    pub fn with_fractional_bits(mut self) -> Self {
+        self.fractional_bits = true;
+        self
+    }
+
+    /// Compute chunk ids with `hash_algorithm` instead of BLAKE3.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Key chunk ids with `key`, for a keyed hash algorithm such as SipHash.
+    pub fn with_hash_key(mut self, key: Option<[u8; 16]>) -> Self {
+        self.hash_key = key;
+        self
+    }
+
+    /// Cut chunks with `strategy` instead of content-defined boundaries.
+    pub fn with_strategy(mut self, strategy: ChunkingStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// The hasher computing chunk ids, keyed with `hash_key` if the algorithm needs it.
+    ///
+    /// # Panics
+    ///
+    /// If the algorithm is keyed and there is no `hash_key`.
+    pub fn chunk_hasher(&self) -> Box<dyn ChunkHasher> {
+        self.hash_algorithm.keyed_hasher(self.hash_key)
+    }
+
+    /// Combinations of parameters that are valid but chunk poorly, for the caller to
+    /// warn about. Parameters that cannot be used at all are not checked here; chunking
+    /// panics on them.
+    pub fn validate(&self) -> Vec<ParamWarning> {
+        let mut warnings = Vec::new();
+        if self.strategy == ChunkingStrategy::FixedSize {
+            return warnings;
+        }
+        let avg = match self.boundary_bits {
+            Some(bits) => 1 << self.clamp_boundary_bits(bits),
+            None => self.target_avg_chunk_size,
+        };
+        if self.max_chunk_size < avg.saturating_mul(2) {
+            warnings.push(ParamWarning::MaxClipsBoundaries {
+                avg,
+                max: self.max_chunk_size,
+            });
+        }
+        warnings
+    }
+
+    fn gear_table(&self) -> [u32; 256] {
+        self.gear_seed
+            .map_or_else(make_gear_table, make_gear_table_seeded)
+    }
+
+    /// Validate the parameters and derive the boundary test.
+    fn boundary_test(&self) -> BoundaryTest {
+        assert!(self.min_chunk_size > 0, "min must be > 0");
+        assert!(
+            self.min_chunk_size <= self.target_avg_chunk_size
+                && self.target_avg_chunk_size <= self.max_chunk_size,
+            "must satisfy min <= avg <= max"
+        );
+        assert!(
+            matches!(self.gear_shift, 1 | 2),
+            "gear shift must be 1 or 2"
+        );
+
+        assert!(
+            DEFAULT_MIN_BOUNDARY_BITS <= self.min_boundary_bits
+                && self.min_boundary_bits <= self.max_boundary_bits
+                && self.max_boundary_bits <= DEFAULT_MAX_BOUNDARY_BITS,
+            "must satisfy 1 <= min boundary bits <= max boundary bits <= 31"
+        );
+
+        // The fractional test needs one bit above the whole ones.
+        if self.fractional_bits
+            && self.boundary_bits.is_none()
+            && self.min_boundary_bits < self.max_boundary_bits
+        {
+            return BoundaryTest::fractional(
+                self.target_avg_chunk_size,
+                self.min_boundary_bits,
+                self.max_boundary_bits - 1,
+            );
+        }
+
+        let boundary_bits = match self.boundary_bits {
+            // Explicit override, decoupled from the average.
+            Some(bits) => bits,
+            // Choose N so that 2^N is close to target_avg_chunk_size.
+            //
+            // Example:
+            //   target_avg_chunk_size = 2048
+            //   log2(2048) = 11
+            //   => probability of boundary ≈ 1/2^11
+            //   => average chunk size ≈ 2^11 = 2048 bytes
+            //
+            // We do this with floats in the demo for readability,
+            // rounding to the nearest integer number of bits.
+            None => (self.target_avg_chunk_size as f64)
+                .log2()
+                .round()
+                .clamp(0.0, DEFAULT_MAX_BOUNDARY_BITS as f64) as u32,
+        };
+
+        // Clamp to the configured range, which is within what u32 bit operations
+        // allow:
+        // - at least 1 bit (mask not zero)
+        // - at most 31 bits (so (1u32 << bits) is valid)
+        let boundary_bits = self.clamp_boundary_bits(boundary_bits);
+
+        // boundary_bitmask has the lowest `boundary_bits` bits set to 1.
+        //
+        // Example boundary_bits = 5:
+        //   boundary_bitmask = (1<<5)-1 = 31 = 0b00011111
+        //
+        // Then (rolling_hash & boundary_bitmask) == 0 means:
+        //   "the lowest 5 bits are all zero"
+        BoundaryTest::whole_bits(boundary_bits)
+    }
+
+    fn clamp_boundary_bits(&self, bits: u32) -> u32 {
+        bits.clamp(self.min_boundary_bits, self.max_boundary_bits)
+    }
+}
+
+/// Which rolling hash values mark a chunk boundary.
+#[derive(Clone, Copy, Debug)]
+struct BoundaryTest {
+    /// Low bits that must all be zero.
+    mask: u32,
+    /// Bit that must be zero as well where the coin asks for it; 0 if never.
+    extra_bit: u32,
+    /// The coin is `hash >> coin_shift`; values below `coin_threshold` ask for
+    /// `extra_bit` to be zero.
+    coin_shift: u32,
+    coin_threshold: u32,
+}
+
+impl BoundaryTest {
+    fn whole_bits(bits: u32) -> Self {
+        BoundaryTest {
+            mask: (1u32 << bits) - 1,
+            extra_bit: 0,
+            coin_shift: 0,
+            coin_threshold: 0,
+        }
+    }
+
+    /// Cuts at a rate of `1 / avg`, see [`CdcParams::with_fractional_bits`], with
+    /// `min_bits..=max_bits` whole bits. An `avg` outside that range ends up as whole
+    /// bits at its end, as the share below saturates.
+    fn fractional(avg: usize, min_bits: u32, max_bits: u32) -> Self {
+        // N whole bits cut at 2^-N; asking for bit N too at a share `f` of them
+        // lowers that to 2^-N * (1 - f / 2), which is 1 / avg for this `f`.
+        let bits = (avg as f64)
+            .log2()
+            .floor()
+            .clamp(min_bits as f64, max_bits as f64) as u32;
+        let share = (2.0 * (1.0 - f64::from(1u32 << bits) / avg as f64)).clamp(0.0, 1.0);
+
+        // The coin uses the bits above the extra one, so it is independent of them.
+        let coin_shift = bits + 1;
+        let coin_range = 1u64 << (u32::BITS - coin_shift);
+        BoundaryTest {
+            mask: (1u32 << bits) - 1,
+            extra_bit: 1u32 << bits,
+            coin_shift,
+            coin_threshold: (share * coin_range as f64).round().min(u32::MAX as f64) as u32,
+        }
+    }
+
+    #[inline(always)]
+    fn is_boundary(&self, hash: u32) -> bool {
+        hash & self.mask == 0
+            && (hash & self.extra_bit == 0 || hash >> self.coin_shift >= self.coin_threshold)
+    }
+
+    /// Cheap part of [`is_boundary`](Self::is_boundary): false means it is no
+    /// boundary, true that it may be one.
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    fn may_be_boundary(&self, hash: u32) -> bool {
+        hash & self.mask == 0
+    }
 }
 
 /// Content-Defined Chunking (CDC) demo using a simple "Gear" rolling hash.
@@ -15,133 +465,902 @@ fn chunk_id_hash(chunk: &[u8]) -> String {
 /// How boundaries are chosen (after we reach `min_chunk_size`):
 /// - We keep a rolling hash `rolling_hash`.
 /// - We cut a chunk when the lowest N bits of `rolling_hash` are all zero:
-///       (rolling_hash & boundary_bitmask) == 0
+///   (rolling_hash & boundary_bitmask) == 0
 /// - If the rolling hash behaves "random enough", this happens with probability 1 / 2^N,
 ///   so the average chunk size is about 2^N bytes.
 ///
 /// We also enforce:
 /// - never cut before min_chunk_size
 /// - always cut at max_chunk_size (forced)
+///
+/// Runs of at least [`ZERO_RUN_MIN`] zero bytes become chunks of their own, cut
+/// exactly as [`chunk_refs_cdc`] cuts them.
 pub fn chunk_bytes_cdc(
     data: &[u8],
     min_chunk_size: usize,
     target_avg_chunk_size: usize,
     max_chunk_size: usize,
-) -> (Vec<Vec<u8>>, HashMap<String, Vec<Vec<u8>>>) {
-    assert!(min_chunk_size > 0, "min must be > 0");
-    assert!(
-        min_chunk_size <= target_avg_chunk_size && target_avg_chunk_size <= max_chunk_size,
-        "must satisfy min <= avg <= max"
-    );
+) -> (Vec<Vec<u8>>, ChunkMap) {
+    let params = CdcParams::new(min_chunk_size, target_avg_chunk_size, max_chunk_size);
+    chunk_bytes_with(data, &params)
+}
+
+/// [`chunk_bytes_cdc`] with the gear table built from `seed` (see
+/// [`make_gear_table_seeded`]).
+///
+/// The same input, sizes and seed always give the same chunks, on any platform and
+/// with any compiler version, which makes it a good base for reproducible tests.
+pub fn chunk_bytes_cdc_seeded(
+    data: &[u8],
+    min_chunk_size: usize,
+    target_avg_chunk_size: usize,
+    max_chunk_size: usize,
+    seed: u32,
+) -> (Vec<Vec<u8>>, ChunkMap) {
+    let params =
+        CdcParams::new(min_chunk_size, target_avg_chunk_size, max_chunk_size).with_gear_seed(seed);
+    chunk_bytes_with(data, &params)
+}
+
+fn chunk_bytes_with(data: &[u8], params: &CdcParams) -> (Vec<Vec<u8>>, ChunkMap) {
+    let mut sink = MemorySink::default();
+    chunk_to_sink(data, params, &mut sink).expect("chunk ids are hashes");
+    sink.into_parts()
+}
+
+/// Chunk `data` like [`chunk_refs_cdc`] and hand every chunk with its id to `sink`,
+/// in order, instead of collecting them; zero chunks come with their
+/// [`zero_chunk_id`]. Stops at the first error of the sink.
+pub fn chunk_to_sink<S: ChunkSink + ?Sized>(
+    data: &[u8],
+    params: &CdcParams,
+    sink: &mut S,
+) -> Result<(), StoreError> {
+    let hasher = params.chunk_hasher();
+    // Empty data has no spans, so an empty file does not get an empty chunk.
+    for (offset, len, zero) in ref_spans(data, params) {
+        let chunk_ref = chunk_ref(data, offset, len, zero, &*hasher);
+        sink.accept(&chunk_ref.hash, &data[offset..offset + len])?;
+    }
+    Ok(())
+}
+
+/// Like [`chunk_to_sink`], but reading the data from `reader` with a
+/// [`StreamChunker`], so no more than one chunk of it is in memory at a time.
+pub fn stream_to_sink<R: Read, S: ChunkSink + ?Sized>(
+    reader: R,
+    params: &CdcParams,
+    sink: &mut S,
+) -> Result<(), StoreError> {
+    for chunk in StreamChunker::new(reader, params) {
+        let (chunk_ref, chunk) = chunk?;
+        sink.accept(&chunk_ref.hash, &chunk)?;
+    }
+    Ok(())
+}
+
+/// Zero-copy version of [`chunk_bytes_cdc`]: chunks borrow from `data` instead of
+/// being copied into their own `Vec`s.
+pub fn chunk_bytes_cdc_ref<'a>(
+    data: &'a [u8],
+    min_chunk_size: usize,
+    target_avg_chunk_size: usize,
+    max_chunk_size: usize,
+) -> (Vec<&'a [u8]>, ChunkRefMap<'a>) {
+    let chunk_offsets = chunk_offsets(chunk_boundaries_cdc(
+        data,
+        min_chunk_size,
+        target_avg_chunk_size,
+        max_chunk_size,
+    ));
+
+    let chunks: Vec<&'a [u8]> = chunk_offsets
+        .windows(2)
+        .map(|w| &data[w[0]..w[1]])
+        .collect();
+
+    let mut chunk_map: ChunkRefMap<'a> = HashMap::new();
+    for &chunk in &chunks {
+        chunk_map
+            .entry(chunk_id_hash(chunk))
+            .or_default()
+            .push(chunk);
+    }
+
+    (chunks, chunk_map)
+}
+
+/// Chunk `data` like [`chunk_to_sink`] with `params`, returning every chunk with its
+/// id as a [`Bytes::slice`] of `data` instead of a copy.
+///
+/// Cuts and ids are those of [`chunk_refs_cdc`], zero chunks included.
+pub fn chunks_cdc(data: &Bytes, params: &CdcParams) -> Vec<Chunk> {
+    let hasher = params.chunk_hasher();
+    ref_spans(data, params)
+        .into_iter()
+        .map(|(offset, len, zero)| Chunk {
+            hash: chunk_ref(data, offset, len, zero, &*hasher).hash,
+            data: data.slice(offset..offset + len),
+        })
+        .collect()
+}
+
+/// Chunk `data` like [`chunk_bytes_cdc`], but return hashed references into `data`
+/// instead of copies of the chunk bytes.
+///
+/// Runs of at least [`ZERO_RUN_MIN`] zero bytes are not chunked or hashed but become
+/// zero chunks (see [`zero_chunk_id`]); the data between them is chunked as usual.
+pub fn chunk_refs_cdc(data: &[u8], params: &CdcParams) -> Vec<ChunkRef> {
+    let spans = ref_spans(data, params);
+
+    let hasher = params.chunk_hasher();
+    spans
+        .into_iter()
+        .map(|(offset, len, zero)| chunk_ref(data, offset, len, zero, &*hasher))
+        .collect()
+}
+
+/// Parallel version of [`chunk_refs_cdc`].
+///
+/// Boundary detection is inherently sequential (each cut depends on the previous one),
+/// but it is cheap. Hashing is the expensive part, and every chunk can be hashed
+/// independently, so we first collect all boundaries and then hash the slices on the
+/// current rayon pool. The output is identical to the serial version, in the same order.
+pub fn chunk_refs_cdc_parallel(data: &[u8], params: &CdcParams) -> Vec<ChunkRef> {
+    hash_chunk_spans(data, chunk_ref_spans(data, params), params)
+}
+
+/// The first half of [`chunk_refs_cdc_parallel`]: chunk boundaries as
+/// `(offset, len, zero)`, without hashing anything.
+pub fn chunk_ref_spans(data: &[u8], params: &CdcParams) -> Vec<(usize, usize, bool)> {
+    ref_spans(data, params)
+}
+
+/// The second half of [`chunk_refs_cdc_parallel`]: hash the `spans` of `data` found by
+/// [`chunk_ref_spans`] on the current rayon pool.
+pub fn hash_chunk_spans(
+    data: &[u8],
+    spans: Vec<(usize, usize, bool)>,
+    params: &CdcParams,
+) -> Vec<ChunkRef> {
+    // Hash borrowed slices only; no chunk bytes are copied.
+    let hasher = params.chunk_hasher();
+    spans
+        .into_par_iter()
+        .map(|(offset, len, zero)| chunk_ref(data, offset, len, zero, &*hasher))
+        .collect()
+}
+
+/// Why a chunk ends where it does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CutReason {
+    /// The rolling hash matched the boundary mask: a content-defined cut.
+    Boundary,
+    /// The chunk reached `max_chunk_size` without a boundary, or its fixed size with
+    /// [`ChunkingStrategy::FixedSize`].
+    Forced,
+    /// The data ran out: at the end of the input, or where a run of zeros starts.
+    EndOfInput,
+    /// The chunk is (part of) a run of zeros, see [`zero_chunk_id`].
+    ZeroRun,
+}
+
+impl fmt::Display for CutReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CutReason::Boundary => "boundary",
+            CutReason::Forced => "forced",
+            CutReason::EndOfInput => "end",
+            CutReason::ZeroRun => "zero",
+        })
+    }
+}
+
+/// Where a chunk boundary landed and why, from [`chunk_debug_info`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkDebugInfo {
+    pub offset: usize,
+    pub len: usize,
+    pub reason: CutReason,
+    /// The rolling hash at the last byte of the chunk; `None` for chunks that were not
+    /// scanned (zero runs, and tails no longer than `min_chunk_size`).
+    pub rolling_hash: Option<u32>,
+}
+
+/// The chunks [`chunk_refs_cdc`] cuts `data` into, with the reason for every cut, to
+/// find out why two nearly identical inputs do not deduplicate. Nothing is hashed
+/// but the rolling hash.
+pub fn chunk_debug_info(data: &[u8], params: &CdcParams) -> Vec<ChunkDebugInfo> {
+    let mut chunks = Vec::new();
+    for_each_chunk(data, params, |chunk| chunks.push(chunk));
+    chunks
+}
+
+fn chunk_ref(
+    data: &[u8],
+    offset: usize,
+    len: usize,
+    zero: bool,
+    hasher: &dyn ChunkHasher,
+) -> ChunkRef {
+    let hash = if zero {
+        zero_chunk_id(len)
+    } else {
+        hasher.hash(&data[offset..offset + len]).to_string()
+    };
+    ChunkRef { hash, offset, len }
+}
+
+/// Chunk boundaries as `(offset, len, zero)`, see [`for_each_chunk`].
+fn ref_spans(data: &[u8], params: &CdcParams) -> Vec<(usize, usize, bool)> {
+    let mut spans = Vec::new();
+    for_each_chunk(data, params, |chunk| {
+        spans.push((chunk.offset, chunk.len, chunk.reason == CutReason::ZeroRun))
+    });
+    spans
+}
+
+/// Call `on_chunk` with every chunk of `data`, in order: runs of at least
+/// [`ZERO_RUN_MIN`] zero bytes as zero chunks of at most [`ZERO_CHUNK_MAX`] bytes,
+/// and everything between them cut by the rolling hash.
+///
+/// This is the one boundary pass behind every chunking function, so the same input is
+/// cut the same way whichever of them chunks it, and chunks deduplicate between them.
+fn for_each_chunk(data: &[u8], params: &CdcParams, on_chunk: impl FnMut(ChunkDebugInfo)) {
+    for_each_chunk_with(data, params, BOUNDARY_SEARCH, on_chunk);
+}
+
+/// [`for_each_chunk`] with the boundary search given.
+fn for_each_chunk_with(
+    data: &[u8],
+    params: &CdcParams,
+    search: BoundarySearch,
+    mut on_chunk: impl FnMut(ChunkDebugInfo),
+) {
+    let mut start = 0;
+    for (run_start, run_end) in zero_runs(data) {
+        for_each_data_chunk(data, start..run_start, params, search, &mut on_chunk);
+        let mut offset = run_start;
+        while offset < run_end {
+            let len = (run_end - offset).min(ZERO_CHUNK_MAX);
+            on_chunk(ChunkDebugInfo {
+                offset,
+                len,
+                reason: CutReason::ZeroRun,
+                rolling_hash: None,
+            });
+            offset += len;
+        }
+        start = run_end;
+    }
+    for_each_data_chunk(data, start..data.len(), params, search, &mut on_chunk);
+}
+
+/// Cut the `range` of `data` between two zero runs with the rolling hash.
+fn for_each_data_chunk(
+    data: &[u8],
+    range: Range<usize>,
+    params: &CdcParams,
+    search: BoundarySearch,
+    on_chunk: &mut impl FnMut(ChunkDebugInfo),
+) {
+    let start = range.start;
+    for_each_cut_with(&data[range], params, search, |end, cut| {
+        on_chunk(ChunkDebugInfo {
+            offset: start + end - cut.len,
+            len: cut.len,
+            reason: cut.reason,
+            rolling_hash: cut.rolling_hash,
+        })
+    });
+}
+
+/// Maximal runs of zero bytes of at least [`ZERO_RUN_MIN`], as `(start, end)`.
+///
+/// A block of ordinary data is usually rejected on its first byte, so input without
+/// zero runs costs next to nothing.
+fn zero_runs(data: &[u8]) -> Vec<(usize, usize)> {
+    let is_zero = |block: &[u8]| block.iter().all(|&byte| byte == 0);
+
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut pos = 0;
+    while pos + ZERO_SCAN_BLOCK <= data.len() {
+        if !is_zero(&data[pos..pos + ZERO_SCAN_BLOCK]) {
+            pos += ZERO_SCAN_BLOCK;
+            continue;
+        }
+
+        // Grow the zero block in both directions, without reaching into the last run.
+        let floor = runs.last().map_or(0, |&(_, end)| end);
+        let start = data[floor..pos]
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(floor, |last| floor + last + 1);
+        let mut end = pos + ZERO_SCAN_BLOCK;
+        while end + ZERO_SCAN_BLOCK <= data.len() && is_zero(&data[end..end + ZERO_SCAN_BLOCK]) {
+            end += ZERO_SCAN_BLOCK;
+        }
+        end += data[end..]
+            .iter()
+            .position(|&byte| byte != 0)
+            .unwrap_or(data.len() - end);
+
+        if end - start >= ZERO_RUN_MIN {
+            runs.push((start, end));
+        }
+        pos = end;
+    }
+    runs
+}
+
+/// Streaming counterpart of [`chunk_refs_cdc`] for input that is not in memory,
+/// such as stdin.
+///
+/// Yields every chunk together with its [`ChunkRef`] (offsets are relative to the
+/// start of the stream), with boundaries and ids identical to chunking the same bytes
+/// as one slice, zero runs included. At most `max_chunk_size` plus [`ZERO_RUN_MIN`]
+/// bytes of lookahead are buffered; a zero run is counted as it is read, and only
+/// the zero chunks cut from it are handed out as zeros, each at most
+/// [`ZERO_CHUNK_MAX`] bytes.
+pub struct StreamChunker<R> {
+    reader: R,
+    min_chunk_size: usize,
+    max_chunk_size: usize,
+    boundary: BoundaryTest,
+    gear_shift: u32,
+    byte_to_random: [u32; 256],
+    // Cut every this many bytes instead, with `ChunkingStrategy::FixedSize`.
+    fixed_size: Option<usize>,
+    hasher: Box<dyn ChunkHasher>,
+    // Bytes read from `reader` that are not part of an emitted chunk yet.
+    buffer: Vec<u8>,
+    // Stream offset of `buffer[0]`.
+    offset: usize,
+    eof: bool,
+    // The last chunk was a `ZERO_CHUNK_MAX` piece of a zero run, so any zeros at the
+    // start of the buffer are the rest of that run, however few.
+    in_zero_run: bool,
+}
+
+impl<R: Read> StreamChunker<R> {
+    pub fn new(reader: R, params: &CdcParams) -> Self {
+        StreamChunker {
+            reader,
+            min_chunk_size: params.min_chunk_size,
+            max_chunk_size: params.max_chunk_size,
+            boundary: params.boundary_test(),
+            gear_shift: params.gear_shift,
+            byte_to_random: params.gear_table(),
+            fixed_size: (params.strategy == ChunkingStrategy::FixedSize)
+                .then_some(params.target_avg_chunk_size),
+            hasher: params.chunk_hasher(),
+            buffer: Vec::with_capacity(params.max_chunk_size + ZERO_RUN_MIN),
+            offset: 0,
+            eof: false,
+            in_zero_run: false,
+        }
+    }
+
+    /// Total number of bytes emitted as chunks so far.
+    pub fn bytes_processed(&self) -> usize {
+        self.offset
+    }
+
+    /// Read until the buffer holds `max_chunk_size` bytes and `ZERO_RUN_MIN` more, or
+    /// the stream ends: a cut never looks past `max_chunk_size`, and a zero run that
+    /// starts before it is either whole in the buffer or reaches its end with at least
+    /// `ZERO_RUN_MIN` zeros.
+    fn fill(&mut self) -> io::Result<()> {
+        let capacity = self.max_chunk_size + ZERO_RUN_MIN;
+        while !self.eof && self.buffer.len() < capacity {
+            let wanted = (capacity - self.buffer.len()) as u64;
+            if self
+                .reader
+                .by_ref()
+                .take(wanted)
+                .read_to_end(&mut self.buffer)?
+                == 0
+            {
+                self.eof = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Consume the zeros at the start of the buffer, reading on while the buffer is
+    /// all zeros, up to `ZERO_CHUNK_MAX` of them. Returns how many there were.
+    fn take_zeros(&mut self) -> io::Result<usize> {
+        let mut len = 0;
+        loop {
+            let limit = self.buffer.len().min(ZERO_CHUNK_MAX - len);
+            let zeros = self.buffer[..limit]
+                .iter()
+                .position(|&byte| byte != 0)
+                .unwrap_or(limit);
+            len += zeros;
+            if zeros < self.buffer.len() || len == ZERO_CHUNK_MAX || self.eof {
+                self.buffer.drain(..zeros);
+                return Ok(len);
+            }
+            self.buffer.clear();
+            self.fill()?;
+        }
+    }
+
+    fn zero_chunk(&mut self) -> io::Result<Option<(ChunkRef, Vec<u8>)>> {
+        let len = self.take_zeros()?;
+        self.in_zero_run = len == ZERO_CHUNK_MAX;
+        if len == 0 {
+            return Ok(None);
+        }
+        let chunk_ref = ChunkRef {
+            hash: zero_chunk_id(len),
+            offset: self.offset,
+            len,
+        };
+        self.offset += len;
+        Ok(Some((chunk_ref, vec![0; len])))
+    }
+}
+
+impl<R: Read> Iterator for StreamChunker<R> {
+    type Item = io::Result<(ChunkRef, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.fill() {
+            return Some(Err(err));
+        }
+
+        // Data is only ever cut up to the start of the first zero run, the same
+        // spans `ref_spans` chunks between runs.
+        let run_start = zero_runs(&self.buffer).first().map(|&(start, _)| start);
+        if self.in_zero_run || run_start == Some(0) {
+            match self.zero_chunk() {
+                Ok(Some(chunk)) => return Some(Ok(chunk)),
+                // The last run ended with its `ZERO_CHUNK_MAX` piece; the buffer is
+                // as it was.
+                Ok(None) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        let data = &self.buffer[..run_start.unwrap_or(self.buffer.len())];
+        let chunk_len = match self.fixed_size {
+            Some(size) => fixed_cut(data, size),
+            None => next_cut(
+                data,
+                self.min_chunk_size,
+                self.max_chunk_size,
+                self.boundary,
+                self.gear_shift,
+                &self.byte_to_random,
+                BOUNDARY_SEARCH,
+            ),
+        }
+        .len;
+
+        let rest = self.buffer.split_off(chunk_len);
+        let chunk = std::mem::replace(&mut self.buffer, rest);
+        let chunk_ref = ChunkRef {
+            hash: self.hasher.hash(&chunk).to_string(),
+            offset: self.offset,
+            len: chunk_len,
+        };
+        self.offset += chunk_len;
+
+        Some(Ok((chunk_ref, chunk)))
+    }
+}
+
+/// Turn chunk end offsets into `[0, end_0, end_1, ...]`, so every chunk is a window of two.
+fn chunk_offsets(chunk_ends: Vec<usize>) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(chunk_ends.len() + 1);
+    offsets.push(0);
+    offsets.extend(chunk_ends);
+    offsets
+}
+
+/// Chunk boundaries only: the exclusive end offset of every chunk in `data`.
+///
+/// Useful when a caller only needs to know where the cuts fall (e.g. to decide which
+/// parts of a large file to upload) and not the chunk bytes themselves.
+/// Chunk `k` is `data[ends[k - 1]..ends[k]]`, with the first chunk starting at 0.
+pub fn chunk_boundaries_cdc(
+    data: &[u8],
+    min_chunk_size: usize,
+    target_avg_chunk_size: usize,
+    max_chunk_size: usize,
+) -> Vec<usize> {
+    let params = CdcParams::new(min_chunk_size, target_avg_chunk_size, max_chunk_size);
+    chunk_ends_cdc(data, &params)
+}
+
+/// Number of chunks [`chunk_bytes_cdc`] would cut `data` into, without building them.
+///
+/// Runs the same boundary scan but only counts the cuts: no chunk data is copied or
+/// hashed, so it is a cheap way to size up a backup.
+pub fn estimate_chunk_count(
+    data: &[u8],
+    min_chunk_size: usize,
+    target_avg_chunk_size: usize,
+    max_chunk_size: usize,
+) -> usize {
+    count_chunks_cdc(
+        data,
+        &CdcParams::new(min_chunk_size, target_avg_chunk_size, max_chunk_size),
+    )
+}
+
+/// [`estimate_chunk_count`] with all of `params`: gear shift and table, boundary bits
+/// and strategy.
+pub fn count_chunks_cdc(data: &[u8], params: &CdcParams) -> usize {
+    let mut count = 0;
+    for_each_chunk(data, params, |_| count += 1);
+    count
+}
+
+/// [`chunk_boundaries_cdc`] with `params`, always using the plain byte-by-byte boundary
+/// search.
+///
+/// The `simd` feature swaps in a faster search that must cut at exactly the same
+/// places; this is the reference it is checked against.
+pub fn chunk_boundaries_scalar(data: &[u8], params: &CdcParams) -> Vec<usize> {
+    let mut chunk_ends: Vec<usize> = Vec::new();
+    for_each_chunk_with(data, params, find_boundary_scalar, |chunk| {
+        chunk_ends.push(chunk.offset + chunk.len)
+    });
+    chunk_ends
+}
 
-    // Choose N so that 2^N is close to target_avg_chunk_size.
-    //
-    // Example:
-    //   target_avg_chunk_size = 2048
-    //   log2(2048) = 11
-    //   => probability of boundary ≈ 1/2^11
-    //   => average chunk size ≈ 2^11 = 2048 bytes
-    //
-    // We do this with floats in the demo for readability.
-    let approx_bits = (target_avg_chunk_size as f64).log2();
-
-    // Round to nearest integer number of bits.
-    let rounded_bits = approx_bits.round();
-
-    // Clamp to a safe range for u32 bit operations:
-    // - at least 1 bit (mask not zero)
-    // - at most 31 bits (so (1u32 << bits) is valid)
-    let boundary_bits: u32 = rounded_bits.clamp(1.0, 31.0) as u32;
-
-    // boundary_bitmask has the lowest `boundary_bits` bits set to 1.
-    //
-    // Example boundary_bits = 5:
-    //   boundary_bitmask = (1<<5)-1 = 31 = 0b00011111
-    //
-    // Then (rolling_hash & boundary_bitmask) == 0 means:
-    //   "the lowest 5 bits are all zero"
-    let boundary_bitmask: u32 = (1u32 << boundary_bits) - 1;
+/// Boundary pass of the chunker: the exclusive end offset of every chunk in `data`.
+///
+/// This only runs the rolling hash; it neither copies nor hashes chunk data.
+fn chunk_ends_cdc(data: &[u8], params: &CdcParams) -> Vec<usize> {
+    let mut chunk_ends: Vec<usize> = Vec::new();
+    for_each_chunk(data, params, |chunk| {
+        chunk_ends.push(chunk.offset + chunk.len)
+    });
+    chunk_ends
+}
+
+/// The rolling hash pass alone: the exclusive end offset of every chunk in `data`,
+/// cutting zero runs like any other data. Only the test vectors pin it; everything
+/// else chunks through [`for_each_chunk`].
+fn data_chunk_ends(data: &[u8], params: &CdcParams) -> Vec<usize> {
+    let mut chunk_ends: Vec<usize> = Vec::new();
+    for_each_cut_with(data, params, BOUNDARY_SEARCH, |end, _| chunk_ends.push(end));
+    chunk_ends
+}
+
+/// Call `on_cut` with the exclusive end offset of every chunk in `data`, in order, and
+/// how that chunk was cut, using `search` to find boundaries. Zero runs are not treated
+/// specially; see [`for_each_chunk`] for that.
+fn for_each_cut_with(
+    data: &[u8],
+    params: &CdcParams,
+    search: BoundarySearch,
+    mut on_cut: impl FnMut(usize, Cut),
+) {
+    if params.strategy == ChunkingStrategy::FixedSize {
+        let mut chunk_start_index = 0;
+        while chunk_start_index < data.len() {
+            let cut = fixed_cut(&data[chunk_start_index..], params.target_avg_chunk_size);
+            chunk_start_index += cut.len;
+            on_cut(chunk_start_index, cut);
+        }
+        return;
+    }
+
+    let boundary = params.boundary_test();
 
     // A 256-entry lookup table that maps each byte (0..255) to a "random-looking" u32.
     // This gives the rolling hash good mixing properties.
-    let byte_to_random: [u32; 256] = make_gear_table();
-
-    let mut chunks: Vec<Vec<u8>> = Vec::new();
-    let mut chunk_map: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+    let byte_to_random: [u32; 256] = params.gear_table();
 
     // Start index of the current chunk inside `data`.
     let mut chunk_start_index: usize = 0;
 
-    // Rolling hash state for the current chunk scan.
-    let mut rolling_hash: u32 = 0;
+    while chunk_start_index < data.len() {
+        let cut = next_cut(
+            &data[chunk_start_index..],
+            params.min_chunk_size,
+            params.max_chunk_size,
+            boundary,
+            params.gear_shift,
+            &byte_to_random,
+            search,
+        );
+
+        // Start a new chunk after the cut.
+        chunk_start_index += cut.len;
+        on_cut(chunk_start_index, cut);
+    }
+}
+
+/// The chunk that starts at the beginning of `data` with [`ChunkingStrategy::FixedSize`]:
+/// `size` bytes, or the rest if that is shorter.
+fn fixed_cut(data: &[u8], size: usize) -> Cut {
+    assert!(size > 0, "fixed chunk size must be > 0");
+    if data.len() <= size {
+        return Cut {
+            len: data.len(),
+            reason: CutReason::EndOfInput,
+            rolling_hash: None,
+        };
+    }
+    Cut {
+        len: size,
+        reason: CutReason::Forced,
+        rolling_hash: None,
+    }
+}
 
-    // Walk through every byte; decide where to cut.
+/// Find the length of the chunk that starts at the beginning of `data`.
+///
+/// Rules, applied to the chunk length `len` if we include byte `i` (inclusive):
+/// 1. Never cut before `min_chunk_size`.
+/// 2. Cut if the rolling hash shows the boundary pattern (probabilistic).
+/// 3. Always cut at `max_chunk_size` (forced boundary).
+///
+/// If none of these fire before the data runs out, the rest is the tail chunk.
+/// Besides the length, the result tells which rule ended the chunk.
+///
+/// Skip-min optimization (same trick as FastCDC):
+/// the gear hash only remembers the last [`gear_window`] bytes, so hashing the bytes
+/// far below `min_chunk_size` is wasted work. We start hashing at
+/// `min_chunk_size - gear_window`, which yields exactly the same hash value at the
+/// first position where a cut is allowed as hashing from the chunk start.
+fn next_cut(
+    data: &[u8],
+    min_chunk_size: usize,
+    max_chunk_size: usize,
+    boundary: BoundaryTest,
+    gear_shift: u32,
+    byte_to_random: &[u32; 256],
+    search: BoundarySearch,
+) -> Cut {
+    // Not enough bytes left for a full minimum chunk: everything is the tail.
+    if data.len() <= min_chunk_size {
+        return Cut {
+            len: data.len(),
+            reason: CutReason::EndOfInput,
+            rolling_hash: None,
+        };
+    }
+
+    // We never look past the forced boundary.
+    let scan_end = data.len().min(max_chunk_size);
+
+    // First byte that can still influence the hash at position `min_chunk_size - 1`.
+    let hash_start = min_chunk_size.saturating_sub(gear_window(gear_shift));
+
+    // Rule 1: never cut before minimum size, so the bytes up to the last one of a
+    // minimum chunk only feed the rolling hash.
+    let rolling_hash = gear_hash_from(
+        0,
+        &data[hash_start..min_chunk_size - 1],
+        gear_shift,
+        byte_to_random,
+    );
+
+    // Rule 2: cut if we see the boundary pattern (probabilistic).
+    let (found, rolling_hash) = search(
+        &data[min_chunk_size - 1..scan_end],
+        rolling_hash,
+        boundary,
+        gear_shift,
+        byte_to_random,
+    );
+    if let Some(index) = found {
+        let current_chunk_len = min_chunk_size + index;
+        // The skipped prefix must not change what the naive loop would have seen.
+        debug_assert!(
+            index > 0
+                || rolling_hash
+                    == gear_hash(&data[..current_chunk_len], gear_shift, byte_to_random),
+            "skip-min changed the rolling hash"
+        );
+        return Cut {
+            len: current_chunk_len,
+            reason: CutReason::Boundary,
+            rolling_hash: Some(rolling_hash),
+        };
+    }
+
+    // Rule 3: forced cut at max size, or the tail if the data ran out first.
+    Cut {
+        len: scan_end,
+        reason: if scan_end == max_chunk_size {
+            CutReason::Forced
+        } else {
+            CutReason::EndOfInput
+        },
+        rolling_hash: Some(rolling_hash),
+    }
+}
+
+/// Feeds `data` into the rolling hash, which starts out as `rolling_hash`, and stops
+/// at the first byte where it is a boundary.
+///
+/// Returns the index of that byte, if any, and the rolling hash there (or after the
+/// last byte). Every implementation must give exactly the same result as
+/// [`find_boundary_scalar`].
+type BoundarySearch = fn(&[u8], u32, BoundaryTest, u32, &[u32; 256]) -> (Option<usize>, u32);
+
+/// The search the chunker uses: strided with the `simd` feature, scalar otherwise.
+#[cfg(feature = "simd")]
+const BOUNDARY_SEARCH: BoundarySearch = find_boundary_strided;
+#[cfg(not(feature = "simd"))]
+const BOUNDARY_SEARCH: BoundarySearch = find_boundary_scalar;
+
+/// The reference search: one byte, one hash update, one boundary test.
+fn find_boundary_scalar(
+    data: &[u8],
+    mut rolling_hash: u32,
+    boundary: BoundaryTest,
+    gear_shift: u32,
+    byte_to_random: &[u32; 256],
+) -> (Option<usize>, u32) {
     for (i, &byte) in data.iter().enumerate() {
         // "Gear" rolling hash update.
         //
         // The shift keeps history (older bytes still affect the hash, but fade over time),
         // and adding a per-byte random value injects entropy.
-        rolling_hash = rolling_hash
-            .wrapping_shl(1)
-            .wrapping_add(byte_to_random[byte as usize]);
+        rolling_hash = gear_step(rolling_hash, byte, gear_shift, byte_to_random);
+        if boundary.is_boundary(rolling_hash) {
+            return (Some(i), rolling_hash);
+        }
+    }
+    (None, rolling_hash)
+}
 
-        // Current chunk length if we include this byte (i is inclusive).
-        let current_chunk_len = i + 1 - chunk_start_index;
+/// Bytes hashed between two boundary checks in [`find_boundary_strided`].
+#[cfg(feature = "simd")]
+const STRIDE: usize = 8;
 
-        // Rule 1: Never cut before minimum size.
-        if current_chunk_len < min_chunk_size {
-            continue;
+/// [`find_boundary_scalar`] with less work per byte.
+///
+/// The loop runs over fixed-size strides, with the shift a constant and no bounds
+/// checks within a stride. Each step of the gear hash depends on the one before, which
+/// is what limits the scalar loop; here the hashes of a stride are all derived from
+/// the hash before it, so they can be computed side by side, and their boundary masks
+/// are combined into a single (rarely taken) branch.
+#[cfg(feature = "simd")]
+fn find_boundary_strided(
+    data: &[u8],
+    rolling_hash: u32,
+    boundary: BoundaryTest,
+    gear_shift: u32,
+    byte_to_random: &[u32; 256],
+) -> (Option<usize>, u32) {
+    match gear_shift {
+        1 => find_boundary_strided_by::<1>(data, rolling_hash, boundary, byte_to_random),
+        2 => find_boundary_strided_by::<2>(data, rolling_hash, boundary, byte_to_random),
+        _ => find_boundary_scalar(data, rolling_hash, boundary, gear_shift, byte_to_random),
+    }
+}
+
+#[cfg(feature = "simd")]
+#[inline(always)]
+fn find_boundary_strided_by<const SHIFT: u32>(
+    data: &[u8],
+    mut rolling_hash: u32,
+    boundary: BoundaryTest,
+    byte_to_random: &[u32; 256],
+) -> (Option<usize>, u32) {
+    let mut strides = data.chunks_exact(STRIDE);
+    for (n, stride) in strides.by_ref().enumerate() {
+        let stride: &[u8; STRIDE] = stride.try_into().expect("exact chunk");
+        // The hash after byte `k` of the stride is the hash before the stride shifted
+        // by `k + 1` steps, plus the contribution of bytes `0..=k` alone. Those
+        // contributions do not depend on the incoming hash, so the loop carries a
+        // single shift and add per stride instead of one per byte.
+        let mut hashes = [0u32; STRIDE];
+        let mut contribution = 0u32;
+        for (k, (hash, &byte)) in hashes.iter_mut().zip(stride).enumerate() {
+            contribution = gear_step(contribution, byte, SHIFT, byte_to_random);
+            *hash = rolling_hash
+                .wrapping_shl(SHIFT * (k as u32 + 1))
+                .wrapping_add(contribution);
+        }
+        // One branch per stride, on the mask alone; the full test only runs for the
+        // rare strides that pass it.
+        if hashes
+            .iter()
+            .fold(false, |any, &hash| any | boundary.may_be_boundary(hash))
+            && let Some(i) = hashes.iter().position(|&hash| boundary.is_boundary(hash))
+        {
+            return (Some(n * STRIDE + i), hashes[i]);
         }
+        rolling_hash = hashes[STRIDE - 1];
+    }
 
-        // Rule 2: Cut if we see the boundary pattern (probabilistic).
-        let boundary_pattern_hit = (rolling_hash & boundary_bitmask) == 0;
+    let done = data.len() - strides.remainder().len();
+    let (found, rolling_hash) = find_boundary_scalar(
+        strides.remainder(),
+        rolling_hash,
+        boundary,
+        SHIFT,
+        byte_to_random,
+    );
+    (found.map(|i| done + i), rolling_hash)
+}
 
-        // Rule 3: Always cut if we hit max size (forced boundary).
-        let forced_cut = current_chunk_len >= max_chunk_size;
+/// One chunk as [`next_cut`] found it.
+#[derive(Clone, Copy, Debug)]
+struct Cut {
+    len: usize,
+    reason: CutReason,
+    /// The rolling hash at the last byte of the chunk; `None` if the chunk was too
+    /// short to be scanned.
+    rolling_hash: Option<u32>,
+}
 
-        if boundary_pattern_hit || forced_cut {
-            // Emit chunk data[chunk_start_index..=i]
-            let tmp_data = data[chunk_start_index..=i].to_vec();
-            chunks.push(tmp_data.clone());
-            chunk_map
-                .entry(chunk_id_hash(&tmp_data))
-                .or_insert_with(Vec::new)
-                .push(tmp_data);
+/// Hash `data` from a zeroed state, byte by byte (the naive, non-skipping loop).
+fn gear_hash(data: &[u8], gear_shift: u32, byte_to_random: &[u32; 256]) -> u32 {
+    gear_hash_from(0, data, gear_shift, byte_to_random)
+}
 
-            // Start a new chunk after this byte.
-            chunk_start_index = i + 1;
-            rolling_hash = 0;
-        }
-    }
+/// Feed `data` into the rolling hash `rolling_hash`.
+fn gear_hash_from(
+    rolling_hash: u32,
+    data: &[u8],
+    gear_shift: u32,
+    byte_to_random: &[u32; 256],
+) -> u32 {
+    data.iter().fold(rolling_hash, |rolling_hash, &byte| {
+        gear_step(rolling_hash, byte, gear_shift, byte_to_random)
+    })
+}
 
-    // Emit tail chunk if any bytes are left.
-    if chunk_start_index < data.len() {
-        let tmp_data = data[chunk_start_index..].to_vec();
-        chunks.push(tmp_data.clone());
-        chunk_map
-            .entry(chunk_id_hash(&tmp_data))
-            .or_insert_with(Vec::new)
-            .push(tmp_data);
-    }
+#[inline(always)]
+fn gear_step(rolling_hash: u32, byte: u8, gear_shift: u32, byte_to_random: &[u32; 256]) -> u32 {
+    rolling_hash
+        .wrapping_shl(gear_shift)
+        .wrapping_add(byte_to_random[byte as usize])
+}
 
-    (chunks, chunk_map)
+/// One step of the gear hash at the default shift of 1: `rolling` with `byte` added,
+/// as the chunker computes it. Folding this over the bytes of a chunk from 0 gives the
+/// hash its boundary was cut at (with the default [`CdcParams::gear_shift`]).
+pub fn gear_hash_step(rolling: u32, byte: u8, table: &[u32; 256]) -> u32 {
+    gear_step(rolling, byte, 1, table)
+}
+
+/// Number of trailing bytes that still influence the gear hash at `gear_shift`.
+fn gear_window(gear_shift: u32) -> usize {
+    GEAR_WINDOW.div_ceil(gear_shift as usize)
+}
+
+/// The gear table used unless a seed is configured, i.e. built from
+/// [`DEFAULT_GEAR_SEED`].
+///
+/// Stable API: the table is part of the chunk format, so it stays the same for a given
+/// [`CHUNKER_FORMAT_VERSION`], and `tests/golden.rs` pins it.
+pub fn make_gear_table() -> [u32; 256] {
+    make_gear_table_seeded(DEFAULT_GEAR_SEED)
 }
 
-/// Build a deterministic "random-looking" table for bytes 0..255.
+/// Build a deterministic "random-looking" table for bytes 0..255 from `seed`.
 ///
 /// In real backup tools, this is typically a hardcoded constant table.
 /// For a demo, generating it deterministically is fine as long as it's stable.
-/// If you change this table, chunk boundaries will change too.
-fn make_gear_table() -> [u32; 256] {
+/// If you change this table, chunk boundaries will change too. Only wrapping 32-bit
+/// integer arithmetic is involved, so a seed gives the same table everywhere.
+pub fn make_gear_table_seeded(seed: u32) -> [u32; 256] {
     let mut table = [0u32; 256];
 
     // Simple deterministic PRNG (Linear Congruential Generator-ish).
     // Not cryptographic. It's just to get stable "randomish" constants.
-    let mut x: u32 = 0x1234_5678;
+    let mut x: u32 = seed;
 
-    for i in 0..256 {
+    for entry in table.iter_mut() {
         x = x.wrapping_mul(1664525).wrapping_add(1013904223);
-        table[i] = x ^ (x >> 16);
+        *entry = x ^ (x >> 16);
     }
 
     table
@@ -0,0 +1,281 @@
+//! Canonical inputs with the chunk boundaries [`CHUNKER_FORMAT_VERSION`](super::CHUNKER_FORMAT_VERSION) cuts them at.
+//!
+//! Stores deduplicate against chunks cut by older releases, so these boundaries are
+//! a compatibility guarantee: a change to the gear table, the shift or the boundary
+//! mask that moves any of them is a new chunker format. `tests/test_vectors.rs`
+//! checks every vector; the expected offsets may only change together with a bump of
+//! [`CHUNKER_FORMAT_VERSION`](super::CHUNKER_FORMAT_VERSION).
+
+use super::{CdcParams, chunk_refs_cdc, data_chunk_ends, zero_chunk_len};
+
+/// Input of a [`TestVector`], built on demand instead of stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// `len` zero bytes.
+    Zeros(usize),
+    /// `len` bytes from a xorshift64 generator started at `seed`, taking bits 32..40
+    /// of every state.
+    Noise { seed: u64, len: usize },
+    /// The UTF-8 bytes of a text.
+    Text(&'static str),
+    /// The bytes of several inputs, one after the other.
+    Concat(&'static [Input]),
+}
+
+impl Input {
+    pub fn bytes(&self) -> Vec<u8> {
+        match *self {
+            Input::Zeros(len) => vec![0; len],
+            Input::Noise { seed, len } => {
+                let mut state = seed;
+                (0..len)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        (state >> 32) as u8
+                    })
+                    .collect()
+            }
+            Input::Text(text) => text.as_bytes().to_vec(),
+            Input::Concat(parts) => parts.iter().flat_map(Input::bytes).collect(),
+        }
+    }
+}
+
+/// An input, chunking parameters and the exclusive end offset of every chunk cut.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub input: Input,
+    pub min_chunk_size: usize,
+    pub target_avg_chunk_size: usize,
+    pub max_chunk_size: usize,
+    /// Gear shift per byte; [`DEFAULT_GEAR_SHIFT`](super::DEFAULT_GEAR_SHIFT) unless
+    /// the vector pins another one.
+    pub gear_shift: u32,
+    /// Chunk ends of the rolling hash pass. Zero runs are cut out as zero chunks
+    /// around it (see [`RefVector`]), so all-zero inputs pin the forced cuts at
+    /// `max_chunk_size` here, although every public chunker makes them zero chunks.
+    pub ends: &'static [usize],
+}
+
+impl TestVector {
+    /// The parameters of the vector, with the default gear table and boundary bits.
+    pub fn params(&self) -> CdcParams {
+        CdcParams::new(
+            self.min_chunk_size,
+            self.target_avg_chunk_size,
+            self.max_chunk_size,
+        )
+        .with_gear_shift(self.gear_shift)
+    }
+
+    /// The chunk end offsets this build cuts the input at; equal to
+    /// [`TestVector::ends`] unless the chunker format changed.
+    pub fn cut(&self) -> Vec<usize> {
+        data_chunk_ends(&self.input.bytes(), &self.params())
+    }
+}
+
+const TEXT: &str = "Chunk boundaries depend only on the content of a file, so an \
+edit near its start moves the cuts around the edit and leaves every later chunk \
+where it was. That is what lets a backup store an edited file as a few new chunks \
+instead of a whole new copy. The boundaries of this text are part of the chunk \
+format: rbckp must cut it at the same places in every release.\n";
+
+/// The canonical vectors, in the order `tests/test_vectors.rs` reports them.
+pub const TEST_VECTORS: &[TestVector] = &[
+    TestVector {
+        name: "zeros-256k",
+        input: Input::Zeros(256 * 1024),
+        min_chunk_size: 1024,
+        target_avg_chunk_size: 4096,
+        max_chunk_size: 16384,
+        gear_shift: 1,
+        ends: &[
+            16384, 32768, 49152, 65536, 81920, 98304, 114688, 131072, 147456, 163840, 180224,
+            196608, 212992, 229376, 245760, 262144,
+        ],
+    },
+    TestVector {
+        name: "zeros-8k",
+        input: Input::Zeros(8 * 1024),
+        min_chunk_size: 1024,
+        target_avg_chunk_size: 4096,
+        max_chunk_size: 16384,
+        gear_shift: 1,
+        ends: &[8192],
+    },
+    TestVector {
+        name: "noise-64k",
+        input: Input::Noise {
+            seed: 0x9e37_79b9_7f4a_7c15,
+            len: 64 * 1024,
+        },
+        min_chunk_size: 1024,
+        target_avg_chunk_size: 4096,
+        max_chunk_size: 16384,
+        gear_shift: 1,
+        ends: &[
+            4396, 7546, 9686, 14070, 18555, 25749, 31265, 33993, 36190, 45895, 51546, 54070, 59602,
+            62610, 65536,
+        ],
+    },
+    TestVector {
+        name: "noise-16k-small",
+        input: Input::Noise {
+            seed: 42,
+            len: 16 * 1024,
+        },
+        min_chunk_size: 256,
+        target_avg_chunk_size: 1024,
+        max_chunk_size: 4096,
+        gear_shift: 1,
+        ends: &[
+            546, 1490, 3292, 5185, 5551, 7099, 7495, 7806, 9983, 10404, 12813, 13223, 13815, 14766,
+            15399, 15768, 16235, 16384,
+        ],
+    },
+    TestVector {
+        name: "noise-16k-shift-2",
+        input: Input::Noise {
+            seed: 42,
+            len: 16 * 1024,
+        },
+        min_chunk_size: 256,
+        target_avg_chunk_size: 1024,
+        max_chunk_size: 4096,
+        gear_shift: 2,
+        ends: &[
+            258, 1168, 2385, 3953, 4904, 5577, 5914, 6611, 7055, 11151, 13470, 13951, 14348, 15989,
+            16384,
+        ],
+    },
+    TestVector {
+        name: "text",
+        input: Input::Text(TEXT),
+        min_chunk_size: 16,
+        target_avg_chunk_size: 64,
+        max_chunk_size: 256,
+        gear_shift: 1,
+        ends: &[35, 51, 82, 131, 169, 208, 297, 333, 349, 365],
+    },
+];
+
+/// An input cut by [`chunk_refs_cdc`], zero runs and all: the
+/// exclusive end offset of every chunk, and whether it is a zero chunk.
+///
+/// [`TestVector`]s pin the boundary pass alone; these pin which spans become zero
+/// chunks and how the data between them is cut.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RefVector {
+    pub name: &'static str,
+    pub input: Input,
+    pub min_chunk_size: usize,
+    pub target_avg_chunk_size: usize,
+    pub max_chunk_size: usize,
+    pub chunks: &'static [(usize, bool)],
+}
+
+impl RefVector {
+    pub fn params(&self) -> CdcParams {
+        CdcParams::new(
+            self.min_chunk_size,
+            self.target_avg_chunk_size,
+            self.max_chunk_size,
+        )
+    }
+
+    /// The chunks this build cuts the input into, as `(end, zero)`; equal to
+    /// [`RefVector::chunks`] unless the chunker format changed.
+    pub fn cut(&self) -> Vec<(usize, bool)> {
+        chunk_refs_cdc(&self.input.bytes(), &self.params())
+            .into_iter()
+            .map(|chunk| {
+                (
+                    chunk.offset + chunk.len,
+                    zero_chunk_len(&chunk.hash).is_some(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// The canonical [`chunk_refs_cdc`] vectors.
+pub const REF_VECTORS: &[RefVector] = &[
+    RefVector {
+        name: "noise-around-zero-runs",
+        input: Input::Concat(&[
+            Input::Noise {
+                seed: 7,
+                len: 20_000,
+            },
+            Input::Zeros(70_000),
+            Input::Noise {
+                seed: 8,
+                len: 30_000,
+            },
+            // One byte short of a zero run: chunked like data.
+            Input::Zeros(64 * 1024 - 1),
+            Input::Noise {
+                seed: 9,
+                len: 5_000,
+            },
+            Input::Zeros(128 * 1024),
+        ]),
+        min_chunk_size: 1024,
+        target_avg_chunk_size: 4096,
+        max_chunk_size: 16384,
+        chunks: &[
+            (2824, false),
+            (4462, false),
+            (8328, false),
+            (9986, false),
+            (13175, false),
+            (19926, false),
+            (20000, false),
+            (90000, true),
+            (91234, false),
+            (96482, false),
+            (99506, false),
+            (103623, false),
+            (105121, false),
+            (107012, false),
+            (110408, false),
+            (114136, false),
+            (115966, false),
+            (132350, false),
+            (148734, false),
+            (165118, false),
+            (181502, false),
+            (187890, false),
+            (190535, false),
+            (321607, true),
+        ],
+    },
+    RefVector {
+        name: "zero-run-first",
+        input: Input::Concat(&[
+            Input::Zeros(64 * 1024),
+            Input::Text(TEXT),
+            Input::Zeros(100),
+        ]),
+        min_chunk_size: 16,
+        target_avg_chunk_size: 64,
+        max_chunk_size: 256,
+        chunks: &[
+            (65536, true),
+            (65571, false),
+            (65587, false),
+            (65618, false),
+            (65667, false),
+            (65705, false),
+            (65744, false),
+            (65833, false),
+            (65869, false),
+            (65885, false),
+            (66001, false),
+        ],
+    },
+];
@@ -0,0 +1,173 @@
+//! Chunk-level integrity checks: every stored chunk against its id, without looking at
+//! any snapshot, and repairs from a second copy of the repository.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::backup::store::{Backend, ChunkStore, StoreError, index::ChunkLocation};
+
+/// Object in the repository listing the chunks `rbckp check --repair` could not
+/// repair, one `<hash> <pack>` per line.
+pub const CORRUPT_LIST_NAME: &str = "corrupt.txt";
+
+/// A chunk whose stored data does not hash to its id, or cannot be read at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptChunk {
+    pub hash: String,
+    /// Name of the pack holding it.
+    pub pack: String,
+    pub location: ChunkLocation,
+}
+
+/// Outcome of [`check_chunks`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkCheck {
+    /// Chunks in the index, all of which were read.
+    pub chunks: usize,
+    pub bytes: u64,
+    pub corrupt: Vec<CorruptChunk>,
+}
+
+/// Outcome of [`repair_chunks`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Corrupt chunks whose pack was restored from the secondary copy.
+    pub repaired: Vec<String>,
+    /// Corrupt chunks listed in [`CORRUPT_LIST_NAME`] instead.
+    pub marked: Vec<String>,
+}
+
+/// Read every chunk in the index of `store` and hash it again with the repository's
+/// hash algorithm, bypassing any chunk cache. Packs are read chunk by chunk, in
+/// offset order.
+///
+/// Unlike [`verify_repo`](super::verify::verify_repo) this needs no snapshot: chunks
+/// are only compared with their own ids. A chunk that cannot be read counts as
+/// corrupt too.
+pub fn check_chunks<B: Backend>(store: &ChunkStore<B>) -> Result<ChunkCheck, StoreError> {
+    let backend = store.backend();
+    let hasher = store.hash_algorithm().keyed_hasher(store.hash_key());
+    let mut check = ChunkCheck::default();
+
+    for (pack_id, chunks) in chunks_by_pack(store) {
+        let name = store.pack_name(pack_id);
+        for (hash, location) in chunks {
+            check.chunks += 1;
+            let intact =
+                match backend.read_range(&name, location.offset, location.compressed_length) {
+                    Ok(data) => {
+                        check.bytes += data.len() as u64;
+                        data.len() as u64 == location.length
+                            && hasher.hash(&data).to_string() == hash
+                    }
+                    Err(err) => {
+                        log::debug!("cannot read chunk {} from {}: {}", hash, name, err);
+                        false
+                    }
+                };
+            if !intact {
+                check.corrupt.push(CorruptChunk {
+                    hash,
+                    pack: name.clone(),
+                    location,
+                });
+            }
+        }
+    }
+    Ok(check)
+}
+
+/// Repair the `corrupt` chunks of `store` from `secondary`, a second copy of the
+/// repository such as the secondary side of a `type = tee` store.
+///
+/// A pack is copied over from the secondary if every chunk the index places in it is
+/// intact there, so a repair never replaces one damaged pack with another. Chunks
+/// that cannot be repaired that way (or at all, without a secondary) are added to
+/// [`CORRUPT_LIST_NAME`] in the repository.
+pub fn repair_chunks<B: Backend>(
+    store: &ChunkStore<B>,
+    secondary: Option<&dyn Backend>,
+    corrupt: &[CorruptChunk],
+) -> Result<RepairReport, StoreError> {
+    let mut by_pack: BTreeMap<&str, Vec<&CorruptChunk>> = BTreeMap::new();
+    for chunk in corrupt {
+        by_pack.entry(&chunk.pack).or_default().push(chunk);
+    }
+    let packs = chunks_by_pack(store);
+    let hasher = store.hash_algorithm().keyed_hasher(store.hash_key());
+
+    let mut report = RepairReport::default();
+    for (name, chunks) in by_pack {
+        let copy = secondary.and_then(|secondary| match secondary.read(name) {
+            Ok(copy) => Some(copy),
+            Err(err) => {
+                log::warn!("cannot read {} from the secondary: {}", name, err);
+                None
+            }
+        });
+        let pack_id = chunks[0].location.pack_id;
+        let intact = copy.as_ref().is_some_and(|copy| {
+            packs
+                .get(&pack_id)
+                .into_iter()
+                .flatten()
+                .all(|(hash, location)| {
+                    let start = location.offset as usize;
+                    let end = start.saturating_add(location.compressed_length as usize);
+                    copy.get(start..end).is_some_and(|data| {
+                        data.len() as u64 == location.length
+                            && hasher.hash(data).to_string() == *hash
+                    })
+                })
+        });
+
+        let hashes = chunks.iter().map(|chunk| chunk.hash.clone());
+        match copy {
+            Some(copy) if intact => {
+                store.backend().write(name, &copy)?;
+                report.repaired.extend(hashes);
+            }
+            _ => report.marked.extend(hashes),
+        }
+    }
+
+    if !report.marked.is_empty() {
+        let marked: BTreeSet<&str> = report.marked.iter().map(String::as_str).collect();
+        let mut list = read_corrupt_list(store.backend())?;
+        for chunk in corrupt.iter().filter(|chunk| marked.contains(&*chunk.hash)) {
+            list.insert(format!("{} {}", chunk.hash, chunk.pack));
+        }
+        let text: String = list.into_iter().map(|line| line + "\n").collect();
+        store.backend().write(CORRUPT_LIST_NAME, text.as_bytes())?;
+    }
+    Ok(report)
+}
+
+/// The lines of [`CORRUPT_LIST_NAME`], or none if there is no such list yet.
+pub fn read_corrupt_list<B: Backend + ?Sized>(backend: &B) -> Result<BTreeSet<String>, StoreError> {
+    if !backend.exists(CORRUPT_LIST_NAME)? {
+        return Ok(BTreeSet::new());
+    }
+    let text = String::from_utf8_lossy(&backend.read(CORRUPT_LIST_NAME)?).into_owned();
+    Ok(text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// The indexed chunks of `store` by pack id, each pack's in offset order.
+fn chunks_by_pack<B: Backend>(
+    store: &ChunkStore<B>,
+) -> BTreeMap<u64, Vec<(String, ChunkLocation)>> {
+    let mut packs: BTreeMap<u64, Vec<(String, ChunkLocation)>> = BTreeMap::new();
+    for (hash, location) in store.chunks() {
+        packs
+            .entry(location.pack_id)
+            .or_default()
+            .push((hash, *location));
+    }
+    for chunks in packs.values_mut() {
+        chunks.sort_unstable_by_key(|(_, location)| location.offset);
+    }
+    packs
+}
@@ -0,0 +1,62 @@
+//! How many chunks one input shares with another: a measure of how well chunk
+//! boundaries survive an edit, and of what backing up the new version would add.
+
+use std::collections::HashSet;
+
+use crate::backup::cdc_chunker::ChunkRef;
+
+/// Chunks of an input `b`, and how many of them an input `a` already has, see
+/// [`shared_chunks`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SharedChunks {
+    /// All chunks of `b`, repeats included.
+    pub chunks: usize,
+    pub bytes: u64,
+    /// Chunks of `b` that are among the chunks of `a`, and their bytes.
+    pub shared_chunks: usize,
+    pub shared_bytes: u64,
+    /// Bytes of the chunks of `b` that `a` does not have, each distinct chunk counted
+    /// once: what a backup of `b` would add to one of `a`.
+    pub new_bytes: u64,
+}
+
+impl SharedChunks {
+    /// Share of the chunks of `b` that `a` has, in percent (100 for an empty `b`).
+    pub fn shared_chunk_percent(&self) -> f64 {
+        percent(self.shared_chunks as u64, self.chunks as u64)
+    }
+
+    /// Share of the bytes of `b` that are in chunks `a` has, in percent (100 for an
+    /// empty `b`).
+    pub fn shared_byte_percent(&self) -> f64 {
+        percent(self.shared_bytes, self.bytes)
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+/// Compare the chunks of `b` with those of `a`, both cut with the same parameters.
+///
+/// Chunks are matched by id only, wherever they are in either input.
+pub fn shared_chunks(a: &[ChunkRef], b: &[ChunkRef]) -> SharedChunks {
+    let known: HashSet<&str> = a.iter().map(|chunk| chunk.hash.as_str()).collect();
+    let mut added: HashSet<&str> = HashSet::new();
+    let mut shared = SharedChunks::default();
+    for chunk in b {
+        shared.chunks += 1;
+        shared.bytes += chunk.len as u64;
+        if known.contains(chunk.hash.as_str()) {
+            shared.shared_chunks += 1;
+            shared.shared_bytes += chunk.len as u64;
+        } else if added.insert(&chunk.hash) {
+            shared.new_bytes += chunk.len as u64;
+        }
+    }
+    shared
+}
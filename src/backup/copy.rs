@@ -0,0 +1,107 @@
+//! Copying snapshots from one repository to another, e.g. from a local repository to
+//! an offsite one, transferring only the chunks the destination does not have.
+
+use std::{collections::HashSet, io};
+
+use crate::backup::{
+    snapshot::Snapshot,
+    store::{Backend, ChunkStore, StoreError},
+};
+
+/// What [`copy_snapshot`] transferred.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    /// Distinct chunks the snapshot references.
+    pub chunks: usize,
+    /// Those that were missing in the destination and were copied.
+    pub chunks_copied: usize,
+    /// Total size of the copied chunks.
+    pub bytes_copied: u64,
+}
+
+impl std::ops::AddAssign for CopyStats {
+    fn add_assign(&mut self, other: Self) {
+        self.chunks += other.chunks;
+        self.chunks_copied += other.chunks_copied;
+        self.bytes_copied += other.bytes_copied;
+    }
+}
+
+/// Copy snapshot `id` of `from` into `to` under the same id.
+///
+/// The chunks the destination lacks are found with one
+/// [`contains_many`](ChunkStore::contains_many) call, read from the source and
+/// stored in the destination; the snapshot itself is only written once they are
+/// flushed, so the destination never lists an incomplete snapshot. Chunks are read
+/// and written as plain data, so how each repository stores them does not matter,
+/// but both must identify chunks the same way (see [`check_compatible`]).
+pub fn copy_snapshot<S: Backend, D: Backend>(
+    from: &ChunkStore<S>,
+    to: &mut ChunkStore<D>,
+    id: &str,
+) -> Result<CopyStats, StoreError> {
+    check_compatible(from, to)?;
+    let snapshot = Snapshot::load(from.backend(), id)?;
+
+    let mut seen = HashSet::new();
+    let hashes: Vec<&str> = snapshot
+        .manifest
+        .entries
+        .iter()
+        .flat_map(|entry| &entry.chunks)
+        .map(String::as_str)
+        .filter(|hash| seen.insert(*hash))
+        .collect();
+    let stored = to.contains_many(&hashes);
+
+    let mut stats = CopyStats {
+        chunks: hashes.len(),
+        ..CopyStats::default()
+    };
+    for (hash, stored) in hashes.into_iter().zip(stored) {
+        if stored {
+            continue;
+        }
+        let chunk = from.get(hash)?;
+        to.put(hash, &chunk)?;
+        stats.chunks_copied += 1;
+        stats.bytes_copied += chunk.len() as u64;
+    }
+    to.flush()?;
+
+    snapshot.update(to.backend(), id)?;
+    Ok(stats)
+}
+
+/// Ids of the snapshots of `from` that `to` does not have yet, sorted.
+pub fn missing_snapshots<S: Backend, D: Backend>(
+    from: &ChunkStore<S>,
+    to: &ChunkStore<D>,
+) -> io::Result<Vec<String>> {
+    let present: HashSet<String> = Snapshot::list(to.backend())?.into_iter().collect();
+    Ok(Snapshot::list(from.backend())?
+        .into_iter()
+        .filter(|id| !present.contains(id))
+        .collect())
+}
+
+/// Fail unless chunk ids of `from` are valid in `to`: both repositories must use the
+/// same hash algorithm, with the same key if it is keyed.
+pub fn check_compatible<S: Backend, D: Backend>(
+    from: &ChunkStore<S>,
+    to: &ChunkStore<D>,
+) -> Result<(), StoreError> {
+    if from.hash_algorithm() != to.hash_algorithm() || from.hash_key() != to.hash_key() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the repositories identify chunks differently ({} and {}, or different keys), \
+                 so snapshots cannot be copied between them",
+                from.hash_algorithm(),
+                to.hash_algorithm()
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,203 @@
+//! What changed between two manifests or snapshots: which entries were added, removed,
+//! or have different content, and how many of their chunks differ.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+use crate::backup::{
+    manifest::{Manifest, ManifestEntry},
+    restore::entry_path,
+    snapshot::Snapshot,
+};
+
+/// Differences between an older and a newer manifest, see [`diff`]. Names are sorted.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestDiff {
+    /// Entries only the newer manifest has.
+    pub added: Vec<String>,
+    /// Entries only the older manifest has.
+    pub removed: Vec<String>,
+    /// Entries both have, with different content.
+    pub changed: Vec<ChangedEntry>,
+    /// How many entries both have with the same content.
+    pub unchanged: usize,
+}
+
+/// An entry whose chunk list differs between the two manifests.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct ChangedEntry {
+    pub name: String,
+    /// Chunks of the new content that the old content does not have, repeats counted
+    /// as often as they are not matched.
+    pub chunks_added: usize,
+    /// Chunks of the old content that the new content no longer has.
+    pub chunks_removed: usize,
+    /// Chunks the old and new content have in common; 0 if the entry was rewritten
+    /// entirely.
+    pub chunks_shared: usize,
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+impl ChangedEntry {
+    /// How many chunks the entry gained (or, if negative, lost).
+    pub fn chunk_delta(&self) -> i64 {
+        self.chunks_added as i64 - self.chunks_removed as i64
+    }
+}
+
+impl ManifestDiff {
+    /// Whether both manifests have the same entries with the same content.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// One line per added, removed and changed entry, then the counts.
+impl fmt::Display for ManifestDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in &self.added {
+            writeln!(f, "added    {}", name)?;
+        }
+        for name in &self.removed {
+            writeln!(f, "removed  {}", name)?;
+        }
+        for entry in &self.changed {
+            writeln!(
+                f,
+                "changed  {}: +{} -{} chunks ({} shared), {} -> {} bytes",
+                entry.name,
+                entry.chunks_added,
+                entry.chunks_removed,
+                entry.chunks_shared,
+                entry.old_size,
+                entry.new_size
+            )?;
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged
+        )
+    }
+}
+
+/// Differences between an older and a newer snapshot, see [`diff_snapshots`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SnapshotDiff {
+    #[serde(flatten)]
+    pub files: ManifestDiff,
+    /// Distinct chunks the newer snapshot references.
+    pub chunks: usize,
+    /// How many of those the older snapshot references too.
+    pub shared_chunks: usize,
+}
+
+/// The file lines and counts of [`ManifestDiff`], then how many chunks are shared.
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.files)?;
+        write!(
+            f,
+            "{} of {} chunks shared with the older snapshot",
+            self.shared_chunks, self.chunks
+        )
+    }
+}
+
+/// Compare the entries of `new` with those of `old`, matched by name.
+///
+/// Content is compared by chunk list (and symlink target), so metadata changes alone
+/// do not make an entry changed. Directories are not compared.
+pub fn diff(old: &Manifest, new: &Manifest) -> ManifestDiff {
+    diff_by(old, new, |entry| entry.name.as_str())
+}
+
+/// Compare the files of two snapshots, which may come from different backup runs.
+///
+/// Entries are matched by [`entry_path`], so `/home/me/a` in one snapshot is the same
+/// file as `home/me/./a` in the other, whatever order either manifest lists them in;
+/// otherwise like [`diff`]. Added and changed entries are named as in `new`, removed
+/// ones as in `old`.
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> SnapshotDiff {
+    let old_chunks: HashSet<&str> = chunk_set(&old.manifest);
+    let new_chunks: HashSet<&str> = chunk_set(&new.manifest);
+    SnapshotDiff {
+        files: diff_by(&old.manifest, &new.manifest, |entry| {
+            entry_path(&entry.name)
+        }),
+        chunks: new_chunks.len(),
+        shared_chunks: new_chunks.intersection(&old_chunks).count(),
+    }
+}
+
+fn chunk_set(manifest: &Manifest) -> HashSet<&str> {
+    manifest
+        .entries
+        .iter()
+        .flat_map(|entry| entry.chunks.iter().map(String::as_str))
+        .collect()
+}
+
+/// [`diff`] with entries matched by `key` instead of by name.
+fn diff_by<'a, K: Ord>(
+    old: &'a Manifest,
+    new: &'a Manifest,
+    key: impl Fn(&'a ManifestEntry) -> K,
+) -> ManifestDiff {
+    let old_entries: BTreeMap<K, &ManifestEntry> = old
+        .entries
+        .iter()
+        .map(|entry| (key(entry), entry))
+        .collect();
+    let new_entries: BTreeMap<K, &ManifestEntry> = new
+        .entries
+        .iter()
+        .map(|entry| (key(entry), entry))
+        .collect();
+
+    let mut diff = ManifestDiff::default();
+    for (key, new_entry) in &new_entries {
+        match old_entries.get(key) {
+            None => diff.added.push(new_entry.name.clone()),
+            Some(old_entry)
+                if old_entry.chunks == new_entry.chunks && old_entry.kind == new_entry.kind =>
+            {
+                diff.unchanged += 1
+            }
+            Some(old_entry) => diff.changed.push(changed_entry(old_entry, new_entry)),
+        }
+    }
+    diff.removed = old_entries
+        .iter()
+        .filter(|(key, _)| !new_entries.contains_key(*key))
+        .map(|(_, entry)| entry.name.clone())
+        .collect();
+    diff
+}
+fn changed_entry(old: &ManifestEntry, new: &ManifestEntry) -> ChangedEntry {
+    let mut old_chunks: HashMap<&str, usize> = HashMap::new();
+    for hash in &old.chunks {
+        *old_chunks.entry(hash).or_default() += 1;
+    }
+    let mut chunks_added = 0;
+    for hash in &new.chunks {
+        match old_chunks.get_mut(hash.as_str()) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => chunks_added += 1,
+        }
+    }
+    ChangedEntry {
+        name: new.name.clone(),
+        chunks_added,
+        chunks_shared: new.chunks.len() - chunks_added,
+        chunks_removed: old_chunks.values().sum(),
+        old_size: old.size,
+        new_size: new.size,
+    }
+}
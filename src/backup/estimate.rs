@@ -0,0 +1,115 @@
+//! What a backup would add to a repository, found without writing anything.
+
+use std::{collections::HashSet, io, path::Path};
+
+use crate::{
+    backup::{
+        cdc_chunker::{self, CdcParams},
+        session,
+        store::{Backend, ChunkStore},
+    },
+    config::Settings,
+};
+
+/// zstd level new chunks are compressed with to estimate their compressed size (the
+/// zstd tool's default).
+pub const ZSTD_LEVEL: i32 = 3;
+
+/// New chunks are all compressed for the estimate until this many bytes were sampled;
+/// after that, only those whose id ends in `0`: about one in 16, since ids are
+/// uniformly distributed.
+pub const FULL_SAMPLE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Totals of an [`Estimator`], in the terms of
+/// [`BackupStats`](crate::backup::session::BackupStats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Estimate {
+    pub files: usize,
+    /// Content bytes read.
+    pub bytes: u64,
+    /// Chunks cut, including duplicates.
+    pub chunks: usize,
+    /// Distinct chunks the repository does not have, and their bytes: what a backup
+    /// would write.
+    pub new_chunks: usize,
+    pub new_bytes: u64,
+    /// Bytes of the new chunks that were compressed as a sample, and the size they
+    /// compressed to.
+    pub sampled_bytes: u64,
+    pub sampled_compressed_bytes: u64,
+}
+
+impl Estimate {
+    /// [`Estimate::new_bytes`] scaled by the compression ratio of the sample.
+    ///
+    /// Packs store chunks uncompressed, so this is what compression would save rather
+    /// than what a backup writes today.
+    pub fn compressed_new_bytes(&self) -> u64 {
+        if self.sampled_bytes == 0 {
+            return self.new_bytes;
+        }
+        (self.new_bytes as f64 * self.sampled_compressed_bytes as f64 / self.sampled_bytes as f64)
+            .round() as u64
+    }
+}
+
+/// Chunks inputs exactly like a [`BackupSession`](crate::backup::session::BackupSession)
+/// into the same store would, but only looks up which chunks the store has.
+pub struct Estimator<'a, B: Backend> {
+    store: &'a ChunkStore<B>,
+    params: CdcParams,
+    // New chunks seen so far, so that repeats are not counted twice.
+    new_chunks: HashSet<String>,
+    estimate: Estimate,
+}
+
+impl<'a, B: Backend> Estimator<'a, B> {
+    pub fn new(settings: &Settings, store: &'a ChunkStore<B>) -> Self {
+        Estimator {
+            params: session::repo_params(settings, store),
+            store,
+            new_chunks: HashSet::new(),
+            estimate: Estimate::default(),
+        }
+    }
+
+    pub fn estimate(&self) -> &Estimate {
+        &self.estimate
+    }
+
+    /// Chunk the file at `path` (following symlinks).
+    pub fn add_file(&mut self, path: &Path) -> io::Result<()> {
+        let data = crate::backup::io::read_file(path, false)?;
+        self.add_bytes(&data)
+    }
+
+    /// Chunk `data` as the content of one file.
+    pub fn add_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        let chunk_refs = cdc_chunker::chunk_refs_cdc_parallel(data, &self.params);
+        let stored = self.store.contains_many(
+            &chunk_refs
+                .iter()
+                .map(|chunk_ref| chunk_ref.hash.as_str())
+                .collect::<Vec<_>>(),
+        );
+
+        self.estimate.files += 1;
+        self.estimate.bytes += data.len() as u64;
+        self.estimate.chunks += chunk_refs.len();
+        for (chunk_ref, stored) in chunk_refs.into_iter().zip(stored) {
+            if stored || self.new_chunks.contains(&chunk_ref.hash) {
+                continue;
+            }
+            let chunk = &data[chunk_ref.offset..chunk_ref.offset + chunk_ref.len];
+            self.estimate.new_chunks += 1;
+            self.estimate.new_bytes += chunk.len() as u64;
+            if self.estimate.sampled_bytes < FULL_SAMPLE_BYTES || chunk_ref.hash.ends_with('0') {
+                self.estimate.sampled_bytes += chunk.len() as u64;
+                self.estimate.sampled_compressed_bytes +=
+                    zstd::bulk::compress(chunk, ZSTD_LEVEL)?.len() as u64;
+            }
+            self.new_chunks.insert(chunk_ref.hash);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, io::Write, path::PathBuf};
+
+use tar::{Builder, EntryType, Header};
+
+use crate::backup::{
+    manifest::{Manifest, ManifestEntry},
+    restore::{self, EntryReader},
+    store::{Backend, ChunkStore, StoreError},
+};
+
+/// Archive formats `rbckp export` can write.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// POSIX (ustar) tar archive.
+    #[default]
+    Tar,
+    /// Tar archive compressed with zstd.
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+/// zstd level of [`ExportFormat::TarZst`], the zstd tool's default.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Write the snapshot content described by `manifest` to `out` as an archive.
+///
+/// Entries are streamed one chunk at a time, so memory use does not depend on file
+/// sizes. See [`write_tar`] for what is archived.
+pub fn export<B: Backend, W: Write>(
+    manifest: &Manifest,
+    store: &ChunkStore<B>,
+    format: ExportFormat,
+    out: W,
+) -> Result<W, StoreError> {
+    match format {
+        ExportFormat::Tar => write_tar(manifest, store, out),
+        ExportFormat::TarZst => {
+            let encoder = zstd::Encoder::new(out, ZSTD_LEVEL)?;
+            Ok(write_tar(manifest, store, encoder)?.finish()?)
+        }
+    }
+}
+
+/// Write the directories, files and symlinks of `manifest` to `out` as a tar archive,
+/// under their [`restore::entry_path`], with their recorded permissions, modification
+/// times and owners. Files of the same link group become hard links to the first.
+pub fn write_tar<B: Backend, W: Write>(
+    manifest: &Manifest,
+    store: &ChunkStore<B>,
+    out: W,
+) -> Result<W, StoreError> {
+    let mut builder = Builder::new(out);
+    for dir in &manifest.directories {
+        // The root directory of an absolute backup path has no name to archive.
+        if restore::entry_path(&dir.name).as_os_str().is_empty() {
+            continue;
+        }
+        let mut header = header(dir, EntryType::Directory, 0o755);
+        builder.append_data(
+            &mut header,
+            restore::entry_path(&dir.name),
+            std::io::empty(),
+        )?;
+    }
+
+    let mut link_groups: HashMap<u64, PathBuf> = HashMap::new();
+    for entry in &manifest.entries {
+        let path = restore::entry_path(&entry.name);
+        if let Some(target) = entry.symlink_target() {
+            let mut header = header(entry, EntryType::Symlink, 0o777);
+            builder.append_link(&mut header, &path, link_target(target))?;
+            continue;
+        }
+        if let Some(group) = entry.link_group {
+            if let Some(first) = link_groups.get(&group) {
+                let mut header = header(entry, EntryType::Link, 0o644);
+                builder.append_link(&mut header, &path, first)?;
+                continue;
+            }
+            link_groups.insert(group, path.clone());
+        }
+
+        let mut header = header(entry, EntryType::Regular, 0o644);
+        header.set_size(entry.size);
+        builder.append_data(&mut header, &path, EntryReader::new(entry, store))?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Header for `entry`, with `default_mode` if no mode was recorded. Path, size and
+/// checksum are left to the caller.
+fn header(entry: &ManifestEntry, entry_type: EntryType, default_mode: u32) -> Header {
+    let mut header = Header::new_ustar();
+    header.set_entry_type(entry_type);
+    header.set_size(0);
+    header.set_mode(entry.mode.unwrap_or(default_mode));
+    if let Some(mtime) = entry.mtime {
+        header.set_mtime(mtime.unix_timestamp().max(0) as u64);
+    }
+    if let Some(uid) = entry.uid {
+        header.set_uid(u64::from(uid));
+    }
+    if let Some(gid) = entry.gid {
+        header.set_gid(u64::from(gid));
+    }
+    header
+}
+
+#[cfg(unix)]
+fn link_target(target: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(target))
+}
+
+#[cfg(not(unix))]
+fn link_target(target: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(target).into_owned())
+}
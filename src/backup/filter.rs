@@ -0,0 +1,150 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use globset::{Glob, GlobBuilder, GlobMatcher};
+
+/// Name of the per-directory file with exclude patterns.
+pub const IGNORE_FILE_NAME: &str = ".rbckpignore";
+
+/// Decides which paths a backup leaves out, using `.gitignore` syntax:
+///
+/// - `*.log` (no `/`) matches the name at any depth,
+/// - `build/out` or `/build` (with a `/`) is anchored where the pattern was given,
+/// - `**` crosses directories, `*` and `?` do not,
+/// - a trailing `/` only matches directories,
+/// - `!pattern` re-includes what an earlier pattern excluded; the last match wins.
+///
+/// Paths are matched relative to the backup root. Patterns from a `.rbckpignore`
+/// file are relative to the directory containing it and only apply below it.
+#[derive(Clone, Debug, Default)]
+pub struct ExcludeFilter {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    matcher: GlobMatcher,
+    negated: bool,
+    dir_only: bool,
+    // Directory (relative to the backup root) the pattern is relative to.
+    base: PathBuf,
+}
+
+impl ExcludeFilter {
+    /// Filter with patterns relative to the backup root, e.g. from `--exclude`.
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> io::Result<Self> {
+        let mut filter = Self::default();
+        for pattern in patterns {
+            filter.add_pattern(pattern.as_ref(), Path::new(""))?;
+        }
+        Ok(filter)
+    }
+
+    /// Add one pattern, relative to `base` (a directory relative to the backup root).
+    /// Empty lines and `#` comments are ignored.
+    pub fn add_pattern(&mut self, pattern: &str, base: &Path) -> io::Result<()> {
+        let pattern = pattern.trim_end();
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return Ok(());
+        }
+
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            // `\!` and `\#` start patterns with a literal `!` or `#`.
+            None => (false, pattern.strip_prefix('\\').unwrap_or(pattern)),
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+
+        let matcher = path_glob(pattern)?.compile_matcher();
+        self.rules.push(Rule {
+            matcher,
+            negated,
+            dir_only,
+            base: base.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// This filter plus the patterns of the `.rbckpignore` in `dir`, if there is one.
+    /// `relative_dir` is `dir` relative to the backup root.
+    pub fn with_ignore_file(&self, dir: &Path, relative_dir: &Path) -> io::Result<Self> {
+        let contents = match fs::read_to_string(dir.join(IGNORE_FILE_NAME)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(self.clone()),
+            Err(err) => return Err(err),
+        };
+
+        let mut filter = self.clone();
+        for line in contents.lines() {
+            filter.add_pattern(line, relative_dir)?;
+        }
+        Ok(filter)
+    }
+
+    /// Whether the path (relative to the backup root) is left out of the backup.
+    pub fn is_excluded(&self, relative_path: &Path, is_dir: bool) -> bool {
+        let mut excluded = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let Ok(path) = relative_path.strip_prefix(&rule.base) else {
+                continue;
+            };
+            if rule.matcher.is_match(path) {
+                excluded = !rule.negated;
+            }
+        }
+        excluded
+    }
+}
+
+/// Compile `pattern` with the path rules of [`ExcludeFilter`]: without a `/` it
+/// matches the name at any depth, with one it is anchored at the backup root, and
+/// `*` does not cross directories.
+pub fn path_glob(pattern: &str) -> io::Result<Glob> {
+    let glob = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if pattern.contains('/') => pattern.to_string(),
+        None => format!("**/{}", pattern),
+    };
+    GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+/// Whitelist of files to back up, from `--include` (and exclude) globs.
+///
+/// With no includes every file is a candidate; otherwise only files matching at least
+/// one include, where matching a directory includes everything below it. Excludes are
+/// applied afterwards and narrow the set further.
+#[derive(Clone, Debug, Default)]
+pub struct FileFilter {
+    includes: Vec<GlobMatcher>,
+    excludes: Vec<GlobMatcher>,
+}
+
+impl FileFilter {
+    pub fn new(includes: &[Glob], excludes: &[Glob]) -> Self {
+        FileFilter {
+            includes: includes.iter().map(Glob::compile_matcher).collect(),
+            excludes: excludes.iter().map(Glob::compile_matcher).collect(),
+        }
+    }
+
+    /// Whether the file at `path` (relative to the backup root) is backed up.
+    pub fn matches(&self, path: &Path) -> bool {
+        let any_match = |matchers: &[GlobMatcher]| {
+            path.ancestors()
+                .filter(|path| !path.as_os_str().is_empty())
+                .any(|path| matchers.iter().any(|matcher| matcher.is_match(path)))
+        };
+        (self.includes.is_empty() || any_match(&self.includes)) && !any_match(&self.excludes)
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    path::{Component, Path},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use fuser::{
+    Errno, FileAttr, FileHandle, FileType, Filesystem, Generation, INodeNo, LockOwner, OpenFlags,
+    ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
+};
+
+use crate::backup::{
+    manifest::ManifestEntry,
+    snapshot::Snapshot,
+    store::{Backend, ChunkStore},
+};
+
+// Snapshots never change, so the kernel may cache everything for as long as it likes.
+const TTL: Duration = Duration::from_secs(3600);
+
+const BLOCK_SIZE: u32 = 4096;
+
+/// A snapshot presented as a read-only filesystem, for `rbckp mount`.
+///
+/// The directory tree is built from the manifest up front; file contents are only
+/// fetched from the store, chunk by chunk, when they are read. Inode numbers are
+/// derived from the paths, so they are the same every time a snapshot is mounted.
+pub struct SnapshotFs<B: Backend> {
+    store: ChunkStore<B>,
+    entries: Vec<ManifestEntry>,
+    nodes: HashMap<u64, Node>,
+    time: SystemTime,
+    uid: u32,
+    gid: u32,
+    // Last chunk read: the kernel reads in pieces smaller than most chunks.
+    last_chunk: Mutex<Option<(String, Arc<Vec<u8>>)>>,
+}
+
+struct Node {
+    parent: u64,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Directory {
+        children: BTreeMap<OsString, u64>,
+    },
+    File {
+        // Index into `entries`.
+        entry: usize,
+        // End offset of every chunk in the file; `None` if a chunk is missing from the store.
+        chunk_ends: Option<Vec<u64>>,
+    },
+    Symlink {
+        // Index into `entries`.
+        entry: usize,
+    },
+}
+
+impl<B: Backend> SnapshotFs<B> {
+    /// Filesystem showing `snapshot`, with chunks read from `store`. Everything is
+    /// owned by `uid`/`gid`.
+    pub fn new(snapshot: Snapshot, store: ChunkStore<B>, uid: u32, gid: u32) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            INodeNo::ROOT.0,
+            Node {
+                parent: INodeNo::ROOT.0,
+                kind: NodeKind::Directory {
+                    children: BTreeMap::new(),
+                },
+            },
+        );
+
+        'entries: for (index, entry) in snapshot.manifest.entries.iter().enumerate() {
+            let components: Vec<&OsStr> = Path::new(&entry.name)
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(name),
+                    _ => None,
+                })
+                .collect();
+            let Some((file_name, dirs)) = components.split_last() else {
+                continue;
+            };
+
+            let mut parent = INodeNo::ROOT.0;
+            let mut path = String::new();
+            for dir in dirs {
+                path = format!("{}/{}", path, dir.to_string_lossy());
+                parent = match add_child(&mut nodes, parent, dir, &path, || NodeKind::Directory {
+                    children: BTreeMap::new(),
+                }) {
+                    Some(inode) => inode,
+                    // Leave the entry out rather than attach it to another directory.
+                    None => continue 'entries,
+                };
+            }
+
+            path = format!("{}/{}", path, file_name.to_string_lossy());
+            if entry.symlink_target().is_some() {
+                add_child(&mut nodes, parent, file_name, &path, || NodeKind::Symlink {
+                    entry: index,
+                });
+                continue;
+            }
+            let chunk_ends = chunk_ends(&store, entry);
+            if chunk_ends.is_none() {
+                log::warn!(
+                    "{}: chunks missing from the store, reads will fail",
+                    entry.name
+                );
+            }
+            add_child(&mut nodes, parent, file_name, &path, || NodeKind::File {
+                entry: index,
+                chunk_ends,
+            });
+        }
+
+        SnapshotFs {
+            store,
+            entries: snapshot.manifest.entries,
+            nodes,
+            time: snapshot.time.into(),
+            uid,
+            gid,
+            last_chunk: Mutex::new(None),
+        }
+    }
+
+    fn attr(&self, inode: u64, node: &Node) -> FileAttr {
+        let (kind, size, perm, nlink, mtime) = match &node.kind {
+            NodeKind::Directory { .. } => (FileType::Directory, 0, 0o555, 2, self.time),
+            NodeKind::File { entry, .. } => {
+                let entry = &self.entries[*entry];
+                // Recorded permissions, minus write access: the filesystem is read-only.
+                let perm = entry.mode.map_or(0o444, |mode| mode as u16 & 0o7555);
+                let mtime = entry.mtime.map_or(self.time, SystemTime::from);
+                (FileType::RegularFile, entry.size, perm, 1, mtime)
+            }
+            NodeKind::Symlink { entry } => {
+                let entry = &self.entries[*entry];
+                let size = entry
+                    .symlink_target()
+                    .map_or(0, |target| target.len() as u64);
+                let mtime = entry.mtime.map_or(self.time, SystemTime::from);
+                (FileType::Symlink, size, 0o777, 1, mtime)
+            }
+        };
+        FileAttr {
+            ino: INodeNo(inode),
+            size,
+            blocks: size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: self.time,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: BLOCK_SIZE,
+        }
+    }
+
+    fn chunk(&self, hash: &str) -> Result<Arc<Vec<u8>>, Errno> {
+        let mut last_chunk = self
+            .last_chunk
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if let Some((last_hash, data)) = &*last_chunk
+            && last_hash == hash
+        {
+            return Ok(Arc::clone(data));
+        }
+
+        let data = Arc::new(self.store.get(hash).map_err(|err| {
+            log::error!("cannot read chunk {}: {}", hash, err);
+            Errno::EIO
+        })?);
+        *last_chunk = Some((hash.to_string(), Arc::clone(&data)));
+        Ok(data)
+    }
+
+    /// Up to `size` bytes of `entry` starting at `offset`.
+    fn read_range(
+        &self,
+        entry: &ManifestEntry,
+        chunk_ends: &[u64],
+        offset: u64,
+        size: u64,
+    ) -> Result<Vec<u8>, Errno> {
+        let end = entry.size.min(offset.saturating_add(size));
+        let mut data = Vec::with_capacity(end.saturating_sub(offset) as usize);
+
+        // First chunk that ends after `offset`.
+        let first = chunk_ends.partition_point(|&chunk_end| chunk_end <= offset);
+        for (index, hash) in entry.chunks.iter().enumerate().skip(first) {
+            let chunk_start = if index == 0 { 0 } else { chunk_ends[index - 1] };
+            if chunk_start >= end {
+                break;
+            }
+            let chunk = self.chunk(hash)?;
+            let from = offset.saturating_sub(chunk_start) as usize;
+            let to = ((end - chunk_start) as usize).min(chunk.len());
+            data.extend_from_slice(&chunk[from..to]);
+        }
+        Ok(data)
+    }
+}
+
+impl<B: Backend + 'static> Filesystem for SnapshotFs<B> {
+    fn lookup(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        let Some(Node {
+            kind: NodeKind::Directory { children },
+            ..
+        }) = self.nodes.get(&parent.0)
+        else {
+            return reply.error(Errno::ENOTDIR);
+        };
+        match children.get(name) {
+            Some(&inode) => {
+                reply.entry(&TTL, &self.attr(inode, &self.nodes[&inode]), Generation(0))
+            }
+            None => reply.error(Errno::ENOENT),
+        }
+    }
+
+    fn getattr(&self, _req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        match self.nodes.get(&ino.0) {
+            Some(node) => reply.attr(&TTL, &self.attr(ino.0, node)),
+            None => reply.error(Errno::ENOENT),
+        }
+    }
+
+    fn readlink(&self, _req: &Request, ino: INodeNo, reply: ReplyData) {
+        match self.nodes.get(&ino.0).map(|node| &node.kind) {
+            Some(NodeKind::Symlink { entry }) => {
+                reply.data(self.entries[*entry].symlink_target().unwrap_or_default())
+            }
+            Some(_) => reply.error(Errno::EINVAL),
+            None => reply.error(Errno::ENOENT),
+        }
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyData,
+    ) {
+        let (entry, chunk_ends) = match self.nodes.get(&ino.0).map(|node| &node.kind) {
+            Some(NodeKind::File { entry, chunk_ends }) => (&self.entries[*entry], chunk_ends),
+            Some(NodeKind::Directory { .. }) => return reply.error(Errno::EISDIR),
+            Some(NodeKind::Symlink { .. }) => return reply.error(Errno::EINVAL),
+            None => return reply.error(Errno::ENOENT),
+        };
+        let Some(chunk_ends) = chunk_ends else {
+            return reply.error(Errno::EIO);
+        };
+
+        match self.read_range(entry, chunk_ends, offset, size.into()) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(Node {
+            parent,
+            kind: NodeKind::Directory { children },
+        }) = self.nodes.get(&ino.0)
+        else {
+            return reply.error(Errno::ENOTDIR);
+        };
+
+        let entries = [
+            (ino.0, FileType::Directory, OsStr::new(".")),
+            (*parent, FileType::Directory, OsStr::new("..")),
+        ]
+        .into_iter()
+        .chain(children.iter().map(|(name, &inode)| {
+            let kind = match self.nodes[&inode].kind {
+                NodeKind::Directory { .. } => FileType::Directory,
+                NodeKind::File { .. } => FileType::RegularFile,
+                NodeKind::Symlink { .. } => FileType::Symlink,
+            };
+            (inode, kind, name.as_os_str())
+        }));
+
+        for (index, (inode, kind, name)) in entries.enumerate().skip(offset as usize) {
+            // The offset passed back to us is that of the next entry.
+            if reply.add(INodeNo(inode), (index + 1) as u64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Inode number of the node at `path` (`/`-separated, from the snapshot root), if it
+/// is not taken yet. A taken one is followed by the next free number, so inodes stay
+/// the same for the same manifest.
+fn inode_for(nodes: &HashMap<u64, Node>, path: &str) -> u64 {
+    let hash = blake3::hash(path.as_bytes());
+    let mut inode = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+    loop {
+        // 0 is invalid and 1 is the root.
+        inode = inode.max(2);
+        if !nodes.contains_key(&inode) {
+            return inode;
+        }
+        log::debug!("{}: inode {} taken, trying the next one", path, inode);
+        inode = inode.wrapping_add(1);
+    }
+}
+
+/// Add a child node to the directory `parent` unless one with that name exists, and
+/// return its inode. `None` if a file is in the way.
+fn add_child(
+    nodes: &mut HashMap<u64, Node>,
+    parent: u64,
+    name: &OsStr,
+    path: &str,
+    kind: impl FnOnce() -> NodeKind,
+) -> Option<u64> {
+    let NodeKind::Directory { children } = &mut nodes.get_mut(&parent)?.kind else {
+        log::warn!("{}: parent is not a directory, skipped", path);
+        return None;
+    };
+    if let Some(&existing) = children.get(name) {
+        return Some(existing);
+    }
+    let inode = inode_for(nodes, path);
+
+    if let Some(Node {
+        kind: NodeKind::Directory { children },
+        ..
+    }) = nodes.get_mut(&parent)
+    {
+        children.insert(name.to_os_string(), inode);
+    }
+    nodes.insert(
+        inode,
+        Node {
+            parent,
+            kind: kind(),
+        },
+    );
+    Some(inode)
+}
+
+/// Cumulative chunk sizes of `entry`, looked up in the store index.
+fn chunk_ends<B: Backend>(store: &ChunkStore<B>, entry: &ManifestEntry) -> Option<Vec<u64>> {
+    let mut end = 0;
+    entry
+        .chunks
+        .iter()
+        .map(|hash| {
+            end += store.chunk_len(hash)?;
+            Some(end)
+        })
+        .collect()
+}
@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{BuildHasher, Hasher, RandomState},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use siphasher::sip128::Hasher128;
+
+/// Content id of a chunk: the configured content hash of its bytes (see
+/// [`HashAlgorithm`]).
+///
+/// Kept as the 32 raw hash bytes; the hex form (64 characters) is only produced for
+/// display and storage, through [`Display`](fmt::Display), [`FromStr`] and serde.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkId([u8; 32]);
+
+impl ChunkId {
+    /// Id of a chunk with content `data` under the default algorithm, BLAKE3.
+    pub fn of(data: &[u8]) -> Self {
+        ChunkId(*blake3::hash(data).as_bytes())
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        ChunkId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChunkId({})", self)
+    }
+}
+
+/// A string that is not 64 hex digits, see [`ChunkId::from_str`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidChunkId(pub String);
+
+impl fmt::Display for InvalidChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid chunk id {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidChunkId {}
+
+impl FromStr for ChunkId {
+    type Err = InvalidChunkId;
+
+    /// Parse the hex form, in either case.
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        blake3::Hash::from_hex(hex)
+            .map(|hash| ChunkId(*hash.as_bytes()))
+            .map_err(|_| InvalidChunkId(hex.to_string()))
+    }
+}
+
+/// Computes the [`ChunkId`] of chunk content.
+pub trait ChunkHasher: Send + Sync {
+    fn hash(&self, data: &[u8]) -> ChunkId;
+}
+
+/// BLAKE3, the default: cryptographic, so distinct chunks never share an id in
+/// practice, even when someone crafts them to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake3Hasher;
+
+impl ChunkHasher for Blake3Hasher {
+    fn hash(&self, data: &[u8]) -> ChunkId {
+        ChunkId::of(data)
+    }
+}
+
+/// 128-bit xxHash3, several times faster than BLAKE3 but not cryptographic. The hash
+/// fills the first 16 bytes of the id, the rest are zero.
+///
+/// Only for trusted, local backups. Anyone who can put data in front of the backup can
+/// craft two chunks with the same xxHash3, and the second one is then silently
+/// replaced by the first on restore. Do not use it when chunks are stored on media
+/// others can write to, or when deduplication must be correct against an attacker.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XxHash3Hasher;
+
+impl ChunkHasher for XxHash3Hasher {
+    fn hash(&self, data: &[u8]) -> ChunkId {
+        let mut bytes = [0; 32];
+        bytes[..16].copy_from_slice(&xxhash_rust::xxh3::xxh3_128(data).to_be_bytes());
+        ChunkId(bytes)
+    }
+}
+
+/// 128-bit SipHash-1-3 keyed with a secret `key`, laid out in the id like
+/// [`XxHash3Hasher`].
+///
+/// Without the key nobody can predict ids, so unlike with xxHash3, chunks cannot be
+/// crafted to collide. It needs no SIMD to be fast: where BLAKE3 gets none, SipHash is
+/// the cheaper keyed choice; where it does, the two run at about the same speed. The
+/// price is a shorter id and a key that has to stay with the repository: chunks hashed
+/// with another key never deduplicate.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SipHasher13 {
+    pub key: [u8; 16],
+}
+
+impl ChunkHasher for SipHasher13 {
+    fn hash(&self, data: &[u8]) -> ChunkId {
+        let mut hasher = siphasher::sip128::SipHasher13::new_with_key(&self.key);
+        hasher.write(data);
+        let mut bytes = [0; 32];
+        bytes[..16].copy_from_slice(&hasher.finish128().as_bytes());
+        ChunkId(bytes)
+    }
+}
+
+impl fmt::Debug for SipHasher13 {
+    // The key is a secret; keep it out of logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SipHasher13").finish_non_exhaustive()
+    }
+}
+
+/// A new secret key for [`SipHasher13`].
+///
+/// Drawn from the operating system's random number generator, through the hash
+/// maps of the standard library, which seed their keys from it.
+pub fn random_key() -> [u8; 16] {
+    let mut key = [0; 16];
+    for (half, bytes) in key.chunks_exact_mut(8).enumerate() {
+        bytes.copy_from_slice(&RandomState::new().hash_one(half).to_le_bytes());
+    }
+    key
+}
+
+/// The [`ChunkHasher`]s a repository can use, by the name stored in its config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// [`Blake3Hasher`].
+    #[default]
+    Blake3,
+    /// [`XxHash3Hasher`], for trusted environments only.
+    XxHash3,
+    /// [`SipHasher13`], with a key kept in the repository config.
+    SipHash,
+}
+
+impl HashAlgorithm {
+    /// Whether chunk ids depend on a secret key as well as on the data.
+    pub fn is_keyed(self) -> bool {
+        self == HashAlgorithm::SipHash
+    }
+
+    /// The hasher of an algorithm that needs no key.
+    ///
+    /// # Panics
+    ///
+    /// For [`HashAlgorithm::SipHash`]; use [`HashAlgorithm::keyed_hasher`].
+    pub fn hasher(self) -> &'static dyn ChunkHasher {
+        match self {
+            HashAlgorithm::Blake3 => &Blake3Hasher,
+            HashAlgorithm::XxHash3 => &XxHash3Hasher,
+            HashAlgorithm::SipHash => panic!("SipHash chunk ids need a key"),
+        }
+    }
+
+    /// The hasher of any algorithm; `key` is only used by the keyed ones.
+    ///
+    /// # Panics
+    ///
+    /// If the algorithm is keyed and `key` is `None`.
+    pub fn keyed_hasher(self, key: Option<[u8; 16]>) -> Box<dyn ChunkHasher> {
+        match (self, key) {
+            (HashAlgorithm::SipHash, Some(key)) => Box::new(SipHasher13 { key }),
+            _ => Box::new(self.hasher()),
+        }
+    }
+}
+
+impl<H: ChunkHasher + ?Sized> ChunkHasher for &H {
+    fn hash(&self, data: &[u8]) -> ChunkId {
+        (**self).hash(data)
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::XxHash3 => "xxhash3",
+            HashAlgorithm::SipHash => "siphash",
+        })
+    }
+}
+
+/// Length of a chunk id in hex, and the default for
+/// [`Settings::hash_prefix_len`](crate::config::Settings::hash_prefix_len).
+pub const CHUNK_ID_HEX_LEN: usize = 64;
+
+/// Two different chunks whose ids start with the same `prefix`, see [`PrefixKeys`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrefixCollision {
+    pub prefix: String,
+    pub first: String,
+    pub second: String,
+}
+
+impl fmt::Display for PrefixCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunks {} and {} share the id prefix {}; use a longer hash_prefix_len",
+            self.first, self.second, self.prefix
+        )
+    }
+}
+
+impl std::error::Error for PrefixCollision {}
+
+/// Hands out chunk ids cut to their first `len` hex digits, as the keys of the chunk
+/// counts `rbckp -F` reports and of the chunk lookups of a
+/// [`ChunkStore`](crate::backup::store::ChunkStore) (see its `with_hash_prefix_len`).
+/// Stored indexes and pack footers always use whole ids.
+///
+/// A cut key is only handed out once checked: the full id of the first chunk with
+/// each prefix is kept, and a different chunk with the same prefix fails with a
+/// [`PrefixCollision`] instead of being taken for a repeat. That costs a full id per
+/// distinct prefix, so whole ids (the default) are handed out without keeping
+/// anything. Strings that are not chunk ids, such as zero chunk ids, are keys as they
+/// are.
+#[derive(Debug)]
+pub struct PrefixKeys {
+    len: usize,
+    seen: HashMap<String, ChunkId>,
+}
+
+impl PrefixKeys {
+    /// Keys of `len` hex digits; [`CHUNK_ID_HEX_LEN`] keeps ids whole.
+    ///
+    /// # Panics
+    ///
+    /// If `len` is 0 or longer than [`CHUNK_ID_HEX_LEN`].
+    pub fn new(len: usize) -> Self {
+        assert!(
+            (1..=CHUNK_ID_HEX_LEN).contains(&len),
+            "hash prefix length must be 1 to {}",
+            CHUNK_ID_HEX_LEN
+        );
+        PrefixKeys {
+            len,
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn prefix_len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of distinct prefixes whose full id is kept for the collision check.
+    pub fn tracked(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// The key of chunk id `hash` (in hex).
+    pub fn key(&mut self, hash: &str) -> Result<String, PrefixCollision> {
+        if self.len == CHUNK_ID_HEX_LEN {
+            return Ok(hash.to_string());
+        }
+        let Ok(id) = hash.parse::<ChunkId>() else {
+            return Ok(hash.to_string());
+        };
+        let prefix = &hash[..self.len];
+        match self.seen.get(prefix) {
+            Some(first) if *first != id => Err(PrefixCollision {
+                prefix: prefix.to_string(),
+                first: first.to_string(),
+                second: id.to_string(),
+            }),
+            Some(_) => Ok(prefix.to_string()),
+            None => {
+                self.seen.insert(prefix.to_string(), id);
+                Ok(prefix.to_string())
+            }
+        }
+    }
+}
+
+impl Serialize for ChunkId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChunkId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex.parse().map_err(de::Error::custom)
+    }
+}
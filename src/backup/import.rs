@@ -0,0 +1,138 @@
+use std::{
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use tar::{Archive, EntryType, Header};
+use time::OffsetDateTime;
+
+use crate::backup::{
+    manifest::{EntryKind, ManifestEntry},
+    session::BackupSession,
+    store::{Backend, StoreError},
+};
+
+/// What [`import_tar`] found in an archive.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportStats {
+    /// Top-level paths of the archive, in order of appearance, to record as the paths
+    /// of the snapshot.
+    pub paths: Vec<String>,
+    /// Files, symlinks and hard links imported.
+    pub entries: usize,
+    /// Directories imported.
+    pub directories: usize,
+    /// Entries left out: types a snapshot cannot hold (devices, fifos, ...) and hard
+    /// links to files not seen before them.
+    pub skipped: usize,
+}
+
+/// Read a tar archive from `archive` and add its content to `session`, with the
+/// recorded permissions, modification times and owners.
+///
+/// Files are chunked as they are read from the stream, without extracting anything to
+/// disk. Names are kept as in the archive, minus `./` prefixes and trailing slashes,
+/// so importing a tar of `data` gives the same entries as backing up `data`. Hard links
+/// join the link group of their target; entry types a snapshot cannot hold are skipped
+/// with a warning.
+pub fn import_tar<B: Backend, R: Read>(
+    session: &mut BackupSession<B>,
+    archive: R,
+) -> Result<ImportStats, StoreError> {
+    let mut stats = ImportStats::default();
+    let mut archive = Archive::new(archive);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry_name(&entry.path()?);
+        if name.is_empty() {
+            continue;
+        }
+        let template = template(&name, entry.header());
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => {
+                let mut data = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut data)?;
+                session.add_content(template, &data)?;
+            }
+            EntryType::Directory => {
+                session.add_directory_entry(template);
+                stats.directories += 1;
+                continue;
+            }
+            EntryType::Symlink => {
+                let target = entry.link_name_bytes().unwrap_or_default().into_owned();
+                let template = ManifestEntry {
+                    kind: EntryKind::Symlink { target },
+                    ..template
+                };
+                session.add_content(template, &[])?;
+            }
+            EntryType::Link => {
+                let target = entry_name(&entry.link_name()?.unwrap_or_default());
+                if session.add_hard_link(&name, &target).is_none() {
+                    log::warn!(
+                        "{}: skipping hard link to {}, which is not a file before it",
+                        name,
+                        target
+                    );
+                    stats.skipped += 1;
+                    continue;
+                }
+            }
+            EntryType::XGlobalHeader => continue,
+            other => {
+                log::warn!("{}: skipping unsupported entry type {:?}", name, other);
+                stats.skipped += 1;
+                continue;
+            }
+        }
+        stats.entries += 1;
+
+        let root = top_level(&name);
+        if !stats.paths.contains(&root) {
+            stats.paths.push(root);
+        }
+    }
+    Ok(stats)
+}
+
+/// `path` without `.` components, which also drops a trailing slash.
+fn entry_name(path: &Path) -> String {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The first named component of `name`, with any root before it.
+fn top_level(name: &str) -> String {
+    let mut root = PathBuf::new();
+    for component in Path::new(name).components() {
+        root.push(component);
+        if matches!(component, Component::Normal(_)) {
+            break;
+        }
+    }
+    root.to_string_lossy().into_owned()
+}
+
+/// An entry called `name` with the metadata of `header`, and no content yet.
+fn template(name: &str, header: &Header) -> ManifestEntry {
+    ManifestEntry {
+        name: name.to_string(),
+        kind: EntryKind::File,
+        size: 0,
+        chunks: Vec::new(),
+        content_hash: None,
+        mtime: header
+            .mtime()
+            .ok()
+            .and_then(|mtime| OffsetDateTime::from_unix_timestamp(mtime as i64).ok()),
+        mode: header.mode().ok().map(|mode| mode & 0o7777),
+        uid: header.uid().ok().and_then(|uid| u32::try_from(uid).ok()),
+        gid: header.gid().ok().and_then(|gid| u32::try_from(gid).ok()),
+        link_group: None,
+    }
+}
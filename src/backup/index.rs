@@ -0,0 +1,112 @@
+//! The repository-wide chunk index kept in `chunks.idx`: every place each chunk is
+//! stored, by pack file, so a backup stores only chunks no earlier run stored.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backup::{hash::ChunkId, store::Backend};
+
+/// Object name of the index in the repository root.
+pub const CHUNK_INDEX_NAME: &str = "chunks.idx";
+
+/// Where one copy of a chunk is stored: `length` bytes at `offset` of a pack file,
+/// as they are in the pack (compressed, if it is).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkLocation {
+    /// Object name of the pack, relative to the repository root.
+    pub pack_file: PathBuf,
+    pub offset: u64,
+    pub length: u32,
+}
+
+/// Chunk id -> every location of that chunk, persisted with bincode.
+///
+/// A chunk normally has one location; it has more when it was written to several
+/// packs, e.g. by backups running at the same time. Any of them can be read.
+#[derive(Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChunkIndex {
+    chunks: HashMap<ChunkId, Vec<ChunkLocation>>,
+}
+
+impl ChunkIndex {
+    /// Load the index of the repository in `backend`. A missing index is empty.
+    pub fn load(backend: &dyn Backend) -> io::Result<Self> {
+        match backend.read(CHUNK_INDEX_NAME) {
+            Ok(bytes) => bincode::deserialize(&bytes)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replace the index of the repository in `backend`.
+    pub fn save(&self, backend: &dyn Backend) -> io::Result<()> {
+        let bytes = bincode::serialize(self).map_err(io::Error::other)?;
+        backend.write(CHUNK_INDEX_NAME, &bytes)
+    }
+
+    /// Whether chunk `id` is stored anywhere.
+    pub fn has(&self, id: &ChunkId) -> bool {
+        self.chunks.contains_key(id)
+    }
+
+    /// Every location of chunk `id`; empty if it is not stored.
+    pub fn locations(&self, id: &ChunkId) -> &[ChunkLocation] {
+        self.chunks.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Record that chunk `id` is stored at `location`. Returns `false` if that
+    /// location was already recorded.
+    pub fn insert(&mut self, id: ChunkId, location: ChunkLocation) -> bool {
+        let locations = self.chunks.entry(id).or_default();
+        if locations.contains(&location) {
+            return false;
+        }
+        locations.push(location);
+        true
+    }
+
+    /// Add the locations of `other` that this index does not have yet. Returns
+    /// whether any were added.
+    pub fn merge(&mut self, other: ChunkIndex) -> bool {
+        let mut changed = false;
+        for (id, locations) in other.chunks {
+            for location in locations {
+                changed |= self.insert(id, location);
+            }
+        }
+        changed
+    }
+
+    /// Drop every location in a pack file that does not satisfy `keep`, and chunks left
+    /// without any. Returns the number of locations removed.
+    pub fn retain_packs(&mut self, mut keep: impl FnMut(&Path) -> bool) -> usize {
+        let mut removed = 0;
+        self.chunks.retain(|_, locations| {
+            let before = locations.len();
+            locations.retain(|location| keep(&location.pack_file));
+            removed += before - locations.len();
+            !locations.is_empty()
+        });
+        removed
+    }
+
+    /// Ids of every chunk stored, in no particular order.
+    pub fn ids(&self) -> impl Iterator<Item = &ChunkId> {
+        self.chunks.keys()
+    }
+
+    /// Number of distinct chunks.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    ops::Deref,
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+/// Files at least this large are memory-mapped even when mmap was not requested.
+pub const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Contents of an input file, either mapped into memory or read into a buffer.
+///
+/// Both variants deref to `&[u8]`, so the chunker does not care which one it gets.
+pub enum FileData {
+    Mapped(Mmap),
+    Buffered(Vec<u8>),
+}
+
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Mapped(mmap) => mmap,
+            FileData::Buffered(data) => data,
+        }
+    }
+}
+
+/// Memory-map `path` read-only.
+///
+/// Fails for zero-length files on some platforms; [`read_file`] handles that case.
+pub fn open_mmap(path: &Path) -> io::Result<Mmap> {
+    map_file(&File::open(path)?)
+}
+
+fn map_file(file: &File) -> io::Result<Mmap> {
+    // SAFETY: the map is read-only. If another process truncates or rewrites the file
+    // while we hold the map, reads may see torn data (or fault on truncation), the same
+    // caveat every mmap-based backup tool has for files that change during the backup.
+    unsafe { Mmap::map(file) }
+}
+
+/// Load `path` for chunking.
+///
+/// With `prefer_mmap` (or for files of at least [`MMAP_THRESHOLD`] bytes) the file is
+/// memory-mapped, so we never hold a second copy of it in memory. If mapping fails
+/// (e.g. on some network filesystems) we fall back to a buffered read.
+pub fn read_file(path: &Path, prefer_mmap: bool) -> io::Result<FileData> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    // Mapping a zero-length file is an error on some platforms, and there is nothing to map.
+    if len == 0 {
+        return Ok(FileData::Buffered(Vec::new()));
+    }
+
+    if prefer_mmap || len >= MMAP_THRESHOLD {
+        match map_file(&file) {
+            Ok(mmap) => return Ok(FileData::Mapped(mmap)),
+            // On Windows a file locked by another process also fails here
+            // (ERROR_LOCK_VIOLATION), so it takes the buffered path too.
+            Err(err) => log::warn!(
+                "mmap of {} failed ({}), falling back to buffered read",
+                path.display(),
+                err
+            ),
+        }
+    }
+
+    let mut data = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut data)?;
+    Ok(FileData::Buffered(data))
+}
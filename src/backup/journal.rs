@@ -0,0 +1,117 @@
+//! Write-ahead journal of a running backup, so an interrupted backup can be resumed.
+//!
+//! The journal is a local file of length-prefixed records (`u32` LE length, then
+//! JSON). The first record names the backed-up paths, every further one a finished
+//! file. A record torn by a crash is ignored when the journal is read back.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backup::manifest::ManifestEntry;
+
+/// File name of the journal in the local cache directory of a repository.
+pub const JOURNAL_NAME: &str = "journal.bin";
+
+#[derive(Serialize, Deserialize)]
+enum JournalRecord {
+    Start { paths: Vec<String> },
+    File(ManifestEntry),
+}
+
+/// What an interrupted backup got done before it stopped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JournalState {
+    /// Paths given to the interrupted backup.
+    pub paths: Vec<String>,
+    /// Files it finished, in order.
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Journal being written by a running backup.
+pub struct BackupJournal {
+    file: File,
+    path: PathBuf,
+}
+
+impl BackupJournal {
+    /// Start a new journal at `path` for a backup of `paths`, replacing any old one.
+    /// The directory it is in is created if needed.
+    pub fn create(path: &Path, paths: &[String]) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        let mut journal = BackupJournal {
+            file,
+            path: path.to_path_buf(),
+        };
+        journal.append(&JournalRecord::Start {
+            paths: paths.to_vec(),
+        })?;
+        Ok(journal)
+    }
+
+    /// Read the journal at `path`, if there is one.
+    pub fn load(path: &Path) -> io::Result<Option<JournalState>> {
+        let mut bytes = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_end(&mut bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut state = JournalState::default();
+        let mut rest = bytes.as_slice();
+        while let Some(record) = next_record(&mut rest) {
+            match serde_json::from_slice(record) {
+                Ok(JournalRecord::Start { paths }) => state.paths = paths,
+                Ok(JournalRecord::File(entry)) => state.entries.push(entry),
+                Err(err) => {
+                    log::warn!("ignoring damaged journal record: {}", err);
+                    break;
+                }
+            }
+        }
+        Ok(Some(state))
+    }
+
+    /// Record a finished file. Returns once the record is on disk.
+    pub fn add_file(&mut self, entry: &ManifestEntry) -> io::Result<()> {
+        self.append(&JournalRecord::File(entry.clone()))
+    }
+
+    /// Delete the journal, after the snapshot it was protecting has been committed.
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> io::Result<()> {
+        let json = serde_json::to_vec(record)?;
+        let len = u32::try_from(json.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "journal record too large"))?;
+
+        let mut buf = Vec::with_capacity(4 + json.len());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&json);
+        self.file.write_all(&buf)?;
+        self.file.sync_data()
+    }
+}
+
+/// Split the next complete record off `rest`; `None` at the end or at a torn record.
+fn next_record<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (len, tail) = rest.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if tail.len() < len {
+        return None;
+    }
+    let (record, tail) = tail.split_at(len);
+    *rest = tail;
+    Some(record)
+}
@@ -0,0 +1,131 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// Description of backed-up content: which chunks, in which order, make up each entry.
+///
+/// Chunk data itself lives in the store; a manifest only references it by hash.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+    /// Directories above the backed-up files, parents before children, so restore can
+    /// give them their metadata back. Only the name and metadata fields are set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<ManifestEntry>,
+}
+
+/// One backed-up file (or named blob) or symlink, or a directory in
+/// [`Manifest::directories`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    /// Regular file unless stated otherwise.
+    #[serde(default, skip_serializing_if = "EntryKind::is_file")]
+    pub kind: EntryKind,
+    /// Content length in bytes, the sum of all chunk lengths.
+    pub size: u64,
+    /// Hex-encoded BLAKE3 hashes of the chunks, in content order.
+    pub chunks: Vec<String>,
+    /// Hex-encoded BLAKE3 hash of the whole content, to recognise identical files
+    /// without chunking them. Not set for empty files and symlinks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// Modification time, if it could be read.
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mtime: Option<OffsetDateTime>,
+    /// Unix permission bits (`0o7777` at most); `None` on other platforms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Unix owner and group ids; `None` on other platforms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Set on files that had other hard links when backed up. Entries with the same
+    /// group were one file and are restored as hard links to each other.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_group: Option<u64>,
+}
+
+/// What a [`ManifestEntry`] describes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// Content made up of the entry's chunks.
+    #[default]
+    File,
+    /// Symbolic link, with no content of its own.
+    Symlink {
+        /// Link target as raw bytes, since it need not be valid UTF-8.
+        target: Vec<u8>,
+    },
+}
+
+impl EntryKind {
+    pub fn is_file(&self) -> bool {
+        matches!(self, EntryKind::File)
+    }
+}
+
+impl ManifestEntry {
+    /// Record the metadata of the file this entry was read from.
+    ///
+    /// Fields the platform or filesystem cannot provide stay `None`.
+    pub fn set_metadata(&mut self, metadata: &fs::Metadata) {
+        self.mtime = metadata.modified().ok().map(OffsetDateTime::from);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            self.mode = Some(metadata.mode() & 0o7777);
+            self.uid = Some(metadata.uid());
+            self.gid = Some(metadata.gid());
+        }
+    }
+
+    /// The target of a symlink entry.
+    pub fn symlink_target(&self) -> Option<&[u8]> {
+        match &self.kind {
+            EntryKind::Symlink { target } => Some(target),
+            EntryKind::File => None,
+        }
+    }
+
+    /// Entry for a directory called `name`, with no content.
+    pub fn directory(name: &str, metadata: &fs::Metadata) -> Self {
+        let mut entry = ManifestEntry {
+            name: name.to_string(),
+            kind: EntryKind::File,
+            size: 0,
+            chunks: Vec::new(),
+            content_hash: None,
+            mtime: None,
+            mode: None,
+            uid: None,
+            gid: None,
+            link_group: None,
+        };
+        entry.set_metadata(metadata);
+        entry
+    }
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Total content size of all entries.
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+}
@@ -1 +1,28 @@
-pub mod cdc_chunker;
\ No newline at end of file
+pub mod cancel;
+pub mod cdc_chunker;
+pub mod check;
+pub mod compare;
+pub mod copy;
+pub mod diff;
+pub mod estimate;
+pub mod export;
+pub mod filter;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+pub mod hash;
+pub mod import;
+pub mod index;
+pub mod io;
+pub mod journal;
+pub mod manifest;
+pub mod preview;
+pub mod restore;
+pub mod retention;
+pub mod session;
+pub mod sink;
+pub mod snapshot;
+pub mod stats;
+pub mod store;
+pub mod timing;
+pub mod verify;
+pub mod walk;
\ No newline at end of file
@@ -0,0 +1,23 @@
+/// Bytes of a chunk shown by [`preview`] unless asked otherwise.
+pub const DEFAULT_PREVIEW_LEN: usize = 60;
+
+/// Marks a preview that does not show the whole chunk. Plain ASCII, so it reads the
+/// same whatever encoding the output is viewed in.
+pub const ELLIPSIS: &str = "...";
+
+/// The first `max_len` bytes of `chunk` as a single line of text, for showing chunks of
+/// text-ish input.
+///
+/// Invalid UTF-8 becomes U+FFFD, newlines, carriage returns and tabs are escaped as
+/// `\n`, `\r` and `\t`, and [`ELLIPSIS`] is appended if the chunk is longer.
+pub fn preview(chunk: &[u8], max_len: usize) -> String {
+    let shown = &chunk[..chunk.len().min(max_len)];
+    let mut preview = String::from_utf8_lossy(shown)
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    if shown.len() < chunk.len() {
+        preview.push_str(ELLIPSIS);
+    }
+    preview
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::backup::{
+    cdc_chunker,
+    manifest::{Manifest, ManifestEntry},
+    store::{Backend, ChunkStore, StoreError},
+};
+
+/// Write the content of `entry` to `out`, chunk by chunk, in order.
+///
+/// Returns the number of bytes written. Only one chunk is held in memory at a time;
+/// zero chunks are written without reading anything.
+pub fn write_entry<B: Backend>(
+    entry: &ManifestEntry,
+    store: &ChunkStore<B>,
+    out: &mut impl Write,
+) -> Result<u64, StoreError> {
+    let mut written = 0u64;
+    for hash in &entry.chunks {
+        if let Some(len) = cdc_chunker::zero_chunk_len(hash) {
+            written += io::copy(&mut io::repeat(0).take(len as u64), out)?;
+            continue;
+        }
+        let chunk = store.get(hash)?;
+        out.write_all(&chunk)?;
+        written += chunk.len() as u64;
+    }
+
+    if written != entry.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: restored {} bytes, expected {}",
+                entry.name, written, entry.size
+            ),
+        )
+        .into());
+    }
+    Ok(written)
+}
+
+/// Reads the content of an entry from the store, one chunk at a time, e.g. to feed it
+/// to an API that wants a [`Read`]. Zero chunks are produced without allocating.
+///
+/// Content that does not add up to the recorded size is an [`io::ErrorKind::InvalidData`]
+/// error, like in [`write_entry`].
+pub struct EntryReader<'a, B: Backend> {
+    store: &'a ChunkStore<B>,
+    name: &'a str,
+    chunks: std::slice::Iter<'a, String>,
+    // Bytes of the recorded size not produced yet.
+    remaining: u64,
+    // The chunk being read and how much of it is done.
+    chunk: Vec<u8>,
+    pos: usize,
+    // Zero bytes still to produce for the current zero chunk.
+    zeros: usize,
+}
+
+impl<'a, B: Backend> EntryReader<'a, B> {
+    pub fn new(entry: &'a ManifestEntry, store: &'a ChunkStore<B>) -> Self {
+        EntryReader {
+            store,
+            name: &entry.name,
+            chunks: entry.chunks.iter(),
+            remaining: entry.size,
+            chunk: Vec::new(),
+            pos: 0,
+            zeros: 0,
+        }
+    }
+
+    fn size_mismatch(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: content does not match the recorded size", self.name),
+        )
+    }
+}
+
+impl<B: Backend> Read for EntryReader<'_, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() && self.zeros == 0 {
+            let Some(hash) = self.chunks.next() else {
+                if self.remaining > 0 {
+                    return Err(self.size_mismatch());
+                }
+                return Ok(0);
+            };
+            if let Some(len) = cdc_chunker::zero_chunk_len(hash) {
+                self.zeros = len;
+            } else {
+                self.chunk = self.store.get(hash).map_err(|err| match err {
+                    StoreError::Io(err) | StoreError::Transient(err) => err,
+                    err => io::Error::other(err),
+                })?;
+                self.pos = 0;
+            }
+        }
+
+        let len = if self.zeros > 0 {
+            let len = buf.len().min(self.zeros);
+            buf[..len].fill(0);
+            self.zeros -= len;
+            len
+        } else {
+            let len = buf.len().min(self.chunk.len() - self.pos);
+            buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+            self.pos += len;
+            len
+        };
+        self.remaining = self
+            .remaining
+            .checked_sub(len as u64)
+            .ok_or_else(|| self.size_mismatch())?;
+        Ok(len)
+    }
+}
+
+/// Recreate the file described by `entry` at `out_path`.
+///
+/// Fails with [`StoreError::ChunkNotFound`] before creating anything if a referenced
+/// chunk is missing from the store, and removes the partial file on any later error.
+pub fn restore_file<B: Backend>(
+    entry: &ManifestEntry,
+    store: &ChunkStore<B>,
+    out_path: &Path,
+) -> Result<(), StoreError> {
+    if let Some(missing) = entry.chunks.iter().find(|hash| !store.contains(hash)) {
+        return Err(StoreError::ChunkNotFound(missing.clone()));
+    }
+
+    let mut out = BufWriter::new(File::create(out_path)?);
+    let result = write_entry(entry, store, &mut out).and_then(|_| {
+        Ok(out
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(out_path);
+    }
+    result
+}
+
+/// What restore does about a file that already exists where an entry goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OverwritePolicy {
+    /// Leave it alone and fail the restore.
+    #[default]
+    Never,
+    /// Replace it.
+    Always,
+    /// Replace it unless it already has the recorded content (or symlink target).
+    IfChanged,
+}
+
+/// What restoring an entry will do, see [`plan_entry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreAction {
+    /// Nothing is there yet.
+    Create,
+    /// Something is there and gets replaced.
+    Overwrite,
+    /// The same content is already there.
+    Skip,
+    /// Something is there that the policy does not allow replacing, or a directory.
+    Conflict,
+}
+
+/// Decide what restoring `entry` to `out_path` does under `policy`.
+///
+/// With [`OverwritePolicy::IfChanged`], an existing file is unchanged if it has the
+/// recorded size and content hash. Entries from before content hashes were recorded
+/// always count as changed.
+pub fn plan_entry(
+    entry: &ManifestEntry,
+    out_path: &Path,
+    policy: OverwritePolicy,
+) -> io::Result<RestoreAction> {
+    let metadata = match fs::symlink_metadata(out_path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(RestoreAction::Create),
+        Err(err) => return Err(err),
+    };
+    if metadata.is_dir() {
+        return Ok(RestoreAction::Conflict);
+    }
+
+    match policy {
+        OverwritePolicy::Never => Ok(RestoreAction::Conflict),
+        OverwritePolicy::Always => Ok(RestoreAction::Overwrite),
+        OverwritePolicy::IfChanged => {
+            let unchanged = match entry.symlink_target() {
+                Some(target) => {
+                    metadata.is_symlink()
+                        && fs::read_link(out_path)?.as_os_str().as_encoded_bytes() == target
+                }
+                None => metadata.is_file() && is_unchanged_file(entry, out_path, &metadata)?,
+            };
+            Ok(if unchanged {
+                RestoreAction::Skip
+            } else {
+                RestoreAction::Overwrite
+            })
+        }
+    }
+}
+
+fn is_unchanged_file(
+    entry: &ManifestEntry,
+    path: &Path,
+    metadata: &fs::Metadata,
+) -> io::Result<bool> {
+    let Some(content_hash) = &entry.content_hash else {
+        return Ok(entry.size == 0 && metadata.len() == 0);
+    };
+    if metadata.len() != entry.size {
+        return Ok(false);
+    }
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().as_str() == content_hash)
+}
+
+/// Files already restored per link group, so that the other entries of a group become
+/// hard links to them instead of separate copies.
+#[derive(Debug, Default)]
+pub struct HardLinks {
+    restored: HashMap<u64, PathBuf>,
+}
+
+impl HardLinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recreate the file described by `entry` at `out_path` like [`restore_file`], or
+    /// as a hard link to the file restored earlier for the same link group.
+    ///
+    /// Where a link cannot be made (e.g. on filesystems without hard links), the file
+    /// is written out as a separate copy.
+    pub fn restore_file<B: Backend>(
+        &mut self,
+        entry: &ManifestEntry,
+        store: &ChunkStore<B>,
+        out_path: &Path,
+    ) -> Result<(), StoreError> {
+        let Some(group) = entry.link_group else {
+            return restore_file(entry, store, out_path);
+        };
+        if let Some(first) = self.restored.get(&group) {
+            match link(first, out_path) {
+                Ok(()) => return Ok(()),
+                Err(err) => log::debug!(
+                    "cannot link {} to {}, copying instead: {}",
+                    out_path.display(),
+                    first.display(),
+                    err
+                ),
+            }
+        }
+
+        restore_file(entry, store, out_path)?;
+        self.add(entry, out_path);
+        Ok(())
+    }
+
+    /// Record that `entry` is at `out_path` already (e.g. because it was unchanged), so
+    /// the rest of its link group can be linked to it.
+    pub fn add(&mut self, entry: &ManifestEntry, out_path: &Path) {
+        if let Some(group) = entry.link_group {
+            self.restored
+                .entry(group)
+                .or_insert_with(|| out_path.to_path_buf());
+        }
+    }
+}
+
+/// Hard link `out_path` to `existing`, replacing a file already at `out_path`.
+fn link(existing: &Path, out_path: &Path) -> io::Result<()> {
+    match fs::hard_link(existing, out_path) {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            fs::remove_file(out_path)?;
+            fs::hard_link(existing, out_path)
+        }
+        result => result,
+    }
+}
+
+/// Recreate the symlink described by `entry` at `out_path`. The target is not
+/// checked or touched; it may well not exist.
+pub fn restore_symlink(entry: &ManifestEntry, out_path: &Path) -> io::Result<()> {
+    let Some(target) = entry.symlink_target() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a symlink", entry.name),
+        ));
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), out_path)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (target, out_path);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symlinks can only be restored on Unix",
+        ))
+    }
+}
+
+/// Fail if writing `out_path` (below `target`) would go through a symlink, i.e. if
+/// any existing component of it below `target`, itself included, is one.
+///
+/// Restored symlinks may point anywhere, so without this check a file stored below
+/// a link in the snapshot could be written outside `target`.
+pub fn check_no_symlinks(target: &Path, out_path: &Path) -> io::Result<()> {
+    let Ok(relative) = out_path.strip_prefix(target) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is outside {}", out_path.display(), target.display()),
+        ));
+    };
+
+    let mut path = target.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_symlink() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("refusing to write through symlink {}", path.display()),
+                ));
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Give the restored file or directory at `path` the modification time and permissions
+/// recorded in `entry`, as far as they were recorded.
+///
+/// Directories must be done after everything inside them, since creating their
+/// children changes their modification time.
+pub fn apply_metadata(entry: &ManifestEntry, path: &Path) -> io::Result<()> {
+    // Before the mode, which may take away the write access this needs.
+    if let Some(mtime) = entry.mtime {
+        let file = if path.is_dir() {
+            File::open(path)?
+        } else {
+            File::options().write(true).open(path)?
+        };
+        file.set_modified(mtime.into())?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = entry.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Give `path` the owner and group recorded in `entry`. Call it before
+/// [`apply_metadata`], since changing the owner can clear setuid and setgid bits.
+///
+/// Only root can give files away, so callers usually treat a failure as a warning.
+pub fn apply_ownership(entry: &ManifestEntry, path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if entry.uid.is_some() || entry.gid.is_some() {
+        std::os::unix::fs::chown(path, entry.uid, entry.gid)?;
+    }
+    #[cfg(not(unix))]
+    let _ = (entry, path);
+    Ok(())
+}
+
+/// Where the entry `name` goes when restoring into `target`.
+///
+/// Only the normal components of `name` are kept, so absolute names and `..` can
+/// never escape `target`.
+pub fn restore_path(target: &Path, name: &str) -> PathBuf {
+    target.join(entry_path(name))
+}
+
+/// The entry `name` as a relative path: its normal components only. Restore filters
+/// and [`find_entry`] match against this.
+pub fn entry_path(name: &str) -> PathBuf {
+    Path::new(name)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The entry of `manifest` recorded as `path`, or failing that, the one with the same
+/// [`entry_path`] (so `/home/me/notes.txt` is also found as `home/me/notes.txt`).
+pub fn find_entry<'a>(manifest: &'a Manifest, path: &str) -> Option<&'a ManifestEntry> {
+    manifest.get(path).or_else(|| {
+        let path = entry_path(path);
+        manifest
+            .entries
+            .iter()
+            .find(|entry| entry_path(&entry.name) == path)
+    })
+}
@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, fmt};
+
+use time::OffsetDateTime;
+
+use crate::backup::snapshot::Snapshot;
+
+/// Which snapshots `rbckp forget` keeps; everything else is forgotten.
+///
+/// The policy applies to each group of snapshots with the same host and tags on its
+/// own (see [`RetentionPolicy::apply_grouped`]), so backups of one machine never
+/// push out those of another.
+///
+/// Each rule keeps the newest snapshot of each of the last N periods (days, ISO
+/// weeks, months, in UTC) that have snapshots at all. A snapshot can be kept by
+/// several rules; an unset rule keeps nothing.
+#[derive(serde::Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// The N newest snapshots.
+    #[serde(default)]
+    pub keep_last: Option<u32>,
+    #[serde(default)]
+    pub keep_daily: Option<u32>,
+    #[serde(default)]
+    pub keep_weekly: Option<u32>,
+    #[serde(default)]
+    pub keep_monthly: Option<u32>,
+}
+
+/// Why a snapshot was kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepReason {
+    Last,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl fmt::Display for KeepReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeepReason::Last => "last",
+            KeepReason::Daily => "daily",
+            KeepReason::Weekly => "weekly",
+            KeepReason::Monthly => "monthly",
+        })
+    }
+}
+
+impl KeepReason {
+    /// The period of the rule that `time` falls in, as (year, number in year).
+    fn period(self, time: OffsetDateTime) -> (i32, u32) {
+        let time = time.to_offset(time::UtcOffset::UTC);
+        match self {
+            // Every snapshot is a period of its own; `apply` does not compare them.
+            KeepReason::Last => (0, 0),
+            KeepReason::Daily => (time.year(), time.ordinal().into()),
+            KeepReason::Weekly => {
+                let (year, week, _) = time.to_iso_week_date();
+                (year, week.into())
+            }
+            KeepReason::Monthly => (time.year(), u8::from(time.month()).into()),
+        }
+    }
+}
+
+/// What [`RetentionPolicy::apply`] decided for one snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetentionDecision {
+    pub id: String,
+    pub time: OffsetDateTime,
+    /// Rules that keep the snapshot; empty if it is to be forgotten.
+    pub reasons: Vec<KeepReason>,
+}
+
+impl RetentionDecision {
+    pub fn keep(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+impl RetentionPolicy {
+    /// Whether no rule is set, i.e. the policy would forget every snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+            && self.keep_monthly.is_none()
+    }
+
+    /// Decide for each `(id, time)` whether to keep it. Decisions come newest first.
+    pub fn apply(&self, snapshots: &[(String, OffsetDateTime)]) -> Vec<RetentionDecision> {
+        let mut decisions: Vec<RetentionDecision> = snapshots
+            .iter()
+            .map(|(id, time)| RetentionDecision {
+                id: id.clone(),
+                time: *time,
+                reasons: Vec::new(),
+            })
+            .collect();
+        decisions.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.id.cmp(&b.id)));
+
+        let rules = [
+            (self.keep_last, KeepReason::Last),
+            (self.keep_daily, KeepReason::Daily),
+            (self.keep_weekly, KeepReason::Weekly),
+            (self.keep_monthly, KeepReason::Monthly),
+        ];
+
+        for (count, reason) in rules {
+            let Some(mut remaining) = count else {
+                continue;
+            };
+            let mut last_period = None;
+            for decision in &mut decisions {
+                if remaining == 0 {
+                    break;
+                }
+                let current = reason.period(decision.time);
+                if reason == KeepReason::Last || last_period != Some(current) {
+                    decision.reasons.push(reason);
+                    last_period = Some(current);
+                    remaining -= 1;
+                }
+            }
+        }
+
+        decisions
+    }
+
+    /// Apply the policy separately to each group of snapshots sharing a host and a
+    /// set of tags. Groups come sorted, decisions within a group newest first.
+    pub fn apply_grouped(
+        &self,
+        snapshots: &[(String, &Snapshot)],
+    ) -> Vec<(SnapshotGroup, Vec<RetentionDecision>)> {
+        let mut groups: BTreeMap<SnapshotGroup, Vec<(String, OffsetDateTime)>> = BTreeMap::new();
+        for (id, snapshot) in snapshots {
+            groups
+                .entry(SnapshotGroup::of(snapshot))
+                .or_default()
+                .push((id.clone(), snapshot.time));
+        }
+
+        groups
+            .into_iter()
+            .map(|(group, snapshots)| {
+                let decisions = self.apply(&snapshots);
+                (group, decisions)
+            })
+            .collect()
+    }
+}
+
+/// Snapshots that retention treats as one history: same host, same tags.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SnapshotGroup {
+    pub host: String,
+    /// Sorted.
+    pub tags: Vec<String>,
+}
+
+impl SnapshotGroup {
+    pub fn of(snapshot: &Snapshot) -> Self {
+        SnapshotGroup {
+            host: snapshot.hostname.clone(),
+            tags: snapshot.tags.clone(),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = if self.host.is_empty() {
+            "unknown host"
+        } else {
+            &self.host
+        };
+        if self.tags.is_empty() {
+            write!(f, "{}, no tags", host)
+        } else {
+            write!(f, "{}, tags {}", host, self.tags.join(", "))
+        }
+    }
+}
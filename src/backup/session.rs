@@ -0,0 +1,508 @@
+use std::{collections::HashMap, fs, io::Read, path::Path, time::Instant};
+
+use crate::{
+    backup::{
+        cancel::CancelToken,
+        cdc_chunker::{self, CdcParams, StreamChunker},
+        io,
+        manifest::{EntryKind, Manifest, ManifestEntry},
+        snapshot::{RunStats, Snapshot},
+        store::{Backend, ChunkStore, StoreError},
+        timing::{Phase, PhaseTimings},
+    },
+    config::Settings,
+};
+
+/// Counters of a backup session, mostly to see how well deduplication worked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BackupStats {
+    /// Entries added to the manifest.
+    pub files: usize,
+    /// Content bytes processed.
+    pub bytes: u64,
+    /// Chunks processed, including duplicates.
+    pub chunks: usize,
+    /// Chunks that were not in the store yet and had to be written.
+    pub new_chunks: usize,
+    /// Bytes of those new chunks.
+    pub new_bytes: u64,
+    /// Entries whose content matched an earlier entry's, which were not chunked again.
+    pub duplicate_files: usize,
+    /// Entries recorded as hard links to an earlier entry, which were not read at all.
+    pub hard_links: usize,
+    /// Entries the parent snapshot (see [`BackupSession::with_parent`]) does not have,
+    /// has with other content, or has with the same content; all entries are new
+    /// without a parent.
+    pub new_files: usize,
+    pub changed_files: usize,
+    pub unchanged_files: usize,
+}
+
+/// One backup run: chunks inputs into a [`ChunkStore`] and records them in a [`Manifest`].
+pub struct BackupSession<B: Backend> {
+    settings: Settings,
+    params: CdcParams,
+    store: ChunkStore<B>,
+    manifest: Manifest,
+    stats: BackupStats,
+    timings: PhaseTimings,
+    // Files with more than one hard link seen so far, by (device, inode), with the
+    // index of their first entry.
+    hard_links: HashMap<(u64, u64), usize>,
+    next_link_group: u64,
+    // Index of the first entry with each content hash.
+    known_contents: HashMap<String, usize>,
+    // Entries of the parent snapshot by name, as (kind, chunks).
+    parent: HashMap<String, (EntryKind, Vec<String>)>,
+    cancel: CancelToken,
+    started: Instant,
+}
+
+impl<B: Backend> BackupSession<B> {
+    /// Session with the chunk sizes of `settings`, identifying chunks with the hash
+    /// algorithm of the repository.
+    ///
+    /// With [`Settings::check_chunk_lengths`], the store's
+    /// [length check](ChunkStore::with_length_check) is turned on, and chunks are
+    /// [looked up](ChunkStore::with_hash_prefix_len) by [`Settings::hash_prefix_len`]
+    /// hex digits of their id.
+    pub fn new(settings: Settings, mut store: ChunkStore<B>) -> Self {
+        let params = repo_params(&settings, &store);
+        if settings.check_chunk_lengths {
+            store = store.with_length_check(true);
+        }
+        store = store.with_hash_prefix_len(settings.hash_prefix_len);
+        BackupSession {
+            settings,
+            params,
+            store,
+            manifest: Manifest::new(),
+            stats: BackupStats::default(),
+            timings: PhaseTimings::default(),
+            hard_links: HashMap::new(),
+            next_link_group: 0,
+            known_contents: HashMap::new(),
+            parent: HashMap::new(),
+            cancel: CancelToken::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Stop when `cancel` is cancelled: adding an entry then fails with
+    /// [`StoreError::Interrupted`] before the next chunk is stored, leaving the entry
+    /// out. The session can still be [checkpointed](Self::checkpoint).
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Compare every entry with the one of the same name in `parent`, the manifest of
+    /// the previous backup of the same paths, to count new, changed and unchanged
+    /// files. Content is compared by chunk list, like [`diff`](super::diff::diff).
+    pub fn with_parent(mut self, parent: &Manifest) -> Self {
+        self.parent = parent
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.name.clone(),
+                    (entry.kind.clone(), entry.chunks.clone()),
+                )
+            })
+            .collect();
+        self
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn store(&self) -> &ChunkStore<B> {
+        &self.store
+    }
+
+    pub fn stats(&self) -> &BackupStats {
+        &self.stats
+    }
+
+    /// Time spent reading, chunking and hashing so far.
+    pub fn timings(&self) -> &PhaseTimings {
+        &self.timings
+    }
+
+    /// What the session did so far, as recorded in the snapshot it commits. The
+    /// duration counts from the start of the session.
+    pub fn run_stats(&self) -> RunStats {
+        RunStats {
+            files_new: self.stats.new_files,
+            files_changed: self.stats.changed_files,
+            files_unchanged: self.stats.unchanged_files,
+            bytes_read: self.stats.bytes,
+            bytes_stored: self.stats.new_bytes,
+            chunks: self.stats.chunks,
+            new_chunks: self.stats.new_chunks,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Back up the file at `path`, recorded under its path as given, together with its
+    /// modification time, permissions and ownership.
+    ///
+    /// A symlink is read through; use [`BackupSession::add_symlink`] to record the link.
+    /// A file with several hard links is only read the first time one of them is added;
+    /// the others reuse its chunks and share its link group.
+    ///
+    /// Metadata that cannot be read is left out of the entry with a warning.
+    pub fn add_file(&mut self, path: &Path) -> Result<&ManifestEntry, StoreError> {
+        let metadata = fs::metadata(path)
+            .inspect_err(|err| log::warn!("{}: cannot read metadata: {}", path.display(), err))
+            .ok();
+        let link_id = metadata.as_ref().and_then(hard_link_id);
+
+        if let Some(&first) = link_id.and_then(|id| self.hard_links.get(&id)) {
+            let mut entry = self.manifest.entries[first].clone();
+            entry.name = path.to_string_lossy().into_owned();
+            if let Some(metadata) = &metadata {
+                entry.set_metadata(metadata);
+            }
+            log::debug!(
+                "{}: hard link to {}",
+                entry.name,
+                self.manifest.entries[first].name
+            );
+            self.stats.hard_links += 1;
+            return Ok(self.push_entry(entry));
+        }
+
+        let data = self
+            .timings
+            .time(Phase::Read, || io::read_file(path, false))?;
+        let mut entry = self.chunk_entry(&path.to_string_lossy(), &data)?;
+        if let Some(metadata) = &metadata {
+            entry.set_metadata(metadata);
+        }
+        if let Some(id) = link_id {
+            entry.link_group = Some(self.next_link_group);
+            self.next_link_group += 1;
+            self.hard_links.insert(id, self.manifest.entries.len());
+        }
+        Ok(self.push_entry(entry))
+    }
+
+    /// Record the symlink at `path` itself, without reading what it points to.
+    pub fn add_symlink(&mut self, path: &Path) -> Result<&ManifestEntry, StoreError> {
+        let metadata = fs::symlink_metadata(path)?;
+        let target = fs::read_link(path)?;
+        #[cfg(unix)]
+        let target = std::os::unix::ffi::OsStringExt::into_vec(target.into_os_string());
+        #[cfg(not(unix))]
+        let target = target.to_string_lossy().into_owned().into_bytes();
+
+        let mut entry = self.chunk_entry(&path.to_string_lossy(), &[])?;
+        entry.kind = EntryKind::Symlink { target };
+        entry.set_metadata(&metadata);
+        Ok(self.push_entry(entry))
+    }
+
+    /// Back up `data`, recorded under `name`.
+    pub fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<&ManifestEntry, StoreError> {
+        let entry = self.chunk_entry(name, data)?;
+        Ok(self.push_entry(entry))
+    }
+
+    /// Back up everything `reader` yields, recorded under `name`, chunking it as it is
+    /// read so that no more than one chunk is held in memory. `progress` is called with
+    /// the number of bytes read so far after every chunk.
+    ///
+    /// Streams are cut by [`StreamChunker`], into the same chunks as a file with the
+    /// same bytes, zero chunks included.
+    pub fn add_reader<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        mut progress: impl FnMut(u64),
+    ) -> Result<&ManifestEntry, StoreError> {
+        let mut chunker = StreamChunker::new(reader, &self.params);
+        let mut content_hasher = blake3::Hasher::new();
+        let mut chunks = Vec::new();
+        // The stream chunker reads, cuts and hashes in one step, all counted as reading:
+        // for a pipe, waiting on the writer is usually what takes the time.
+        while let Some(chunk) = self.timings.time(Phase::Read, || chunker.next()) {
+            let (chunk_ref, chunk) = chunk?;
+            self.timings
+                .time(Phase::Hash, || content_hasher.update(&chunk));
+            self.store_chunk(&chunk_ref.hash, &chunk)?;
+            chunks.push(chunk_ref.hash);
+            progress((chunk_ref.offset + chunk_ref.len) as u64);
+        }
+
+        let size = chunker.bytes_processed() as u64;
+        let entry = ManifestEntry {
+            name: name.to_string(),
+            kind: EntryKind::File,
+            size,
+            chunks,
+            content_hash: (size > 0).then(|| content_hasher.finalize().to_hex().to_string()),
+            mtime: None,
+            mode: None,
+            uid: None,
+            gid: None,
+            link_group: None,
+        };
+        Ok(self.push_entry(entry))
+    }
+
+    /// Back up `data` as the entry `template` describes, for content that is not read
+    /// from a file (such as an archive member). Name, kind and metadata come from
+    /// `template`; size, chunks and content hash from `data`.
+    pub fn add_content(
+        &mut self,
+        template: ManifestEntry,
+        data: &[u8],
+    ) -> Result<&ManifestEntry, StoreError> {
+        let chunked = self.chunk_entry(&template.name, data)?;
+        let entry = ManifestEntry {
+            size: chunked.size,
+            chunks: chunked.chunks,
+            content_hash: chunked.content_hash,
+            link_group: None,
+            ..template
+        };
+        Ok(self.push_entry(entry))
+    }
+
+    /// Record `name` as a hard link to the file entry called `target`, added earlier,
+    /// putting both in the same link group. `None` if there is no such entry.
+    pub fn add_hard_link(&mut self, name: &str, target: &str) -> Option<&ManifestEntry> {
+        let first = self
+            .manifest
+            .entries
+            .iter()
+            .rposition(|entry| entry.name == target && entry.kind.is_file())?;
+        if self.manifest.entries[first].link_group.is_none() {
+            self.manifest.entries[first].link_group = Some(self.next_link_group);
+            self.next_link_group += 1;
+        }
+
+        let mut entry = self.manifest.entries[first].clone();
+        entry.name = name.to_string();
+        self.stats.hard_links += 1;
+        Some(self.push_entry(entry))
+    }
+
+    /// Record a file that was already backed up earlier (e.g. by an interrupted run),
+    /// without reading it again. All its chunks must be in the store.
+    ///
+    /// If the entry is in a link group and the file it names is still a hard link, other
+    /// links to it added later with [`add_file`](Self::add_file) join its group.
+    pub fn add_entry(&mut self, entry: ManifestEntry) -> Result<&ManifestEntry, StoreError> {
+        let stored = self.store.contains_many(&entry.chunks);
+        if let Some((missing, _)) = entry.chunks.iter().zip(stored).find(|(_, stored)| !stored) {
+            return Err(StoreError::ChunkNotFound(missing.clone()));
+        }
+
+        // Groups handed out from now on must not join this one by accident.
+        if let Some(group) = entry.link_group {
+            self.next_link_group = self.next_link_group.max(group + 1);
+            let link_id = fs::metadata(&entry.name)
+                .ok()
+                .and_then(|metadata| hard_link_id(&metadata));
+            if let Some(id) = link_id {
+                self.hard_links
+                    .entry(id)
+                    .or_insert(self.manifest.entries.len());
+            }
+        }
+        Ok(self.push_entry(entry))
+    }
+
+    /// Record the metadata of the directory at `path`, recorded under its path as given.
+    /// Directories do not count as files in the stats.
+    pub fn add_directory(&mut self, path: &Path) -> Result<&ManifestEntry, StoreError> {
+        let metadata = fs::metadata(path)?;
+        let directories = &mut self.manifest.directories;
+        directories.push(ManifestEntry::directory(&path.to_string_lossy(), &metadata));
+        Ok(&directories[directories.len() - 1])
+    }
+
+    /// Record the directory `entry` describes, for directories that are not read from
+    /// the filesystem. Only its name and metadata fields are kept.
+    pub fn add_directory_entry(&mut self, entry: ManifestEntry) -> &ManifestEntry {
+        let directories = &mut self.manifest.directories;
+        directories.push(ManifestEntry {
+            kind: EntryKind::File,
+            size: 0,
+            chunks: Vec::new(),
+            content_hash: None,
+            link_group: None,
+            ..entry
+        });
+        &directories[directories.len() - 1]
+    }
+
+    /// Chunk `data` into the store and describe it as an entry called `name`.
+    ///
+    /// Content identical to an entry added before is not chunked at all; the new entry
+    /// gets that entry's chunk list.
+    fn chunk_entry(&mut self, name: &str, data: &[u8]) -> Result<ManifestEntry, StoreError> {
+        let content_hash = self.timings.time(Phase::Hash, || {
+            (!data.is_empty()).then(|| blake3::hash(data).to_hex().to_string())
+        });
+        let known = content_hash
+            .as_ref()
+            .and_then(|hash| self.known_contents.get(hash));
+        let chunks = if let Some(&known) = known {
+            log::debug!(
+                "{}: same content as {}",
+                name,
+                self.manifest.entries[known].name
+            );
+            self.stats.duplicate_files += 1;
+            self.manifest.entries[known].chunks.clone()
+        } else {
+            self.store_chunks(data)?
+        };
+
+        Ok(ManifestEntry {
+            name: name.to_string(),
+            kind: EntryKind::File,
+            size: data.len() as u64,
+            chunks,
+            content_hash,
+            mtime: None,
+            mode: None,
+            uid: None,
+            gid: None,
+            link_group: None,
+        })
+    }
+
+    /// Chunk `data` into the store, returning the chunk ids in order.
+    fn store_chunks(&mut self, data: &[u8]) -> Result<Vec<String>, StoreError> {
+        self.cancel.check()?;
+        let params = &self.params;
+        let spans = self
+            .timings
+            .time(Phase::Chunk, || cdc_chunker::chunk_ref_spans(data, params));
+        let chunk_refs = self.timings.time(Phase::Hash, || {
+            cdc_chunker::hash_chunk_spans(data, spans, params)
+        });
+
+        // One lookup for the whole chunk list; only the chunks reported missing are
+        // handed to the store.
+        let hashes: Vec<&str> = chunk_refs.iter().map(|chunk| chunk.hash.as_str()).collect();
+        let stored = self.store.contains_many(&hashes);
+
+        let mut chunks = Vec::with_capacity(chunk_refs.len());
+        for (chunk_ref, stored) in chunk_refs.into_iter().zip(stored) {
+            if stored {
+                self.store
+                    .check_length(&chunk_ref.hash, chunk_ref.len as u64)?;
+            } else {
+                let chunk = &data[chunk_ref.offset..chunk_ref.offset + chunk_ref.len];
+                self.store_chunk(&chunk_ref.hash, chunk)?;
+            }
+            chunks.push(chunk_ref.hash);
+        }
+        Ok(chunks)
+    }
+
+    fn store_chunk(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError> {
+        self.cancel.check()?;
+        if self.store.put(hash, chunk)? {
+            self.stats.new_chunks += 1;
+            self.stats.new_bytes += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn push_entry(&mut self, entry: ManifestEntry) -> &ManifestEntry {
+        self.stats.files += 1;
+        self.stats.bytes += entry.size;
+        self.stats.chunks += entry.chunks.len();
+        match self.parent.get(&entry.name) {
+            None => self.stats.new_files += 1,
+            Some((kind, chunks)) if *kind == entry.kind && *chunks == entry.chunks => {
+                self.stats.unchanged_files += 1
+            }
+            Some(_) => self.stats.changed_files += 1,
+        }
+
+        if let Some(hash) = &entry.content_hash {
+            self.known_contents
+                .entry(hash.clone())
+                .or_insert(self.manifest.entries.len());
+        }
+        self.manifest.entries.push(entry);
+        &self.manifest.entries[self.manifest.entries.len() - 1]
+    }
+
+    /// Write out all pending chunks, then save the manifest as a snapshot of `paths`
+    /// with the given tags.
+    ///
+    /// Returns the new snapshot's id.
+    pub fn commit(
+        mut self,
+        paths: Vec<String>,
+        tags: &[String],
+    ) -> Result<(String, Snapshot), StoreError> {
+        self.store.flush()?;
+
+        let stats = self.run_stats();
+        let mut snapshot = Snapshot::new(paths, self.manifest);
+        snapshot.stats = Some(stats);
+        for tag in tags {
+            snapshot.add_tag(tag);
+        }
+        let id = snapshot.save(self.store.backend())?;
+        Ok((id, snapshot))
+    }
+
+    /// Write out all pending chunks without ending the session, e.g. after it was
+    /// interrupted, so that a resumed backup finds them in the store instead of
+    /// storing them again.
+    pub fn checkpoint(&mut self) -> Result<(), StoreError> {
+        self.store.flush()
+    }
+
+    /// Write out all pending chunks and hand over the manifest.
+    pub fn finish(mut self) -> Result<Manifest, StoreError> {
+        self.store.flush()?;
+        Ok(self.manifest)
+    }
+}
+
+/// Chunking parameters for backing up into `store`: the chunk sizes of `settings`, and
+/// the hash algorithm (and key) of the repository. Warns about poor chunk sizes and a
+/// hash algorithm setting that does not apply.
+pub(crate) fn repo_params<B: Backend>(settings: &Settings, store: &ChunkStore<B>) -> CdcParams {
+    let params = settings
+        .chunk_settings
+        .cdc_params()
+        .with_hash_algorithm(store.hash_algorithm())
+        .with_hash_key(store.hash_key());
+    for warning in params.validate() {
+        log::warn!("{}", warning);
+    }
+    if settings.hash_algorithm != store.hash_algorithm() {
+        log::warn!(
+            "the repository identifies chunks with {}; the hash_algorithm setting ({}) \
+             only applies to new repositories",
+            store.hash_algorithm(),
+            settings.hash_algorithm
+        );
+    }
+    params
+}
+
+/// (device, inode) of a file that has other hard links, if the platform has them.
+#[cfg(unix)]
+fn hard_link_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn hard_link_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
@@ -0,0 +1,144 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::backup::{
+    cdc_chunker::{ChunkMap, zero_chunk_len},
+    hash::ChunkId,
+    store::{Backend, ChunkStore, LocalFsBackend, StoreError},
+};
+
+/// Where the chunker delivers chunks, see [`chunk_to_sink`](crate::backup::cdc_chunker::chunk_to_sink).
+///
+/// Every chunk of the input is passed once, in input order, duplicates included; a
+/// sink that stores chunks decides itself whether to skip ones it already has.
+pub trait ChunkSink {
+    fn accept(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError>;
+}
+
+/// Keeps all chunks in memory, in order and grouped by id, which is what
+/// [`chunk_bytes_cdc`](crate::backup::cdc_chunker::chunk_bytes_cdc) returns.
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    pub chunks: Vec<Vec<u8>>,
+    pub chunk_map: ChunkMap,
+}
+
+impl MemorySink {
+    pub fn into_parts(self) -> (Vec<Vec<u8>>, ChunkMap) {
+        (self.chunks, self.chunk_map)
+    }
+}
+
+impl ChunkSink for MemorySink {
+    /// Zero chunks are grouped under the hash of their bytes; fails for other ids that
+    /// are not hashes.
+    fn accept(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError> {
+        let id: ChunkId = match zero_chunk_len(hash) {
+            Some(_) => ChunkId::of(chunk),
+            None => hash
+                .parse()
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?,
+        };
+        self.chunks.push(chunk.to_vec());
+        self.chunk_map.entry(id).or_default().push(chunk.to_vec());
+        Ok(())
+    }
+}
+
+/// Passes only the first occurrence of every chunk on to another sink, remembering
+/// nothing but the ids it has seen.
+///
+/// Unlike [`MemorySink`], which keeps the bytes of every chunk, memory grows with the
+/// number of distinct chunks by one id each, so input far larger than RAM can be
+/// deduplicated on its way to a sink that stores chunks elsewhere. Duplicates are
+/// recognised by id alone; their bytes are not compared.
+#[derive(Debug, Default)]
+pub struct DedupSink<S> {
+    inner: S,
+    seen: HashSet<String>,
+    /// Chunks that were already seen and not passed on.
+    pub duplicates: usize,
+    /// Total size of those chunks.
+    pub duplicate_bytes: u64,
+}
+
+impl<S: ChunkSink> DedupSink<S> {
+    pub fn new(inner: S) -> Self {
+        DedupSink {
+            inner,
+            seen: HashSet::new(),
+            duplicates: 0,
+            duplicate_bytes: 0,
+        }
+    }
+
+    /// Ids of the chunks passed on so far.
+    pub fn seen(&self) -> &HashSet<String> {
+        &self.seen
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: ChunkSink> ChunkSink for DedupSink<S> {
+    fn accept(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError> {
+        if self.seen.contains(hash) {
+            self.duplicates += 1;
+            self.duplicate_bytes += chunk.len() as u64;
+            return Ok(());
+        }
+        self.inner.accept(hash, chunk)?;
+        self.seen.insert(hash.to_string());
+        Ok(())
+    }
+}
+
+/// A plain content-addressed directory: every distinct chunk is a file named by its
+/// id, below a subdirectory named by the first two characters of it
+/// (`<root>/ab/abcdef...`).
+///
+/// Unlike a repository, chunks are not packed, so this suits a few large chunks
+/// better than many small ones.
+pub struct FsSink {
+    backend: LocalFsBackend,
+    /// Chunks written, not counting those that were already there.
+    pub written: usize,
+}
+
+impl FsSink {
+    pub fn new(root: &Path) -> Self {
+        FsSink {
+            backend: LocalFsBackend::new(root),
+            written: 0,
+        }
+    }
+
+    /// Name of the file of chunk `hash`, relative to the root.
+    pub fn chunk_name(hash: &str) -> String {
+        let prefix = hash.get(..2).unwrap_or(hash);
+        format!("{}/{}", prefix, hash)
+    }
+}
+
+impl ChunkSink for FsSink {
+    fn accept(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError> {
+        let name = Self::chunk_name(hash);
+        if !self.backend.exists(&name)? {
+            self.backend.write(&name, chunk)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Chunks go into the repository's packs, on whatever backend it is kept.
+impl<B: Backend> ChunkSink for ChunkStore<B> {
+    fn accept(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError> {
+        self.put(hash, chunk).map(|_| ())
+    }
+}
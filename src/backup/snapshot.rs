@@ -0,0 +1,257 @@
+use std::{io, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::backup::{manifest::Manifest, store::Backend, timing};
+
+const SNAPSHOTS_PREFIX: &str = "snapshots/";
+
+/// A committed backup: what was backed up, when, and the resulting manifest.
+///
+/// Stored as `snapshots/<id>.json`, where the id is the BLAKE3 hash of the first
+/// serialized form. Snapshots are only written once the chunks they reference are
+/// in the store, so a listed snapshot is always complete.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+    /// Paths given to the backup, as typed: absolute, or relative to `cwd`.
+    pub paths: Vec<String>,
+    pub manifest: Manifest,
+    /// Names given to the snapshot by the user, unique and sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Machine and user that took the snapshot; empty for snapshots from before they
+    /// were recorded.
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub username: String,
+    /// Working directory of the backup, which relative paths are relative to; empty
+    /// if unknown or for snapshots from before it was recorded.
+    #[serde(default)]
+    pub cwd: String,
+    /// What the backup (or import) that took the snapshot did; `None` for snapshots
+    /// from before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<RunStats>,
+}
+
+/// Statistics of the backup run that took a snapshot, kept with it.
+///
+/// Files are classified against the parent snapshot the run compared with: the
+/// latest one of the same paths from the same host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Files the parent snapshot does not have.
+    pub files_new: usize,
+    /// Files the parent snapshot has with different content.
+    pub files_changed: usize,
+    /// Files the parent snapshot has with the same content.
+    pub files_unchanged: usize,
+    /// Content bytes read.
+    pub bytes_read: u64,
+    /// Bytes written to the store after deduplication.
+    pub bytes_stored: u64,
+    /// Chunks of all files, repeats included, and how many were new to the store.
+    pub chunks: usize,
+    pub new_chunks: usize,
+    pub duration_ms: u64,
+}
+
+impl RunStats {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+
+    /// Bytes read per second, in MB/s.
+    pub fn throughput_mb_s(&self) -> f64 {
+        timing::throughput_mb_s(self.bytes_read, self.duration())
+    }
+}
+
+impl Snapshot {
+    /// Snapshot of `paths` taken now, by this user on this machine.
+    pub fn new(paths: Vec<String>, manifest: Manifest) -> Self {
+        let username = ["USER", "USERNAME"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok())
+            .unwrap_or_default();
+        Snapshot {
+            time: OffsetDateTime::now_utc(),
+            paths,
+            manifest,
+            tags: Vec::new(),
+            hostname: this_host(),
+            username,
+            cwd: std::env::current_dir()
+                .map(|cwd| cwd.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            stats: None,
+        }
+    }
+
+    /// Add a tag. Returns `false` if the snapshot already had it.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        match self.tags.binary_search_by(|t| t.as_str().cmp(tag)) {
+            Ok(_) => false,
+            Err(pos) => {
+                self.tags.insert(pos, tag.to_string());
+                true
+            }
+        }
+    }
+
+    /// Remove a tag. Returns `false` if the snapshot did not have it.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let len = self.tags.len();
+        self.tags.retain(|t| t != tag);
+        self.tags.len() != len
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Write a new snapshot to the repository and return its id.
+    pub fn save(&self, backend: &dyn Backend) -> io::Result<String> {
+        let bytes = serde_json::to_vec_pretty(self)?;
+        let id = blake3::hash(&bytes).to_hex().to_string();
+        backend.write(&snapshot_name(&id), &bytes)?;
+        Ok(id)
+    }
+
+    /// Overwrite the stored snapshot `id` with this one, e.g. after changing its tags.
+    ///
+    /// The id stays the same even though it no longer matches the content.
+    pub fn update(&self, backend: &dyn Backend, id: &str) -> io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(self)?;
+        backend.write(&snapshot_name(id), &bytes)
+    }
+
+    pub fn load(backend: &dyn Backend, id: &str) -> io::Result<Self> {
+        let bytes = backend.read(&snapshot_name(id))?;
+        serde_json::from_slice(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Delete snapshot `id`. Its chunks stay in the store until they are pruned.
+    pub fn remove(backend: &dyn Backend, id: &str) -> io::Result<()> {
+        backend.remove(&snapshot_name(id))
+    }
+
+    /// Ids of all snapshots in the repository, sorted.
+    pub fn list(backend: &dyn Backend) -> io::Result<Vec<String>> {
+        Ok(backend
+            .list(SNAPSHOTS_PREFIX)?
+            .iter()
+            .filter_map(|name| {
+                let id = name.strip_prefix(SNAPSHOTS_PREFIX)?.strip_suffix(".json")?;
+                Some(id.to_string())
+            })
+            .collect())
+    }
+
+    /// The snapshot a new backup of `paths` from `hostname` is compared with: the
+    /// latest one of exactly those paths from that host, with its id.
+    pub fn find_parent(
+        backend: &dyn Backend,
+        paths: &[String],
+        hostname: &str,
+    ) -> io::Result<Option<(String, Snapshot)>> {
+        let mut parent: Option<(String, Snapshot)> = None;
+        for id in Self::list(backend)? {
+            let snapshot = Self::load(backend, &id)?;
+            if snapshot.paths == paths
+                && snapshot.hostname == hostname
+                && parent
+                    .as_ref()
+                    .is_none_or(|(_, parent)| parent.time < snapshot.time)
+            {
+                parent = Some((id, snapshot));
+            }
+        }
+        Ok(parent)
+    }
+
+    /// Full id of the one snapshot whose id starts with `prefix`.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if there is none and
+    /// [`io::ErrorKind::InvalidInput`] if the prefix is ambiguous.
+    pub fn resolve_id(backend: &dyn Backend, prefix: &str) -> io::Result<String> {
+        let mut matches = Self::list(backend)?
+            .into_iter()
+            .filter(|id| id.starts_with(prefix));
+
+        match (matches.next(), matches.next()) {
+            (Some(id), None) => Ok(id),
+            (None, _) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no snapshot {}", prefix),
+            )),
+            (Some(_), Some(_)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("snapshot id {} is ambiguous", prefix),
+            )),
+        }
+    }
+}
+
+/// A snapshot as `rbckp list-snapshots --json` shows it: everything but the manifest.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+    pub paths: Vec<String>,
+    pub tags: Vec<String>,
+    pub hostname: String,
+    pub username: String,
+    /// Entries in the manifest.
+    pub files: usize,
+    pub stats: Option<RunStats>,
+    /// Of the run, from `stats`.
+    pub throughput_mb_s: Option<f64>,
+}
+
+impl SnapshotInfo {
+    pub fn new(id: &str, snapshot: &Snapshot) -> Self {
+        SnapshotInfo {
+            id: id.to_string(),
+            time: snapshot.time,
+            paths: snapshot.paths.clone(),
+            tags: snapshot.tags.clone(),
+            hostname: snapshot.hostname.clone(),
+            username: snapshot.username.clone(),
+            files: snapshot.manifest.entries.len(),
+            stats: snapshot.stats,
+            throughput_mb_s: snapshot.stats.map(|stats| stats.throughput_mb_s()),
+        }
+    }
+}
+
+/// Name of this machine, as recorded in the snapshots taken on it.
+pub fn this_host() -> String {
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+/// Which snapshots a command considers, from `--tag` and `--host` options.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotFilter {
+    /// Snapshots must have all of these tags.
+    pub tags: Vec<String>,
+    /// Snapshots must be from one of these hosts; empty means any host.
+    pub hosts: Vec<String>,
+}
+
+impl SnapshotFilter {
+    pub fn matches(&self, snapshot: &Snapshot) -> bool {
+        self.tags.iter().all(|tag| snapshot.has_tag(tag))
+            && (self.hosts.is_empty() || self.hosts.contains(&snapshot.hostname))
+    }
+}
+
+fn snapshot_name(id: &str) -> String {
+    format!("{}{}.json", SNAPSHOTS_PREFIX, id)
+}
@@ -0,0 +1,63 @@
+use crate::backup::cdc_chunker::ChunkingStrategy;
+
+/// Observed chunk size statistics, to check that the chunk parameters behave as intended.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct ChunkSizeSummary {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    /// Middle size; the mean of the two middle sizes for an even chunk count.
+    pub median: f64,
+}
+
+impl ChunkSizeSummary {
+    /// Summarize a list of chunk sizes. Returns `None` when there are no chunks.
+    pub fn from_sizes(sizes: &[usize]) -> Option<Self> {
+        if sizes.is_empty() {
+            return None;
+        }
+
+        let mut sorted = sizes.to_vec();
+        sorted.sort_unstable();
+
+        let count = sorted.len();
+        let total: usize = sorted.iter().sum();
+        let median = if count % 2 == 1 {
+            sorted[count / 2] as f64
+        } else {
+            (sorted[count / 2 - 1] + sorted[count / 2]) as f64 / 2.0
+        };
+
+        Some(ChunkSizeSummary {
+            min: sorted[0],
+            max: sorted[count - 1],
+            mean: total as f64 / count as f64,
+            median,
+        })
+    }
+}
+
+/// What chunking the `-F` target produced, printed by `rbckp -F <file> --json`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct RunSummary {
+    /// The target file, or `<stdin>`.
+    pub file: String,
+    pub total_bytes: usize,
+    pub chunks: usize,
+    /// Chunks with distinct ids.
+    pub unique_chunks: usize,
+    /// Total bytes over the bytes of the unique chunks; 1 when nothing repeats.
+    pub dedup_ratio: f64,
+    pub strategy: ChunkingStrategy,
+    pub params: RunParams,
+    /// `None` when there are no chunks.
+    pub chunk_sizes: Option<ChunkSizeSummary>,
+}
+
+/// The chunk sizes a [`RunSummary`] was cut with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct RunParams {
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
@@ -0,0 +1,68 @@
+//! Uploading many chunks at once to an [`AsyncChunkStore`] (`async` feature).
+
+use std::sync::Arc;
+
+use tokio::task::JoinSet;
+
+use super::{
+    StoreError,
+    async_store::{AsyncChunkStore, join_error},
+};
+use crate::{backup::hash::ChunkId, config::Settings};
+
+/// Uploads chunks to a store with up to `max_concurrent` uploads in flight.
+///
+/// Must be used from within a tokio runtime.
+pub struct ConcurrentUploader<S> {
+    store: Arc<S>,
+    max_concurrent: usize,
+}
+
+impl<S: AsyncChunkStore> ConcurrentUploader<S> {
+    /// At most `max_concurrent` uploads at a time; 0 counts as 1.
+    pub fn new(store: Arc<S>, max_concurrent: usize) -> Self {
+        ConcurrentUploader {
+            store,
+            max_concurrent: max_concurrent.max(1),
+        }
+    }
+
+    /// With [`Settings::max_concurrent_uploads`] uploads at a time.
+    pub fn from_settings(store: Arc<S>, settings: &Settings) -> Self {
+        Self::new(store, settings.max_concurrent_uploads())
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    /// The store chunks are uploaded to.
+    pub fn store(&self) -> &Arc<S> {
+        &self.store
+    }
+
+    /// Upload all `chunks` and return how many there were.
+    ///
+    /// Fewer chunks than the limit are simply all uploaded at once. The first failed
+    /// upload is returned and cancels the ones not finished yet; chunks uploaded
+    /// before that stay stored.
+    pub async fn upload(&self, chunks: Vec<(ChunkId, Vec<u8>)>) -> Result<usize, StoreError> {
+        let count = chunks.len();
+        let mut pending = chunks.into_iter();
+        let mut uploads = JoinSet::new();
+
+        for (id, data) in pending.by_ref().take(self.max_concurrent) {
+            let store = Arc::clone(&self.store);
+            uploads.spawn(async move { store.put(id, data).await });
+        }
+        while let Some(result) = uploads.join_next().await {
+            result.map_err(join_error)??;
+            // One finished, so there is room for the next.
+            if let Some((id, data)) = pending.next() {
+                let store = Arc::clone(&self.store);
+                uploads.spawn(async move { store.put(id, data).await });
+            }
+        }
+        Ok(count)
+    }
+}
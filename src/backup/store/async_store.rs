@@ -0,0 +1,53 @@
+//! Chunk storage with an async interface, for remote backends where waiting on one
+//! request at a time wastes most of the time (`async` feature).
+
+use std::{future::Future, io};
+
+use tokio::task::JoinError;
+
+use super::StoreError;
+use crate::backup::{hash::ChunkId, sink::FsSink};
+
+/// Chunk storage that can have many requests in flight, see
+/// [`ConcurrentUploader`](super::async_pool::ConcurrentUploader).
+///
+/// The counterpart of [`ChunkStore`](super::ChunkStore)'s `put`/`get`/`contains`,
+/// but chunks are stored one object each (see [`chunk_object_name`]) rather than in
+/// packs: a pack is written by one writer in sequence, which is what this avoids.
+pub trait AsyncChunkStore: Send + Sync + 'static {
+    /// Store `data` as chunk `id`, replacing any chunk stored under it.
+    fn put(
+        &self,
+        id: ChunkId,
+        data: Vec<u8>,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send;
+
+    /// Fails with [`StoreError::ChunkNotFound`] if chunk `id` is not stored.
+    fn get(&self, id: ChunkId) -> impl Future<Output = Result<Vec<u8>, StoreError>> + Send;
+
+    fn has(&self, id: ChunkId) -> impl Future<Output = Result<bool, StoreError>> + Send;
+
+    /// Removing a chunk that is not stored is not an error.
+    fn delete(&self, id: ChunkId) -> impl Future<Output = Result<(), StoreError>> + Send;
+}
+
+/// Object name of chunk `id` in an [`AsyncChunkStore`]: `chunks/ab/abcdef...`, the
+/// layout of an [`FsSink`] below `chunks/`.
+pub fn chunk_object_name(id: &ChunkId) -> String {
+    format!("chunks/{}", FsSink::chunk_name(&id.to_string()))
+}
+
+/// Run a blocking backend call on tokio's blocking thread pool, which is how backends
+/// with a blocking client take part in an [`AsyncChunkStore`].
+pub(crate) async fn blocking<T: Send + 'static>(
+    call: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> Result<T, StoreError> {
+    Ok(tokio::task::spawn_blocking(call)
+        .await
+        .map_err(join_error)??)
+}
+
+/// A task that panicked or was cancelled.
+pub(crate) fn join_error(err: JoinError) -> StoreError {
+    StoreError::Io(io::Error::other(format!("store task failed: {}", err)))
+}
@@ -0,0 +1,533 @@
+//! Repository in a Backblaze B2 bucket, through B2's native API.
+//!
+//! Bucket and application key come from the `[backend.b2]` settings section. Object
+//! names map to file names below the configured prefix. The native API is used
+//! rather than B2's S3-compatible one: it needs no region setup and lets uploads be
+//! checked against a SHA-1 of their content.
+
+use std::{io, sync::Mutex, thread, time::Duration};
+
+use attohttpc::{ErrorKind, Response};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+
+use super::{
+    backend::Backend,
+    retry::{self, RetryPolicy},
+};
+use crate::config::B2Settings;
+
+/// Where accounts are authorized unless the settings name another server.
+pub const DEFAULT_API_URL: &str = "https://api.backblazeb2.com";
+
+/// How often an upload is tried, each time with a fresh upload URL.
+///
+/// B2 hands out upload URLs for one pod at a time; when the pod is busy or the URL's
+/// token has expired, the upload fails and has to go to a new URL.
+pub const UPLOAD_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    initial_delay: Duration::from_millis(200),
+    max_delay: Duration::from_secs(10),
+};
+
+/// File names per `b2_list_file_names` call; the most a call costs the same for.
+const LIST_PAGE_SIZE: u32 = 1000;
+
+/// B2 file name of the object `name` below `prefix`.
+pub fn file_name(prefix: &str, name: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Settings for `b2://bucket/prefix`, with the application key taken from `settings`
+/// (if any). A bare `b2://` uses bucket and prefix from `settings` as well.
+pub fn settings_for_url(url: &str, settings: Option<&B2Settings>) -> io::Result<B2Settings> {
+    let rest = url.strip_prefix("b2://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not a b2:// URL: {}", url),
+        )
+    })?;
+
+    let mut settings = settings.cloned().unwrap_or_default();
+    if !rest.is_empty() {
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket != settings.bucket_name {
+            // The configured id belongs to another bucket.
+            settings.bucket_id.clear();
+        }
+        settings.bucket_name = bucket.to_string();
+        settings.prefix = prefix.to_string();
+    }
+
+    if settings.bucket_name.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no bucket in {} or the [backend.b2] settings", url),
+        ));
+    }
+    Ok(settings)
+}
+
+/// `name` as it goes into a B2 URL or `X-Bz-File-Name` header: percent-encoded UTF-8,
+/// with `/` kept as it is.
+pub fn encode_file_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Result of `b2_authorize_account`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Account {
+    account_id: String,
+    authorization_token: String,
+    api_url: String,
+    download_url: String,
+}
+
+/// Result of `b2_get_upload_url`: where one upload at a time can go.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadUrl {
+    upload_url: String,
+    authorization_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileNames {
+    files: Vec<FileVersion>,
+    next_file_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileVersionPage {
+    files: Vec<FileVersion>,
+    next_file_name: Option<String>,
+    next_file_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileVersion {
+    file_name: String,
+    file_id: String,
+}
+
+#[derive(Deserialize)]
+struct Buckets {
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bucket {
+    bucket_id: String,
+}
+
+/// Error body of a failed B2 call.
+#[derive(Debug, Default, Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+/// Why a B2 call failed.
+enum Failure {
+    /// B2 answered with an error status.
+    Status { status: u16, error: ApiError },
+    /// The request did not get an answer.
+    Io(io::Error),
+}
+
+impl Failure {
+    fn status(&self) -> Option<u16> {
+        match self {
+            Failure::Status { status, .. } => Some(*status),
+            Failure::Io(_) => None,
+        }
+    }
+
+    /// Whether an upload that failed like this should be tried again at a new upload
+    /// URL: an expired URL token, a busy pod or a dropped connection.
+    fn needs_new_upload_url(&self) -> bool {
+        match self {
+            Failure::Status { status, .. } => matches!(status, 401 | 408 | 429 | 500..=599),
+            Failure::Io(_) => true,
+        }
+    }
+
+    fn into_io(self, what: &str) -> io::Error {
+        match self {
+            Failure::Status { status, error } => {
+                let kind = retry::http_status_kind(status);
+                let message = if error.code.is_empty() {
+                    format!("HTTP {} for {}", status, what)
+                } else {
+                    format!(
+                        "HTTP {} for {}: {} ({})",
+                        status, what, error.message, error.code
+                    )
+                };
+                io::Error::new(kind, message)
+            }
+            Failure::Io(err) => io::Error::new(err.kind(), format!("{}: {}", what, err)),
+        }
+    }
+}
+
+impl From<attohttpc::Error> for Failure {
+    fn from(err: attohttpc::Error) -> Self {
+        match err.into_kind() {
+            // Keeps the kind, so a dropped connection counts as transient.
+            ErrorKind::Io(err) => Failure::Io(err),
+            kind => Failure::Io(io::Error::other(attohttpc::Error::from(kind))),
+        }
+    }
+}
+
+/// `response`, if it has a success status.
+fn check(response: Response) -> Result<Response, Failure> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    // HEAD responses and some proxies have no error body.
+    let error = response.json().unwrap_or_default();
+    Err(Failure::Status {
+        status: status.as_u16(),
+        error,
+    })
+}
+
+/// Backend storing every object as a B2 file.
+///
+/// Authorization tokens expire after a day, upload URLs whenever B2 sees fit: a call
+/// rejected as unauthorized is repeated once with a new authorization, a failed
+/// upload goes to a new upload URL. B2 keeps old versions of a file that is written
+/// again; [`remove`](Backend::remove) deletes all of them.
+pub struct B2Backend {
+    settings: B2Settings,
+    bucket_id: String,
+    prefix: String,
+    account: Mutex<Account>,
+    // Upload URLs not in use; each takes one upload at a time.
+    upload_urls: Mutex<Vec<UploadUrl>>,
+}
+
+impl B2Backend {
+    pub fn new(settings: &B2Settings) -> io::Result<Self> {
+        let account = authorize(settings).map_err(|err| err.into_io("b2_authorize_account"))?;
+        let mut backend = B2Backend {
+            settings: settings.clone(),
+            bucket_id: settings.bucket_id.clone(),
+            prefix: settings.prefix.trim_matches('/').to_string(),
+            account: Mutex::new(account),
+            upload_urls: Mutex::new(Vec::new()),
+        };
+        if backend.bucket_id.is_empty() {
+            backend.bucket_id = backend.find_bucket_id()?;
+        }
+        Ok(backend)
+    }
+
+    fn name(&self, name: &str) -> String {
+        file_name(&self.prefix, name)
+    }
+
+    fn find_bucket_id(&self) -> io::Result<String> {
+        let name = &self.settings.bucket_name;
+        let account_id = self.account.lock().unwrap().account_id.clone();
+        let buckets: Buckets = self
+            .api(
+                "b2_list_buckets",
+                &json!({ "accountId": account_id, "bucketName": name }),
+            )
+            .map_err(|err| err.into_io(name))?;
+        buckets
+            .buckets
+            .into_iter()
+            .next()
+            .map(|bucket| bucket.bucket_id)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no B2 bucket {}", name))
+            })
+    }
+
+    /// Run `call` with the current authorization, and once more with a new one if
+    /// B2 rejects it.
+    fn authorized<T>(
+        &self,
+        mut call: impl FnMut(&Account) -> Result<T, Failure>,
+    ) -> Result<T, Failure> {
+        let account = self.account.lock().unwrap().clone();
+        match call(&account) {
+            Err(failure) if failure.status() == Some(401) => {
+                log::debug!("B2 authorization rejected, authorizing again");
+                call(&self.reauthorize(&account)?)
+            }
+            result => result,
+        }
+    }
+
+    /// A new authorization in place of `stale`, unless another call already got one.
+    fn reauthorize(&self, stale: &Account) -> Result<Account, Failure> {
+        let mut account = self.account.lock().unwrap();
+        if account.authorization_token == stale.authorization_token {
+            *account = authorize(&self.settings)?;
+        }
+        Ok(account.clone())
+    }
+
+    /// Call the B2 API function `function`.
+    fn api<T: DeserializeOwned>(
+        &self,
+        function: &str,
+        body: &serde_json::Value,
+    ) -> Result<T, Failure> {
+        self.authorized(|account| {
+            let response = attohttpc::post(format!("{}/b2api/v2/{}", account.api_url, function))
+                .header("Authorization", &account.authorization_token)
+                .json(body)?
+                .send()?;
+            Ok(check(response)?.json()?)
+        })
+    }
+
+    /// GET (or with `head`, HEAD) `file`, or the `range` of it.
+    fn download(
+        &self,
+        file: &str,
+        range: Option<(u64, u64)>,
+        head: bool,
+    ) -> Result<Response, Failure> {
+        self.authorized(|account| {
+            let url = format!(
+                "{}/file/{}/{}",
+                account.download_url,
+                self.settings.bucket_name,
+                encode_file_name(file)
+            );
+            let mut request = if head {
+                attohttpc::head(url)
+            } else {
+                attohttpc::get(url)
+            }
+            .header("Authorization", &account.authorization_token);
+            if let Some((offset, len)) = range {
+                request = request.header("Range", format!("bytes={}-{}", offset, offset + len - 1));
+            }
+            check(request.send()?)
+        })
+    }
+
+    fn upload_url(&self) -> Result<UploadUrl, Failure> {
+        if let Some(url) = self.upload_urls.lock().unwrap().pop() {
+            return Ok(url);
+        }
+        self.api("b2_get_upload_url", &json!({ "bucketId": self.bucket_id }))
+    }
+
+    fn upload(&self, url: &UploadUrl, file: &str, data: &[u8], sha1: &str) -> Result<(), Failure> {
+        let response = attohttpc::post(&url.upload_url)
+            .header("Authorization", &url.authorization_token)
+            .header("X-Bz-File-Name", encode_file_name(file))
+            .header("Content-Type", "application/octet-stream")
+            .header("X-Bz-Content-Sha1", sha1)
+            .bytes(data)
+            .send()?;
+        check(response)?;
+        Ok(())
+    }
+
+    /// Every version of `file`, newest first.
+    fn versions(&self, file: &str) -> Result<Vec<FileVersion>, Failure> {
+        let mut versions = Vec::new();
+        let mut start: Option<(String, String)> = None;
+        loop {
+            let mut body = json!({
+                "bucketId": self.bucket_id,
+                "prefix": file,
+                "startFileName": file,
+                "maxFileCount": LIST_PAGE_SIZE,
+            });
+            if let Some((name, id)) = &start {
+                body["startFileName"] = json!(name);
+                body["startFileId"] = json!(id);
+            }
+            let page: FileVersionPage = self.api("b2_list_file_versions", &body)?;
+            versions.extend(page.files.into_iter().filter(|f| f.file_name == file));
+            match (page.next_file_name, page.next_file_id) {
+                (Some(name), Some(id)) if name == file => start = Some((name, id)),
+                _ => break,
+            }
+        }
+        Ok(versions)
+    }
+}
+
+fn authorize(settings: &B2Settings) -> Result<Account, Failure> {
+    let api_url = settings.api_url.as_deref().unwrap_or(DEFAULT_API_URL);
+    let response = attohttpc::get(format!(
+        "{}/b2api/v2/b2_authorize_account",
+        api_url.trim_end_matches('/')
+    ))
+    .basic_auth(
+        &settings.application_key_id,
+        Some(&settings.application_key),
+    )
+    .send()?;
+    Ok(check(response)?.json()?)
+}
+
+impl Backend for B2Backend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let file = self.name(name);
+        self.download(&file, None, false)
+            .and_then(|response| Ok(response.bytes()?))
+            .map_err(|err| err.into_io(&file))
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        // B2 files only become visible once completely uploaded.
+        let file = self.name(name);
+        let sha1 = format!("{:x}", Sha1::digest(data));
+        let mut retry = 0;
+        loop {
+            let url = self.upload_url().map_err(|err| err.into_io(&file))?;
+            match self.upload(&url, &file, data, &sha1) {
+                Ok(()) => {
+                    self.upload_urls.lock().unwrap().push(url);
+                    return Ok(());
+                }
+                Err(failure)
+                    if failure.needs_new_upload_url() && retry + 1 < UPLOAD_RETRY.max_attempts =>
+                {
+                    // The failed URL is dropped; the next attempt gets a new one.
+                    let delay = UPLOAD_RETRY.delay(retry);
+                    log::warn!(
+                        "{}, retrying at a new upload URL in {:?}",
+                        failure.into_io(&file),
+                        delay
+                    );
+                    thread::sleep(delay);
+                    retry += 1;
+                }
+                Err(failure) => return Err(failure.into_io(&file)),
+            }
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let name_prefix = self.name("");
+        let prefix = self.name(prefix);
+        let mut names = Vec::new();
+        let mut start: Option<String> = None;
+        loop {
+            let mut body = json!({
+                "bucketId": self.bucket_id,
+                "prefix": prefix,
+                "maxFileCount": LIST_PAGE_SIZE,
+            });
+            if let Some(start) = &start {
+                body["startFileName"] = json!(start);
+            }
+            let page: FileNames = self
+                .api("b2_list_file_names", &body)
+                .map_err(|err| err.into_io(&prefix))?;
+            names.extend(page.files.into_iter().filter_map(|file| {
+                file.file_name
+                    .strip_prefix(&name_prefix)
+                    .map(str::to_string)
+            }));
+            match page.next_file_name {
+                Some(next) => start = Some(next),
+                None => break,
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let file = self.name(name);
+        let versions = self.versions(&file).map_err(|err| err.into_io(&file))?;
+        if versions.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no B2 file {}", file),
+            ));
+        }
+        for version in versions {
+            self.api::<serde_json::Value>(
+                "b2_delete_file_version",
+                &json!({ "fileName": version.file_name, "fileId": version.file_id }),
+            )
+            .map_err(|err| err.into_io(&file))?;
+        }
+        Ok(())
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        // HEAD instead of GET: pack files can be large.
+        let file = self.name(name);
+        match self.download(&file, None, true) {
+            Ok(_) => Ok(true),
+            Err(failure) if failure.status() == Some(404) => Ok(false),
+            Err(failure) => Err(failure.into_io(&file)),
+        }
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        let file = self.name(name);
+        let response = self
+            .download(&file, None, true)
+            .map_err(|err| err.into_io(&file))?;
+        response
+            .headers()
+            .get("Content-Length")
+            .and_then(|len| len.to_str().ok()?.parse().ok())
+            .ok_or_else(|| io::Error::other(format!("no content length for {}", file)))
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+
+        let file = self.name(name);
+        let data = self
+            .download(&file, Some((offset, len)), false)
+            .and_then(|response| Ok(response.bytes()?))
+            .map_err(|err| err.into_io(&file))?;
+        if data.len() as u64 != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "range {}..{} is past the end of {}",
+                    offset,
+                    offset + len,
+                    file
+                ),
+            ));
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use super::retry::RetryPolicy;
+
+/// Suffix of the temporary files [`write_atomic`] writes objects to before renaming
+/// them into place. Listings of a [`LocalFsBackend`] leave them out.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// Raw object storage underneath a repository.
+///
+/// Objects are addressed by opaque `/`-separated names such as `packs/0000000000000000.pack`;
+/// a backend only stores bytes under names and does not need to understand the repository
+/// layout. Writes replace whole objects.
+pub trait Backend: Send + Sync {
+    /// Read a whole object. Missing objects fail with [`io::ErrorKind::NotFound`].
+    fn read(&self, name: &str) -> io::Result<Vec<u8>>;
+
+    /// Create or replace an object. Readers never observe a partially written object.
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Names of all objects starting with `prefix`, sorted.
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    fn remove(&self, name: &str) -> io::Result<()>;
+
+    fn exists(&self, name: &str) -> io::Result<bool>;
+
+    /// Size of an object in bytes.
+    fn size(&self, name: &str) -> io::Result<u64> {
+        Ok(self.read(name)?.len() as u64)
+    }
+
+    /// Read `len` bytes at `offset` of an object.
+    ///
+    /// The default reads the whole object; backends that can do better should.
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let data = self.read(name)?;
+        let start = usize::try_from(offset).unwrap_or(usize::MAX);
+        let end = start.saturating_add(len as usize);
+        data.get(start..end).map(<[u8]>::to_vec).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("range {}..{} is past the end of {}", start, end, name),
+            )
+        })
+    }
+
+    /// The directory the objects are kept in, for a backend on the local filesystem.
+    /// Repository locks (see [`RepoLocks`](super::lock::RepoLocks)) live there; other
+    /// backends return `None` and cannot be locked.
+    fn local_root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Lets the backend be chosen at runtime, e.g. `ChunkStore<Box<dyn Backend>>`.
+impl<B: Backend + ?Sized> Backend for Box<B> {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        (**self).read(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        (**self).write(name, data)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        (**self).list(prefix)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        (**self).remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        (**self).exists(name)
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        (**self).size(name)
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        (**self).read_range(name, offset, len)
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        (**self).local_root()
+    }
+}
+
+/// Backend storing every object as a file below a root directory.
+///
+/// Objects are written to a temporary file first and renamed into place, so readers
+/// never see a partially written object, even after a crash. Writes that fail with a
+/// [transient](super::retry::is_transient) error are retried as set up with
+/// [`with_retry`](Self::with_retry); by default they are not.
+pub struct LocalFsBackend {
+    root: PathBuf,
+    retry: RetryPolicy,
+}
+
+impl LocalFsBackend {
+    pub fn new(root: &Path) -> Self {
+        LocalFsBackend {
+            root: root.to_path_buf(),
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Retry failed writes according to `retry`.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+}
+
+impl Backend for LocalFsBackend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(name))
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.retry.run(|| write_atomic(&path, data))
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        // Only the directory holding the prefix (and below) can contain matches.
+        let dir = match prefix.rfind('/') {
+            Some(slash) => &prefix[..slash],
+            None => "",
+        };
+
+        let mut names = Vec::new();
+        match list_files(&self.root, &self.path(dir), &mut names) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+        names.retain(|name| name.starts_with(prefix) && !name.ends_with(TEMP_SUFFIX));
+        names.sort();
+        Ok(names)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.path(name))
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.path(name).try_exists()
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        Ok(fs::metadata(self.path(name))?.len())
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut file = File::open(self.path(name))?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut data = vec![0u8; len as usize];
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// Collect the names (relative to `root`, `/`-separated) of all files below `dir`.
+fn list_files(root: &Path, dir: &Path, names: &mut Vec<String>) -> io::Result<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+
+        if dir_entry.file_type()?.is_dir() {
+            list_files(root, &path, names)?;
+            continue;
+        }
+
+        // Names are UTF-8 by construction; anything else was not written by us.
+        let Some(name) = path.strip_prefix(root).ok().and_then(Path::to_str) else {
+            continue;
+        };
+        names.push(name.replace(std::path::MAIN_SEPARATOR, "/"));
+    }
+    Ok(())
+}
+
+/// Write `data` to `path` so that readers see either the old or the new content,
+/// never a partially written file.
+///
+/// The data goes to a temporary file next to `path`, named after it with the process
+/// id and [`TEMP_SUFFIX`] appended, which is synced and then renamed over `path`.
+/// The directory is synced after the rename too, so the rename survives a crash.
+/// Concurrent writers each use their own temporary file. If reading `data` or
+/// writing fails, the temporary file is removed again and `path` is left alone.
+pub fn write_atomic(path: &Path, mut data: impl Read) -> io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(
+        ".{}-{}{}",
+        process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed),
+        TEMP_SUFFIX
+    ));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let written = File::create(&tmp_path).and_then(|mut file| {
+        io::copy(&mut data, &mut file)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written?;
+    sync_parent_dir(path)
+}
+
+/// Flush the directory entry of `path` to disk, e.g. after renaming a file into place.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+/// Directories cannot be opened (and need not be synced) on other platforms.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Backend keeping all objects in memory, e.g. to test store logic without a disk.
+#[derive(Default)]
+pub struct InMemoryBackend {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn objects(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<u8>>> {
+        // A panic while holding the lock cannot leave a half-updated map behind.
+        self.objects
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Backend for InMemoryBackend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.objects()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| not_found(name))
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.objects().insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        Ok(self
+            .objects()
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.objects()
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_found(name))
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        Ok(self.objects().contains_key(name))
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        self.objects()
+            .get(name)
+            .map(|data| data.len() as u64)
+            .ok_or_else(|| not_found(name))
+    }
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no object named {}", name))
+}
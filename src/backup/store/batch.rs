@@ -0,0 +1,120 @@
+//! Buffering chunk uploads so they go to an [`AsyncChunkStore`] in parallel batches
+//! (`async` feature).
+
+use std::sync::Mutex;
+
+use super::{StoreError, async_pool::ConcurrentUploader, async_store::AsyncChunkStore};
+use crate::backup::hash::ChunkId;
+
+/// Chunks buffered before a batch is uploaded, if not configured otherwise.
+pub const DEFAULT_FLUSH_THRESHOLD: usize = 64;
+
+/// An [`AsyncChunkStore`] that holds back `put`s until `flush_threshold` chunks have
+/// been buffered, then uploads them together through a [`ConcurrentUploader`].
+///
+/// Chunks still in the buffer are read from it, so `get` and `has` see every `put`.
+/// Whatever is left over at the end needs an explicit [`flush`](Self::flush).
+///
+/// Must be used from within a tokio runtime.
+pub struct BatchingStore<S> {
+    // The wrapped store, with the uploader that puts a batch into it.
+    inner: ConcurrentUploader<S>,
+    buffer: Mutex<Vec<(ChunkId, Vec<u8>)>>,
+    flush_threshold: usize,
+}
+
+impl<S: AsyncChunkStore> BatchingStore<S> {
+    /// Batches of `flush_threshold` chunks (0 counts as 1), uploaded by `uploader`.
+    pub fn new(uploader: ConcurrentUploader<S>, flush_threshold: usize) -> Self {
+        BatchingStore {
+            inner: uploader,
+            buffer: Mutex::new(Vec::new()),
+            flush_threshold: flush_threshold.max(1),
+        }
+    }
+
+    pub fn flush_threshold(&self) -> usize {
+        self.flush_threshold
+    }
+
+    /// Number of chunks waiting for the next batch.
+    pub fn buffered(&self) -> usize {
+        self.buffer.lock().unwrap().len()
+    }
+
+    /// Upload every buffered chunk and return how many there were.
+    ///
+    /// The buffer is emptied before uploading, so if an upload fails the chunks of
+    /// the batch that did not make it are gone from the buffer too, and `has` tells
+    /// which of them have to be put again.
+    pub async fn flush(&self) -> Result<usize, StoreError> {
+        let batch = std::mem::take(&mut *self.buffer.lock().unwrap());
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        self.inner.upload(batch).await
+    }
+
+    fn buffered_chunk(&self, id: ChunkId) -> Option<Vec<u8>> {
+        let buffer = self.buffer.lock().unwrap();
+        buffer
+            .iter()
+            .find(|(buffered, _)| *buffered == id)
+            .map(|(_, data)| data.clone())
+    }
+}
+
+impl<S: AsyncChunkStore> AsyncChunkStore for BatchingStore<S> {
+    /// Buffer the chunk, and upload the whole buffer once it is full.
+    async fn put(&self, id: ChunkId, data: Vec<u8>) -> Result<(), StoreError> {
+        let batch = {
+            let mut buffer = self.buffer.lock().unwrap();
+            match buffer.iter_mut().find(|(buffered, _)| *buffered == id) {
+                Some(chunk) => chunk.1 = data,
+                None => buffer.push((id, data)),
+            }
+            if buffer.len() < self.flush_threshold {
+                return Ok(());
+            }
+            std::mem::take(&mut *buffer)
+        };
+        self.inner.upload(batch).await.map(|_| ())
+    }
+
+    async fn get(&self, id: ChunkId) -> Result<Vec<u8>, StoreError> {
+        match self.buffered_chunk(id) {
+            Some(data) => Ok(data),
+            None => self.inner.store().get(id).await,
+        }
+    }
+
+    async fn has(&self, id: ChunkId) -> Result<bool, StoreError> {
+        let buffered = self
+            .buffer
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(buffered, _)| *buffered == id);
+        if buffered {
+            return Ok(true);
+        }
+        self.inner.store().has(id).await
+    }
+
+    async fn delete(&self, id: ChunkId) -> Result<(), StoreError> {
+        self.buffer
+            .lock()
+            .unwrap()
+            .retain(|(buffered, _)| *buffered != id);
+        self.inner.store().delete(id).await
+    }
+}
+
+impl<S> Drop for BatchingStore<S> {
+    fn drop(&mut self) {
+        let left = self.buffer.get_mut().map_or(0, |buffer| buffer.len());
+        if left > 0 {
+            log::warn!("{} buffered chunks were never uploaded", left);
+        }
+    }
+}
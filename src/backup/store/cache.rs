@@ -0,0 +1,242 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use lru::LruCache;
+
+use super::{
+    backend::{Backend, LocalFsBackend},
+    index::ChunkIndex,
+};
+use crate::backup::hash::ChunkId;
+
+const INDEX_NAME: &str = "index.json";
+const GENERATION_NAME: &str = "generation";
+
+/// Local copy of a remote repository's chunk index.
+///
+/// Opening a remote store otherwise means downloading the index and reading the footer
+/// of every pack. The cache is tagged with the repository generation it mirrors; when
+/// another client has changed the repository since, the generations differ and the
+/// cache is ignored (and later overwritten), so a stale cache never hides a missing chunk.
+pub struct IndexCache {
+    dir: LocalFsBackend,
+}
+
+impl IndexCache {
+    /// Cache kept in `dir`, which is created on first save.
+    pub fn new(dir: &Path) -> Self {
+        IndexCache {
+            dir: LocalFsBackend::new(dir),
+        }
+    }
+
+    /// Default directory holding the caches of all repositories:
+    /// `$XDG_CACHE_HOME/rbckp`, falling back to `~/.cache/rbckp`.
+    pub fn default_root() -> Option<PathBuf> {
+        let cache_home = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+        Some(cache_home.join("rbckp"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.root()
+    }
+
+    /// The cached index, if it mirrors exactly `generation` of the repository.
+    pub fn load(&self, generation: u64) -> Option<ChunkIndex> {
+        let cached_generation = self.dir.read(GENERATION_NAME).ok()?;
+        if parse_generation(&cached_generation) != Some(generation) {
+            return None;
+        }
+
+        ChunkIndex::load(&self.dir, INDEX_NAME)
+            .inspect_err(|err| log::warn!("ignoring unreadable index cache: {}", err))
+            .ok()
+    }
+
+    /// Replace the cache with `index` as of `generation`.
+    pub fn save(&self, generation: u64, index: &ChunkIndex) -> io::Result<()> {
+        // Invalidate first: a crash between the two writes must not leave the new
+        // generation next to the old index.
+        match self.dir.remove(GENERATION_NAME) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        index.save(&self.dir, INDEX_NAME)?;
+        self.dir
+            .write(GENERATION_NAME, generation.to_string().as_bytes())
+    }
+}
+
+/// Local copies of chunks read from (or written to) a remote repository, so that
+/// reading them again does not take a round trip.
+///
+/// Every chunk is a file named by its id, below a subdirectory named by the first two
+/// characters of it. When the files add up to more than the maximum size, the least
+/// recently used ones are deleted. Use is tracked in memory; chunks cached by an
+/// earlier run count as used when they were cached.
+pub struct ChunkCache {
+    dir: PathBuf,
+    max_size: u64,
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    /// Size of every cached chunk, least recently used first.
+    chunks: LruCache<ChunkId, u64>,
+    size: u64,
+}
+
+impl ChunkCache {
+    /// Cache in `dir` holding at most `max_size` bytes of chunks, with the chunks
+    /// already there from earlier runs.
+    pub fn open(dir: &Path, max_size: u64) -> io::Result<Self> {
+        let mut cached: Vec<(SystemTime, ChunkId, u64)> = Vec::new();
+        let names = match LocalFsBackend::new(dir).list("") {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            result => result?,
+        };
+        for name in names {
+            // Anything else, such as a file a crash left half written, is not ours.
+            let Some(id) = name
+                .rsplit('/')
+                .next()
+                .and_then(|hash| hash.parse::<ChunkId>().ok())
+            else {
+                continue;
+            };
+            let metadata = fs::metadata(dir.join(&name))?;
+            cached.push((metadata.modified()?, id, metadata.len()));
+        }
+        cached.sort();
+
+        let cache = ChunkCache {
+            dir: dir.to_path_buf(),
+            max_size,
+            state: Mutex::new(CacheState {
+                chunks: LruCache::unbounded(),
+                size: 0,
+            }),
+        };
+        let mut state = cache.state();
+        for (_, id, len) in cached {
+            state.chunks.put(id, len);
+            state.size += len;
+        }
+        cache.evict(&mut state);
+        drop(state);
+        Ok(cache)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Total size of the cached chunks.
+    pub fn size(&self) -> u64 {
+        self.state().size
+    }
+
+    /// Number of cached chunks.
+    pub fn len(&self) -> usize {
+        self.state().chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Chunk `hash`, if it is cached; it becomes the most recently used one.
+    pub fn get(&self, hash: &str) -> Option<Vec<u8>> {
+        let id: ChunkId = hash.parse().ok()?;
+        let mut state = self.state();
+        state.chunks.get(&id)?;
+        match fs::read(self.path(hash)) {
+            Ok(chunk) => Some(chunk),
+            Err(err) => {
+                log::warn!("dropping chunk {} from the cache: {}", hash, err);
+                if let Some(len) = state.chunks.pop(&id) {
+                    state.size -= len;
+                }
+                None
+            }
+        }
+    }
+
+    /// Drop chunk `hash` from the cache, e.g. because it turned out to be damaged.
+    pub fn remove(&self, hash: &str) {
+        let Ok(id) = hash.parse::<ChunkId>() else {
+            return;
+        };
+        let mut state = self.state();
+        if let Some(len) = state.chunks.pop(&id) {
+            state.size -= len;
+            let _ = fs::remove_file(self.path(hash));
+        }
+    }
+
+    /// Cache `chunk` under `hash`, evicting the least recently used chunks to stay
+    /// within the maximum size. Chunks that are not identified by a [`ChunkId`] (zero
+    /// chunks) or larger than the whole cache are not cached. Failures to write are
+    /// only logged: the cache is an optimization.
+    pub fn insert(&self, hash: &str, chunk: &[u8]) {
+        let Ok(id) = hash.parse::<ChunkId>() else {
+            return;
+        };
+        let len = chunk.len() as u64;
+        if len > self.max_size {
+            return;
+        }
+        let mut state = self.state();
+        if state.chunks.get(&id).is_some() {
+            return;
+        }
+
+        let path = self.path(hash);
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, chunk));
+        if let Err(err) = written {
+            log::warn!("cannot cache chunk {}: {}", hash, err);
+            return;
+        }
+        state.chunks.put(id, len);
+        state.size += len;
+        self.evict(&mut state);
+    }
+
+    fn evict(&self, state: &mut CacheState) {
+        while state.size > self.max_size {
+            let Some((id, len)) = state.chunks.pop_lru() else {
+                break;
+            };
+            state.size -= len;
+            if let Err(err) = fs::remove_file(self.path(&id.to_string())) {
+                log::warn!("cannot evict chunk {} from the cache: {}", id, err);
+            }
+        }
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash.get(..2).unwrap_or(hash)).join(hash)
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Parse a stored generation counter.
+pub(super) fn parse_generation(bytes: &[u8]) -> Option<u64> {
+    std::str::from_utf8(bytes).ok()?.trim().parse().ok()
+}
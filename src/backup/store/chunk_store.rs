@@ -0,0 +1,893 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    StoreError,
+    backend::{Backend, LocalFsBackend},
+    cache::{self, ChunkCache, IndexCache},
+    index::{ChunkIndex, ChunkLocation},
+    lock::{DEFAULT_LOCK_WAIT, LockKind, RepoLock, RepoLocks},
+    pack::{self, PackEntry, PackReader, PackWriter},
+    repo_config::{MAX_FANOUT_DEPTH, REPO_CONFIG_NAME, RepoConfig},
+    upload::ConcurrentUploader,
+};
+use crate::backup::{
+    cdc_chunker,
+    hash::{self, ChunkId, HashAlgorithm, PrefixKeys},
+    index as chunks_idx,
+};
+
+/// Packs with at least this share of unreferenced bytes are rewritten by
+/// [`ChunkStore::prune`]; packs below it keep their dead chunks.
+pub const REPACK_THRESHOLD: f64 = 0.2;
+
+/// Default size at which an open pack is sealed and a new one is started.
+pub const DEFAULT_PACK_SIZE: u64 = 32 * 1024 * 1024;
+
+const PACKS_PREFIX: &str = "packs/";
+const INDEX_NAME: &str = "index.json";
+const GENERATION_NAME: &str = "generation";
+
+/// Content-addressed chunk store on top of a [`Backend`].
+///
+/// Chunks are not stored one object each (millions of tiny files kill most filesystems);
+/// they are appended to packs of about `pack_size` bytes instead:
+///
+/// ```text
+/// repo.json              repository config, written by `init`
+/// packs/<pack id>.pack   chunk data + footer, see `pack`
+/// index.json             chunk hash -> (pack id, offset, length)
+/// chunks.idx             chunk id -> every (pack file, offset, length), see `index`
+/// generation             counter bumped on every index change, see `IndexCache`
+/// ```
+///
+/// Whether a chunk is stored already is answered by `chunks.idx`
+/// ([`chunks_idx::ChunkIndex`]), where to read it by `index.json`. Both are only
+/// updated after a pack is written, and on open they are reconciled with the footers
+/// of the packs actually present. A pack left behind damaged by a crash (no valid
+/// footer) therefore never contributes chunks.
+///
+/// An open store holds a repository lock: shared for backups and restores, so several
+/// can run at once, or exclusive for pruning. Writers under a shared lock name their
+/// packs and commit the index under the short exclusive commit lock (see
+/// [`RepoLock::acquire_commit`]), merging in whatever other writers committed since, so
+/// concurrent backups neither overwrite each other's packs nor lose index entries.
+pub struct ChunkStore<B: Backend> {
+    backend: B,
+    config: RepoConfig,
+    pack_size: u64,
+    index: ChunkIndex,
+    chunks_idx: chunks_idx::ChunkIndex,
+    cache: Option<IndexCache>,
+    chunk_cache: Option<ChunkCache>,
+    // Pack currently being filled.
+    open_pack: Option<PackWriter>,
+    // Chunks in `open_pack` that are not in the index yet; their pack id is only
+    // known once the pack is finished.
+    pending: HashMap<String, ChunkLocation>,
+    // Full packs waiting to be written together, see `with_upload_batch`.
+    sealed: Vec<SealedPack>,
+    upload_batch: usize,
+    uploader: ConcurrentUploader,
+    // Lowest id the next pack may get; ids already taken are skipped.
+    next_pack_id: u64,
+    // Packs found missing or unreadable on open, whose chunks must not come back into
+    // the index when it is merged with the stored one.
+    dropped_packs: HashSet<u64>,
+    locks: RepoLocks,
+    // Held for as long as the store is open.
+    _lock: Option<RepoLock>,
+    lock_kind: LockKind,
+    check_lengths: bool,
+    // Chunk ids by their first hex digits, see `with_hash_prefix_len`.
+    prefix_keys: Option<PrefixKeys>,
+}
+
+/// Chunk store in a local directory.
+pub type LocalFsStore = ChunkStore<LocalFsBackend>;
+
+impl<B: Backend> ChunkStore<B> {
+    /// Create a new, empty repository in `backend`.
+    ///
+    /// Refuses to touch a location that already holds a repository.
+    pub fn init(backend: &B) -> Result<RepoConfig, StoreError> {
+        Self::init_with_fanout(backend, 0)
+    }
+
+    /// Like [`ChunkStore::init`], spreading packs over `fanout_depth` levels of
+    /// subdirectories (see [`RepoConfig::fanout_depth`]).
+    pub fn init_with_fanout(backend: &B, fanout_depth: u32) -> Result<RepoConfig, StoreError> {
+        let config = RepoConfig {
+            fanout_depth,
+            ..RepoConfig::new()
+        };
+        Self::init_with_config(backend, config)
+    }
+
+    /// Like [`ChunkStore::init`], with the repository-wide settings of `config` (such
+    /// as the fanout depth and hash algorithm) instead of the defaults. A keyed hash
+    /// algorithm without a key gets a new random one.
+    pub fn init_with_config(backend: &B, mut config: RepoConfig) -> Result<RepoConfig, StoreError> {
+        let fanout_depth = config.fanout_depth;
+        if fanout_depth > MAX_FANOUT_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "fanout depth {} is not between 0 and {}",
+                    fanout_depth, MAX_FANOUT_DEPTH
+                ),
+            )
+            .into());
+        }
+        if backend.exists(REPO_CONFIG_NAME)? {
+            return Err(StoreError::AlreadyInitialized);
+        }
+
+        if config.hash_algorithm.is_keyed() && config.hash_key.is_none() {
+            config.hash_key = Some(hash::random_key());
+        }
+        ChunkIndex::default().save(backend, INDEX_NAME)?;
+        chunks_idx::ChunkIndex::default().save(backend)?;
+
+        // Written last: a repository only counts as initialized once it is complete.
+        config.save(backend)?;
+
+        Ok(config)
+    }
+
+    /// Open the store kept in `backend`.
+    ///
+    /// Fails with [`StoreError::NotInitialized`] unless the repository was created
+    /// with [`ChunkStore::init`], so chunks never end up in a random directory, and with
+    /// [`StoreError::Locked`] if another process still holds a conflicting lock on it
+    /// after [`DEFAULT_LOCK_WAIT`].
+    pub fn open(backend: B, pack_size: u64, lock_kind: LockKind) -> Result<Self, StoreError> {
+        Self::open_inner(backend, pack_size, lock_kind, None)
+    }
+
+    /// Like [`ChunkStore::open`], but keeps a copy of the index in a per-repository
+    /// directory below `cache_root` (see [`IndexCache`]).
+    ///
+    /// Meant for remote backends: as long as nobody else changed the repository, opening
+    /// it then takes a few small requests instead of reading every pack footer.
+    pub fn open_cached(
+        backend: B,
+        pack_size: u64,
+        lock_kind: LockKind,
+        cache_root: &Path,
+    ) -> Result<Self, StoreError> {
+        Self::open_inner(backend, pack_size, lock_kind, Some(cache_root))
+    }
+
+    fn open_inner(
+        backend: B,
+        pack_size: u64,
+        lock_kind: LockKind,
+        cache_root: Option<&Path>,
+    ) -> Result<Self, StoreError> {
+        let config = RepoConfig::load(&backend)?;
+        if !config.chunker_compatible() {
+            log::warn!(
+                "repository was created with chunker format {}, this build cuts format {}: \
+                 new chunks will not deduplicate against stored ones",
+                config.chunker_version,
+                cdc_chunker::CHUNKER_FORMAT_VERSION
+            );
+        }
+        let locks = RepoLocks::of(&backend);
+        let lock = locks.acquire(lock_kind, DEFAULT_LOCK_WAIT)?;
+
+        let cache = cache_root.and_then(|root| {
+            if config.id.is_empty() {
+                log::warn!("repository has no id, its index is not cached");
+                return None;
+            }
+            Some(IndexCache::new(&root.join(&config.id)))
+        });
+
+        let generation = read_generation(&backend)?;
+        // Read before the packs are listed: every pack the index names was written
+        // before it, so it is listed unless it is really gone.
+        let (index, cached) = match cache.as_ref().and_then(|cache| cache.load(generation)) {
+            Some(index) => (index, true),
+            None => {
+                let index = ChunkIndex::load(&backend, INDEX_NAME).unwrap_or_else(|err| {
+                    log::warn!("ignoring unreadable index: {}", err);
+                    ChunkIndex::default()
+                });
+                (index, false)
+            }
+        };
+        let chunks_idx = chunks_idx::ChunkIndex::load(&backend).unwrap_or_else(|err| {
+            log::warn!("ignoring unreadable chunk index: {}", err);
+            chunks_idx::ChunkIndex::default()
+        });
+        let pack_ids = list_pack_ids(&backend)?;
+
+        let mut store = ChunkStore {
+            backend,
+            config,
+            pack_size,
+            index,
+            chunks_idx,
+            cache,
+            chunk_cache: None,
+            open_pack: None,
+            pending: HashMap::new(),
+            sealed: Vec::new(),
+            upload_batch: 1,
+            uploader: ConcurrentUploader::default(),
+            next_pack_id: pack_ids.last().map_or(0, |pack_id| pack_id.wrapping_add(1)),
+            dropped_packs: HashSet::new(),
+            locks,
+            _lock: lock,
+            lock_kind,
+            check_lengths: false,
+            prefix_keys: None,
+        };
+
+        if cached {
+            // Nobody changed the index since it was cached. Packs written by a crashed
+            // client are not in it, which only costs re-uploading their chunks.
+            // Repositories from before `chunks.idx` lack it; it is filled in memory.
+            let locations: Vec<_> = store
+                .index
+                .iter()
+                .filter_map(|(hash, location)| {
+                    let pack_name = store.pack_name(location.pack_id);
+                    Some((hash.parse().ok()?, pack_file_location(pack_name, location)?))
+                })
+                .collect();
+            for (id, location) in locations {
+                store.chunks_idx.insert(id, location);
+            }
+            return Ok(store);
+        }
+
+        let mut index_changed = false;
+        let mut chunks_idx_changed = false;
+
+        // Reconcile the indexes with the packs that are actually stored.
+        let mut valid_packs = HashSet::new();
+        for pack_id in pack_ids {
+            let pack_name = store.pack_name(pack_id);
+            match pack::read_footer(&store.backend, &pack_name) {
+                Ok(entries) => {
+                    valid_packs.insert(pack_id);
+                    for entry in entries {
+                        let location = chunk_location(pack_id, &entry);
+                        index_changed |= store.index.insert(&entry.hash, location);
+                        if let (Ok(id), Some(location)) = (
+                            entry.hash.parse(),
+                            pack_file_location(pack_name.clone(), &location),
+                        ) {
+                            chunks_idx_changed |= store.chunks_idx.insert(id, location);
+                        }
+                    }
+                }
+                Err(err) => log::warn!("ignoring pack {:016x}: {}", pack_id, err),
+            }
+        }
+        let valid_files: HashSet<PathBuf> = valid_packs
+            .iter()
+            .map(|&pack_id| store.pack_name(pack_id).into())
+            .collect();
+        chunks_idx_changed |= store
+            .chunks_idx
+            .retain_packs(|pack_file| valid_files.contains(pack_file))
+            > 0;
+        let mut dropped_packs = HashSet::new();
+        index_changed |= store.index.retain_packs(|pack_id| {
+            let valid = valid_packs.contains(&pack_id);
+            if !valid {
+                dropped_packs.insert(pack_id);
+            }
+            valid
+        }) > 0;
+        store.dropped_packs = dropped_packs;
+
+        if index_changed {
+            store.save_index()?;
+        } else {
+            store.update_cache(generation);
+            if chunks_idx_changed {
+                let _commit = store.locks.acquire_commit(DEFAULT_LOCK_WAIT)?;
+                store.chunks_idx.save(&store.backend)?;
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Regenerate the index of the store kept in `backend` from scratch, using only the packs.
+    ///
+    /// Packs whose footer cannot be read are reported and left out instead of aborting.
+    /// With `read_data`, every chunk payload is re-read and re-hashed as well, and chunks
+    /// whose content no longer matches their hash are left out too.
+    /// The new index replaces the old one atomically. Takes an exclusive lock, waiting
+    /// up to [`DEFAULT_LOCK_WAIT`] for running backups and restores.
+    pub fn rebuild_index(backend: &B, read_data: bool) -> Result<RebuildReport, StoreError> {
+        let config = RepoConfig::load(backend)?;
+        let fanout_depth = config.fanout_depth;
+        let hasher = config.hash_algorithm.keyed_hasher(config.hash_key);
+        let _lock = RepoLocks::of(backend).acquire(LockKind::Exclusive, DEFAULT_LOCK_WAIT)?;
+        let mut index = ChunkIndex::default();
+        let mut chunks_idx = chunks_idx::ChunkIndex::default();
+        let mut report = RebuildReport::default();
+
+        for pack_id in list_pack_ids(backend)? {
+            let name = pack_name(pack_id, fanout_depth);
+            let reader = match PackReader::open(backend, &name) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    report.problems.push(err);
+                    continue;
+                }
+            };
+            report.packs += 1;
+
+            for entry in reader.entries() {
+                if read_data {
+                    let intact = reader
+                        .get(&entry.hash)
+                        .map(|chunk| hasher.hash(&chunk).to_string() == entry.hash);
+                    if !matches!(intact, Ok(true)) {
+                        report.problems.push(StoreError::CorruptPack {
+                            name: name.clone(),
+                            reason: format!("chunk {} does not match its hash", entry.hash),
+                        });
+                        continue;
+                    }
+                }
+
+                let location = chunk_location(pack_id, entry);
+                if let (Ok(id), Some(location)) = (
+                    entry.hash.parse(),
+                    pack_file_location(name.clone(), &location),
+                ) {
+                    chunks_idx.insert(id, location);
+                }
+                if index.insert(&entry.hash, location) {
+                    report.chunks += 1;
+                }
+            }
+        }
+
+        bump_generation(backend)?;
+        index.save(backend, INDEX_NAME)?;
+        chunks_idx.save(backend)?;
+
+        Ok(report)
+    }
+
+    /// Make [`put`](Self::put) compare the length of a chunk that is already stored
+    /// with the stored length and fail with [`StoreError::HashCollision`] if they
+    /// differ, instead of taking the hash's word that the chunks are the same.
+    pub fn with_length_check(mut self, check: bool) -> Self {
+        self.check_lengths = check;
+        self
+    }
+
+    /// Look chunks up by the first `len` hex digits of their id, see
+    /// [`Settings::hash_prefix_len`](crate::config::Settings::hash_prefix_len):
+    /// [`put`](Self::put) fails with [`StoreError::PrefixCollision`] for a chunk whose
+    /// prefix belongs to another chunk, instead of taking it for that one.
+    /// [`CHUNK_ID_HEX_LEN`](hash::CHUNK_ID_HEX_LEN), the default, looks up whole ids.
+    ///
+    /// # Panics
+    ///
+    /// If `len` is 0 or longer than [`CHUNK_ID_HEX_LEN`](hash::CHUNK_ID_HEX_LEN).
+    pub fn with_hash_prefix_len(mut self, len: usize) -> Self {
+        self.prefix_keys = (len != hash::CHUNK_ID_HEX_LEN).then(|| {
+            let mut keys = PrefixKeys::new(len);
+            self.key_stored_chunks(&mut keys);
+            keys
+        });
+        self
+    }
+
+    /// Keep chunks read from and put into the store in `cache` too, and read them from
+    /// there when they are needed again: for remote repositories, where every read is a
+    /// round trip.
+    pub fn with_chunk_cache(mut self, cache: ChunkCache) -> Self {
+        self.chunk_cache = Some(cache);
+        self
+    }
+
+    /// Keep up to `packs` full packs in memory and write them together, committing the
+    /// index once for all of them instead of once per pack: fewer round trips to remote
+    /// backends, for up to `packs` times the pack size of memory. 1 (the default, and
+    /// what 0 means too) writes every pack as soon as it is full.
+    ///
+    /// Chunks of packs that are not written yet are found like stored ones.
+    pub fn with_upload_batch(mut self, packs: usize) -> Self {
+        self.upload_batch = packs.max(1);
+        self
+    }
+
+    /// Write the packs of an [upload batch](Self::with_upload_batch) on up to
+    /// `max_concurrent` threads at once (see [`ConcurrentUploader`]) instead of one
+    /// after the other. The index is only committed once all of them are written.
+    pub fn with_concurrent_uploads(mut self, max_concurrent: usize) -> Self {
+        self.uploader = ConcurrentUploader::new(max_concurrent);
+        self
+    }
+
+    pub fn chunk_cache(&self) -> Option<&ChunkCache> {
+        self.chunk_cache.as_ref()
+    }
+
+    /// With [`with_length_check`](Self::with_length_check) on, fail with
+    /// [`StoreError::HashCollision`] if chunk `hash` is stored with another length than
+    /// `len`. Chunks that are not stored, and any chunk with the check off, pass.
+    pub fn check_length(&self, hash: &str, len: u64) -> Result<(), StoreError> {
+        match self.chunk_len(hash) {
+            Some(stored_len) if self.check_lengths && stored_len != len => {
+                Err(StoreError::HashCollision {
+                    hash: hash.to_string(),
+                    stored_len,
+                    len,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn config(&self) -> &RepoConfig {
+        &self.config
+    }
+
+    /// How the chunks of this repository are identified; chunk ids passed to
+    /// [`ChunkStore::put`] must be computed with it.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.config.hash_algorithm
+    }
+
+    /// Secret key of a keyed [`ChunkStore::hash_algorithm`].
+    pub fn hash_key(&self) -> Option<[u8; 16]> {
+        self.config.hash_key
+    }
+
+    /// Whether a chunk with this hash is stored (or pending in a pack not written yet).
+    pub fn contains(&self, hash: &str) -> bool {
+        hash.parse::<ChunkId>()
+            .is_ok_and(|id| self.chunks_idx.has(&id))
+            || self.unwritten(hash).is_some()
+            || cdc_chunker::zero_chunk_len(hash).is_some()
+    }
+
+    /// [`ChunkStore::contains`] for each of `hashes`, in order: the check for a whole
+    /// file's chunk list at once.
+    ///
+    /// The index is loaded once when the store is opened and held in memory, so this
+    /// is one pass over it and never touches the backend, however many hashes there
+    /// are.
+    pub fn contains_many<S: AsRef<str>>(&self, hashes: &[S]) -> Vec<bool> {
+        hashes
+            .iter()
+            .map(|hash| self.contains(hash.as_ref()))
+            .collect()
+    }
+
+    /// Every chunk in the index with its location, in no particular order. Chunks
+    /// put since the last flush are not included.
+    pub fn chunks(&self) -> impl Iterator<Item = (String, &ChunkLocation)> {
+        self.index.iter()
+    }
+
+    /// Length of a stored chunk, without reading it.
+    pub fn chunk_len(&self, hash: &str) -> Option<u64> {
+        if let Some(len) = cdc_chunker::zero_chunk_len(hash) {
+            return Some(len as u64);
+        }
+        self.unwritten(hash)
+            .map(|(_, location)| location)
+            .or_else(|| self.index.get(hash))
+            .map(|location| location.length)
+    }
+
+    /// Store a chunk under its hash. Returns `false` if it was already stored.
+    ///
+    /// Zero chunks count as always stored and are never written. See
+    /// [`with_length_check`](Self::with_length_check) for chunks that are stored already.
+    /// Chunks of 4 GiB or more cannot be recorded in `chunks.idx` and are refused.
+    pub fn put(&mut self, hash: &str, chunk: &[u8]) -> Result<bool, StoreError> {
+        if let Some(keys) = &mut self.prefix_keys {
+            keys.key(hash).map_err(StoreError::PrefixCollision)?;
+        }
+        if self.contains(hash) {
+            self.check_length(hash, chunk.len() as u64)?;
+            return Ok(false);
+        }
+        if u32::try_from(chunk.len()).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("chunk {} of {} bytes is too large", hash, chunk.len()),
+            )
+            .into());
+        }
+
+        let writer = self.open_pack.get_or_insert_with(PackWriter::new);
+
+        let entry = writer.add(hash, chunk)?;
+        let location = chunk_location(0, &entry);
+        self.pending.insert(entry.hash, location);
+        if let Some(cache) = &self.chunk_cache {
+            cache.insert(hash, chunk);
+        }
+
+        if writer.len() >= self.pack_size {
+            self.seal_pack()?;
+            if self.sealed.len() >= self.upload_batch {
+                self.finish_packs()?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Read a chunk back by hash. Zero chunks come back as zeros.
+    ///
+    /// With a [chunk cache](Self::with_chunk_cache), a cached chunk of the length
+    /// recorded in the index is taken from the cache.
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>, StoreError> {
+        if let Some(len) = cdc_chunker::zero_chunk_len(hash) {
+            return Ok(vec![0; len]);
+        }
+        if let Some((data, location)) = self.unwritten(hash) {
+            let start = location.offset as usize;
+            let end = start + location.compressed_length as usize;
+            return Ok(data[start..end].to_vec());
+        }
+
+        let location = self
+            .index
+            .get(hash)
+            .ok_or_else(|| StoreError::ChunkNotFound(hash.to_string()))?;
+
+        if let Some(cache) = &self.chunk_cache
+            && let Some(chunk) = cache.get(hash)
+        {
+            if chunk.len() as u64 == location.length {
+                return Ok(chunk);
+            }
+            log::warn!("cached chunk {} has the wrong length, dropping it", hash);
+            cache.remove(hash);
+        }
+
+        let chunk = self.backend.read_range(
+            &self.pack_name(location.pack_id),
+            location.offset,
+            location.compressed_length,
+        )?;
+        if let Some(cache) = &self.chunk_cache {
+            cache.insert(hash, &chunk);
+        }
+        Ok(chunk)
+    }
+
+    /// Write out the open pack and any full ones held back (if any) and persist the
+    /// index.
+    ///
+    /// Chunks put since the last flush are lost if the process dies before this.
+    pub fn flush(&mut self) -> Result<(), StoreError> {
+        if self.open_pack.is_some() || !self.sealed.is_empty() {
+            self.finish_packs()?;
+        }
+        Ok(())
+    }
+
+    /// Delete every chunk not in `referenced`, e.g. after snapshots were forgotten.
+    ///
+    /// Packs without referenced chunks are deleted; packs with at least
+    /// [`REPACK_THRESHOLD`] unreferenced bytes have their live chunks copied to new
+    /// packs first. Old packs are only deleted once the new packs and the index are
+    /// written, so an interrupted prune loses nothing.
+    ///
+    /// # Panics
+    ///
+    /// Unless the store was opened with an exclusive lock: a concurrent backup could
+    /// reference chunks this considers unused.
+    pub fn prune(&mut self, referenced: &HashSet<String>) -> Result<PruneReport, StoreError> {
+        assert_eq!(
+            self.lock_kind,
+            LockKind::Exclusive,
+            "pruning needs an exclusive lock"
+        );
+        self.flush()?;
+
+        // Live and dead bytes per pack.
+        let mut packs: HashMap<u64, (u64, u64)> = HashMap::new();
+        for (hash, location) in self.index.iter() {
+            let (live, dead) = packs.entry(location.pack_id).or_default();
+            if referenced.contains(&hash) {
+                *live += location.compressed_length;
+            } else {
+                *dead += location.compressed_length;
+            }
+        }
+
+        let mut report = PruneReport::default();
+        let mut obsolete: HashSet<u64> = HashSet::new();
+        for (&pack_id, &(live, dead)) in &packs {
+            if live == 0 || dead as f64 >= (live + dead) as f64 * REPACK_THRESHOLD {
+                obsolete.insert(pack_id);
+            }
+        }
+
+        // Chunks only in these packs count as not stored, so live ones are put again.
+        let obsolete_files: HashSet<PathBuf> = obsolete
+            .iter()
+            .map(|&pack_id| self.pack_name(pack_id).into())
+            .collect();
+        self.chunks_idx
+            .retain_packs(|pack_file| !obsolete_files.contains(pack_file));
+
+        let moved: Vec<(String, ChunkLocation)> = self
+            .index
+            .iter()
+            .filter(|(_, location)| obsolete.contains(&location.pack_id))
+            .map(|(hash, location)| (hash, *location))
+            .collect();
+        for (hash, location) in moved {
+            if referenced.contains(&hash) {
+                let chunk = self.get(&hash)?;
+                self.index.remove(&hash);
+                self.put(&hash, &chunk)?;
+                report.repacked_chunks += 1;
+            } else {
+                self.index.remove(&hash);
+                report.removed_chunks += 1;
+                report.removed_bytes += location.compressed_length;
+            }
+        }
+
+        // New packs and the index first, then the old packs can go.
+        self.finish_packs()?;
+        let mut obsolete: Vec<u64> = obsolete.into_iter().collect();
+        obsolete.sort_unstable();
+        for pack_id in obsolete {
+            self.backend.remove(&self.pack_name(pack_id))?;
+            report.removed_packs += 1;
+        }
+        // The prefixes of deleted chunks are free again.
+        if let Some(len) = self.prefix_keys.as_ref().map(PrefixKeys::prefix_len) {
+            let mut keys = PrefixKeys::new(len);
+            self.key_stored_chunks(&mut keys);
+            self.prefix_keys = Some(keys);
+        }
+
+        Ok(report)
+    }
+
+    /// Object name of pack `pack_id` in this repository, see [`pack_name`].
+    pub fn pack_name(&self, pack_id: u64) -> String {
+        pack_name(pack_id, self.config.fanout_depth)
+    }
+
+    /// Give every stored chunk its key in `keys`. Of stored chunks that share a prefix,
+    /// one keeps it and putting the others fails.
+    fn key_stored_chunks(&self, keys: &mut PrefixKeys) {
+        for id in self.chunks_idx.ids() {
+            let _ = keys.key(&id.to_string());
+        }
+    }
+
+    /// The pack data and location of a chunk that was put but is not written yet.
+    fn unwritten(&self, hash: &str) -> Option<(&[u8], &ChunkLocation)> {
+        if let (Some(location), Some(writer)) = (self.pending.get(hash), &self.open_pack) {
+            return Some((writer.data(), location));
+        }
+        self.sealed
+            .iter()
+            .find_map(|pack| Some((&pack.data[..], pack.chunks.get(hash)?)))
+    }
+
+    /// Close the open pack (if any) and queue it to be written.
+    fn seal_pack(&mut self) -> Result<(), StoreError> {
+        if let Some(writer) = self.open_pack.take() {
+            let (data, _) = writer.finish()?;
+            let chunks = std::mem::take(&mut self.pending);
+            self.sealed.push(SealedPack { data, chunks });
+        }
+        Ok(())
+    }
+
+    /// Write the open pack and all sealed ones, then commit the index, under one commit
+    /// lock.
+    fn finish_packs(&mut self) -> Result<(), StoreError> {
+        self.seal_pack()?;
+        let _commit = self.locks.acquire_commit(DEFAULT_LOCK_WAIT)?;
+        let mut objects = Vec::with_capacity(self.sealed.len());
+        let mut packs = Vec::with_capacity(self.sealed.len());
+        for pack in std::mem::take(&mut self.sealed) {
+            // Another writer may have taken the id since the store was opened.
+            let mut pack_id = self.next_pack_id;
+            while self.backend.exists(&self.pack_name(pack_id))? {
+                pack_id = pack_id.wrapping_add(1);
+            }
+            self.next_pack_id = pack_id.wrapping_add(1);
+            objects.push((self.pack_name(pack_id), pack.data));
+            packs.push((pack_id, pack.chunks));
+        }
+        self.uploader.upload(&self.backend, &objects)?;
+
+        for (pack_id, chunks) in packs {
+            let pack_name = self.pack_name(pack_id);
+            for (hash, mut location) in chunks {
+                location.pack_id = pack_id;
+                if let (Ok(id), Some(file_location)) = (
+                    hash.parse(),
+                    pack_file_location(pack_name.clone(), &location),
+                ) {
+                    self.chunks_idx.insert(id, file_location);
+                }
+                self.index.insert(&hash, location);
+            }
+        }
+        self.commit_index()
+    }
+
+    fn save_index(&mut self) -> Result<(), StoreError> {
+        let _commit = self.locks.acquire_commit(DEFAULT_LOCK_WAIT)?;
+        self.commit_index()
+    }
+
+    /// Write both indexes; the commit lock must be held.
+    ///
+    /// Under a shared lock, other writers may have committed since the indexes were
+    /// read, so their entries are merged in first. Only an exclusive holder (prune) may
+    /// drop entries by writing its indexes as they are.
+    fn commit_index(&mut self) -> Result<(), StoreError> {
+        if self.lock_kind == LockKind::Shared {
+            match ChunkIndex::load(&self.backend, INDEX_NAME) {
+                Ok(mut stored) => {
+                    stored.retain_packs(|pack_id| !self.dropped_packs.contains(&pack_id));
+                    self.index.merge(stored);
+                }
+                Err(err) => log::warn!("ignoring unreadable index: {}", err),
+            }
+            match chunks_idx::ChunkIndex::load(&self.backend) {
+                Ok(mut stored) => {
+                    let dropped_files: HashSet<PathBuf> = self
+                        .dropped_packs
+                        .iter()
+                        .map(|&pack_id| self.pack_name(pack_id).into())
+                        .collect();
+                    stored.retain_packs(|pack_file| !dropped_files.contains(pack_file));
+                    self.chunks_idx.merge(stored);
+                }
+                Err(err) => log::warn!("ignoring unreadable chunk index: {}", err),
+            }
+        }
+
+        // Bump first: if we die in between, cached copies are invalidated for nothing,
+        // instead of an index change going unnoticed by them.
+        let generation = bump_generation(&self.backend)?;
+        self.index.save(&self.backend, INDEX_NAME)?;
+        self.chunks_idx.save(&self.backend)?;
+        self.update_cache(generation);
+        Ok(())
+    }
+
+    fn update_cache(&self, generation: u64) {
+        if let Some(cache) = &self.cache
+            && let Err(err) = cache.save(generation, &self.index)
+        {
+            // The cache only saves time; the next open will simply not use it.
+            log::warn!(
+                "cannot update index cache {}: {}",
+                cache.dir().display(),
+                err
+            );
+        }
+    }
+}
+
+/// A full pack that is not written yet.
+struct SealedPack {
+    data: Vec<u8>,
+    // Its chunks, with pack id 0 until it is written.
+    chunks: HashMap<String, ChunkLocation>,
+}
+
+/// Outcome of [`ChunkStore::rebuild_index`].
+#[derive(Debug, Default)]
+pub struct RebuildReport {
+    /// Packs whose footer was readable.
+    pub packs: usize,
+    /// Distinct chunks in the new index.
+    pub chunks: usize,
+    /// Unreadable packs and damaged chunks that were left out.
+    pub problems: Vec<StoreError>,
+}
+
+/// Outcome of [`ChunkStore::prune`].
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    /// Unreferenced chunks deleted.
+    pub removed_chunks: usize,
+    pub removed_bytes: u64,
+    /// Referenced chunks copied out of packs that were deleted.
+    pub repacked_chunks: usize,
+    /// Packs deleted.
+    pub removed_packs: usize,
+}
+
+fn chunk_location(pack_id: u64, entry: &PackEntry) -> ChunkLocation {
+    ChunkLocation {
+        pack_id,
+        offset: entry.offset,
+        length: entry.length,
+        compressed_length: entry.compressed_length,
+    }
+}
+
+/// Where `location` in the pack named `pack_name` is, as `chunks.idx` records it;
+/// `None` for a chunk too large to be recorded there.
+fn pack_file_location(
+    pack_name: String,
+    location: &ChunkLocation,
+) -> Option<chunks_idx::ChunkLocation> {
+    Some(chunks_idx::ChunkLocation {
+        pack_file: pack_name.into(),
+        offset: location.offset,
+        length: u32::try_from(location.compressed_length).ok()?,
+    })
+}
+
+/// Object name of a pack in a repository with the given [`RepoConfig::fanout_depth`],
+/// e.g. `packs/ef/cd/0123456789abcdef.pack` at depth 2.
+///
+/// The directories are named after the lowest bytes of the id, lowest first: ids are
+/// handed out counting up from 0, so their leading digits are the same for every pack.
+pub fn pack_name(pack_id: u64, fanout_depth: u32) -> String {
+    let id = format!("{:016x}", pack_id);
+    let mut name = PACKS_PREFIX.to_string();
+    for level in 0..fanout_depth as usize {
+        let end = id.len() - level * 2;
+        name += &id[end - 2..end];
+        name.push('/');
+    }
+    name + &id + ".pack"
+}
+
+/// Current index generation of the repository; 0 if it never changed.
+fn read_generation(backend: &dyn Backend) -> io::Result<u64> {
+    match backend.read(GENERATION_NAME) {
+        Ok(bytes) => cache::parse_generation(&bytes).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "invalid generation counter")
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+/// Increment the index generation, returning the new value.
+fn bump_generation(backend: &dyn Backend) -> io::Result<u64> {
+    let generation = read_generation(backend)? + 1;
+    backend.write(GENERATION_NAME, generation.to_string().as_bytes())?;
+    Ok(generation)
+}
+
+/// Ids of all `<id>.pack` objects below `packs/`, at any fan-out depth.
+fn list_pack_ids(backend: &dyn Backend) -> Result<Vec<u64>, StoreError> {
+    let mut pack_ids: Vec<u64> = backend
+        .list(PACKS_PREFIX)?
+        .iter()
+        .filter_map(|name| {
+            let file_name = name.rsplit('/').next()?;
+            let id = file_name.strip_suffix(".pack")?;
+            u64::from_str_radix(id, 16).ok()
+        })
+        .collect();
+    pack_ids.sort_unstable();
+    Ok(pack_ids)
+}
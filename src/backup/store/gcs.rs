@@ -0,0 +1,251 @@
+//! Repository in a Google Cloud Storage bucket.
+//!
+//! Bucket and prefix come from the `[backend.gcs]` (or `[store]`) settings section,
+//! credentials from the configured service account key file, or else the usual
+//! Google chain: `GOOGLE_APPLICATION_CREDENTIALS`, gcloud's application default
+//! credentials, then the metadata server. Object names map to names below the
+//! configured prefix.
+
+use std::io;
+
+use gcloud_storage::{
+    client::{Client, ClientConfig, google_cloud_auth::credentials::CredentialsFile},
+    http::{
+        Error,
+        objects::{
+            delete::DeleteObjectRequest,
+            download::Range,
+            get::GetObjectRequest,
+            list::ListObjectsRequest,
+            upload::{Media, UploadObjectRequest, UploadType},
+        },
+    },
+};
+use tokio::runtime::Runtime;
+
+use super::{backend::Backend, retry};
+use crate::config::GcsSettings;
+
+/// GCS name of the object `name` below `prefix`.
+pub fn object_name(prefix: &str, name: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Settings for `gs://bucket/prefix`, with the credentials taken from `settings` (if
+/// any). A bare `gs://` uses bucket and prefix from `settings` as well.
+pub fn settings_for_url(url: &str, settings: Option<&GcsSettings>) -> io::Result<GcsSettings> {
+    let rest = url.strip_prefix("gs://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not a gs:// URL: {}", url),
+        )
+    })?;
+
+    let mut settings = settings.cloned().unwrap_or_default();
+    if !rest.is_empty() {
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        settings.bucket = bucket.to_string();
+        settings.prefix = prefix.to_string();
+    }
+
+    if settings.bucket.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no bucket in {} or the [backend.gcs] settings", url),
+        ));
+    }
+    Ok(settings)
+}
+
+/// Backend storing every object as a GCS object.
+///
+/// The client is async; every call is run to completion on a runtime owned by the
+/// backend. GCS objects only become visible once completely uploaded, and reads
+/// after writes are strongly consistent, so unlike S3 nothing needs retrying.
+pub struct GcsBackend {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    runtime: Runtime,
+}
+
+impl GcsBackend {
+    pub fn new(settings: &GcsSettings) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        let config = runtime.block_on(async {
+            let config = ClientConfig::default();
+            match &settings.credentials_file {
+                Some(path) => {
+                    let credentials =
+                        CredentialsFile::new_from_file(path.to_string_lossy().into_owned())
+                            .await
+                            .map_err(|err| {
+                                io::Error::other(format!(
+                                    "cannot read GCS credentials {}: {}",
+                                    path.display(),
+                                    err
+                                ))
+                            })?;
+                    config.with_credentials(credentials).await
+                }
+                None => config.with_auth().await,
+            }
+            .map_err(|err| io::Error::other(format!("cannot authenticate to GCS: {}", err)))
+        })?;
+
+        Ok(GcsBackend {
+            client: Client::new(config),
+            bucket: settings.bucket.clone(),
+            prefix: settings.prefix.trim_matches('/').to_string(),
+            runtime,
+        })
+    }
+
+    fn name(&self, name: &str) -> String {
+        object_name(&self.prefix, name)
+    }
+
+    fn object_request(&self, object: &str) -> GetObjectRequest {
+        GetObjectRequest {
+            bucket: self.bucket.clone(),
+            object: object.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn download(&self, object: &str, range: Range) -> io::Result<Vec<u8>> {
+        self.runtime
+            .block_on(
+                self.client
+                    .download_object(&self.object_request(object), &range),
+            )
+            .map_err(|err| map_err(err, object))
+    }
+}
+
+impl Backend for GcsBackend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.download(&self.name(name), Range::default())
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let object = self.name(name);
+        let request = UploadObjectRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+        let upload_type = UploadType::Simple(Media::new(object.clone()));
+        self.runtime
+            .block_on(
+                self.client
+                    .upload_object(&request, data.to_vec(), &upload_type),
+            )
+            .map_err(|err| map_err(err, &object))?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let name_prefix = self.name("");
+        let mut request = ListObjectsRequest {
+            bucket: self.bucket.clone(),
+            prefix: Some(self.name(prefix)),
+            ..Default::default()
+        };
+
+        let mut names = Vec::new();
+        loop {
+            let page = self
+                .runtime
+                .block_on(self.client.list_objects(&request))
+                .map_err(|err| map_err(err, &name_prefix))?;
+            names.extend(
+                page.items.into_iter().flatten().filter_map(|object| {
+                    object.name.strip_prefix(&name_prefix).map(str::to_string)
+                }),
+            );
+            match page.next_page_token {
+                Some(token) => request.page_token = Some(token),
+                None => break,
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let object = self.name(name);
+        let request = DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            object: object.clone(),
+            ..Default::default()
+        };
+        self.runtime
+            .block_on(self.client.delete_object(&request))
+            .map_err(|err| map_err(err, &object))
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        // Object metadata instead of the content: pack files can be large.
+        let object = self.name(name);
+        match self
+            .runtime
+            .block_on(self.client.get_object(&self.object_request(&object)))
+            .map_err(|err| map_err(err, &object))
+        {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        let object = self.name(name);
+        let metadata = self
+            .runtime
+            .block_on(self.client.get_object(&self.object_request(&object)))
+            .map_err(|err| map_err(err, &object))?;
+        u64::try_from(metadata.size)
+            .map_err(|_| io::Error::other(format!("invalid size for {}", object)))
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+
+        let object = self.name(name);
+        let data = self.download(&object, Range(Some(offset), Some(offset + len - 1)))?;
+        if data.len() as u64 != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "range {}..{} is past the end of {}",
+                    offset,
+                    offset + len,
+                    object
+                ),
+            ));
+        }
+        Ok(data)
+    }
+}
+
+/// The kind of an error GCS returned: a 404 is [`io::ErrorKind::NotFound`], which is
+/// how backends report missing objects, not a failure of the request.
+fn map_err(err: Error, object: &str) -> io::Error {
+    let status = match &err {
+        Error::Response(response) => Some(response.code),
+        Error::HttpClient(err) => err.status().map(|status| status.as_u16()),
+        _ => None,
+    };
+    let kind = status.map_or(io::ErrorKind::Other, retry::http_status_kind);
+    io::Error::new(kind, format!("{}: {}", object, err))
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::backend::Backend;
+use crate::backup::hash::ChunkId;
+
+/// Where a chunk lives inside the repository.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkLocation {
+    pub pack_id: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub compressed_length: u64,
+}
+
+/// Repository-level index: chunk hash -> location of the chunk in a pack.
+///
+/// The index is derived data; every entry can be recomputed from the pack footers.
+/// Hashes are [`ChunkId`]s in memory and hex-encoded in the stored form and the API;
+/// strings that are not chunk ids are never indexed.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct ChunkIndex {
+    chunks: HashMap<ChunkId, ChunkLocation>,
+}
+
+impl ChunkIndex {
+    /// Load the index stored as `name`. A missing object is an empty index.
+    pub fn load(backend: &dyn Backend, name: &str) -> io::Result<Self> {
+        match backend.read(name) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replace the index stored as `name`.
+    pub fn save(&self, backend: &dyn Backend, name: &str) -> io::Result<()> {
+        let bytes = serde_json::to_vec(self)?;
+        backend.write(name, &bytes)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&ChunkLocation> {
+        self.chunks.get(&hash.parse().ok()?)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.get(hash).is_some()
+    }
+
+    /// Record a chunk location. Keeps the existing location if the chunk is already indexed.
+    pub fn insert(&mut self, hash: &str, location: ChunkLocation) -> bool {
+        let Ok(hash) = hash.parse::<ChunkId>() else {
+            return false;
+        };
+        match self.chunks.entry(hash) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(location);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, hash: &str) -> Option<ChunkLocation> {
+        self.chunks.remove(&hash.parse().ok()?)
+    }
+
+    /// All entries, with hex-encoded hashes.
+    pub fn iter(&self) -> impl Iterator<Item = (String, &ChunkLocation)> {
+        self.chunks
+            .iter()
+            .map(|(hash, location)| (hash.to_string(), location))
+    }
+
+    /// Add the entries of `other` for chunks this index does not have yet.
+    pub fn merge(&mut self, other: ChunkIndex) {
+        for (hash, location) in other.chunks {
+            self.chunks.entry(hash).or_insert(location);
+        }
+    }
+
+    /// Drop every entry whose pack does not satisfy `keep`. Returns the number removed.
+    pub fn retain_packs(&mut self, mut keep: impl FnMut(u64) -> bool) -> usize {
+        let before = self.chunks.len();
+        self.chunks.retain(|_, location| keep(location.pack_id));
+        before - self.chunks.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
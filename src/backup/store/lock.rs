@@ -0,0 +1,365 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions, TryLockError},
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use super::{Backend, StoreError};
+
+/// Name of the lock file in the repository root.
+pub const LOCK_NAME: &str = "lock";
+
+/// Name of the lock file that serialises index commits, see [`RepoLock::acquire_commit`].
+pub const COMMIT_LOCK_NAME: &str = "commit-lock";
+
+/// Directory next to the lock file with one record per holder, see [`LockInfo`].
+pub const LOCK_RECORDS_DIR: &str = "locks";
+
+/// How long opening a locked repository waits for the lock by default.
+pub const DEFAULT_LOCK_WAIT: Duration = Duration::from_secs(30);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Tells apart the records of locks held by the same process.
+static NEXT_RECORD: AtomicU64 = AtomicU64::new(0);
+
+/// What a lock allows other processes to do at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockKind {
+    /// Any number of shared holders, e.g. backups and restores.
+    Shared,
+    /// A single holder and nobody else, e.g. rebuilding the index or pruning.
+    Exclusive,
+}
+
+/// Who holds a repository lock, recorded in `locks/` for error messages and `unlock`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub host: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub since: OffsetDateTime,
+    pub kind: LockKind,
+}
+
+impl LockInfo {
+    fn current(kind: LockKind) -> Self {
+        LockInfo {
+            pid: std::process::id(),
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            since: OffsetDateTime::now_utc(),
+            kind,
+        }
+    }
+
+    /// Whether the holder is known to have exited: it ran on this host and its process
+    /// is gone. Holders on other hosts are never presumed gone.
+    pub fn is_gone(&self) -> bool {
+        self.host == gethostname::gethostname().to_string_lossy() && !process_exists(self.pid)
+    }
+
+    /// How long the lock has been held.
+    pub fn age(&self) -> Duration {
+        (OffsetDateTime::now_utc() - self.since)
+            .try_into()
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = self
+            .since
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| self.since.to_string());
+        write!(f, "pid {} on host {} since {}", self.pid, self.host, since)
+    }
+}
+
+/// Advisory lock on a local repository, released on drop.
+///
+/// Uses `flock` on Unix and `LockFileEx` on Windows, so the lock goes away with the
+/// holding process even if it crashes. The OS lock is what counts; the holder records
+/// in `locks/` only say who holds it, and one left behind by a crash is harmless.
+#[derive(Debug)]
+pub struct RepoLock {
+    // Closing the file releases the lock.
+    _file: File,
+    path: PathBuf,
+    // Holder record, removed with the lock; commit locks have none.
+    record: Option<PathBuf>,
+}
+
+impl RepoLock {
+    /// Lock the repository in `root`, waiting up to `wait` for other holders to let go.
+    ///
+    /// Fails with [`StoreError::Locked`] if the lock is still taken after that.
+    pub fn acquire(root: &Path, kind: LockKind, wait: Duration) -> Result<Self, StoreError> {
+        let path = root.join(LOCK_NAME);
+        let file = lock_file(&path, kind, wait, || blocking_holder(root, kind))?;
+
+        let records = root.join(LOCK_RECORDS_DIR);
+        if kind == LockKind::Exclusive {
+            // Nobody else holds the lock, so all records left are from crashed processes.
+            remove_records(&records)?;
+        }
+
+        let info = LockInfo::current(kind);
+        let record = records.join(format!(
+            "{}-{}-{}.json",
+            info.host,
+            info.pid,
+            NEXT_RECORD.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&records)?;
+        fs::write(
+            &record,
+            serde_json::to_vec_pretty(&info).map_err(io::Error::from)?,
+        )?;
+
+        Ok(RepoLock {
+            _file: file,
+            path,
+            record: Some(record),
+        })
+    }
+
+    /// Take the commit lock of the repository in `root`, waiting up to `wait` for it.
+    ///
+    /// Writers holding the (shared) repository lock take it exclusively, and only for
+    /// as long as it takes to name a new pack or to read, merge and write the index,
+    /// so that one writer's update cannot overwrite another's.
+    pub fn acquire_commit(root: &Path, wait: Duration) -> Result<Self, StoreError> {
+        let path = root.join(COMMIT_LOCK_NAME);
+        let file = lock_file(&path, LockKind::Exclusive, wait, || None)?;
+        Ok(RepoLock {
+            _file: file,
+            path,
+            record: None,
+        })
+    }
+
+    /// Holders of the lock of the repository in `root` according to their records,
+    /// oldest first. May include processes that crashed while holding it.
+    pub fn holders(root: &Path) -> io::Result<Vec<LockInfo>> {
+        let entries = match fs::read_dir(root.join(LOCK_RECORDS_DIR)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut holders = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            // Records can disappear or be half-written while we look at them.
+            if let Some(info) = fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<LockInfo>(&bytes).ok())
+            {
+                holders.push(info);
+            }
+        }
+        holders.sort_by_key(|info| info.since);
+        Ok(holders)
+    }
+
+    /// Clear the lock of the repository in `root` after a crash: remove the holder
+    /// records, and the lock files too if the processes holding them are gone.
+    ///
+    /// Fails with [`StoreError::Locked`] and changes nothing while a live process still
+    /// holds the lock. A lock only counts as abandoned if it is free, or if every
+    /// recorded holder ran on this host and has exited (e.g. a child process that
+    /// inherited the lock is keeping it).
+    pub fn break_lock(root: &Path) -> Result<(), StoreError> {
+        let mut held = Vec::new();
+        for name in [LOCK_NAME, COMMIT_LOCK_NAME] {
+            let path = root.join(name);
+            let file = match OpenOptions::new().read(true).write(true).open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            match file.try_lock() {
+                // Keep it locked until the records are gone, so nobody takes it meanwhile.
+                Ok(()) => held.push(file),
+                Err(TryLockError::WouldBlock) => {
+                    let holders = RepoLock::holders(root)?;
+                    if holders.is_empty() || !holders.iter().all(LockInfo::is_gone) {
+                        return Err(StoreError::Locked {
+                            holder: blocking_holder(root, LockKind::Exclusive),
+                        });
+                    }
+                    // Lockers check that the file they locked is still in place.
+                    fs::remove_file(&path)?;
+                }
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+        remove_records(&root.join(LOCK_RECORDS_DIR))?;
+        drop(held);
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        if let Some(record) = &self.record {
+            let _ = fs::remove_file(record);
+        }
+    }
+}
+
+/// Open (creating it if needed) and lock the file at `path`, waiting up to `wait`.
+/// Fails with [`StoreError::Locked`] naming `holder()` if it is still taken then.
+fn lock_file(
+    path: &Path,
+    kind: LockKind,
+    wait: Duration,
+    holder: impl FnOnce() -> Option<LockInfo>,
+) -> Result<File, StoreError> {
+    let deadline = Instant::now() + wait;
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        loop {
+            let result = match kind {
+                LockKind::Shared => file.try_lock_shared(),
+                LockKind::Exclusive => file.try_lock(),
+            };
+            match result {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(StoreError::Locked { holder: holder() });
+                }
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+        // A lock on a file that `break_lock` removed meanwhile locks nothing.
+        if is_same_file(&file, path)? {
+            return Ok(file);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let opened = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(opened.dev() == current.dev() && opened.ino() == current.ino()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// Windows cannot remove a file that is open.
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(target_os = "linux")]
+fn process_exists(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// Without a cheap way to tell, assume the process is still running.
+#[cfg(not(target_os = "linux"))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+/// The holder to name when a `kind` lock could not be taken: an exclusive holder if
+/// there is one, otherwise the most recent shared one.
+fn blocking_holder(root: &Path, kind: LockKind) -> Option<LockInfo> {
+    let holders = RepoLock::holders(root).ok()?;
+    let exclusive = holders
+        .iter()
+        .rfind(|info| info.kind == LockKind::Exclusive);
+    match (exclusive, kind) {
+        (Some(info), _) => Some(info.clone()),
+        (None, LockKind::Exclusive) => holders.last().cloned(),
+        (None, LockKind::Shared) => None,
+    }
+}
+
+fn remove_records(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// The locks of the repository behind a [`Backend`], for backends that support them.
+///
+/// Only repositories on the local filesystem can be locked; for all others taking a
+/// lock succeeds without one, and there is nothing to list or break.
+#[derive(Clone, Debug)]
+pub struct RepoLocks {
+    root: Option<PathBuf>,
+}
+
+impl RepoLocks {
+    pub fn of<B: Backend + ?Sized>(backend: &B) -> Self {
+        RepoLocks {
+            root: backend.local_root().map(Path::to_path_buf),
+        }
+    }
+
+    /// Whether the repository can be locked at all.
+    pub fn supported(&self) -> bool {
+        self.root.is_some()
+    }
+
+    /// See [`RepoLock::acquire`]; `None` if the repository cannot be locked.
+    pub fn acquire(&self, kind: LockKind, wait: Duration) -> Result<Option<RepoLock>, StoreError> {
+        self.root
+            .as_deref()
+            .map(|root| RepoLock::acquire(root, kind, wait))
+            .transpose()
+    }
+
+    /// See [`RepoLock::acquire_commit`]; `None` if the repository cannot be locked.
+    pub fn acquire_commit(&self, wait: Duration) -> Result<Option<RepoLock>, StoreError> {
+        self.root
+            .as_deref()
+            .map(|root| RepoLock::acquire_commit(root, wait))
+            .transpose()
+    }
+
+    /// See [`RepoLock::holders`].
+    pub fn holders(&self) -> io::Result<Vec<LockInfo>> {
+        match &self.root {
+            Some(root) => RepoLock::holders(root),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// See [`RepoLock::break_lock`].
+    pub fn break_lock(&self) -> Result<(), StoreError> {
+        match &self.root {
+            Some(root) => RepoLock::break_lock(root),
+            None => Ok(()),
+        }
+    }
+}
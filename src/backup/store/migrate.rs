@@ -0,0 +1,96 @@
+//! Step-by-step upgrades of the repository format, run by `rbckp migrate`.
+//!
+//! Every [`Migration`] takes a repository from one format version to the next.
+//! The version in the repository config is bumped after each step, so a migration
+//! that is interrupted continues with the step that did not finish.
+
+use super::{
+    Backend, StoreError,
+    lock::{DEFAULT_LOCK_WAIT, LockKind, RepoLocks},
+    repo_config::{REPO_VERSION, RepoConfig},
+};
+
+/// One step: from format version `from` to `from + 1`.
+///
+/// `run` may be interrupted and run again, so it must cope with finding its work
+/// partly done. It may change `config`, which is saved with the new version.
+#[derive(Clone, Copy, Debug)]
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub run: fn(&dyn Backend, &mut RepoConfig) -> Result<(), StoreError>,
+}
+
+/// The steps up to [`REPO_VERSION`]. Format 1 is the first, so there are none yet.
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// What [`migrate`] did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrateReport {
+    /// Format version before.
+    pub from: u32,
+    /// Format version now.
+    pub to: u32,
+    /// Descriptions of the steps run, in order.
+    pub applied: Vec<&'static str>,
+}
+
+/// Bring the repository in `backend` up to [`REPO_VERSION`]. Does nothing if it is
+/// there already.
+pub fn migrate(backend: &dyn Backend) -> Result<MigrateReport, StoreError> {
+    migrate_to(backend, MIGRATIONS, REPO_VERSION)
+}
+
+/// Run the steps of `migrations` that take the repository in `backend` up to format
+/// version `target`, with an exclusive lock held, waiting up to [`DEFAULT_LOCK_WAIT`]
+/// for running backups and restores.
+///
+/// Fails with [`StoreError::UnsupportedVersion`] if the repository is newer than
+/// `target`, and before changing anything if a step is missing.
+pub fn migrate_to(
+    backend: &dyn Backend,
+    migrations: &[Migration],
+    target: u32,
+) -> Result<MigrateReport, StoreError> {
+    let _lock = RepoLocks::of(backend).acquire(LockKind::Exclusive, DEFAULT_LOCK_WAIT)?;
+    let mut config = RepoConfig::read(backend)?;
+    if config.version > target {
+        return Err(StoreError::UnsupportedVersion {
+            version: config.version,
+            supported: target,
+        });
+    }
+
+    let steps = (config.version..target)
+        .map(|from| {
+            migrations
+                .iter()
+                .find(|migration| migration.from == from)
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        format!("no migration from repository format version {}", from),
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut report = MigrateReport {
+        from: config.version,
+        to: config.version,
+        applied: Vec::new(),
+    };
+    for step in steps {
+        log::info!(
+            "migrating from format version {}: {}",
+            step.from,
+            step.description
+        );
+        (step.run)(backend, &mut config)?;
+        config.version = step.from + 1;
+        config.save(backend)?;
+        report.to = config.version;
+        report.applied.push(step.description);
+    }
+    Ok(report)
+}
@@ -0,0 +1,370 @@
+#[cfg(feature = "async")]
+pub mod async_pool;
+#[cfg(feature = "async")]
+pub mod async_store;
+#[cfg(feature = "b2")]
+pub mod b2;
+pub mod backend;
+#[cfg(feature = "async")]
+pub mod batch;
+pub mod cache;
+pub mod chunk_store;
+#[cfg(feature = "gcs")]
+pub mod gcs;
+pub mod index;
+pub mod lock;
+pub mod migrate;
+pub mod pack;
+pub mod repo_config;
+pub mod retry;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+pub mod tee;
+pub mod upload;
+
+use std::{
+    borrow::Cow,
+    fmt, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    backup::hash::PrefixCollision,
+    config::{BackendSettings, StoreKind},
+};
+
+pub use backend::{Backend, InMemoryBackend, LocalFsBackend};
+pub use chunk_store::{ChunkStore, LocalFsStore};
+
+/// Where a repository location given on the command line is: the location itself,
+/// or, without a scheme and with `[store] type = s3` (or `gcs`, `sftp`), an `s3://`
+/// (or `gs://`, `sftp://`) URL below the configured bucket and prefix (or server and
+/// remote root). With `type = tee`, [`open_backend`] resolves the location once for
+/// each side.
+pub fn repo_location<'a>(location: &'a Path, settings: &BackendSettings) -> Cow<'a, Path> {
+    let (scheme, authority, prefix) = match settings.default_kind {
+        StoreKind::S3 => match &settings.s3 {
+            Some(s3) => ("s3", s3.bucket.clone(), &s3.prefix),
+            None => return Cow::Borrowed(location),
+        },
+        StoreKind::Gcs => match &settings.gcs {
+            Some(gcs) => ("gs", gcs.bucket.clone(), &gcs.prefix),
+            None => return Cow::Borrowed(location),
+        },
+        StoreKind::Sftp => match &settings.sftp {
+            Some(sftp) => ("sftp", sftp.authority(), &sftp.remote_root),
+            None => return Cow::Borrowed(location),
+        },
+        StoreKind::Local | StoreKind::Tee => return Cow::Borrowed(location),
+    };
+    if location.to_string_lossy().contains("://") {
+        return Cow::Borrowed(location);
+    }
+
+    let name = location
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        });
+    let key = prefix
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(Cow::Borrowed)
+        .chain(name)
+        .collect::<Vec<_>>()
+        .join("/");
+    Cow::Owned(PathBuf::from(format!("{}://{}/{}", scheme, authority, key)))
+}
+
+/// The secondary side of the repository at `location` on its own, if it is opened as
+/// a [`TeeBackend`](tee::TeeBackend) by [`open_backend`]; `None` otherwise.
+pub fn open_secondary(
+    location: &Path,
+    settings: &BackendSettings,
+) -> io::Result<Option<Box<dyn Backend>>> {
+    match settings.tee {
+        Some(tee)
+            if settings.default_kind == StoreKind::Tee
+                && !location.to_string_lossy().contains("://") =>
+        {
+            let settings = BackendSettings {
+                default_kind: tee.secondary,
+                ..settings.clone()
+            };
+            open_backend(location, &settings).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Backend for a repository location: a local directory, an `sftp://user@host/path`
+/// URL (`sftp` feature), an `s3://bucket/prefix` URL (`s3` feature), a
+/// `gs://bucket/prefix` URL (`gcs` feature) or a `b2://bucket/prefix` URL (`b2`
+/// feature), resolved with [`repo_location`].
+///
+/// With `[store] type = tee`, a location without a scheme is opened as a
+/// [`TeeBackend`](tee::TeeBackend) over that location on both configured sides.
+#[cfg_attr(
+    not(any(feature = "sftp", feature = "s3", feature = "gcs", feature = "b2")),
+    allow(unused_variables)
+)]
+pub fn open_backend(location: &Path, settings: &BackendSettings) -> io::Result<Box<dyn Backend>> {
+    if let Some(tee) = settings
+        .tee
+        .filter(|_| settings.default_kind == StoreKind::Tee)
+        && !location.to_string_lossy().contains("://")
+    {
+        let side = |kind| {
+            let settings = BackendSettings {
+                default_kind: kind,
+                ..settings.clone()
+            };
+            open_backend(location, &settings)
+        };
+        return Ok(Box::new(tee::TeeBackend::new(
+            side(tee.primary)?,
+            side(tee.secondary)?,
+        )));
+    }
+
+    let location = repo_location(location, settings);
+    let location = location.as_ref();
+    if let Some(url) = location.to_str().filter(|url| url.starts_with("sftp://")) {
+        #[cfg(feature = "sftp")]
+        {
+            let sftp_settings = settings.sftp.clone().unwrap_or_default();
+            // Retries within each call, reconnecting as needed.
+            return Ok(Box::new(
+                sftp::SftpBackend::connect(
+                    sftp::SftpLocation::parse(url).map_err(|err| {
+                        io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", err, url))
+                    })?,
+                    sftp_settings.key_path.as_deref(),
+                )?
+                .with_max_connections(sftp_settings.connections)
+                .with_retry(settings.retry.policy()),
+            ));
+        }
+
+        #[cfg(not(feature = "sftp"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: rbckp was built without the `sftp` feature", url),
+        ));
+    }
+
+    if let Some(url) = location.to_str().filter(|url| url.starts_with("s3://")) {
+        #[cfg(feature = "s3")]
+        return Ok(retrying(
+            s3::S3Backend::new(&s3::settings_for_url(url, settings.s3.as_ref())?)?,
+            settings,
+        ));
+
+        #[cfg(not(feature = "s3"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: rbckp was built without the `s3` feature", url),
+        ));
+    }
+
+    if let Some(url) = location.to_str().filter(|url| url.starts_with("gs://")) {
+        #[cfg(feature = "gcs")]
+        return Ok(retrying(
+            gcs::GcsBackend::new(&gcs::settings_for_url(url, settings.gcs.as_ref())?)?,
+            settings,
+        ));
+
+        #[cfg(not(feature = "gcs"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: rbckp was built without the `gcs` feature", url),
+        ));
+    }
+
+    if let Some(url) = location.to_str().filter(|url| url.starts_with("b2://")) {
+        #[cfg(feature = "b2")]
+        return Ok(retrying(
+            b2::B2Backend::new(&b2::settings_for_url(url, settings.b2.as_ref())?)?,
+            settings,
+        ));
+
+        #[cfg(not(feature = "b2"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: rbckp was built without the `b2` feature", url),
+        ));
+    }
+
+    Ok(Box::new(
+        LocalFsBackend::new(location).with_retry(settings.retry.policy()),
+    ))
+}
+
+/// `backend` behind a [`RetryBackend`](retry::RetryBackend) as set up in `[retry]`.
+#[cfg(any(feature = "s3", feature = "gcs", feature = "b2"))]
+fn retrying(backend: impl Backend + 'static, settings: &BackendSettings) -> Box<dyn Backend> {
+    Box::new(retry::RetryBackend::new(
+        Box::new(backend),
+        settings.retry.policy(),
+    ))
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    /// An error that may well go away when the operation is tried again, e.g. a
+    /// network timeout or an HTTP 503, see [`retry::is_transient`]. Other I/O errors,
+    /// such as a missing object or a failed authentication, are [`StoreError::Io`].
+    Transient(io::Error),
+    /// No chunk with this hash is stored.
+    ChunkNotFound(String),
+    /// A pack file without a valid footer (unfinished, truncated or damaged).
+    CorruptPack {
+        name: String,
+        reason: String,
+    },
+    /// The location has no repository config; `rbckp init` was never run there.
+    NotInitialized,
+    /// `init` was run on a location that already holds a repository.
+    AlreadyInitialized,
+    /// Another process holds a conflicting repository lock; `holder` names it if known.
+    Locked {
+        holder: Option<lock::LockInfo>,
+    },
+    /// A chunk with this hash is stored with a different length than the one being
+    /// deduplicated against it, so the two chunks cannot be the same.
+    HashCollision {
+        hash: String,
+        stored_len: u64,
+        len: u64,
+    },
+    /// Two different chunks share the id prefix the store looks chunks up by, see
+    /// [`ChunkStore::with_hash_prefix_len`].
+    PrefixCollision(PrefixCollision),
+    /// The repository is in a newer format than this build supports.
+    UnsupportedVersion {
+        version: u32,
+        supported: u32,
+    },
+    /// The repository is in an older format; `rbckp migrate` upgrades it.
+    NeedsMigration {
+        version: u32,
+        current: u32,
+    },
+    /// A write through a [`TeeBackend`](tee::TeeBackend) failed on at least one side;
+    /// the flags say which sides have the object.
+    TeeWritePartial {
+        primary_ok: bool,
+        secondary_ok: bool,
+        error: io::Error,
+    },
+    /// A backup was asked to stop through its
+    /// [`CancelToken`](crate::backup::cancel::CancelToken).
+    Interrupted,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "store I/O error: {}", err),
+            StoreError::Transient(err) => {
+                write!(f, "store I/O error (may be temporary): {}", err)
+            }
+            StoreError::ChunkNotFound(hash) => write!(f, "chunk {} not found in store", hash),
+            StoreError::CorruptPack { name, reason } => {
+                write!(f, "corrupt pack {}: {}", name, reason)
+            }
+            StoreError::NotInitialized => write!(f, "not an initialized repository"),
+            StoreError::AlreadyInitialized => write!(f, "repository is already initialized"),
+            StoreError::Locked { holder: None } => {
+                write!(f, "repository is locked by another process")
+            }
+            StoreError::Locked {
+                holder: Some(holder),
+            } => write!(f, "repository is locked by {}", holder),
+            StoreError::HashCollision {
+                hash,
+                stored_len,
+                len,
+            } => write!(
+                f,
+                "hash collision on chunk {}: stored with {} bytes, found with {}",
+                hash, stored_len, len
+            ),
+            StoreError::PrefixCollision(collision) => write!(f, "{}", collision),
+            StoreError::UnsupportedVersion { version, supported } => write!(
+                f,
+                "repository format version {} is newer than this rbckp supports ({}); please upgrade rbckp",
+                version, supported
+            ),
+            StoreError::NeedsMigration { version, current } => write!(
+                f,
+                "repository format version {} is older than the current one ({}); run `rbckp migrate` first",
+                version, current
+            ),
+            StoreError::TeeWritePartial {
+                primary_ok,
+                secondary_ok,
+                error,
+            } => write!(
+                f,
+                "write failed on {}: {}",
+                match (primary_ok, secondary_ok) {
+                    (true, _) => "the secondary",
+                    (false, true) => "the primary",
+                    (false, false) => "both sides",
+                },
+                error
+            ),
+            StoreError::Interrupted => write!(f, "backup interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(err)
+            | StoreError::Transient(err)
+            | StoreError::TeeWritePartial { error: err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// A [`TeeWriteError`](tee::TeeWriteError) inside the error becomes
+/// [`StoreError::TeeWritePartial`], a [transient](retry::is_transient) one
+/// [`StoreError::Transient`].
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        if err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<tee::TeeWriteError>())
+        {
+            let tee = *err
+                .into_inner()
+                .expect("checked above")
+                .downcast::<tee::TeeWriteError>()
+                .expect("checked above");
+            return StoreError::TeeWritePartial {
+                primary_ok: tee.primary_ok,
+                secondary_ok: tee.secondary_ok,
+                error: tee.error,
+            };
+        }
+        if retry::is_transient(&err) {
+            return StoreError::Transient(err);
+        }
+        StoreError::Io(err)
+    }
+}
+
+impl StoreError {
+    /// Whether trying the operation again may succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, StoreError::Transient(_))
+    }
+}
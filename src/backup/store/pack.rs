@@ -0,0 +1,220 @@
+//! On-disk pack file format.
+//!
+//! A pack is a plain concatenation of chunk payloads followed by a footer that
+//! describes where each chunk lives:
+//!
+//! ```text
+//! [chunk 0][chunk 1]...[chunk n-1][entry 0]...[entry n-1][trailer]
+//!
+//! entry   = hash (32 bytes) | offset (u64 LE) | length (u64 LE) | stored length (u64 LE)
+//! trailer = entry count (u64 LE) | BLAKE3 of all entry bytes (32 bytes) | PACK_MAGIC
+//! ```
+//!
+//! The footer comes last. A pack without a valid trailer is one whose writer died
+//! half way (or that was damaged later), and none of its chunks count as stored.
+
+use std::{collections::HashMap, io};
+
+use super::{StoreError, backend::Backend};
+use crate::backup::hash::ChunkId;
+
+/// Last bytes of every complete pack file.
+pub const PACK_MAGIC: &[u8; 8] = b"RBCKPAK1";
+
+const ENTRY_LEN: usize = 32 + 8 + 8 + 8;
+const TRAILER_LEN: usize = 8 + 32 + PACK_MAGIC.len();
+
+/// Location of one chunk inside a pack, as listed in the pack footer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackEntry {
+    /// [`ChunkId`] of the chunk, hex-encoded.
+    pub hash: String,
+    /// Offset of the stored chunk bytes from the start of the pack.
+    pub offset: u64,
+    /// Length of the original chunk.
+    pub length: u64,
+    /// Length of the chunk as stored in the pack. Chunks are stored uncompressed
+    /// for now, so this equals `length`, but readers must only rely on this one.
+    pub compressed_length: u64,
+}
+
+/// Collects chunks for a new pack and seals them with a footer.
+///
+/// The pack is assembled in memory and handed to a [`Backend`] in one piece,
+/// so its size is bounded by the store's pack size.
+#[derive(Default)]
+pub struct PackWriter {
+    data: Vec<u8>,
+    entries: Vec<PackEntry>,
+}
+
+impl PackWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes of chunk data added so far (footer not included).
+    pub fn len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries of the chunks added so far.
+    pub fn entries(&self) -> &[PackEntry] {
+        &self.entries
+    }
+
+    /// Chunk data added so far; entry offsets index into this.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Append a chunk and return its entry.
+    pub fn add(&mut self, hash: &str, chunk: &[u8]) -> io::Result<PackEntry> {
+        // Reject bad hashes now rather than when writing the footer.
+        parse_hash(hash)?;
+
+        let entry = PackEntry {
+            hash: hash.to_string(),
+            offset: self.len(),
+            length: chunk.len() as u64,
+            compressed_length: chunk.len() as u64,
+        };
+        self.data.extend_from_slice(chunk);
+        self.entries.push(entry.clone());
+
+        Ok(entry)
+    }
+
+    /// Append the footer and return the complete pack bytes and its entries.
+    pub fn finish(mut self) -> io::Result<(Vec<u8>, Vec<PackEntry>)> {
+        let mut footer = Vec::with_capacity(self.entries.len() * ENTRY_LEN + TRAILER_LEN);
+        for entry in &self.entries {
+            footer.extend_from_slice(&parse_hash(&entry.hash)?);
+            footer.extend_from_slice(&entry.offset.to_le_bytes());
+            footer.extend_from_slice(&entry.length.to_le_bytes());
+            footer.extend_from_slice(&entry.compressed_length.to_le_bytes());
+        }
+        let checksum = blake3::hash(&footer);
+
+        footer.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        footer.extend_from_slice(checksum.as_bytes());
+        footer.extend_from_slice(PACK_MAGIC);
+
+        self.data.extend_from_slice(&footer);
+
+        Ok((self.data, self.entries))
+    }
+}
+
+/// Read and validate the footer of the pack stored as `name`.
+///
+/// Returns [`StoreError::CorruptPack`] for packs that were never finished (or were
+/// truncated/damaged afterwards), so callers can treat their chunks as absent.
+pub fn read_footer(backend: &dyn Backend, name: &str) -> Result<Vec<PackEntry>, StoreError> {
+    let corrupt = |reason: &str| StoreError::CorruptPack {
+        name: name.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let pack_len = backend.size(name)?;
+    if pack_len < TRAILER_LEN as u64 {
+        return Err(corrupt("too short for a footer"));
+    }
+
+    let trailer = backend.read_range(name, pack_len - TRAILER_LEN as u64, TRAILER_LEN as u64)?;
+    let (count, rest) = trailer.split_at(8);
+    let (checksum, magic) = rest.split_at(32);
+    if magic != PACK_MAGIC {
+        return Err(corrupt("missing footer magic"));
+    }
+
+    let count = u64::from_le_bytes(count.try_into().unwrap());
+    let entries_len = count
+        .checked_mul(ENTRY_LEN as u64)
+        .filter(|len| len + (TRAILER_LEN as u64) <= pack_len)
+        .ok_or_else(|| corrupt("entry count does not fit in the pack"))?;
+    let data_end = pack_len - TRAILER_LEN as u64 - entries_len;
+
+    let entry_bytes = backend.read_range(name, data_end, entries_len)?;
+    if blake3::hash(&entry_bytes).as_bytes() != checksum {
+        return Err(corrupt("footer checksum mismatch"));
+    }
+
+    let mut entries = Vec::with_capacity(count as usize);
+    for raw in entry_bytes.chunks_exact(ENTRY_LEN) {
+        let field = |at: usize| u64::from_le_bytes(raw[at..at + 8].try_into().unwrap());
+        let hash: [u8; 32] = raw[..32].try_into().unwrap();
+        let entry = PackEntry {
+            hash: ChunkId::from_bytes(hash).to_string(),
+            offset: field(32),
+            length: field(40),
+            compressed_length: field(48),
+        };
+
+        if entry.offset.saturating_add(entry.compressed_length) > data_end {
+            return Err(corrupt("entry points past the chunk data"));
+        }
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Random access to the chunks of one finished pack, looked up by hash through
+/// the pack's own footer (no repository index needed).
+pub struct PackReader<'a> {
+    backend: &'a dyn Backend,
+    name: String,
+    entries: HashMap<String, PackEntry>,
+}
+
+impl<'a> PackReader<'a> {
+    /// Open the pack stored as `name` and load its footer.
+    pub fn open(backend: &'a dyn Backend, name: &str) -> Result<Self, StoreError> {
+        let entries = read_footer(backend, name)?
+            .into_iter()
+            .map(|entry| (entry.hash.clone(), entry))
+            .collect();
+
+        Ok(PackReader {
+            backend,
+            name: name.to_string(),
+            entries,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Footer entries of this pack, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = &PackEntry> {
+        self.entries.values()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Extract the chunk with this hash.
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>, StoreError> {
+        let entry = self
+            .entries
+            .get(hash)
+            .ok_or_else(|| StoreError::ChunkNotFound(hash.to_string()))?;
+
+        Ok(self
+            .backend
+            .read_range(&self.name, entry.offset, entry.compressed_length)?)
+    }
+}
+
+fn parse_hash(hash: &str) -> io::Result<[u8; 32]> {
+    hash.parse::<ChunkId>()
+        .map(|id| *id.as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
@@ -0,0 +1,172 @@
+use std::io;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{StoreError, backend::Backend};
+use crate::backup::{cdc_chunker::CHUNKER_FORMAT_VERSION, hash::HashAlgorithm};
+
+/// Name of the repository config object; its presence marks an initialized repository.
+pub const REPO_CONFIG_NAME: &str = "repo.json";
+
+/// Current repository format version: the layout of the store, index and manifests
+/// this build reads and writes. Older repositories are brought up to it by
+/// [`migrate`](super::migrate::migrate).
+pub const REPO_VERSION: u32 = 1;
+
+/// Deepest supported [`RepoConfig::fanout_depth`].
+pub const MAX_FANOUT_DEPTH: u32 = 2;
+
+/// Repository-wide settings, written once by `init`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepoConfig {
+    /// Format version the repository is in, [`REPO_VERSION`] when created.
+    pub version: u32,
+    /// Random id telling repositories apart, e.g. to key local caches.
+    /// Empty for repositories created before ids existed.
+    #[serde(default)]
+    pub id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub chunk_algorithm: String,
+    /// [`CHUNKER_FORMAT_VERSION`] of the build that created the repository; 0 for
+    /// repositories created before it was recorded. Chunks cut by another version do
+    /// not deduplicate against the stored ones.
+    #[serde(default)]
+    pub chunker_version: u32,
+    /// How chunk ids are computed. Fixed when the repository is created, since ids
+    /// from different algorithms never deduplicate against each other.
+    pub hash_algorithm: HashAlgorithm,
+    /// Secret key of a keyed `hash_algorithm` (SipHash), in hex; generated by `init`.
+    /// Without it, no chunk id of the repository can be computed again.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "hex_key")]
+    pub hash_key: Option<[u8; 16]>,
+    /// Levels of two-hex-digit subdirectories packs are spread over, 0 to
+    /// [`MAX_FANOUT_DEPTH`]: `packs/<id>.pack`, `packs/ab/<id>.pack` or
+    /// `packs/ab/cd/<id>.pack`, named after the last digits of the id (see
+    /// [`pack_name`](super::chunk_store::pack_name)). Fixed when the repository is
+    /// created.
+    #[serde(default)]
+    pub fanout_depth: u32,
+}
+
+impl RepoConfig {
+    /// Config for a repository created now.
+    pub fn new() -> Self {
+        let created_at = OffsetDateTime::now_utc();
+
+        // Unique enough without a random number generator: no two repositories are
+        // created by the same process in the same nanosecond.
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&created_at.unix_timestamp_nanos().to_le_bytes());
+        hasher.update(&std::process::id().to_le_bytes());
+        let id = hasher.finalize().to_hex()[..32].to_string();
+
+        RepoConfig {
+            version: REPO_VERSION,
+            id,
+            created_at,
+            chunk_algorithm: "gear".to_string(),
+            chunker_version: CHUNKER_FORMAT_VERSION,
+            hash_algorithm: HashAlgorithm::default(),
+            hash_key: None,
+            fanout_depth: 0,
+        }
+    }
+
+    /// Load the config of the repository in `backend`, if this build can work with
+    /// its format version (see [`RepoConfig::check_version`]).
+    ///
+    /// Fails with [`StoreError::NotInitialized`] if there is none.
+    pub fn load(backend: &dyn Backend) -> Result<Self, StoreError> {
+        let config = Self::read(backend)?;
+        config.check_version()?;
+        Ok(config)
+    }
+
+    /// Like [`RepoConfig::load`], whatever the format version.
+    pub fn read(backend: &dyn Backend) -> Result<Self, StoreError> {
+        let bytes = match backend.read(REPO_CONFIG_NAME) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(StoreError::NotInitialized);
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        serde_json::from_slice(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err).into())
+    }
+
+    /// Fail with [`StoreError::UnsupportedVersion`] for repositories written by a newer
+    /// rbckp, and with [`StoreError::NeedsMigration`] for ones in an older format.
+    pub fn check_version(&self) -> Result<(), StoreError> {
+        if self.version > REPO_VERSION {
+            return Err(StoreError::UnsupportedVersion {
+                version: self.version,
+                supported: REPO_VERSION,
+            });
+        }
+        if self.version < REPO_VERSION {
+            return Err(StoreError::NeedsMigration {
+                version: self.version,
+                current: REPO_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether this build cuts chunks at the same boundaries as the one that created
+    /// the repository. Unknown for repositories without a recorded version, which
+    /// count as compatible.
+    pub fn chunker_compatible(&self) -> bool {
+        self.chunker_version == 0 || self.chunker_version == CHUNKER_FORMAT_VERSION
+    }
+
+    pub fn save(&self, backend: &dyn Backend) -> io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(self)?;
+        backend.write(REPO_CONFIG_NAME, &bytes)
+    }
+}
+
+impl Default for RepoConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `Option<[u8; 16]>` as an optional hex string.
+mod hex_key {
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    pub fn serialize<S: Serializer>(
+        key: &Option<[u8; 16]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match key {
+            Some(key) => {
+                let hex: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+                serializer.serialize_some(&hex)
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<[u8; 16]>, D::Error> {
+        let Some(hex) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        let invalid = || de::Error::custom(format!("invalid hash key {:?}", hex));
+        if hex.len() != 32 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut key = [0; 16];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(Some(key))
+    }
+}
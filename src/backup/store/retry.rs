@@ -0,0 +1,169 @@
+use std::{io, path::Path, thread, time::Duration};
+
+use super::Backend;
+
+/// Upper bound for the delay between two attempts of a [`RetryPolicy::with_retries`].
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How often and how patiently to retry an operation that failed transiently.
+///
+/// Backups usually run unattended, so a dropped connection should cost a few
+/// seconds instead of the whole run. Delays double after every failed attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Retry `retries` times after the first attempt, waiting `base_delay` before the
+    /// first retry and doubling that up to [`MAX_RETRY_DELAY`].
+    pub fn with_retries(retries: u32, base_delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: retries.saturating_add(1),
+            initial_delay: base_delay,
+            max_delay: MAX_RETRY_DELAY,
+        }
+    }
+
+    /// Delay before retry number `retry` (0 for the first retry).
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay)
+    }
+
+    /// Run `op` until it succeeds, fails permanently, or the attempts are used up.
+    ///
+    /// Only errors accepted by [`is_transient`] are retried; the last error is returned.
+    pub fn run<T>(&self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut retry = 0;
+        loop {
+            match op() {
+                Err(err) if is_transient(&err) && retry + 1 < self.max_attempts => {
+                    let delay = self.delay(retry);
+                    log::warn!("{}, retrying in {:?}", err, delay);
+                    thread::sleep(delay);
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error is likely to go away when the operation is simply tried again,
+/// e.g. a dropped or timed out connection (as opposed to a missing object).
+pub fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ResourceBusy
+    )
+}
+
+/// The error kind a failed HTTP request with this status maps to.
+///
+/// A 404 is [`io::ErrorKind::NotFound`], which is how backends report missing
+/// objects. Timeouts, throttling and server errors (408, 429 and 5xx) are
+/// [`is_transient`]; other statuses, e.g. a failed authentication, are permanent.
+pub fn http_status_kind(status: u16) -> io::ErrorKind {
+    match status {
+        404 => io::ErrorKind::NotFound,
+        401 | 403 => io::ErrorKind::PermissionDenied,
+        416 => io::ErrorKind::UnexpectedEof,
+        408 | 504 => io::ErrorKind::TimedOut,
+        429 | 500..=599 => io::ErrorKind::ResourceBusy,
+        _ => io::ErrorKind::Other,
+    }
+}
+
+/// Retries the operations of a backend that fail with a transient error, e.g. on a
+/// network backend whose connection drops or which answers 503 now and then.
+///
+/// Reads, writes and existence checks are retried; writing the same object again is
+/// harmless, as objects are only ever written whole. Removals are not retried, since
+/// a removal that went through before its answer got lost would fail with
+/// [`io::ErrorKind::NotFound`] on the retry. Errors that are still transient after
+/// the last attempt become [`StoreError::Transient`].
+pub struct RetryBackend {
+    inner: Box<dyn Backend>,
+    policy: RetryPolicy,
+}
+
+impl RetryBackend {
+    pub fn new(inner: Box<dyn Backend>, policy: RetryPolicy) -> Self {
+        RetryBackend { inner, policy }
+    }
+
+    pub fn inner(&self) -> &dyn Backend {
+        &*self.inner
+    }
+
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+}
+
+impl Backend for RetryBackend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.policy.run(|| self.inner.read(name))
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.policy.run(|| self.inner.write(name, data))
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.policy.run(|| self.inner.list(prefix))
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.inner.remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.policy.run(|| self.inner.exists(name))
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        self.policy.run(|| self.inner.size(name))
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.policy.run(|| self.inner.read_range(name, offset, len))
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        self.inner.local_root()
+    }
+}
@@ -0,0 +1,328 @@
+//! Repository in an S3-compatible bucket (AWS S3, MinIO, ...).
+//!
+//! Region and endpoint come from the `[backend.s3]` (or `[store]`) settings section,
+//! credentials from the standard AWS chain: the `AWS_ACCESS_KEY_ID` /
+//! `AWS_SECRET_ACCESS_KEY` / `AWS_SESSION_TOKEN` environment variables, then the
+//! `~/.aws/credentials` profile, then web identity, container and instance metadata.
+//! Object names map to keys below the configured prefix.
+
+use std::{
+    collections::HashSet,
+    io,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use s3::{Bucket, Region, creds::Credentials, error::S3Error};
+
+#[cfg(feature = "async")]
+use super::{
+    StoreError,
+    async_store::{AsyncChunkStore, blocking, chunk_object_name},
+};
+use super::{
+    backend::Backend,
+    retry::{self, RetryPolicy},
+};
+#[cfg(feature = "async")]
+use crate::backup::hash::ChunkId;
+use crate::config::S3Settings;
+
+/// Objects at least this large are uploaded in parts; S3 caps single PUTs at 5 GiB
+/// and a failed part is much cheaper to redo than a whole pack.
+pub const MULTIPART_THRESHOLD: usize = 16 * 1024 * 1024;
+
+/// Size of every part but the last one (S3 requires at least 5 MiB).
+pub const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// How long [`Backend::exists`] keeps looking for an object this backend has just
+/// written: S3-compatible stores that are only eventually consistent may not list a
+/// new object right away, and reporting it missing would make it be written again.
+pub const READ_AFTER_WRITE_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 4,
+    initial_delay: Duration::from_millis(100),
+    max_delay: Duration::from_secs(2),
+};
+
+/// Whether an object of `len` bytes is uploaded with a multipart upload.
+pub fn uses_multipart(len: usize) -> bool {
+    len >= MULTIPART_THRESHOLD
+}
+
+/// S3 key of the object `name` below `prefix`.
+pub fn object_key(prefix: &str, name: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Settings for `s3://bucket/prefix`, with region and endpoint taken from `settings`
+/// (if any). A bare `s3://` uses bucket and prefix from `settings` as well.
+pub fn settings_for_url(url: &str, settings: Option<&S3Settings>) -> io::Result<S3Settings> {
+    let rest = url.strip_prefix("s3://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not an s3:// URL: {}", url),
+        )
+    })?;
+
+    let mut settings = settings.cloned().unwrap_or_default();
+    if !rest.is_empty() {
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        settings.bucket = bucket.to_string();
+        settings.prefix = prefix.to_string();
+    }
+
+    if settings.bucket.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no bucket in {} or the [backend.s3] settings", url),
+        ));
+    }
+    Ok(settings)
+}
+
+/// Backend storing every object as an S3 object.
+///
+/// Clones share the connection settings and the keys written so far.
+#[derive(Clone)]
+pub struct S3Backend {
+    bucket: Box<Bucket>,
+    prefix: String,
+    // Keys written by this backend, whose absence is not believed right away.
+    written: Arc<Mutex<HashSet<String>>>,
+}
+
+impl S3Backend {
+    pub fn new(settings: &S3Settings) -> io::Result<Self> {
+        let region = match &settings.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: settings.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => settings
+                .region
+                .parse()
+                .map_err(|err: std::str::Utf8Error| map_err(err.into()))?,
+        };
+        let credentials = Credentials::default().map_err(|err| map_err(err.into()))?;
+
+        let mut bucket = Bucket::new(&settings.bucket, region, credentials).map_err(map_err)?;
+        // Custom endpoints (MinIO and friends) rarely have per-bucket DNS names.
+        if settings.endpoint.is_some() {
+            bucket.set_path_style();
+        }
+
+        Ok(S3Backend {
+            bucket,
+            prefix: settings.prefix.trim_matches('/').to_string(),
+            written: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        object_key(&self.prefix, name)
+    }
+
+    fn write_multipart(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let content_type = "application/octet-stream";
+        let upload = self
+            .bucket
+            .initiate_multipart_upload(key, content_type)
+            .map_err(map_err)?;
+
+        let result = self.upload_parts(key, &upload.upload_id, content_type, data);
+        if result.is_err() {
+            // S3 keeps the parts of an upload that is neither completed nor aborted,
+            // and bills for them; leave no half-finished upload behind.
+            let _ = self.bucket.abort_upload(key, &upload.upload_id);
+        }
+        result
+    }
+
+    /// Upload `data` in parts to the multipart upload `upload_id` and complete it.
+    fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        content_type: &str,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut parts = Vec::new();
+        for (part, chunk) in data.chunks(MULTIPART_PART_SIZE).enumerate() {
+            let part = self
+                .bucket
+                .put_multipart_chunk(chunk, key, part as u32 + 1, upload_id, content_type)
+                .map_err(map_err)?;
+            parts.push(part);
+        }
+
+        self.bucket
+            .complete_multipart_upload(key, upload_id, parts)
+            .map_err(map_err)
+            .and_then(|response| check_status(response.status_code(), key))
+    }
+}
+
+impl Backend for S3Backend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let key = self.key(name);
+        let response = self.bucket.get_object(&key).map_err(map_err)?;
+        check_status(response.status_code(), &key)?;
+        Ok(response.to_vec())
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        // S3 objects only become visible once completely uploaded.
+        let key = self.key(name);
+        if uses_multipart(data.len()) {
+            self.write_multipart(&key, data)?;
+        } else {
+            let response = self.bucket.put_object(&key, data).map_err(map_err)?;
+            check_status(response.status_code(), &key)?;
+        }
+        self.written.lock().unwrap().insert(key);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        // `Bucket::list` follows the continuation tokens of paginated listings.
+        let pages = self.bucket.list(self.key(prefix), None).map_err(map_err)?;
+
+        let key_prefix = self.key("");
+        let mut names: Vec<String> = pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .filter_map(|object| object.key.strip_prefix(&key_prefix).map(str::to_string))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let key = self.key(name);
+        self.written.lock().unwrap().remove(&key);
+        let response = self.bucket.delete_object(&key).map_err(map_err)?;
+        check_status(response.status_code(), &key)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        // HEAD instead of GET: pack files can be large.
+        let key = self.key(name);
+        let attempts = if self.written.lock().unwrap().contains(&key) {
+            READ_AFTER_WRITE_RETRY.max_attempts
+        } else {
+            1
+        };
+        for retry in 0..attempts {
+            if retry > 0 {
+                let delay = READ_AFTER_WRITE_RETRY.delay(retry - 1);
+                log::debug!(
+                    "{} not visible yet after writing it, retrying in {:?}",
+                    key,
+                    delay
+                );
+                thread::sleep(delay);
+            }
+            let (_, status) = self.bucket.head_object(&key).map_err(map_err)?;
+            match check_status(status, &key) {
+                Ok(()) => return Ok(true),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(false)
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        let key = self.key(name);
+        let (head, status) = self.bucket.head_object(&key).map_err(map_err)?;
+        check_status(status, &key)?;
+        head.content_length
+            .and_then(|len| u64::try_from(len).ok())
+            .ok_or_else(|| io::Error::other(format!("no content length for {}", key)))
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+
+        let key = self.key(name);
+        let response = self
+            .bucket
+            .get_object_range(&key, offset, Some(offset + len - 1))
+            .map_err(map_err)?;
+        check_status(response.status_code(), &key)?;
+
+        let data = response.to_vec();
+        if data.len() as u64 != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "range {}..{} is past the end of {}",
+                    offset,
+                    offset + len,
+                    key
+                ),
+            ));
+        }
+        Ok(data)
+    }
+}
+
+/// The S3 client is blocking, so each call runs on tokio's blocking thread pool on a
+/// clone of the backend; many can be in flight at once.
+#[cfg(feature = "async")]
+impl AsyncChunkStore for S3Backend {
+    async fn put(&self, id: ChunkId, data: Vec<u8>) -> Result<(), StoreError> {
+        let backend = self.clone();
+        blocking(move || backend.write(&chunk_object_name(&id), &data)).await
+    }
+
+    async fn get(&self, id: ChunkId) -> Result<Vec<u8>, StoreError> {
+        let backend = self.clone();
+        match blocking(move || backend.read(&chunk_object_name(&id))).await {
+            Err(StoreError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                Err(StoreError::ChunkNotFound(id.to_string()))
+            }
+            result => result,
+        }
+    }
+
+    async fn has(&self, id: ChunkId) -> Result<bool, StoreError> {
+        let backend = self.clone();
+        blocking(move || backend.exists(&chunk_object_name(&id))).await
+    }
+
+    async fn delete(&self, id: ChunkId) -> Result<(), StoreError> {
+        let backend = self.clone();
+        blocking(move || backend.remove(&chunk_object_name(&id))).await
+    }
+}
+
+fn check_status(status: u16, key: &str) -> io::Result<()> {
+    match status {
+        200..=299 => Ok(()),
+        _ => Err(status_error(status, format!("HTTP {} for {}", status, key))),
+    }
+}
+
+fn status_error(status: u16, message: String) -> io::Error {
+    io::Error::new(retry::http_status_kind(status), message)
+}
+
+fn map_err(err: S3Error) -> io::Error {
+    match err {
+        S3Error::Io(err) => err,
+        // Also used for errors S3 reports in the body of a 200 response.
+        S3Error::HttpFailWithBody(status, ref body) => {
+            status_error(status, format!("HTTP {}: {}", status, body))
+        }
+        err => io::Error::other(err),
+    }
+}
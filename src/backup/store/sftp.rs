@@ -0,0 +1,470 @@
+//! Repository on a remote host, accessed over SFTP.
+//!
+//! Locations look like `sftp://user@host:port/path/to/repo`; user and port are
+//! optional (default: `$USER` and 22). Authentication uses the private key set as
+//! `key_path` in `[backend.sftp]` (or `[store]`) if there is one, else the SSH agent
+//! first, then the default keys in `~/.ssh`; there is never a password prompt.
+
+use std::{
+    env, fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex, MutexGuard},
+};
+
+use ssh2::{ErrorCode, Session, Sftp};
+
+use super::{
+    backend::Backend,
+    retry::{RetryPolicy, is_transient},
+};
+use crate::config::DEFAULT_SFTP_CONNECTIONS;
+
+const DEFAULT_PORT: u16 = 22;
+
+/// Network operations taking longer than this fail with [`io::ErrorKind::TimedOut`].
+const TIMEOUT_MS: u32 = 30_000;
+
+// libssh2 error codes that deserve a more specific error kind than `Other`.
+const LIBSSH2_ERROR_SOCKET_SEND: i32 = -7;
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+const LIBSSH2_ERROR_AUTHENTICATION_FAILED: i32 = -18;
+const LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED: i32 = -19;
+const LIBSSH2_ERROR_CHANNEL_CLOSED: i32 = -26;
+const LIBSSH2_ERROR_SOCKET_RECV: i32 = -43;
+const LIBSSH2_FX_PERMISSION_DENIED: i32 = 3;
+
+/// Parsed `sftp://` repository location.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SftpLocation {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    /// Repository directory on the remote host.
+    pub path: String,
+}
+
+/// Why an `sftp://` URL could not be parsed, see [`SftpLocation::parse`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SftpUrlError {
+    /// The URL does not start with `sftp://`.
+    NotSftp,
+    /// The user name before `@` is empty, or there is none and `$USER` is not set.
+    MissingUser,
+    /// Nothing between `sftp://` (or `user@`) and the port or path.
+    MissingHost,
+    /// A bracketed IPv6 host that is not closed, or is followed by something other than
+    /// a port.
+    BadHost(String),
+    /// The port is not a number from 0 to 65535.
+    BadPort(String),
+    /// There is no path after the host, or it is just `/`.
+    BadPath,
+}
+
+impl fmt::Display for SftpUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SftpUrlError::NotSftp => write!(f, "not an sftp:// URL"),
+            SftpUrlError::MissingUser => write!(f, "no user given and $USER is not set"),
+            SftpUrlError::MissingHost => write!(f, "missing host"),
+            SftpUrlError::BadHost(host) => write!(f, "invalid host {}", host),
+            SftpUrlError::BadPort(port) => write!(f, "invalid port {}", port),
+            SftpUrlError::BadPath => write!(f, "missing repository path"),
+        }
+    }
+}
+
+impl std::error::Error for SftpUrlError {}
+
+impl SftpLocation {
+    /// Parse `sftp://[user@]host[:port]/path`. IPv6 hosts go in brackets.
+    pub fn parse(url: &str) -> Result<Self, SftpUrlError> {
+        let rest = url.strip_prefix("sftp://").ok_or(SftpUrlError::NotSftp)?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) if !user.is_empty() => (user.to_string(), host_port),
+            Some(_) => return Err(SftpUrlError::MissingUser),
+            None => (
+                env::var("USER").map_err(|_| SftpUrlError::MissingUser)?,
+                authority,
+            ),
+        };
+
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let bad_host = || SftpUrlError::BadHost(host_port.to_string());
+            let (host, after) = bracketed.split_once(']').ok_or_else(bad_host)?;
+            match after {
+                "" => (host, None),
+                _ => (host, Some(after.strip_prefix(':').ok_or_else(bad_host)?)),
+            }
+        } else {
+            match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(SftpUrlError::MissingHost);
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| SftpUrlError::BadPort(port.to_string()))?,
+            None => DEFAULT_PORT,
+        };
+        if path.trim_start_matches('/').is_empty() {
+            return Err(SftpUrlError::BadPath);
+        }
+
+        Ok(SftpLocation {
+            user,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Backend storing objects as files below a directory on an SFTP server.
+///
+/// SSH sessions are pooled: one is opened up front, more (up to
+/// [`with_max_connections`](Self::with_max_connections)) when operations run
+/// concurrently, and every session is kept for the operations after it. When a
+/// session breaks, it is dropped and the failing operation retried on another one
+/// according to the [`RetryPolicy`]. Remote errors are mapped to distinct
+/// [`io::ErrorKind`]s: `PermissionDenied` for failed authentication, `NotFound` for
+/// missing paths and `BrokenPipe` for lost connections.
+pub struct SftpBackend {
+    location: SftpLocation,
+    key_path: Option<PathBuf>,
+    retry: RetryPolicy,
+    max_connections: usize,
+    pool: Mutex<Pool>,
+    // Signalled whenever a session is returned to the pool or closed.
+    returned: Condvar,
+}
+
+/// Sessions not in use, and how many are open in total.
+struct Pool {
+    idle: Vec<Connection>,
+    open: usize,
+}
+
+struct Connection {
+    // Keeps the session alive for as long as `sftp` is used.
+    _session: Session,
+    sftp: Sftp,
+}
+
+impl SftpBackend {
+    /// Connect to `location`, logging in with the private key at `key_path` if given.
+    /// Fails right away if the host is unreachable or authentication fails.
+    pub fn connect(location: SftpLocation, key_path: Option<&Path>) -> io::Result<Self> {
+        let retry = RetryPolicy::default();
+        let connection = retry.run(|| Connection::open(&location, key_path))?;
+
+        Ok(SftpBackend {
+            location,
+            key_path: key_path.map(Path::to_path_buf),
+            retry,
+            max_connections: DEFAULT_SFTP_CONNECTIONS,
+            pool: Mutex::new(Pool {
+                idle: vec![connection],
+                open: 1,
+            }),
+            returned: Condvar::new(),
+        })
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Keep at most `max` SSH sessions open (at least one).
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max.max(1);
+        self
+    }
+
+    pub fn location(&self) -> &SftpLocation {
+        &self.location
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        Path::new(&self.location.path).join(name)
+    }
+
+    fn pool(&self) -> MutexGuard<'_, Pool> {
+        self.pool
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// An idle session from the pool, or a new one if fewer than the maximum are open;
+    /// otherwise waits for one to be returned.
+    fn checkout(&self) -> io::Result<Connection> {
+        let mut pool = self.pool();
+        loop {
+            if let Some(connection) = pool.idle.pop() {
+                return Ok(connection);
+            }
+            if pool.open < self.max_connections {
+                pool.open += 1;
+                drop(pool);
+                return Connection::open(&self.location, self.key_path.as_deref())
+                    .inspect_err(|_| self.checkin(None));
+            }
+            pool = self
+                .returned
+                .wait(pool)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Give a session back to the pool, or with `None`, account for one that was closed.
+    fn checkin(&self, connection: Option<Connection>) {
+        let mut pool = self.pool();
+        match connection {
+            Some(connection) => pool.idle.push(connection),
+            None => pool.open -= 1,
+        }
+        self.returned.notify_one();
+    }
+
+    /// Run `op` on a pooled SFTP channel, reconnecting and retrying on transient errors.
+    fn with_sftp<T>(&self, op: impl Fn(&Sftp) -> io::Result<T>) -> io::Result<T> {
+        self.retry.run(|| {
+            let connection = self.checkout()?;
+            let result = op(&connection.sftp);
+            // A session that failed like this is most likely dead; the retry gets a
+            // fresh one.
+            let broken = matches!(&result, Err(err) if is_transient(err));
+            self.checkin((!broken).then_some(connection));
+            result
+        })
+    }
+}
+
+impl Connection {
+    fn open(location: &SftpLocation, key_path: Option<&Path>) -> io::Result<Self> {
+        let tcp = TcpStream::connect((location.host.as_str(), location.port))?;
+
+        let mut session = Session::new().map_err(map_err)?;
+        session.set_timeout(TIMEOUT_MS);
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(map_err)?;
+
+        authenticate(&session, &location.user, key_path)?;
+
+        let sftp = session.sftp().map_err(map_err)?;
+        Ok(Connection {
+            _session: session,
+            sftp,
+        })
+    }
+}
+
+/// Log in with the key at `key_path`, or else try the SSH agent, then the usual key
+/// files.
+fn authenticate(session: &Session, user: &str, key_path: Option<&Path>) -> io::Result<()> {
+    if let Some(key_path) = key_path {
+        if !key_path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("SSH key {} does not exist", key_path.display()),
+            ));
+        }
+        session
+            .userauth_pubkey_file(user, None, key_path, None)
+            .map_err(map_err)?;
+        if session.authenticated() {
+            return Ok(());
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "SSH authentication with key {} failed for user {}",
+                key_path.display(),
+                user
+            ),
+        ));
+    }
+
+    if session.userauth_agent(user).is_ok() && session.authenticated() {
+        return Ok(());
+    }
+
+    if let Some(home) = env::var_os("HOME") {
+        let ssh_dir = Path::new(&home).join(".ssh");
+        for key in ["id_ed25519", "id_ecdsa", "id_rsa"] {
+            let key_path = ssh_dir.join(key);
+            if key_path.is_file()
+                && session
+                    .userauth_pubkey_file(user, None, &key_path, None)
+                    .is_ok()
+                && session.authenticated()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("SSH authentication failed for user {}", user),
+    ))
+}
+
+fn map_err(err: ssh2::Error) -> io::Error {
+    let kind = match err.code() {
+        ErrorCode::Session(
+            LIBSSH2_ERROR_AUTHENTICATION_FAILED | LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED,
+        )
+        | ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED) => io::ErrorKind::PermissionDenied,
+        ErrorCode::Session(
+            LIBSSH2_ERROR_SOCKET_SEND
+            | LIBSSH2_ERROR_SOCKET_RECV
+            | LIBSSH2_ERROR_SOCKET_DISCONNECT
+            | LIBSSH2_ERROR_CHANNEL_CLOSED,
+        ) => io::ErrorKind::BrokenPipe,
+        // ssh2 already maps timeouts and missing files.
+        _ => return err.into(),
+    };
+    io::Error::new(kind, err.to_string())
+}
+
+/// `mkdir -p` on the remote side.
+fn create_dir_all(sftp: &Sftp, dir: &Path) -> io::Result<()> {
+    if dir.as_os_str().is_empty() || sftp.stat(dir).is_ok() {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        create_dir_all(sftp, parent)?;
+    }
+
+    match sftp.mkdir(dir, 0o755) {
+        Ok(()) => Ok(()),
+        // Lost a race against another writer, which is fine.
+        Err(_) if sftp.stat(dir).is_ok() => Ok(()),
+        Err(err) => Err(map_err(err)),
+    }
+}
+
+/// Collect the names (relative to `root`, `/`-separated) of all files below `dir`.
+fn list_files(sftp: &Sftp, root: &Path, dir: &Path, names: &mut Vec<String>) -> io::Result<()> {
+    for (path, stat) in sftp.readdir(dir).map_err(map_err)? {
+        if stat.is_dir() {
+            list_files(sftp, root, &path, names)?;
+            continue;
+        }
+
+        let Some(name) = path.strip_prefix(root).ok().and_then(Path::to_str) else {
+            continue;
+        };
+        names.push(name.to_string());
+    }
+    Ok(())
+}
+
+impl Backend for SftpBackend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let path = self.path(name);
+        self.with_sftp(|sftp| {
+            let mut data = Vec::new();
+            sftp.open(&path).map_err(map_err)?.read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.path(name);
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        self.with_sftp(|sftp| {
+            if let Some(parent) = path.parent() {
+                create_dir_all(sftp, parent)?;
+            }
+
+            let mut file = sftp.create(&tmp_path).map_err(map_err)?;
+            file.write_all(data)?;
+            file.close().map_err(map_err)?;
+
+            // Servers without the posix-rename extension refuse to replace the target.
+            if sftp.rename(&tmp_path, &path, None).is_err() {
+                if sftp.stat(&path).is_ok() {
+                    sftp.unlink(&path).map_err(map_err)?;
+                }
+                sftp.rename(&tmp_path, &path, None).map_err(map_err)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        // Only the directory holding the prefix (and below) can contain matches.
+        let dir = match prefix.rfind('/') {
+            Some(slash) => &prefix[..slash],
+            None => "",
+        };
+        let root = Path::new(&self.location.path);
+        let dir_path = self.path(dir);
+
+        let mut names = self.with_sftp(|sftp| {
+            let mut names = Vec::new();
+            match list_files(sftp, root, &dir_path, &mut names) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+            Ok(names)
+        })?;
+        names.retain(|name| name.starts_with(prefix));
+        names.sort();
+        Ok(names)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let path = self.path(name);
+        self.with_sftp(|sftp| sftp.unlink(&path).map_err(map_err))
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        let path = self.path(name);
+        self.with_sftp(|sftp| match sftp.stat(&path).map_err(map_err) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        })
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        let path = self.path(name);
+        self.with_sftp(|sftp| {
+            let stat = sftp.stat(&path).map_err(map_err)?;
+            stat.size.ok_or_else(|| {
+                io::Error::other(format!("server did not report the size of {}", name))
+            })
+        })
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let path = self.path(name);
+        self.with_sftp(|sftp| {
+            let mut file = sftp.open(&path).map_err(map_err)?;
+            file.seek(SeekFrom::Start(offset))?;
+
+            let mut data = vec![0u8; len as usize];
+            file.read_exact(&mut data)?;
+            Ok(data)
+        })
+    }
+}
@@ -0,0 +1,151 @@
+//! A backend that keeps the same objects on two backends for redundancy.
+//!
+//! Configured with `[store] type = tee`, which opens a plain repository name on both
+//! sides:
+//!
+//! ```ini
+//! [store]
+//! type = tee
+//! primary.type = local
+//! secondary.type = s3
+//! bucket = my-backups
+//! ```
+
+use std::{fmt, io, path::Path};
+
+use super::Backend;
+
+/// Writes every object to both `primary` and `secondary`, and reads from the
+/// secondary where the primary fails.
+///
+/// Both sides end up as complete copies of the repository, each of which can be
+/// opened on its own. The repository lock is only taken on the primary.
+pub struct TeeBackend {
+    primary: Box<dyn Backend>,
+    secondary: Box<dyn Backend>,
+}
+
+/// A write that did not reach both sides of a [`TeeBackend`], wrapped in the
+/// [`io::Error`] it returns; turned into [`StoreError::TeeWritePartial`].
+#[derive(Debug)]
+pub struct TeeWriteError {
+    pub primary_ok: bool,
+    pub secondary_ok: bool,
+    /// Why the write failed: the primary's error if it did.
+    pub error: io::Error,
+}
+
+impl fmt::Display for TeeWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.primary_ok, self.secondary_ok) {
+            (true, _) => write!(f, "written to the primary only: {}", self.error),
+            (false, true) => write!(f, "written to the secondary only: {}", self.error),
+            (false, false) => write!(f, "written to neither side: {}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for TeeWriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl TeeBackend {
+    pub fn new(primary: Box<dyn Backend>, secondary: Box<dyn Backend>) -> Self {
+        TeeBackend { primary, secondary }
+    }
+
+    pub fn primary(&self) -> &dyn Backend {
+        &*self.primary
+    }
+
+    pub fn secondary(&self) -> &dyn Backend {
+        &*self.secondary
+    }
+
+    /// `op` on the primary, or on the secondary if that fails. If both fail, the
+    /// primary's error is returned.
+    fn read_either<T>(&self, op: impl Fn(&dyn Backend) -> io::Result<T>) -> io::Result<T> {
+        op(&*self.primary).or_else(|err| {
+            log::debug!("primary failed ({}), trying the secondary", err);
+            op(&*self.secondary).map_err(|_| err)
+        })
+    }
+}
+
+impl Backend for TeeBackend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.read_either(|backend| backend.read(name))
+    }
+
+    /// Writes to both sides even if the first fails, then fails with a
+    /// [`TeeWriteError`] unless both succeeded.
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        match (
+            self.primary.write(name, data),
+            self.secondary.write(name, data),
+        ) {
+            (Ok(()), Ok(())) => Ok(()),
+            (primary, secondary) => {
+                let (primary_ok, secondary_ok) = (primary.is_ok(), secondary.is_ok());
+                let error = primary.err().or(secondary.err()).expect("one side failed");
+                Err(io::Error::other(TeeWriteError {
+                    primary_ok,
+                    secondary_ok,
+                    error,
+                }))
+            }
+        }
+    }
+
+    /// Names on either side. A side that cannot be listed is left out, unless
+    /// neither can.
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut names = match (self.primary.list(prefix), self.secondary.list(prefix)) {
+            (Ok(mut primary), Ok(secondary)) => {
+                primary.extend(secondary);
+                primary
+            }
+            (Ok(names), Err(err)) | (Err(err), Ok(names)) => {
+                log::warn!("listing {} on one side failed: {}", prefix, err);
+                names
+            }
+            (Err(err), Err(_)) => return Err(err),
+        };
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Removes from both sides; an object missing on one of them is fine.
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let missing = |err: &io::Error| err.kind() == io::ErrorKind::NotFound;
+        match (self.primary.remove(name), self.secondary.remove(name)) {
+            (Err(err), _) | (_, Err(err)) if !missing(&err) => Err(err),
+            // Missing on both sides.
+            (Err(err), Err(_)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        match self.primary.exists(name) {
+            Ok(true) => Ok(true),
+            Ok(false) => self.secondary.exists(name).or(Ok(false)),
+            Err(err) => self.secondary.exists(name).map_err(|_| err),
+        }
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        self.read_either(|backend| backend.size(name))
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.read_either(|backend| backend.read_range(name, offset, len))
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        self.primary.local_root()
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    io,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use super::Backend;
+
+/// Writes a set of objects to a backend on up to `max_concurrent` threads at once.
+///
+/// Backends are blocking, so for remote ones every write waits out a round trip; a few
+/// writes in flight hide most of that latency. Used by [`ChunkStore`](super::ChunkStore)
+/// for the packs of one upload batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConcurrentUploader {
+    max_concurrent: usize,
+}
+
+impl Default for ConcurrentUploader {
+    /// One write at a time.
+    fn default() -> Self {
+        ConcurrentUploader::new(1)
+    }
+}
+
+impl ConcurrentUploader {
+    /// Uploader with up to `max_concurrent` writes in flight; 0 counts as 1.
+    pub fn new(max_concurrent: usize) -> Self {
+        ConcurrentUploader {
+            max_concurrent: max_concurrent.max(1),
+        }
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    /// Write every `(name, data)` of `objects` to `backend`.
+    ///
+    /// Fewer objects than `max_concurrent` get one thread each, a single one is written
+    /// on the calling thread. After the first failed write no new ones are started; the
+    /// ones in flight finish, and that first error is returned.
+    pub fn upload<B: Backend + ?Sized>(
+        &self,
+        backend: &B,
+        objects: &[(String, Vec<u8>)],
+    ) -> io::Result<()> {
+        let workers = self.max_concurrent.min(objects.len());
+        if workers <= 1 {
+            return objects
+                .iter()
+                .try_for_each(|(name, data)| backend.write(name, data));
+        }
+
+        let next = AtomicUsize::new(0);
+        let first_error: Mutex<Option<io::Error>> = Mutex::new(None);
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while first_error.lock().unwrap().is_none() {
+                        let Some((name, data)) = objects.get(next.fetch_add(1, Ordering::SeqCst))
+                        else {
+                            break;
+                        };
+                        if let Err(err) = backend.write(name, data) {
+                            first_error.lock().unwrap().get_or_insert(err);
+                        }
+                    }
+                });
+            }
+        });
+        match first_error.into_inner().unwrap() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
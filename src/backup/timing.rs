@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+/// The parts of a backup that are timed separately, to see where the time goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading file content, or a stream.
+    Read,
+    /// Finding chunk boundaries.
+    Chunk,
+    /// Hashing chunks and whole-file content.
+    Hash,
+}
+
+/// Time spent in each [`Phase`], summed over all the times it was entered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    pub read: Duration,
+    pub chunk: Duration,
+    pub hash: Duration,
+}
+
+impl PhaseTimings {
+    /// Count `elapsed` towards `phase`.
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        *self.get_mut(phase) += elapsed;
+    }
+
+    /// Run `f`, counting the time it takes towards `phase`.
+    pub fn time<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Read => self.read,
+            Phase::Chunk => self.chunk,
+            Phase::Hash => self.hash,
+        }
+    }
+
+    /// Time spent in all phases together. Anything else a backup does, such as writing
+    /// chunks, is not included.
+    pub fn total(&self) -> Duration {
+        self.read + self.chunk + self.hash
+    }
+
+    fn get_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Read => &mut self.read,
+            Phase::Chunk => &mut self.chunk,
+            Phase::Hash => &mut self.hash,
+        }
+    }
+}
+
+/// `bytes` processed in `elapsed`, in MB (10^6 bytes) per second; 0 if no time passed.
+pub fn throughput_mb_s(bytes: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        bytes as f64 / 1e6 / seconds
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use time::OffsetDateTime;
+
+use crate::backup::{
+    cdc_chunker::{self, CdcParams},
+    filter::{ExcludeFilter, FileFilter},
+    manifest::{Manifest, ManifestEntry},
+    restore,
+    snapshot::Snapshot,
+    store::{Backend, ChunkStore, StoreError, index::ChunkLocation, pack},
+    walk,
+};
+
+/// How a live tree differs from a snapshot, see [`verify_tree`]. Paths are relative
+/// ([`restore::entry_path`]) and sorted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeReport {
+    /// Files in the tree that the snapshot does not have.
+    pub added: Vec<PathBuf>,
+    /// Files in the snapshot that are gone from the tree.
+    pub missing: Vec<PathBuf>,
+    /// Files in both whose content (or symlink target) differs.
+    pub modified: Vec<PathBuf>,
+    /// Files compared, in the tree and the snapshot.
+    pub checked: usize,
+}
+
+impl TreeReport {
+    /// Whether the tree matches the snapshot.
+    pub fn is_clean(&self) -> bool {
+        self.added.is_empty() && self.missing.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Compare the files at or below `root` with those of `manifest` below the same path,
+/// without restoring anything.
+///
+/// Files are found like a backup finds them (honoring `.rbckpignore` files, symlinks
+/// as links). A file is modified if its size or modification time differs from the
+/// recorded ones; with `read_data` it is also read and chunked with those parameters,
+/// and modified if its chunks differ, which catches changes that kept the size and
+/// modification time.
+pub fn verify_tree(
+    manifest: &Manifest,
+    root: &Path,
+    read_data: Option<&CdcParams>,
+) -> io::Result<TreeReport> {
+    let mut files = Vec::new();
+    walk::collect_files(
+        root,
+        &ExcludeFilter::default(),
+        &FileFilter::default(),
+        false,
+        &mut files,
+    )?;
+
+    let root_key = restore::entry_path(&root.to_string_lossy());
+    let mut recorded: BTreeMap<PathBuf, &ManifestEntry> = manifest
+        .entries
+        .iter()
+        .map(|entry| (restore::entry_path(&entry.name), entry))
+        .filter(|(key, _)| key.starts_with(&root_key))
+        .collect();
+
+    let mut report = TreeReport::default();
+    for file in files {
+        let key = restore::entry_path(&file.to_string_lossy());
+        let Some(entry) = recorded.remove(&key) else {
+            report.added.push(key);
+            continue;
+        };
+        report.checked += 1;
+        if is_modified(entry, &file, read_data)? {
+            log::debug!("{} differs from the snapshot", file.display());
+            report.modified.push(key);
+        }
+    }
+    report.missing = recorded.into_keys().collect();
+
+    report.added.sort();
+    report.modified.sort();
+    Ok(report)
+}
+
+fn is_modified(
+    entry: &ManifestEntry,
+    path: &Path,
+    read_data: Option<&CdcParams>,
+) -> io::Result<bool> {
+    let metadata = fs::symlink_metadata(path)?;
+    if let Some(target) = entry.symlink_target() {
+        return Ok(
+            !metadata.is_symlink() || fs::read_link(path)?.as_os_str().as_encoded_bytes() != target
+        );
+    }
+    if metadata.is_symlink() || metadata.len() != entry.size {
+        return Ok(true);
+    }
+    if let Some(mtime) = entry.mtime
+        && metadata.modified().ok().map(OffsetDateTime::from) != Some(mtime)
+    {
+        return Ok(true);
+    }
+
+    let Some(params) = read_data else {
+        return Ok(false);
+    };
+    let data = crate::backup::io::read_file(path, false)?;
+    let chunks = cdc_chunker::chunk_refs_cdc_parallel(&data, params);
+    Ok(!chunks
+        .iter()
+        .map(|chunk_ref| &chunk_ref.hash)
+        .eq(&entry.chunks))
+}
+
+/// Which chunks [`verify_repo`] re-reads: about `P%` of them, or bucket `K/N`.
+///
+/// Chunks are picked by a seeded hash of their id, so a subset is the same on every
+/// run and every machine, and buckets `1/N` to `N/N` split the chunks into `N`
+/// disjoint parts that together cover all of them: checking `1/7` on Monday, `2/7`
+/// on Tuesday and so on re-reads the whole repository once a week.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataSubset {
+    /// About this share of the chunks, in percent (0 to 100).
+    Percent(f64),
+    /// Bucket `bucket` (from 1) of `buckets`.
+    Bucket { bucket: u32, buckets: u32 },
+}
+
+// Hashed with each chunk id to pick subsets. Changing it reshuffles all buckets.
+const SUBSET_SEED: &[u8] = b"rbckp read-data-subset";
+
+impl DataSubset {
+    /// Every chunk.
+    pub const ALL: DataSubset = DataSubset::Percent(100.0);
+
+    /// Whether the chunk with id `hash` is in the subset.
+    pub fn contains(&self, hash: &str) -> bool {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(SUBSET_SEED);
+        hasher.update(hash.as_bytes());
+        let value = u64::from_le_bytes(hasher.finalize().as_bytes()[..8].try_into().unwrap());
+        match *self {
+            DataSubset::Percent(percent) => {
+                percent >= 100.0 || (value as f64) < percent / 100.0 * u64::MAX as f64
+            }
+            DataSubset::Bucket { bucket, buckets } => {
+                value % u64::from(buckets) == u64::from(bucket - 1)
+            }
+        }
+    }
+}
+
+impl FromStr for DataSubset {
+    type Err = String;
+
+    /// `P%` (e.g. `2.5%`) or `K/N` with `1 <= K <= N` (e.g. `3/7`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(percent) = s.strip_suffix('%') {
+            return match percent.parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(DataSubset::Percent(percent)),
+                _ => Err(format!("{} is not a percentage from 0% to 100%", s)),
+            };
+        }
+        let bucket = s.split_once('/').and_then(|(bucket, buckets)| {
+            Some((bucket.parse::<u32>().ok()?, buckets.parse::<u32>().ok()?))
+        });
+        match bucket {
+            Some((bucket, buckets)) if 1 <= bucket && bucket <= buckets => {
+                Ok(DataSubset::Bucket { bucket, buckets })
+            }
+            _ => Err(format!(
+                "expected a percentage like 10% or a bucket K/N with 1 <= K <= N, got {}",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DataSubset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataSubset::Percent(percent) => write!(f, "{}%", percent),
+            DataSubset::Bucket { bucket, buckets } => write!(f, "{}/{}", bucket, buckets),
+        }
+    }
+}
+
+/// Something wrong with a repository, found by [`verify_repo`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RepoProblem {
+    /// A snapshot that cannot be loaded.
+    BrokenSnapshot { id: String, error: String },
+    /// A chunk a snapshot refers to that is not in the index.
+    MissingChunk { snapshot: String, hash: String },
+    /// A pack the index points into that is missing, damaged, or does not hold a
+    /// chunk where the index says.
+    BadPack { name: String, reason: String },
+    /// A chunk whose stored data no longer matches its id.
+    CorruptChunk { hash: String, pack: String },
+}
+
+impl fmt::Display for RepoProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoProblem::BrokenSnapshot { id, error } => {
+                write!(f, "snapshot {} cannot be read: {}", id, error)
+            }
+            RepoProblem::MissingChunk { snapshot, hash } => {
+                write!(f, "snapshot {} refers to missing chunk {}", snapshot, hash)
+            }
+            RepoProblem::BadPack { name, reason } => write!(f, "pack {}: {}", name, reason),
+            RepoProblem::CorruptChunk { hash, pack } => {
+                write!(f, "chunk {} in {} does not match its id", hash, pack)
+            }
+        }
+    }
+}
+
+/// Outcome of [`verify_repo`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepoReport {
+    pub snapshots: usize,
+    /// Chunks in the index.
+    pub chunks: usize,
+    /// Packs the index points into.
+    pub packs: usize,
+    /// Chunks re-read and re-hashed.
+    pub chunks_read: usize,
+    pub bytes_read: u64,
+    pub problems: Vec<RepoProblem>,
+}
+
+impl RepoReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check the structure of the repository in `store`, and re-read the chunks in
+/// `read_data`.
+///
+/// The structure is always checked in full, which only reads snapshots and pack
+/// footers: every snapshot must load and refer to indexed chunks only, and every
+/// indexed chunk must be listed at its indexed place in the footer of its pack.
+/// The chunks in `read_data` are then read from the backend (bypassing any chunk
+/// cache) and re-hashed.
+pub fn verify_repo<B: Backend>(
+    store: &ChunkStore<B>,
+    read_data: Option<&DataSubset>,
+) -> Result<RepoReport, StoreError> {
+    let backend = store.backend();
+    let mut report = RepoReport::default();
+
+    for id in Snapshot::list(backend)? {
+        report.snapshots += 1;
+        let snapshot = match Snapshot::load(backend, &id) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                report.problems.push(RepoProblem::BrokenSnapshot {
+                    id,
+                    error: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let mut seen = HashSet::new();
+        for hash in snapshot
+            .manifest
+            .entries
+            .iter()
+            .flat_map(|entry| &entry.chunks)
+        {
+            if seen.insert(hash) && !store.contains(hash) {
+                report.problems.push(RepoProblem::MissingChunk {
+                    snapshot: id.clone(),
+                    hash: hash.clone(),
+                });
+            }
+        }
+    }
+
+    let mut packs: BTreeMap<u64, Vec<(String, &ChunkLocation)>> = BTreeMap::new();
+    for (hash, location) in store.chunks() {
+        packs
+            .entry(location.pack_id)
+            .or_default()
+            .push((hash, location));
+    }
+    report.packs = packs.len();
+
+    let hasher = store.hash_algorithm().keyed_hasher(store.hash_key());
+    for (pack_id, mut chunks) in packs {
+        report.chunks += chunks.len();
+        let name = store.pack_name(pack_id);
+        let footer: HashMap<String, pack::PackEntry> = match pack::read_footer(backend, &name) {
+            Ok(entries) => entries
+                .into_iter()
+                .map(|entry| (entry.hash.clone(), entry))
+                .collect(),
+            Err(err) => {
+                report.problems.push(RepoProblem::BadPack {
+                    name,
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+
+        chunks.sort_unstable_by_key(|(_, location)| location.offset);
+        for (hash, location) in chunks {
+            let listed = footer.get(&hash).is_some_and(|entry| {
+                (entry.offset, entry.length, entry.compressed_length)
+                    == (location.offset, location.length, location.compressed_length)
+            });
+            if !listed {
+                report.problems.push(RepoProblem::BadPack {
+                    name: name.clone(),
+                    reason: format!("does not hold chunk {} where the index says", hash),
+                });
+                continue;
+            }
+
+            if !read_data.is_some_and(|subset| subset.contains(&hash)) {
+                continue;
+            }
+            let data = match backend.read_range(&name, location.offset, location.compressed_length)
+            {
+                Ok(data) => data,
+                Err(err) => {
+                    report.problems.push(RepoProblem::BadPack {
+                        name: name.clone(),
+                        reason: format!("cannot read chunk {}: {}", hash, err),
+                    });
+                    continue;
+                }
+            };
+            report.chunks_read += 1;
+            report.bytes_read += data.len() as u64;
+            if data.len() as u64 != location.length || hasher.hash(&data).to_string() != hash {
+                report.problems.push(RepoProblem::CorruptChunk {
+                    hash,
+                    pack: name.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
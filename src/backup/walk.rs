@@ -0,0 +1,210 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::backup::filter::{ExcludeFilter, FileFilter};
+
+/// All regular files and symlinks at or below `path` that `filter` does not exclude
+/// and `file_filter` matches, in a stable (sorted) order.
+///
+/// `path` is the backup root the filter's patterns are relative to; `.rbckpignore`
+/// files found on the way add to it. Excluded directories are not descended into.
+/// Symlinks are collected as they are unless `follow_symlinks` is set; then their
+/// targets are walked instead, each directory only once so that link loops end, and
+/// links that cannot be followed are skipped with a warning. Other special files are
+/// always skipped with a warning.
+pub fn collect_files(
+    path: &Path,
+    filter: &ExcludeFilter,
+    file_filter: &FileFilter,
+    follow_symlinks: bool,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    // A root that is a single file is matched by its name.
+    let root_metadata = if follow_symlinks {
+        fs::metadata(path)?
+    } else {
+        fs::symlink_metadata(path)?
+    };
+    let relative = if root_metadata.is_dir() {
+        PathBuf::new()
+    } else {
+        path.file_name().map(PathBuf::from).unwrap_or_default()
+    };
+    let mut walk = Walk {
+        file_filter,
+        follow_symlinks,
+        visited: HashSet::new(),
+        files,
+    };
+    walk.collect(path, &relative, filter)
+}
+
+struct Walk<'a> {
+    file_filter: &'a FileFilter,
+    follow_symlinks: bool,
+    // (device, inode) of the directories entered so far.
+    visited: HashSet<(u64, u64)>,
+    files: &'a mut Vec<PathBuf>,
+}
+
+impl Walk<'_> {
+    fn collect(&mut self, path: &Path, relative: &Path, filter: &ExcludeFilter) -> io::Result<()> {
+        let mut metadata = fs::symlink_metadata(path)?;
+        if metadata.is_symlink() && self.follow_symlinks {
+            metadata = match fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    log::warn!(
+                        "skipping {}: cannot follow symlink: {}",
+                        path.display(),
+                        err
+                    );
+                    return Ok(());
+                }
+            };
+        }
+
+        let file_type = metadata.file_type();
+        if !relative.as_os_str().is_empty() && filter.is_excluded(relative, file_type.is_dir()) {
+            log::debug!("excluded {}", path.display());
+            return Ok(());
+        }
+
+        if file_type.is_file() || file_type.is_symlink() {
+            if self.file_filter.matches(relative) {
+                self.files.push(path.to_path_buf());
+            } else {
+                log::debug!("not included: {}", path.display());
+            }
+        } else if file_type.is_dir() {
+            if let Some(id) = file_id(&metadata)
+                && !self.visited.insert(id)
+            {
+                log::warn!(
+                    "skipping {}: directory was already backed up (symlink loop?)",
+                    path.display()
+                );
+                return Ok(());
+            }
+            let filter = filter.with_ignore_file(path, relative)?;
+
+            let mut children = fs::read_dir(path)?
+                .map(|dir_entry| dir_entry.map(|dir_entry| dir_entry.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+
+            for child in children {
+                self.collect(&path.join(&child), &relative.join(&child), &filter)?;
+            }
+        } else {
+            log::warn!(
+                "skipping {}: not a regular file, directory or symlink",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// The backup roots among `paths`, in the order given: without `.` components (so
+/// `./src/` becomes `src`), and without paths that another one already covers, i.e.
+/// the same path spelled differently or one inside another. Covered paths are left to
+/// the walk of the path covering them, excludes included.
+///
+/// Paths are compared by where they are on disk, with the directories they are in
+/// resolved but not a final symlink, which is backed up as a link of its own. Paths
+/// that cannot be resolved are kept as given, for the walk to report.
+pub fn backup_roots<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Vec<PathBuf> {
+    let mut roots: Vec<(PathBuf, Option<PathBuf>)> = Vec::new();
+    for path in paths {
+        let path: PathBuf = path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        let path = if path.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            path
+        };
+        let location = location(&path);
+
+        if let Some(location) = &location {
+            if let Some((outer, _)) = roots.iter().find(|(_, other)| {
+                other
+                    .as_ref()
+                    .is_some_and(|other| location.starts_with(other))
+            }) {
+                log::debug!(
+                    "{} is already backed up with {}",
+                    path.display(),
+                    outer.display()
+                );
+                continue;
+            }
+            roots.retain(|(inner, other)| {
+                let covered = other
+                    .as_ref()
+                    .is_some_and(|other| other.starts_with(location));
+                if covered {
+                    log::debug!(
+                        "{} is already backed up with {}",
+                        inner.display(),
+                        path.display()
+                    );
+                }
+                !covered
+            });
+        }
+        roots.push((path, location));
+    }
+    roots.into_iter().map(|(path, _)| path).collect()
+}
+
+/// Absolute location of `path` with every component but a last named one resolved;
+/// `None` if there is nothing at `path`.
+fn location(path: &Path) -> Option<PathBuf> {
+    fs::symlink_metadata(path).ok()?;
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            Some(fs::canonicalize(parent).ok()?.join(name))
+        }
+        _ => fs::canonicalize(path).ok(),
+    }
+}
+
+/// The directories from `root` down to each of `files` (found below it by
+/// [`collect_files`]), `root` included, parents before children. Empty if `root` is a
+/// single file.
+pub fn parent_dirs(root: &Path, files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs = BTreeSet::new();
+    for file in files {
+        if !file.starts_with(root) || file == root {
+            continue;
+        }
+        for dir in file.ancestors().skip(1) {
+            if !dirs.insert(dir.to_path_buf()) || dir == root {
+                break;
+            }
+        }
+    }
+    dirs.into_iter().collect()
+}
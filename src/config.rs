@@ -1,23 +1,669 @@
-use config::{Config, ConfigError, File};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use config::{Config, ConfigError, File, FileFormat};
+
+use crate::backup::{
+    cdc_chunker::{
+        CdcParams, ChunkingStrategy, DEFAULT_GEAR_SHIFT, DEFAULT_MAX_BOUNDARY_BITS,
+        DEFAULT_MIN_BOUNDARY_BITS,
+    },
+    hash::{CHUNK_ID_HEX_LEN, HashAlgorithm},
+    retention::RetentionPolicy,
+    store::{chunk_store::DEFAULT_PACK_SIZE, retry::RetryPolicy},
+};
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct ChunkSettings {
     pub min: usize,
     pub avg: usize,
     pub max: usize,
+    /// Overrides the boundary bits otherwise derived from `avg`; must be within
+    /// `min_boundary_bits..=max_boundary_bits`.
+    #[serde(default)]
+    pub boundary_bits: Option<u32>,
+    /// Fewest boundary bits, however they are chosen; raises the average of a tiny `avg`.
+    #[serde(default = "default_min_boundary_bits")]
+    pub min_boundary_bits: u32,
+    /// Most boundary bits, at most 31.
+    #[serde(default = "default_max_boundary_bits")]
+    pub max_boundary_bits: u32,
+    /// Per-byte shift of the rolling hash (1 or 2); changing it moves all boundaries.
+    #[serde(default = "default_gear_shift")]
+    pub gear_shift: u32,
+    /// Seed of the gear table; unset means the built-in one. Changing it moves all
+    /// boundaries, so existing chunks no longer deduplicate.
+    #[serde(default)]
+    pub gear_seed: Option<u32>,
+    /// Aim at `avg` exactly rather than the nearest power of two; changing it moves
+    /// all boundaries unless `avg` is a power of two.
+    #[serde(default)]
+    pub fractional_bits: bool,
+    /// `cdc` (the default), or `fixed-size` to cut every `avg` bytes and see how much
+    /// worse that deduplicates.
+    #[serde(default)]
+    pub strategy: ChunkingStrategy,
+}
+
+fn default_gear_shift() -> u32 {
+    DEFAULT_GEAR_SHIFT
+}
+
+fn default_min_boundary_bits() -> u32 {
+    DEFAULT_MIN_BOUNDARY_BITS
+}
+
+fn default_max_boundary_bits() -> u32 {
+    DEFAULT_MAX_BOUNDARY_BITS
+}
+
+impl ChunkSettings {
+    /// Reject values the chunker cannot work with, which it would otherwise panic on:
+    /// chunk sizes other than `0 < min <= avg <= max`, a `gear_shift` other than 1 or 2,
+    /// and boundary bits out of range.
+    fn check(&self) -> Result<(), ConfigError> {
+        if !(0 < self.min && self.min <= self.avg && self.avg <= self.max) {
+            return Err(ConfigError::Message(format!(
+                "[chunk_settings] needs 0 < min <= avg <= max, not min = {}, avg = {}, max = {}",
+                self.min, self.avg, self.max
+            )));
+        }
+        if !matches!(self.gear_shift, 1 | 2) {
+            return Err(ConfigError::Message(format!(
+                "[chunk_settings] gear_shift must be 1 or 2, not {}",
+                self.gear_shift
+            )));
+        }
+        if !(DEFAULT_MIN_BOUNDARY_BITS <= self.min_boundary_bits
+            && self.min_boundary_bits <= self.max_boundary_bits
+            && self.max_boundary_bits <= DEFAULT_MAX_BOUNDARY_BITS)
+        {
+            return Err(ConfigError::Message(format!(
+                "[chunk_settings] needs {} <= min_boundary_bits <= max_boundary_bits <= {}",
+                DEFAULT_MIN_BOUNDARY_BITS, DEFAULT_MAX_BOUNDARY_BITS
+            )));
+        }
+        // Clamping an explicit value would quietly give another average than asked for.
+        if let Some(bits) = self.boundary_bits
+            && !(self.min_boundary_bits..=self.max_boundary_bits).contains(&bits)
+        {
+            return Err(ConfigError::Message(format!(
+                "[chunk_settings] boundary_bits = {} is outside {}..={} (min_boundary_bits..=max_boundary_bits)",
+                bits, self.min_boundary_bits, self.max_boundary_bits
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn cdc_params(&self) -> CdcParams {
+        CdcParams {
+            boundary_bits: self.boundary_bits,
+            min_boundary_bits: self.min_boundary_bits,
+            max_boundary_bits: self.max_boundary_bits,
+            gear_shift: self.gear_shift,
+            gear_seed: self.gear_seed,
+            fractional_bits: self.fractional_bits,
+            strategy: self.strategy,
+            ..CdcParams::new(self.min, self.avg, self.max)
+        }
+    }
+}
+
+/// Settings of the remote storage backends.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct BackendSettings {
+    /// `[backend.s3]`
+    #[serde(default)]
+    pub s3: Option<S3Settings>,
+    /// `[backend.gcs]`
+    #[serde(default)]
+    pub gcs: Option<GcsSettings>,
+    /// `[backend.b2]`
+    #[serde(default)]
+    pub b2: Option<B2Settings>,
+    /// `[backend.sftp]`
+    #[serde(default)]
+    pub sftp: Option<SftpSettings>,
+    /// The sides of `[store] type = tee`.
+    #[serde(skip)]
+    pub tee: Option<TeeSettings>,
+    /// Where repositories given by a plain name live, from `[store] type`.
+    #[serde(skip)]
+    pub default_kind: StoreKind,
+    /// Chunk cache of remote repositories, from `[cache]`.
+    #[serde(skip)]
+    pub chunk_cache: CacheSettings,
+    /// Retries of network backends, from `[retry]`.
+    #[serde(skip)]
+    pub retry: RetrySettings,
+    /// How packs are written, from `[upload]`.
+    #[serde(skip)]
+    pub upload: UploadSettings,
+}
+
+/// Kinds of storage a repository can live in.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// A directory.
+    #[default]
+    Local,
+    /// An S3 bucket.
+    S3,
+    /// A Google Cloud Storage bucket.
+    Gcs,
+    /// A directory on an SFTP server.
+    Sftp,
+    /// Two of the others at once, see [`TeeSettings`].
+    Tee,
+}
+
+/// `[store]`: where repositories are kept by default, e.g.
+///
+/// ```ini
+/// [store]
+/// type = s3
+/// bucket = my-backups
+/// prefix = rbckp/
+/// ```
+///
+/// With `type = s3`, a repository location without a scheme (`rbckp init laptop`) is
+/// a key prefix below `prefix` in `bucket`. The S3 fields are the same as in
+/// `[backend.s3]`, which they replace. `type = gcs` works the same way with the fields
+/// of `[backend.gcs]`:
+///
+/// ```ini
+/// [store]
+/// type = gcs
+/// bucket = my-gcs-bucket
+/// credentials_file = /path/to/sa.json
+/// ```
+///
+/// With `type = sftp`, plain names are directories below `remote_root` on `host`:
+///
+/// ```ini
+/// [store]
+/// type = sftp
+/// host = nas.local
+/// user = backup
+/// key_path = /home/me/.ssh/id_backup
+/// remote_root = /srv/backups
+/// ```
+///
+/// With `type = tee`, every repository is kept on two kinds of store, each set up
+/// with the fields above:
+///
+/// ```ini
+/// [store]
+/// type = tee
+/// primary.type = local
+/// secondary.type = s3
+/// bucket = my-backups
+/// ```
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct StoreSettings {
+    #[serde(default, rename = "type")]
+    pub kind: StoreKind,
+    /// Bucket and prefix are used by every kind of bucket.
+    #[serde(flatten)]
+    pub s3: S3Settings,
+    /// Service account key of a GCS bucket.
+    #[serde(default)]
+    pub credentials_file: Option<PathBuf>,
+    /// The fields of `[backend.sftp]`.
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub user: String,
+    #[serde(default)]
+    pub key_path: Option<PathBuf>,
+    #[serde(default)]
+    pub remote_root: String,
+    /// The two sides of a `type = tee` store.
+    #[serde(default, rename = "primary.type")]
+    pub primary: Option<StoreKind>,
+    #[serde(default, rename = "secondary.type")]
+    pub secondary: Option<StoreKind>,
+}
+
+impl StoreSettings {
+    /// The fields of a `type = gcs` store.
+    pub fn gcs(&self) -> GcsSettings {
+        GcsSettings {
+            bucket: self.s3.bucket.clone(),
+            prefix: self.s3.prefix.clone(),
+            credentials_file: self.credentials_file.clone(),
+        }
+    }
+
+    /// The fields of a `type = sftp` store.
+    pub fn sftp(&self) -> SftpSettings {
+        SftpSettings {
+            host: self.host.clone(),
+            port: self.port.unwrap_or(DEFAULT_SFTP_PORT),
+            user: self.user.clone(),
+            key_path: self.key_path.clone(),
+            remote_root: self.remote_root.clone(),
+            ..SftpSettings::default()
+        }
+    }
+}
+
+/// Where an S3 repository lives. Credentials come from the AWS environment variables.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct S3Settings {
+    #[serde(default)]
+    pub bucket: String,
+    /// Key prefix of all repository objects, e.g. `backups/laptop`.
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// Endpoint URL of an S3-compatible service such as MinIO; `None` means AWS.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+/// Where a Google Cloud Storage repository lives.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct GcsSettings {
+    #[serde(default)]
+    pub bucket: String,
+    /// Name prefix of all repository objects, e.g. `backups/laptop`.
+    #[serde(default)]
+    pub prefix: String,
+    /// Service account key file; without one, credentials are looked up the usual way
+    /// (`GOOGLE_APPLICATION_CREDENTIALS`, gcloud's application default credentials,
+    /// then the metadata server).
+    #[serde(default)]
+    pub credentials_file: Option<PathBuf>,
+}
+
+/// Where a Backblaze B2 repository lives, and the application key to reach it with.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct B2Settings {
+    #[serde(default)]
+    pub application_key_id: String,
+    #[serde(default)]
+    pub application_key: String,
+    #[serde(default)]
+    pub bucket_name: String,
+    /// Looked up by `bucket_name` if not given.
+    #[serde(default)]
+    pub bucket_id: String,
+    /// File name prefix of all repository objects, e.g. `backups/laptop`.
+    #[serde(default)]
+    pub prefix: String,
+    /// Where accounts are authorized, e.g. a test server; `https://api.backblazeb2.com`
+    /// if not given.
+    #[serde(default)]
+    pub api_url: Option<String>,
+}
+
+/// Port of an SFTP server unless stated otherwise.
+pub const DEFAULT_SFTP_PORT: u16 = 22;
+
+/// SSH sessions an SFTP backend keeps open at most by default.
+pub const DEFAULT_SFTP_CONNECTIONS: usize = 4;
+
+/// The two kinds of store a `[store] type = tee` repository is kept on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TeeSettings {
+    /// Read first, and holds the repository lock.
+    pub primary: StoreKind,
+    pub secondary: StoreKind,
+}
+
+/// Where an SFTP repository lives, and the key to log in with.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SftpSettings {
+    #[serde(default)]
+    pub host: String,
+    #[serde(default = "default_sftp_port")]
+    pub port: u16,
+    /// Remote user; `$USER` if empty.
+    #[serde(default)]
+    pub user: String,
+    /// SSH private key to log in with. Without one, the SSH agent and the default keys
+    /// in `~/.ssh` are tried; there is never a password prompt.
+    #[serde(default)]
+    pub key_path: Option<PathBuf>,
+    /// Directory on the server that repositories given by a plain name are created in.
+    #[serde(default)]
+    pub remote_root: String,
+    /// SSH sessions kept open at most, each serving one operation at a time.
+    #[serde(default = "default_sftp_connections")]
+    pub connections: usize,
+}
+
+impl Default for SftpSettings {
+    fn default() -> Self {
+        SftpSettings {
+            host: String::new(),
+            port: DEFAULT_SFTP_PORT,
+            user: String::new(),
+            key_path: None,
+            remote_root: String::new(),
+            connections: DEFAULT_SFTP_CONNECTIONS,
+        }
+    }
+}
+
+impl SftpSettings {
+    /// `[user@]host[:port]` as in an `sftp://` URL.
+    pub fn authority(&self) -> String {
+        let mut authority = String::new();
+        if !self.user.is_empty() {
+            authority.push_str(&self.user);
+            authority.push('@');
+        }
+        if self.host.contains(':') {
+            authority.push_str(&format!("[{}]", self.host));
+        } else {
+            authority.push_str(&self.host);
+        }
+        if self.port != DEFAULT_SFTP_PORT {
+            authority.push_str(&format!(":{}", self.port));
+        }
+        authority
+    }
+}
+
+fn default_sftp_port() -> u16 {
+    DEFAULT_SFTP_PORT
+}
+
+fn default_sftp_connections() -> usize {
+    DEFAULT_SFTP_CONNECTIONS
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct Settings {
     pub chunk_settings: ChunkSettings,
     pub debug: bool,
+    /// Worker threads used for chunk hashing (0 = one per CPU).
+    #[serde(default)]
+    pub threads: usize,
+    /// Size in bytes at which a pack file is sealed and a new one started.
+    #[serde(default = "default_pack_size")]
+    pub pack_size: u64,
+    #[serde(default)]
+    pub backend: BackendSettings,
+    /// Levels of subdirectories `rbckp init` spreads packs over (0 to 2); existing
+    /// repositories keep the depth they were created with.
+    #[serde(default)]
+    pub fanout_depth: u32,
+    /// `[retention]`: what a bare `rbckp forget` keeps.
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Chunk id hash of repositories created by `rbckp init`: `blake3` (the default),
+    /// `xxhash3` or `siphash`. Existing repositories keep the one they were created with.
+    ///
+    /// xxHash3 is much faster but not cryptographic: only use it for trusted, local
+    /// repositories, never on storage others can write to or where deduplication must
+    /// hold up against crafted data (see
+    /// [`XxHash3Hasher`](crate::backup::hash::XxHash3Hasher)). SipHash is keyed with a
+    /// secret that `init` stores in the repository config, which makes crafted
+    /// collisions impractical (see
+    /// [`SipHasher13`](crate::backup::hash::SipHasher13)).
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    /// Exclude patterns for every backup, separated by whitespace (e.g.
+    /// `exclude = target/ .git/ *.tmp`), applied before those given with `--exclude`.
+    #[serde(default, deserialize_with = "deserialize_patterns")]
+    pub exclude: Vec<String>,
+    /// `[store]`; also reflected in `backend` once loaded.
+    #[serde(default)]
+    pub store: StoreSettings,
+    /// Make backups check that a chunk found in the repository by its hash has the
+    /// same length there, failing on a mismatch rather than recording the wrong chunk.
+    /// Off by default: for a cryptographic hash, such a collision is astronomically
+    /// unlikely.
+    #[serde(default)]
+    pub check_chunk_lengths: bool,
+    /// Hex digits of a chunk id used as its key in the chunk counts of `rbckp -F` and in
+    /// the chunk lookups of backups, 1 to 64 (the default, whole ids). Shorter keys
+    /// suffice for small inputs; two chunks whose ids share the prefix are an error
+    /// rather than counted or stored as one. Stored indexes always keep whole ids.
+    #[serde(default = "default_hash_prefix_len")]
+    pub hash_prefix_len: usize,
+    /// `[cache]`; also reflected in `backend` once loaded.
+    #[serde(default)]
+    pub cache: CacheSettings,
+    /// `[retry]`; also reflected in `backend` once loaded.
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// `[upload]`; also reflected in `backend` once loaded.
+    #[serde(default)]
+    pub upload: UploadSettings,
 }
 
+/// `[cache]`: local copies of the chunks of remote repositories, e.g.
+///
+/// ```ini
+/// [cache]
+/// enabled = true
+/// max_size_gb = 10
+/// ```
+///
+/// Chunks are kept below the index cache of each repository (see
+/// [`IndexCache::default_root`](crate::backup::store::cache::IndexCache::default_root)).
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CacheSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Size the cached chunks of one repository are kept below, in GB (10^9 bytes).
+    #[serde(default = "default_cache_size_gb")]
+    pub max_size_gb: f64,
+}
+
+/// Default of [`CacheSettings::max_size_gb`].
+pub const DEFAULT_CACHE_SIZE_GB: f64 = 10.0;
+
+fn default_cache_size_gb() -> f64 {
+    DEFAULT_CACHE_SIZE_GB
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            enabled: false,
+            max_size_gb: DEFAULT_CACHE_SIZE_GB,
+        }
+    }
+}
+
+impl CacheSettings {
+    /// [`CacheSettings::max_size_gb`] in bytes.
+    pub fn max_size(&self) -> u64 {
+        (self.max_size_gb.max(0.0) * 1e9) as u64
+    }
+}
+
+/// `[retry]`: how network backends (S3, GCS, B2, SFTP) retry operations that fail
+/// with a transient error such as a timeout or an HTTP 503, and how local
+/// repositories retry writes that were interrupted or found the disk busy, e.g.
+///
+/// ```ini
+/// [retry]
+/// max_retries = 4
+/// base_delay_ms = 200
+/// ```
+///
+/// The delay doubles after every retry, up to a minute.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetrySettings {
+    /// Retries after the first attempt; 0 turns retrying off.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Delay before the first retry, in milliseconds.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub base_delay_ms: u64,
+}
+
+/// Default of [`RetrySettings::max_retries`].
+pub const DEFAULT_MAX_RETRIES: u32 = 4;
+
+/// Default of [`RetrySettings::base_delay_ms`].
+pub const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 200;
+
+fn default_max_retries() -> u32 {
+    DEFAULT_MAX_RETRIES
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    DEFAULT_RETRY_BASE_DELAY_MS
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay_ms: DEFAULT_RETRY_BASE_DELAY_MS,
+        }
+    }
+}
+
+impl RetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy::with_retries(self.max_retries, Duration::from_millis(self.base_delay_ms))
+    }
+}
+
+/// `[upload]`: how backups write their packs, e.g.
+///
+/// ```ini
+/// [upload]
+/// batch_packs = 4
+/// max_concurrent = 4
+/// ```
+///
+/// See [`ChunkStore::with_upload_batch`](crate::backup::store::ChunkStore::with_upload_batch)
+/// and [`ChunkStore::with_concurrent_uploads`](crate::backup::store::ChunkStore::with_concurrent_uploads).
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UploadSettings {
+    /// Full packs held in memory and written together, with one index update for all
+    /// of them; 1 writes every pack as soon as it is full.
+    #[serde(default = "default_upload_batch_packs")]
+    pub batch_packs: usize,
+    /// Packs of a batch written at the same time, which only matters with
+    /// `batch_packs` above 1, and chunks uploaded at the same time by an async
+    /// uploader; see [`Settings::max_concurrent_uploads`].
+    #[serde(default = "default_max_concurrent_uploads")]
+    pub max_concurrent: usize,
+}
+
+/// Default of [`UploadSettings::batch_packs`].
+pub const DEFAULT_UPLOAD_BATCH_PACKS: usize = 1;
+
+/// Default of [`UploadSettings::max_concurrent`].
+pub const DEFAULT_MAX_CONCURRENT_UPLOADS: usize = 4;
+
+fn default_upload_batch_packs() -> usize {
+    DEFAULT_UPLOAD_BATCH_PACKS
+}
+
+fn default_max_concurrent_uploads() -> usize {
+    DEFAULT_MAX_CONCURRENT_UPLOADS
+}
+
+impl Default for UploadSettings {
+    fn default() -> Self {
+        UploadSettings {
+            batch_packs: DEFAULT_UPLOAD_BATCH_PACKS,
+            max_concurrent: DEFAULT_MAX_CONCURRENT_UPLOADS,
+        }
+    }
+}
+
+fn default_pack_size() -> u64 {
+    DEFAULT_PACK_SIZE
+}
+
+fn default_hash_prefix_len() -> usize {
+    CHUNK_ID_HEX_LEN
+}
+
+fn deserialize_patterns<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let patterns = <String as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(patterns.split_whitespace().map(String::from).collect())
+}
+
+/// Where settings are read from unless `--config` says otherwise.
+pub const DEFAULT_SETTINGS_PATH: &str = "./settings.ini";
+
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
-        let config_file = File::with_name("./settings.ini");
+        Self::from_path(Path::new(DEFAULT_SETTINGS_PATH))
+    }
+
+    /// Load settings from the INI file at `path`, whatever its extension.
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let config_file = File::from(path).format(FileFormat::Ini);
         let settings_builder = Config::builder().add_source(config_file).build()?;
 
-        settings_builder.try_deserialize::<Settings>()
+        let mut settings = settings_builder.try_deserialize::<Settings>()?;
+        settings.backend.default_kind = settings.store.kind;
+        settings.backend.chunk_cache = settings.cache;
+        settings.backend.retry = settings.retry;
+        settings.backend.upload = settings.upload;
+        settings.chunk_settings.check()?;
+        if !(1..=CHUNK_ID_HEX_LEN).contains(&settings.hash_prefix_len) {
+            return Err(ConfigError::Message(format!(
+                "hash_prefix_len must be 1 to {}",
+                CHUNK_ID_HEX_LEN
+            )));
+        }
+        if settings.store.kind == StoreKind::Tee {
+            let side = |kind: Option<StoreKind>, name| match kind {
+                Some(StoreKind::Tee) => Err(ConfigError::Message(format!(
+                    "[store] {}.type cannot be tee",
+                    name
+                ))),
+                Some(kind) => Ok(kind),
+                None => Err(ConfigError::Message(format!(
+                    "[store] type = tee needs {}.type",
+                    name
+                ))),
+            };
+            let tee = TeeSettings {
+                primary: side(settings.store.primary, "primary")?,
+                secondary: side(settings.store.secondary, "secondary")?,
+            };
+            settings.use_store(tee.primary);
+            settings.use_store(tee.secondary);
+            settings.backend.tee = Some(tee);
+        } else {
+            settings.use_store(settings.store.kind);
+        }
+        Ok(settings)
+    }
+
+    /// Uploads in flight at the same time, `[upload] max_concurrent`: packs of an
+    /// upload batch, and chunks of an
+    /// [`async_pool::ConcurrentUploader`](crate::backup::store::async_pool::ConcurrentUploader)
+    /// (`async` feature).
+    pub fn max_concurrent_uploads(&self) -> usize {
+        self.upload.max_concurrent
+    }
+
+    /// Set up the backend of `kind` from the fields of `[store]`.
+    fn use_store(&mut self, kind: StoreKind) {
+        match kind {
+            StoreKind::Local | StoreKind::Tee => {}
+            StoreKind::S3 => self.backend.s3 = Some(self.store.s3.clone()),
+            StoreKind::Gcs => self.backend.gcs = Some(self.store.gcs()),
+            StoreKind::Sftp => self.backend.sftp = Some(self.store.sftp()),
+        }
     }
 }
@@ -1,68 +1,1742 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::{self, File},
-    io::Write,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use clap::Parser;
+use globset::Glob;
+use rbckp::{
+    args::{
+        Args, BackupArgs, BenchArgs, CatArgs, CheckArgs, Command, CompareArgs, CopyArgs, DiffArgs,
+        EstimateArgs, ExportArgs, ForgetArgs, ImportArgs, InitArgs, ListSnapshotsArgs, MigrateArgs,
+        MountArgs, RebuildIndexArgs, RestoreArgs, TagArgs, UnlockArgs, VerifyArgs, VerifyTreeArgs,
+    },
+    backup::{
+        cancel::CancelToken,
+        cdc_chunker::{self, StreamChunker},
+        check, compare, copy, diff,
+        estimate::Estimator,
+        export,
+        filter::{self, ExcludeFilter, FileFilter},
+        hash::{self, PrefixKeys},
+        import,
+        io::FileData,
+        journal::{self, BackupJournal},
+        manifest::ManifestEntry,
+        preview,
+        restore::{self, RestoreAction},
+        retention::RetentionPolicy,
+        session::BackupSession,
+        snapshot::{self, Snapshot, SnapshotInfo},
+        stats::{ChunkSizeSummary, RunParams, RunSummary},
+        store::{
+            self, Backend, ChunkStore, StoreError,
+            cache::{ChunkCache, IndexCache},
+            chunk_store::DEFAULT_PACK_SIZE,
+            lock::{DEFAULT_LOCK_WAIT, LockKind, RepoLocks},
+            migrate,
+            repo_config::RepoConfig,
+        },
+        timing::{self, PhaseTimings},
+        verify, walk,
+    },
+    config::{BackendSettings, DEFAULT_SETTINGS_PATH, Settings},
+};
+use simplelog::{ColorChoice, ConfigBuilder, LevelFilter, TermLogger, TerminalMode};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+/// How often progress lines are redrawn.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Print a status line on stdout, unless `--quiet` was given.
+///
+/// For messages about what a command did; the data a command exists to show (lists,
+/// reports) is printed unconditionally.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if log::log_enabled!(log::Level::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Exit code of a backup stopped by SIGINT or SIGTERM, as shells report a process
+/// killed by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
 
 fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = args.config.as_deref();
+    init_logging(&args);
+
+    let result = run(&args, config);
+    if let Err(err) = &result
+        && matches!(err.downcast_ref(), Some(StoreError::Interrupted))
+    {
+        log::error!("{:#}", err);
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+    result
+}
+
+fn run(args: &Args, config: Option<&Path>) -> Result<()> {
+    match &args.command {
+        Some(Command::Init(init_args)) => init_repo(init_args, config),
+        Some(Command::Backup(backup_args)) => backup(backup_args, config),
+        Some(Command::Restore(restore_args)) => restore(restore_args, config),
+        Some(Command::Cat(cat_args)) => cat(cat_args, config),
+        Some(Command::Export(export_args)) => export(export_args, config),
+        Some(Command::Import(import_args)) => import(import_args, config),
+        Some(Command::VerifyTree(verify_args)) => verify_tree(verify_args, config),
+        Some(Command::Verify(verify_args)) => verify_repo(verify_args, config),
+        Some(Command::Check(check_args)) => check_repo(check_args, config),
+        Some(Command::Forget(forget_args)) => forget(forget_args, config),
+        Some(Command::Mount(mount_args)) => mount(mount_args, config),
+        Some(Command::ListSnapshots(list_args)) => list_snapshots(list_args, config),
+        Some(Command::Tag(tag_args)) => tag_snapshot(tag_args, config, true),
+        Some(Command::Untag(tag_args)) => tag_snapshot(tag_args, config, false),
+        Some(Command::RebuildIndex(rebuild_args)) => rebuild_index(rebuild_args, config),
+        Some(Command::Unlock(unlock_args)) => unlock(unlock_args, config),
+        Some(Command::Migrate(migrate_args)) => migrate_repo(migrate_args, config),
+        Some(Command::Bench(bench_args)) => bench(bench_args, config),
+        Some(Command::Compare(compare_args)) => compare_files(compare_args, config),
+        Some(Command::Estimate(estimate_args)) => estimate(estimate_args, config),
+        Some(Command::Diff(diff_args)) => diff_snapshots(diff_args, config),
+        Some(Command::Copy(copy_args)) => copy_snapshots(copy_args, config),
+        None => chunk_target(args),
+    }
+}
+
+/// Send log messages to stderr, at the level asked for by `--quiet` / `--verbose`
+/// (or `debug = true` in the settings).
+fn init_logging(args: &Args) {
+    let debug_setting = optional_settings(args.config.as_deref())
+        .ok()
+        .flatten()
+        .is_some_and(|settings| settings.debug);
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) if debug_setting => LevelFilter::Debug,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+
+    let config = ConfigBuilder::new()
+        .set_time_level(LevelFilter::Off)
+        .set_thread_level(LevelFilter::Off)
+        .set_target_level(LevelFilter::Off)
+        .set_location_level(LevelFilter::Off)
+        .build();
+    let color = if io::stderr().is_terminal() {
+        ColorChoice::Auto
+    } else {
+        ColorChoice::Never
+    };
+    // Only fails if a logger is already set, which cannot happen this early.
+    let _ = TermLogger::init(level, config, TerminalMode::Stderr, color);
+}
+
+/// Chunk the `-F` target and report what the chunker did.
+fn chunk_target(args: &Args) -> Result<()> {
+    let config = args.config.as_deref();
+    let target_file = args
+        .target_file
+        .as_deref()
+        .context("no target file given (-F)")?;
+
     let cwd = std::env::current_dir()?;
-    println!("Current dir: {}", cwd.display());
+    log::debug!("Current dir: {}", cwd.display());
 
-    let settings = rbckp::config::Settings::new()?;
-    let args = rbckp::args::Args::parse();
+    let settings = load_settings(config)?;
 
-    println!("Current settings: {:?}", settings);
-    println!("Args: {:?}", args);
+    log::debug!("Current settings: {:?}", settings);
+    log::debug!("Args: {:?}", args);
 
-    let data = fs::read(&args.target_file)?;
+    // Bound the pool used for chunk hashing; 0 lets rayon pick one thread per CPU.
+    let threads = args.threads.unwrap_or(settings.threads);
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()?;
 
     // For text files, smaller numbers make it easier to observe behavior.
     let min_chunk_size = settings.chunk_settings.min;
     let target_avg_chunk_size = settings.chunk_settings.avg;
     let max_chunk_size = settings.chunk_settings.max;
+    let params = settings
+        .chunk_settings
+        .cdc_params()
+        .with_strategy(args.strategy.unwrap_or(settings.chunk_settings.strategy))
+        .with_hash_algorithm(settings.hash_algorithm)
+        // Ids are only compared within this run, so any key does for SipHash.
+        .with_hash_key(Some(hash::random_key()));
+    for warning in params.validate() {
+        log::warn!("{}", warning);
+    }
 
-    let (chunks, chunk_map) = rbckp::backup::cdc_chunker::chunk_bytes_cdc(
-        &data,
-        min_chunk_size,
-        target_avg_chunk_size,
-        max_chunk_size,
-    );
+    // `-F -` reads the data from stdin, e.g. `tar c dir | rbckp -F -`.
+    let read_stdin = target_file == Path::new("-");
+    if read_stdin && args.debug_boundaries {
+        bail!("--debug-boundaries needs a file to chunk, not stdin");
+    }
 
-    println!("File: {}", args.target_file.display());
-    println!("Total bytes: {}", data.len());
-    println!("Chunks: {}", chunks.len());
+    // Load (and validate) the target before creating any output.
+    let file_data = if read_stdin {
+        None
+    } else {
+        Some(read_target_file(target_file, args.mmap)?)
+    };
+
+    let mut out_file = File::create_new("./output.txt")?;
+    // Keyed by id prefix, see `hash_prefix_len`.
+    let mut keys = PrefixKeys::new(settings.hash_prefix_len);
+    let mut chunk_counts: HashMap<String, usize> = HashMap::new();
+    let mut chunk_sizes: Vec<usize> = Vec::new();
+    let mut unique_bytes = 0;
+
+    let mut boundaries = Vec::new();
+    let (source, total_bytes) = if let Some(data) = file_data {
+        if args.debug_boundaries {
+            boundaries = cdc_chunker::chunk_debug_info(&data, &params);
+        }
+        let chunks = cdc_chunker::chunk_refs_cdc_parallel(&data, &params);
+
+        for chunk_ref in chunks {
+            let chunk = &data[chunk_ref.offset..chunk_ref.offset + chunk_ref.len];
+            write_chunk_preview(&mut out_file, chunk_sizes.len(), chunk, args.preview_len)?;
+            count_chunk(
+                &mut chunk_counts,
+                &mut unique_bytes,
+                keys.key(&chunk_ref.hash)?,
+                chunk.len(),
+            );
+            chunk_sizes.push(chunk.len());
+        }
+
+        (target_file.display().to_string(), data.len())
+    } else {
+        let mut chunker = StreamChunker::new(io::stdin().lock(), &params);
+
+        for chunk in chunker.by_ref() {
+            let (chunk_ref, chunk) = chunk?;
+            write_chunk_preview(&mut out_file, chunk_sizes.len(), &chunk, args.preview_len)?;
+            count_chunk(
+                &mut chunk_counts,
+                &mut unique_bytes,
+                keys.key(&chunk_ref.hash)?,
+                chunk.len(),
+            );
+            chunk_sizes.push(chunk.len());
+        }
+
+        ("<stdin>".to_string(), chunker.bytes_processed())
+    };
+
+    let chunk_total = chunk_sizes.len();
+
+    if args.json {
+        let summary = RunSummary {
+            file: source,
+            total_bytes,
+            chunks: chunk_total,
+            unique_chunks: chunk_counts.len(),
+            dedup_ratio: if unique_bytes == 0 {
+                1.0
+            } else {
+                total_bytes as f64 / unique_bytes as f64
+            },
+            strategy: params.strategy,
+            params: RunParams {
+                min: min_chunk_size,
+                avg: target_avg_chunk_size,
+                max: max_chunk_size,
+            },
+            chunk_sizes: ChunkSizeSummary::from_sizes(&chunk_sizes),
+        };
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    println!("File: {}", source);
+    println!("Total bytes: {}", total_bytes);
+    println!("Chunks: {}", chunk_total);
     println!(
         "Params: min={} avg={} max={}",
         min_chunk_size, target_avg_chunk_size, max_chunk_size
     );
+    println!("Strategy: {}", params.strategy);
     println!();
 
-    println!("Chunks total: {}", chunks.len());
+    println!("Chunks total: {}", chunk_total);
+    if let Some(summary) = ChunkSizeSummary::from_sizes(&chunk_sizes) {
+        println!(
+            "Chunk sizes: min={} max={} mean={:.1} median={:.1}",
+            summary.min, summary.max, summary.mean, summary.median
+        );
+    }
 
-    let mut out_file = File::create_new("./output.txt")?;
-    for (idx, chunk) in chunks.iter().enumerate() {
-        // Show a small preview (safe for text-ish input).
-        let preview_len = chunk.len().min(60);
-        let preview = String::from_utf8_lossy(&chunk[..preview_len])
-            .replace('\n', "\\n")
-            .replace('\r', "\\r")
-            .replace('\t', "\\t");
+    if !boundaries.is_empty() {
+        println!();
+        println!("offset len reason hash");
+        for chunk in &boundaries {
+            let hash = chunk
+                .rolling_hash
+                .map_or_else(|| "-".to_string(), |hash| format!("{:08x}", hash));
+            println!("{} {} {} {}", chunk.offset, chunk.len, chunk.reason, hash);
+        }
+    }
+
+    for (k, v) in chunk_counts.iter() {
+        log::debug!("Chunk [{}] - count {}", k, v);
+    }
+
+    Ok(())
+}
+
+/// Count one chunk of the `-F` target, adding its length to `unique_bytes` the first
+/// time its id is seen.
+fn count_chunk(
+    counts: &mut HashMap<String, usize>,
+    unique_bytes: &mut usize,
+    hash: String,
+    len: usize,
+) {
+    let count = counts.entry(hash).or_default();
+    if *count == 0 {
+        *unique_bytes += len;
+    }
+    *count += 1;
+}
+
+/// Chunk a file repeatedly and report the throughput of each chunking stage.
+///
+/// Measures the boundary scan alone and the full pass a backup does (boundaries plus
+/// hashing on all threads), with the chunk sizes from the settings.
+fn bench(args: &BenchArgs, config: Option<&Path>) -> Result<()> {
+    let settings = load_settings(config)?;
+    let chunk_settings = &settings.chunk_settings;
+    let params = chunk_settings.cdc_params();
+    let data = read_target_file(&args.file, false)?;
+
+    // Each stage chunks the data it is given and returns the number of chunks.
+    type Stage<'a> = &'a dyn Fn(&[u8]) -> usize;
+    let stages: [(&str, Stage); 2] = [
+        ("gear boundaries", &|data| {
+            cdc_chunker::count_chunks_cdc(data, &params)
+        }),
+        ("gear chunk + hash", &|data| {
+            cdc_chunker::chunk_refs_cdc_parallel(data, &params).len()
+        }),
+    ];
+
+    println!(
+        "File: {} ({} bytes), {} iterations, min={} avg={} max={}",
+        args.file.display(),
+        data.len(),
+        args.iterations,
+        chunk_settings.min,
+        chunk_settings.avg,
+        chunk_settings.max
+    );
+    println!();
+    println!(
+        "{:<20} {:>10} {:>10} {:>10}",
+        "stage", "MB/s", "std dev", "chunks"
+    );
+    for (name, stage) in stages {
+        let mut throughputs = Vec::with_capacity(args.iterations as usize);
+        let mut chunks = 0;
+        for _ in 0..args.iterations {
+            let start = Instant::now();
+            chunks = stage(&data);
+            let seconds = start.elapsed().as_secs_f64();
+            throughputs.push(data.len() as f64 / 1e6 / seconds);
+        }
+        let (mean, std_dev) = mean_and_std_dev(&throughputs);
+        println!(
+            "{:<20} {:>10.1} {:>10.1} {:>10}",
+            name, mean, std_dev, chunks
+        );
+    }
+    Ok(())
+}
+
+/// Chunk two files with the configured parameters and report how many chunks of the
+/// second one the first one already has.
+fn compare_files(args: &CompareArgs, config: Option<&Path>) -> Result<()> {
+    let [path_a, path_b] = args.files.as_slice() else {
+        bail!("compare takes exactly two files (-F old -F new)");
+    };
+    let settings = load_settings(config)?;
+    let params = settings.chunk_settings.cdc_params();
+    let chunks_a = cdc_chunker::chunk_refs_cdc_parallel(&read_target_file(path_a, false)?, &params);
+    let chunks_b = cdc_chunker::chunk_refs_cdc_parallel(&read_target_file(path_b, false)?, &params);
+    let shared = compare::shared_chunks(&chunks_a, &chunks_b);
+
+    if args.json {
+        let report = serde_json::json!({
+            "a": { "file": path_a, "chunks": chunks_a.len() },
+            "b": { "file": path_b, "chunks": shared.chunks, "bytes": shared.bytes },
+            "shared_chunks": shared.shared_chunks,
+            "shared_chunk_percent": shared.shared_chunk_percent(),
+            "shared_bytes": shared.shared_bytes,
+            "shared_byte_percent": shared.shared_byte_percent(),
+            "new_bytes": shared.new_bytes,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("{}: {} chunks", path_a.display(), chunks_a.len());
+    println!(
+        "{}: {} chunks, {} bytes",
+        path_b.display(),
+        shared.chunks,
+        shared.bytes
+    );
+    println!(
+        "Shared: {} chunks ({:.1}%), {} bytes ({:.1}%)",
+        shared.shared_chunks,
+        shared.shared_chunk_percent(),
+        shared.shared_bytes,
+        shared.shared_byte_percent()
+    );
+    println!("New: {} bytes", shared.new_bytes);
+    Ok(())
+}
+
+/// Show what changed between two snapshots.
+fn diff_snapshots(args: &DiffArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot read from {}", args.repo.display());
+    let backend = open_repo(&args.repo, config).with_context(context)?;
+    let load = |snapshot: &str| -> Result<Snapshot> {
+        let id = Snapshot::resolve_id(&backend, snapshot).with_context(context)?;
+        Snapshot::load(&backend, &id).with_context(|| format!("cannot read snapshot {}", id))
+    };
+    let (old, new) = (load(&args.old)?, load(&args.new)?);
+    let diff = diff::diff_snapshots(&old, &new);
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        println!("{}", diff);
+    }
+    Ok(())
+}
+
+/// Copy one snapshot, or all missing ones, from one repository to another.
+fn copy_snapshots(args: &CopyArgs, config: Option<&Path>) -> Result<()> {
+    let (backend_settings, pack_size) = match optional_settings(config)? {
+        Some(settings) => (settings.backend, settings.pack_size),
+        None => (BackendSettings::default(), DEFAULT_PACK_SIZE),
+    };
+    let from = open_store(&args.from, &backend_settings, pack_size, LockKind::Shared)
+        .with_context(|| format!("cannot read from {}", args.from.display()))?;
+    let context = || format!("cannot copy to {}", args.to.display());
+    let mut to = open_store(&args.to, &backend_settings, pack_size, LockKind::Shared)
+        .with_context(context)?;
+
+    let ids = match &args.snapshot {
+        Some(snapshot) => vec![
+            Snapshot::resolve_id(from.backend(), snapshot)
+                .with_context(|| format!("cannot read from {}", args.from.display()))?,
+        ],
+        None => copy::missing_snapshots(&from, &to).with_context(context)?,
+    };
+    if ids.is_empty() {
+        status!(
+            "{} has every snapshot of {}",
+            args.to.display(),
+            args.from.display()
+        );
+        return Ok(());
+    }
+
+    let mut total = copy::CopyStats::default();
+    for id in &ids {
+        let stats = copy::copy_snapshot(&from, &mut to, id)
+            .with_context(|| format!("cannot copy snapshot {}", id))?;
+        status!(
+            "Copied snapshot {}: {} of {} chunks transferred ({} bytes)",
+            id,
+            stats.chunks_copied,
+            stats.chunks,
+            stats.bytes_copied
+        );
+        total += stats;
+    }
+    if ids.len() > 1 {
+        status!(
+            "Copied {} snapshots: {} chunks transferred ({} bytes)",
+            ids.len(),
+            total.chunks_copied,
+            total.bytes_copied
+        );
+    }
+    Ok(())
+}
+
+/// Mean and sample standard deviation (0 for a single value) of `values`.
+fn mean_and_std_dev(values: &[f64]) -> (f64, f64) {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (values.len() - 1) as f64;
+    (mean, variance.sqrt())
+}
+
+/// Create a new repository at the given path.
+fn init_repo(args: &InitArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot initialize repository {}", args.path.display());
+    let mut repo_config = RepoConfig::new();
+    if let Some(settings) = optional_settings(config)? {
+        repo_config.fanout_depth = settings.fanout_depth;
+        repo_config.hash_algorithm = settings.hash_algorithm;
+    }
+    let backend =
+        store::open_backend(&args.path, &backend_settings(config)?).with_context(context)?;
+    ChunkStore::init_with_config(&backend, repo_config).with_context(context)?;
+
+    status!("Initialized repository at {}", args.path.display());
+    Ok(())
+}
+
+/// Back up the given paths into a repository as a new snapshot.
+///
+/// Every finished file is recorded in a journal first, so a backup that gets killed
+/// can be resumed without reading the files it already did again.
+fn backup(args: &BackupArgs, config: Option<&Path>) -> Result<()> {
+    let started = Instant::now();
+    let settings = load_settings(config)?;
+    let context = || format!("cannot back up to {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &settings.backend,
+        settings.pack_size,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+    let mut session = BackupSession::new(settings, store);
+    if args.stdin {
+        let session = with_parent(session, std::slice::from_ref(&args.stdin_name))?
+            .with_cancel(cancel_on_signals()?);
+        return backup_stdin(args, session, started);
+    }
+
+    let roots = walk::backup_roots(args.paths.iter().chain(&args.targets).map(PathBuf::as_path));
+    let paths: Vec<String> = roots
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    session = with_parent(session, &paths)?;
+
+    // Pick up where an interrupted backup of the same paths left off. Files whose
+    // chunks did not make it into the store (e.g. the last, unfinished pack) are redone.
+    let location = store::repo_location(&args.repo, &session.settings().backend);
+    let journal_path = journal_path(&session.store().config().id, &location);
+    let mut resumed = Vec::new();
+    match BackupJournal::load(&journal_path)? {
+        Some(state) if state.paths != paths => {
+            log::warn!("discarding the journal of an interrupted backup of other paths");
+        }
+        Some(_) if args.no_resume => {
+            log::info!("discarding the journal of an interrupted backup (--no-resume)");
+        }
+        Some(state) if resume_interrupted(args, state.entries.len())? => {
+            status!(
+                "Resuming an interrupted backup that finished {} files",
+                state.entries.len()
+            );
+            for entry in state.entries {
+                if entry
+                    .chunks
+                    .iter()
+                    .all(|hash| session.store().contains(hash))
+                {
+                    resumed.push(session.add_entry(entry)?.clone());
+                }
+            }
+        }
+        None if args.resume => log::warn!("no interrupted backup to resume"),
+        _ => {}
+    }
+
+    // Only now: a handler installed before the question above would swallow a Ctrl-C
+    // meant to quit while it waits for an answer.
+    session = session.with_cancel(cancel_on_signals()?);
+
+    let mut journal = BackupJournal::create(&journal_path, &paths)
+        .with_context(|| format!("cannot write journal {}", journal_path.display()))?;
+    let mut done = HashSet::new();
+    for entry in resumed {
+        journal.add_file(&entry)?;
+        done.insert(entry.name);
+    }
+
+    let (files, dirs) = collect_targets(
+        &roots,
+        session.settings(),
+        &args.exclude,
+        &args.include,
+        args.follow_symlinks,
+    )?;
+
+    for dir in dirs {
+        session
+            .add_directory(&dir)
+            .with_context(|| format!("cannot back up {}", dir.display()))?;
+    }
+
+    for file in files {
+        if done.contains(file.to_string_lossy().as_ref()) {
+            continue;
+        }
+        let is_symlink = fs::symlink_metadata(&file).is_ok_and(|metadata| metadata.is_symlink());
+        let entry = match if is_symlink && !args.follow_symlinks {
+            session.add_symlink(&file)
+        } else {
+            session.add_file(&file)
+        } {
+            Ok(entry) => entry,
+            Err(StoreError::Interrupted) => {
+                // Keep the journal for `--resume`, and what was stored until now.
+                session.checkpoint().with_context(context)?;
+                status!(
+                    "Stopped after {} files; back up the same paths with --resume to continue",
+                    session.stats().files
+                );
+                return Err(StoreError::Interrupted.into());
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("cannot back up {}", file.display()));
+            }
+        };
+        journal.add_file(entry)?;
+    }
+
+    let timings = *session.timings();
+    let hard_links = session.stats().hard_links;
+    let (id, snapshot) = session.commit(paths, &args.tag).with_context(context)?;
+    journal.remove()?;
+
+    print_saved(&id, &snapshot, &timings, started.elapsed());
+    if hard_links > 0 {
+        status!(
+            "{} hard links recorded without reading them again",
+            hard_links
+        );
+    }
+    Ok(())
+}
+
+/// `session`, comparing its files with those of the last snapshot of `paths` from this
+/// host, if there is one.
+fn with_parent(
+    session: BackupSession<Box<dyn Backend>>,
+    paths: &[String],
+) -> Result<BackupSession<Box<dyn Backend>>> {
+    let parent = Snapshot::find_parent(session.store().backend(), paths, &snapshot::this_host())
+        .context("cannot read the snapshots to compare with")?;
+    Ok(match parent {
+        Some((id, parent)) => {
+            log::debug!("comparing with snapshot {}", id);
+            session.with_parent(&parent.manifest)
+        }
+        None => session,
+    })
+}
+
+/// A token cancelled by the first SIGINT (Ctrl-C) or SIGTERM; a second one exits at
+/// once.
+fn cancel_on_signals() -> Result<CancelToken> {
+    let cancel = CancelToken::new();
+    let handler_cancel = cancel.clone();
+    ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        log::warn!("stopping after the current chunk; interrupt again to quit at once");
+        handler_cancel.cancel();
+    })
+    .context("cannot install a signal handler")?;
+    Ok(cancel)
+}
+
+/// Back up stdin as a single file called `--stdin-name`. A stream cannot be resumed,
+/// so no journal is kept, and progress only shows the bytes read since the total size
+/// is unknown.
+fn backup_stdin(
+    args: &BackupArgs,
+    mut session: BackupSession<Box<dyn Backend>>,
+    started: Instant,
+) -> Result<()> {
+    if io::stdin().is_terminal() {
+        bail!("refusing to back up a terminal; pipe the data to back up into --stdin");
+    }
+
+    let show_progress = io::stderr().is_terminal() && log::log_enabled!(log::Level::Info);
+    let mut last_shown = Instant::now();
+    let added = session.add_reader(&args.stdin_name, io::stdin().lock(), |bytes| {
+        if show_progress && last_shown.elapsed() >= PROGRESS_INTERVAL {
+            eprint!("\r{} bytes read", bytes);
+            last_shown = Instant::now();
+        }
+    });
+    if show_progress {
+        // Clear the progress line.
+        eprint!("\r\x1b[K");
+    }
+    match added {
+        Ok(_) => {}
+        // Nothing to resume, but the chunks stored so far spare the next backup
+        // of the same data some work.
+        Err(StoreError::Interrupted) => {
+            session.checkpoint().context("cannot back up stdin")?;
+            return Err(StoreError::Interrupted.into());
+        }
+        Err(err) => return Err(err).context("cannot back up stdin"),
+    }
+
+    let timings = *session.timings();
+    let (id, snapshot) = session
+        .commit(vec![args.stdin_name.clone()], &args.tag)
+        .with_context(|| format!("cannot back up to {}", args.repo.display()))?;
+    print_saved(&id, &snapshot, &timings, started.elapsed());
+    Ok(())
+}
 
-        writeln!(
-            out_file,
-            "chunk {:>4}: {:>6} bytes | preview: \"{}{}\"",
-            idx,
-            chunk.len(),
-            preview,
-            if chunk.len() > preview_len { "…" } else { "" }
-        )?;
+/// The files below `roots` that a backup reads, and the directories it records, as
+/// `--exclude`, `--include` and `--follow-symlinks` select them.
+fn collect_targets(
+    roots: &[PathBuf],
+    settings: &Settings,
+    exclude: &[String],
+    include: &[String],
+    follow_symlinks: bool,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    // Patterns from the command line come last, so they can re-include with `!`.
+    let excludes: Vec<&String> = settings.exclude.iter().chain(exclude).collect();
+    let filter = ExcludeFilter::new(&excludes).context("invalid exclude pattern")?;
+    // Excludes stay with `filter`, which also handles `!` and `.rbckpignore` files.
+    let includes = path_globs(include).context("invalid --include pattern")?;
+    let file_filter = FileFilter::new(&includes, &[]);
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for path in roots {
+        let first = files.len();
+        walk::collect_files(path, &filter, &file_filter, follow_symlinks, &mut files)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        dirs.extend(walk::parent_dirs(path, &files[first..]));
     }
+    Ok((files, dirs))
+}
 
-    for (k, v) in chunk_map.iter() {
-        println!("Chunk [{}] - count {}", k, v.len());
+/// Chunk what a backup of the given paths would and report how much of it the
+/// repository does not have yet.
+fn estimate(args: &EstimateArgs, config: Option<&Path>) -> Result<()> {
+    let settings = load_settings(config)?;
+    let context = || format!("cannot estimate a backup to {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &settings.backend,
+        settings.pack_size,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+
+    let roots = walk::backup_roots(args.paths.iter().chain(&args.targets).map(PathBuf::as_path));
+    let (files, _) = collect_targets(
+        &roots,
+        &settings,
+        &args.exclude,
+        &args.include,
+        args.follow_symlinks,
+    )?;
+    let mut estimator = Estimator::new(&settings, &store);
+    for file in files {
+        // A backup records symlinks it does not follow without reading anything.
+        let is_symlink = fs::symlink_metadata(&file).is_ok_and(|metadata| metadata.is_symlink());
+        if is_symlink && !args.follow_symlinks {
+            continue;
+        }
+        estimator
+            .add_file(&file)
+            .with_context(|| format!("cannot read {}", file.display()))?;
     }
 
+    let estimate = estimator.estimate();
+    println!(
+        "Total: {} files, {} bytes in {} chunks",
+        estimate.files, estimate.bytes, estimate.chunks
+    );
+    println!(
+        "New: {} bytes in {} chunks",
+        estimate.new_bytes, estimate.new_chunks
+    );
+    println!(
+        "New, compressed: about {} bytes (zstd, from a sample of {} bytes)",
+        estimate.compressed_new_bytes(),
+        estimate.sampled_bytes
+    );
     Ok(())
 }
+
+/// The status lines of a new snapshot, with the time it took from start to finish.
+/// `--verbose` adds where that time went.
+fn print_saved(id: &str, snapshot: &Snapshot, timings: &PhaseTimings, elapsed: Duration) {
+    let stats = snapshot.stats.unwrap_or_default();
+    status!(
+        "Snapshot {} saved: {} files, {} bytes, {} of {} chunks new ({} bytes)",
+        id,
+        snapshot.manifest.entries.len(),
+        stats.bytes_read,
+        stats.new_chunks,
+        stats.chunks,
+        stats.bytes_stored
+    );
+    status!(
+        "Files: {} new, {} changed, {} unchanged",
+        stats.files_new,
+        stats.files_changed,
+        stats.files_unchanged
+    );
+    status!(
+        "Processed {} bytes in {:.2}s ({:.1} MB/s)",
+        stats.bytes_read,
+        elapsed.as_secs_f64(),
+        timing::throughput_mb_s(stats.bytes_read, elapsed)
+    );
+    if log::log_enabled!(log::Level::Debug) {
+        println!(
+            "Time spent: read {:.2}s, chunk {:.2}s, hash {:.2}s, other {:.2}s",
+            timings.read.as_secs_f64(),
+            timings.chunk.as_secs_f64(),
+            timings.hash.as_secs_f64(),
+            elapsed.saturating_sub(timings.total()).as_secs_f64()
+        );
+    }
+}
+
+/// Restore all files of a snapshot below a target directory.
+fn restore(args: &RestoreArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot restore from {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &backend_settings(config)?,
+        DEFAULT_PACK_SIZE,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+
+    let id = Snapshot::resolve_id(store.backend(), &args.snapshot).with_context(context)?;
+    let snapshot = Snapshot::load(store.backend(), &id).with_context(context)?;
+
+    let file_filter = FileFilter::new(
+        &path_globs(&args.include).context("invalid --include pattern")?,
+        &path_globs(&args.exclude).context("invalid --exclude pattern")?,
+    );
+
+    // Symlinks after all files, which must not be written through them.
+    let (links, files): (Vec<_>, Vec<_>) = snapshot
+        .manifest
+        .entries
+        .iter()
+        .filter(|entry| file_filter.matches(&restore::entry_path(&entry.name)))
+        .partition(|entry| entry.symlink_target().is_some());
+    let mut plan = Vec::new();
+    for entry in files.into_iter().chain(links) {
+        let out_path = restore::restore_path(&args.target, &entry.name);
+        let action = restore::plan_entry(entry, &out_path, args.overwrite)
+            .with_context(|| format!("cannot check {}", out_path.display()))?;
+        plan.push((entry, out_path, action));
+    }
+    let count = |action| {
+        plan.iter()
+            .filter(|(_, _, planned)| *planned == action)
+            .count()
+    };
+
+    if args.dry_run {
+        for (_, out_path, action) in &plan {
+            let verb = match action {
+                RestoreAction::Create => "create",
+                RestoreAction::Overwrite => "overwrite",
+                RestoreAction::Skip => "skip",
+                RestoreAction::Conflict => "conflict",
+            };
+            println!("{:<9} {}", verb, out_path.display());
+        }
+        println!(
+            "Would create {}, overwrite {} and skip {} files; {} conflicts",
+            count(RestoreAction::Create),
+            count(RestoreAction::Overwrite),
+            count(RestoreAction::Skip),
+            count(RestoreAction::Conflict)
+        );
+    }
+    // Nothing is written unless everything can be.
+    if let Some((_, first, _)) = plan
+        .iter()
+        .find(|(_, _, action)| *action == RestoreAction::Conflict)
+    {
+        bail!(
+            "{} paths to restore are in the way, such as {} (see --overwrite)",
+            count(RestoreAction::Conflict),
+            first.display()
+        );
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let mut restored_bytes = 0;
+    let mut hard_links = restore::HardLinks::new();
+    for (entry, out_path, action) in &plan {
+        let restore_context = || format!("cannot restore {}", out_path.display());
+        if *action == RestoreAction::Skip {
+            hard_links.add(entry, out_path);
+            continue;
+        }
+        let parent = out_path.parent().unwrap_or(&args.target);
+        if *action == RestoreAction::Overwrite {
+            // Removed rather than written over, which would change other hard links to it.
+            restore::check_no_symlinks(&args.target, parent).with_context(restore_context)?;
+            fs::remove_file(out_path).with_context(restore_context)?;
+        }
+        restore::check_no_symlinks(&args.target, out_path).with_context(restore_context)?;
+        fs::create_dir_all(parent)
+            .with_context(|| format!("cannot create {}", parent.display()))?;
+        if entry.symlink_target().is_some() {
+            // Metadata calls would follow the link, so it keeps what it was created with.
+            restore::restore_symlink(entry, out_path).with_context(restore_context)?;
+            continue;
+        }
+        hard_links
+            .restore_file(entry, &store, out_path)
+            .with_context(restore_context)?;
+        restored_bytes += entry.size;
+        if !args.no_metadata {
+            restore_metadata(entry, out_path);
+        }
+    }
+    // Last and children first, so that restoring their contents does not touch them again.
+    if !args.no_metadata {
+        for dir in snapshot.manifest.directories.iter().rev() {
+            let out_path = restore::restore_path(&args.target, &dir.name);
+            if restore::check_no_symlinks(&args.target, &out_path).is_ok() && out_path.is_dir() {
+                restore_metadata(dir, &out_path);
+            }
+        }
+    }
+
+    status!(
+        "Restored {} files ({} bytes) from snapshot {} to {}: {} created, {} overwritten, {} unchanged",
+        count(RestoreAction::Create) + count(RestoreAction::Overwrite),
+        restored_bytes,
+        id,
+        args.target.display(),
+        count(RestoreAction::Create),
+        count(RestoreAction::Overwrite),
+        count(RestoreAction::Skip)
+    );
+    Ok(())
+}
+
+/// Stream the content of one file of a snapshot to stdout.
+fn cat(args: &CatArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot read from {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &backend_settings(config)?,
+        DEFAULT_PACK_SIZE,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+
+    let id = Snapshot::resolve_id(store.backend(), &args.snapshot).with_context(context)?;
+    let snapshot = Snapshot::load(store.backend(), &id).with_context(context)?;
+    let Some(entry) = restore::find_entry(&snapshot.manifest, &args.path) else {
+        bail!("{} is not in snapshot {}", args.path, id);
+    };
+    if let Some(target) = entry.symlink_target() {
+        bail!(
+            "{} is a symlink to {}",
+            entry.name,
+            String::from_utf8_lossy(target)
+        );
+    }
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    restore::write_entry(entry, &store, &mut out)
+        .with_context(|| format!("cannot read {}", entry.name))?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write a snapshot as an archive to a file or stdout.
+fn export(args: &ExportArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot read from {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &backend_settings(config)?,
+        DEFAULT_PACK_SIZE,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+
+    let id = Snapshot::resolve_id(store.backend(), &args.snapshot).with_context(context)?;
+    let snapshot = Snapshot::load(store.backend(), &id).with_context(context)?;
+
+    match &args.output {
+        Some(path) => {
+            let file =
+                File::create(path).with_context(|| format!("cannot create {}", path.display()))?;
+            let out = export::export(
+                &snapshot.manifest,
+                &store,
+                args.format,
+                io::BufWriter::new(file),
+            )
+            .with_context(|| format!("cannot export snapshot {}", id))?;
+            out.into_inner()
+                .map_err(|err| err.into_error())?
+                .sync_all()?;
+            status!("Exported snapshot {} to {}", id, path.display());
+        }
+        None => {
+            if io::stdout().is_terminal() {
+                bail!("refusing to write an archive to a terminal; use --output or a pipe");
+            }
+            let out = io::BufWriter::new(io::stdout().lock());
+            export::export(&snapshot.manifest, &store, args.format, out)
+                .with_context(|| format!("cannot export snapshot {}", id))?
+                .flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a tar archive from stdin into a new snapshot.
+fn import(args: &ImportArgs, config: Option<&Path>) -> Result<()> {
+    if io::stdin().is_terminal() {
+        bail!("refusing to read an archive from a terminal; pipe or redirect one to stdin");
+    }
+    let started = Instant::now();
+    let settings = load_settings(config)?;
+    let context = || format!("cannot import to {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &settings.backend,
+        settings.pack_size,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+    let mut session = BackupSession::new(settings, store);
+
+    let imported =
+        import::import_tar(&mut session, io::stdin().lock()).context("cannot read the archive")?;
+    let timings = *session.timings();
+    let (id, snapshot) = session
+        .commit(imported.paths, &args.tag)
+        .with_context(context)?;
+
+    print_saved(&id, &snapshot, &timings, started.elapsed());
+    if imported.skipped > 0 {
+        status!(
+            "Skipped {} archive entries a snapshot cannot hold",
+            imported.skipped
+        );
+    }
+    Ok(())
+}
+
+/// Check a repository, re-reading all or some of its chunks if asked to. Fails if
+/// anything is wrong.
+fn verify_repo(args: &VerifyArgs, config: Option<&Path>) -> Result<()> {
+    let backend_settings = backend_settings(config)?;
+    let context = || format!("cannot verify {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &backend_settings,
+        DEFAULT_PACK_SIZE,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+
+    let read_data = match args.read_data_subset {
+        Some(subset) => Some(subset),
+        None => args.read_data.then_some(verify::DataSubset::ALL),
+    };
+    let report = verify::verify_repo(&store, read_data.as_ref()).with_context(context)?;
+
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    if !report.is_clean() {
+        bail!(
+            "{} problem(s) found in {}",
+            report.problems.len(),
+            args.repo.display()
+        );
+    }
+    status!(
+        "No problems in {}: {} snapshots, {} chunks in {} packs",
+        args.repo.display(),
+        report.snapshots,
+        report.chunks,
+        report.packs
+    );
+    if let Some(subset) = read_data {
+        status!(
+            "Re-read {} chunks ({} bytes, subset {})",
+            report.chunks_read,
+            report.bytes_read,
+            subset
+        );
+    }
+    Ok(())
+}
+
+/// Re-hash every chunk of a repository, and with `--repair` restore or list the
+/// corrupt ones. Fails if any chunk is corrupt, repaired or not.
+fn check_repo(args: &CheckArgs, config: Option<&Path>) -> Result<()> {
+    let backend_settings = backend_settings(config)?;
+    let context = || format!("cannot check {}", args.repo.display());
+    // A repair rewrites packs, which nobody may be reading from meanwhile.
+    let lock_kind = if args.repair {
+        LockKind::Exclusive
+    } else {
+        LockKind::Shared
+    };
+    let store = open_store(&args.repo, &backend_settings, DEFAULT_PACK_SIZE, lock_kind)
+        .with_context(context)?;
+
+    let report = check::check_chunks(&store).with_context(context)?;
+    for chunk in &report.corrupt {
+        println!("corrupt chunk {} in {}", chunk.hash, chunk.pack);
+    }
+    if report.corrupt.is_empty() {
+        status!(
+            "All {} chunks ({} bytes) in {} match their ids",
+            report.chunks,
+            report.bytes,
+            args.repo.display()
+        );
+        return Ok(());
+    }
+
+    if args.repair {
+        let secondary =
+            store::open_secondary(&args.repo, &backend_settings).with_context(context)?;
+        if secondary.is_none() {
+            log::warn!("no secondary store to repair from");
+        }
+        let repair = check::repair_chunks(&store, secondary.as_deref(), &report.corrupt)
+            .with_context(context)?;
+        for hash in &repair.repaired {
+            println!("repaired chunk {}", hash);
+        }
+        if !repair.marked.is_empty() {
+            println!(
+                "{} chunk(s) could not be repaired; listed in {}",
+                repair.marked.len(),
+                check::CORRUPT_LIST_NAME
+            );
+        }
+    }
+    bail!(
+        "{} of {} chunks in {} are corrupt",
+        report.corrupt.len(),
+        report.chunks,
+        args.repo.display()
+    );
+}
+
+/// Compare a live directory with a snapshot and list what differs. Fails unless
+/// everything matches.
+fn verify_tree(args: &VerifyTreeArgs, config: Option<&Path>) -> Result<()> {
+    let settings = load_settings(config)?;
+    let context = || format!("cannot read from {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &settings.backend,
+        DEFAULT_PACK_SIZE,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+
+    let id = Snapshot::resolve_id(store.backend(), &args.snapshot).with_context(context)?;
+    let snapshot = Snapshot::load(store.backend(), &id).with_context(context)?;
+    let params = settings
+        .chunk_settings
+        .cdc_params()
+        .with_hash_algorithm(store.hash_algorithm())
+        .with_hash_key(store.hash_key());
+    let report = verify::verify_tree(
+        &snapshot.manifest,
+        &args.path,
+        args.read_data.then_some(&params),
+    )
+    .with_context(|| format!("cannot check {}", args.path.display()))?;
+
+    for (change, paths) in [
+        ("added", &report.added),
+        ("missing", &report.missing),
+        ("modified", &report.modified),
+    ] {
+        for path in paths {
+            println!("{:<9}{}", change, path.display());
+        }
+    }
+    if !report.is_clean() {
+        bail!(
+            "{} differs from snapshot {}: {} added, {} missing, {} modified",
+            args.path.display(),
+            id,
+            report.added.len(),
+            report.missing.len(),
+            report.modified.len()
+        );
+    }
+    status!(
+        "{} matches snapshot {} ({} files checked)",
+        args.path.display(),
+        id,
+        report.checked
+    );
+    Ok(())
+}
+
+/// Reapply the ownership and metadata of `entry` to `path`, warning about what fails.
+fn restore_metadata(entry: &ManifestEntry, path: &Path) {
+    if let Err(err) = restore::apply_ownership(entry, path) {
+        log::warn!("cannot restore owner of {}: {}", path.display(), err);
+    }
+    if let Err(err) = restore::apply_metadata(entry, path) {
+        log::warn!("cannot restore metadata of {}: {}", path.display(), err);
+    }
+}
+
+/// Forget the snapshots a retention policy does not keep, and optionally prune the
+/// chunks only they used.
+fn forget(args: &ForgetArgs, config: Option<&Path>) -> Result<()> {
+    let settings = optional_settings(config)?;
+    let cli_policy = RetentionPolicy {
+        keep_last: args.keep_last,
+        keep_daily: args.keep_daily,
+        keep_weekly: args.keep_weekly,
+        keep_monthly: args.keep_monthly,
+    };
+    let policy = match &settings {
+        Some(settings) if cli_policy.is_empty() => settings.retention.clone(),
+        _ => cli_policy,
+    };
+    if policy.is_empty() {
+        bail!("no retention policy: pass --keep-* options or set them in [retention]");
+    }
+
+    let context = || format!("cannot forget snapshots in {}", args.repo.display());
+    let (backend_settings, pack_size) = settings.map_or_else(
+        || (BackendSettings::default(), DEFAULT_PACK_SIZE),
+        |settings| (settings.backend, settings.pack_size),
+    );
+    // Pruning must not race a backup that still references chunks it considers unused.
+    let lock_kind = if args.prune && !args.dry_run {
+        LockKind::Exclusive
+    } else {
+        LockKind::Shared
+    };
+    let mut store =
+        open_store(&args.repo, &backend_settings, pack_size, lock_kind).with_context(context)?;
+
+    let filter = args.filter.snapshot_filter();
+    let mut snapshots = HashMap::new();
+    for id in Snapshot::list(store.backend()).with_context(context)? {
+        let snapshot = Snapshot::load(store.backend(), &id)
+            .with_context(|| format!("cannot read snapshot {}", id))?;
+        snapshots.insert(id, snapshot);
+    }
+    let considered: Vec<_> = snapshots
+        .iter()
+        .filter(|(_, snapshot)| filter.matches(snapshot))
+        .map(|(id, snapshot)| (id.clone(), snapshot))
+        .collect();
+    let groups = policy.apply_grouped(&considered);
+
+    for (group, decisions) in &groups {
+        println!("{}:", group);
+        for decision in decisions {
+            let time = format_time(decision.time);
+            if decision.keep() {
+                let reasons: Vec<String> = decision.reasons.iter().map(|r| r.to_string()).collect();
+                println!(
+                    "  keep    {}  {}  ({})",
+                    short_id(&decision.id),
+                    time,
+                    reasons.join(", ")
+                );
+            } else {
+                println!("  forget  {}  {}", short_id(&decision.id), time);
+            }
+        }
+    }
+
+    let decisions: Vec<_> = groups
+        .into_iter()
+        .flat_map(|(_, decisions)| decisions)
+        .collect();
+    let forgotten = decisions.iter().filter(|decision| !decision.keep()).count();
+    if args.dry_run {
+        println!(
+            "Would forget {} of {} snapshots",
+            forgotten,
+            decisions.len()
+        );
+        return Ok(());
+    }
+    for decision in decisions.iter().filter(|decision| !decision.keep()) {
+        Snapshot::remove(store.backend(), &decision.id).with_context(context)?;
+    }
+    status!("Forgot {} of {} snapshots", forgotten, decisions.len());
+
+    if args.prune {
+        // Snapshots outside the filter are kept as well.
+        let forgotten: HashSet<&str> = decisions
+            .iter()
+            .filter(|decision| !decision.keep())
+            .map(|decision| decision.id.as_str())
+            .collect();
+        let referenced: HashSet<String> = snapshots
+            .iter()
+            .filter(|(id, _)| !forgotten.contains(id.as_str()))
+            .flat_map(|(_, snapshot)| &snapshot.manifest.entries)
+            .flat_map(|entry| entry.chunks.iter().cloned())
+            .collect();
+        let report = store.prune(&referenced).with_context(context)?;
+        status!(
+            "Pruned {} chunks ({} bytes), repacked {} chunks, deleted {} packs",
+            report.removed_chunks,
+            report.removed_bytes,
+            report.repacked_chunks,
+            report.removed_packs
+        );
+    }
+    Ok(())
+}
+
+/// Mount a snapshot read-only until the filesystem is unmounted.
+#[cfg(feature = "fuse")]
+fn mount(args: &MountArgs, config: Option<&Path>) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let context = || format!("cannot mount from {}", args.repo.display());
+    let store = open_store(
+        &args.repo,
+        &backend_settings(config)?,
+        DEFAULT_PACK_SIZE,
+        LockKind::Shared,
+    )
+    .with_context(context)?;
+    let id = Snapshot::resolve_id(store.backend(), &args.snapshot).with_context(context)?;
+    let snapshot = Snapshot::load(store.backend(), &id).with_context(context)?;
+
+    // Files show up as owned by whoever owns the mountpoint.
+    let owner = fs::metadata(&args.mountpoint)
+        .with_context(|| format!("cannot use mountpoint {}", args.mountpoint.display()))?;
+    let filesystem =
+        rbckp::backup::fuse::SnapshotFs::new(snapshot, store, owner.uid(), owner.gid());
+
+    let mut config = fuser::Config::default();
+    config.mount_options.extend([
+        fuser::MountOption::RO,
+        fuser::MountOption::FSName(format!("rbckp:{}", short_id(&id))),
+    ]);
+
+    status!(
+        "Snapshot {} mounted at {}; unmount it to exit",
+        id,
+        args.mountpoint.display()
+    );
+    fuser::mount(filesystem, &args.mountpoint, &config)
+        .with_context(|| format!("cannot mount on {}", args.mountpoint.display()))
+}
+
+#[cfg(not(feature = "fuse"))]
+fn mount(_args: &MountArgs, _config: Option<&Path>) -> Result<()> {
+    bail!("rbckp was built without the `fuse` feature")
+}
+
+/// List the snapshots of a repository, oldest first.
+fn list_snapshots(args: &ListSnapshotsArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot list snapshots of {}", args.repo.display());
+    let backend = open_repo(&args.repo, config).with_context(context)?;
+
+    let filter = args.filter.snapshot_filter();
+    let mut snapshots = Vec::new();
+    for id in Snapshot::list(&backend).with_context(context)? {
+        let snapshot = Snapshot::load(&backend, &id)
+            .with_context(|| format!("cannot read snapshot {}", id))?;
+        if filter.matches(&snapshot) {
+            snapshots.push((id, snapshot));
+        }
+    }
+    snapshots.sort_by_key(|(_, snapshot)| snapshot.time);
+
+    if args.json {
+        let infos: Vec<SnapshotInfo> = snapshots
+            .iter()
+            .map(|(id, snapshot)| SnapshotInfo::new(id, snapshot))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&infos)?);
+        return Ok(());
+    }
+
+    for (id, snapshot) in &snapshots {
+        let mut line = format!(
+            "{}  {}  {}  {} files  {}",
+            short_id(id),
+            format_time(snapshot.time),
+            if snapshot.hostname.is_empty() {
+                "-"
+            } else {
+                &snapshot.hostname
+            },
+            snapshot.manifest.entries.len(),
+            snapshot.paths.join(" ")
+        );
+        if !snapshot.tags.is_empty() {
+            line += &format!("  [{}]", snapshot.tags.join(", "));
+        }
+        println!("{}", line);
+        if args.long {
+            match snapshot.stats {
+                Some(stats) => println!(
+                    "    {} new, {} changed, {} unchanged; {} bytes read, {} stored; \
+                     {} of {} chunks new; {:.2}s, {:.1} MB/s",
+                    stats.files_new,
+                    stats.files_changed,
+                    stats.files_unchanged,
+                    stats.bytes_read,
+                    stats.bytes_stored,
+                    stats.new_chunks,
+                    stats.chunks,
+                    stats.duration().as_secs_f64(),
+                    stats.throughput_mb_s()
+                ),
+                None => println!("    no run statistics"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add (`add`) or remove a tag of a snapshot.
+fn tag_snapshot(args: &TagArgs, config: Option<&Path>, add: bool) -> Result<()> {
+    let context = || format!("cannot change tags in {}", args.repo.display());
+    let backend = open_repo(&args.repo, config).with_context(context)?;
+    let locks = RepoLocks::of(&backend);
+    let _lock = locks
+        .acquire(LockKind::Shared, DEFAULT_LOCK_WAIT)
+        .with_context(context)?;
+    // Two tag changes to the same snapshot must not both start from its old tags.
+    let _commit = locks
+        .acquire_commit(DEFAULT_LOCK_WAIT)
+        .with_context(context)?;
+
+    let id = Snapshot::resolve_id(&backend, &args.snapshot).with_context(context)?;
+    let mut snapshot = Snapshot::load(&backend, &id).with_context(context)?;
+
+    if add && !snapshot.add_tag(&args.tag) {
+        status!("Snapshot {} is already tagged {}", id, args.tag);
+    } else if !add && !snapshot.remove_tag(&args.tag) {
+        status!("Snapshot {} is not tagged {}", id, args.tag);
+    } else {
+        snapshot.update(&backend, &id).with_context(context)?;
+        let verb = if add { "Tagged" } else { "Untagged" };
+        status!("{} snapshot {} {}", verb, id, args.tag);
+    }
+    Ok(())
+}
+
+/// Backend of the initialized repository at `repo`, for commands that do not need chunks.
+fn open_repo(repo: &Path, config: Option<&Path>) -> Result<Box<dyn Backend>> {
+    let backend = store::open_backend(repo, &backend_settings(config)?)?;
+    RepoConfig::load(&backend)?;
+    Ok(backend)
+}
+
+/// Open the chunk store of `repo`; remote repositories get a local index cache, and
+/// with `[cache] enabled`, a chunk cache. Packs are written as `[upload]` says.
+fn open_store(
+    repo: &Path,
+    backend_settings: &BackendSettings,
+    pack_size: u64,
+    lock_kind: LockKind,
+) -> Result<ChunkStore<Box<dyn Backend>>> {
+    let backend = store::open_backend(repo, backend_settings)?;
+    let store = match IndexCache::default_root() {
+        Some(cache_root) if is_remote(&store::repo_location(repo, backend_settings)) => {
+            let store = ChunkStore::open_cached(backend, pack_size, lock_kind, &cache_root)?;
+            let chunk_cache = &backend_settings.chunk_cache;
+            if chunk_cache.enabled && !store.config().id.is_empty() {
+                let dir = cache_root.join(&store.config().id).join("chunks");
+                let cache = ChunkCache::open(&dir, chunk_cache.max_size())
+                    .with_context(|| format!("cannot open chunk cache {}", dir.display()))?;
+                store.with_chunk_cache(cache)
+            } else {
+                store
+            }
+        }
+        _ => ChunkStore::open(backend, pack_size, lock_kind)?,
+    };
+    let upload = &backend_settings.upload;
+    Ok(store
+        .with_upload_batch(upload.batch_packs)
+        .with_concurrent_uploads(upload.max_concurrent))
+}
+
+/// Where the backup journal of a repository lives: in its directory of the local
+/// cache (see [`IndexCache::default_root`]), keyed by repository id, so it never ends
+/// up in the repository. Without an id or a cache directory, in the temp directory,
+/// keyed by the repository location.
+fn journal_path(repo_id: &str, location: &Path) -> PathBuf {
+    match IndexCache::default_root() {
+        Some(cache_root) if !repo_id.is_empty() => {
+            cache_root.join(repo_id).join(journal::JOURNAL_NAME)
+        }
+        _ => {
+            let key = blake3::hash(location.as_os_str().as_encoded_bytes()).to_hex();
+            std::env::temp_dir().join(format!("rbckp-{}-{}", &key[..16], journal::JOURNAL_NAME))
+        }
+    }
+}
+
+/// Whether to resume an interrupted backup that finished `files` files. It is resumed
+/// unless `--no-resume` is given; only on a terminal, and without `--resume` or
+/// `--yes`, is the user asked first.
+fn resume_interrupted(args: &BackupArgs, files: usize) -> Result<bool> {
+    if args.resume || args.yes || !io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    confirm_or_yes(&format!(
+        "An interrupted backup of these paths finished {} files. Resume it?",
+        files
+    ))
+}
+
+/// Whether a repository location is a URL rather than a local directory.
+fn is_remote(location: &Path) -> bool {
+    location.to_str().is_some_and(|url| url.contains("://"))
+}
+
+/// Regenerate a repository index from its packs, e.g. after it was lost or damaged.
+fn rebuild_index(args: &RebuildIndexArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot rebuild index of {}", args.repo.display());
+    let backend =
+        store::open_backend(&args.repo, &backend_settings(config)?).with_context(context)?;
+
+    let report = match ChunkStore::rebuild_index(&backend, args.read_data) {
+        Err(err @ StoreError::Locked { .. }) if args.force_unlock => {
+            if !confirm(&format!(
+                "{}. Only break the lock if that process is gone. Break it?",
+                err
+            ))? {
+                bail!("repository is still locked");
+            }
+            RepoLocks::of(&backend).break_lock().with_context(context)?;
+            ChunkStore::rebuild_index(&backend, args.read_data)
+        }
+        result => result,
+    }
+    .with_context(context)?;
+
+    for problem in &report.problems {
+        log::warn!("skipped {}", problem);
+    }
+    status!(
+        "Index rebuilt: {} chunks from {} packs",
+        report.chunks,
+        report.packs
+    );
+
+    if !report.problems.is_empty() {
+        bail!(
+            "{} problem(s) found, affected chunks are missing from the index",
+            report.problems.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Upgrade a repository to the current format, one step at a time.
+fn migrate_repo(args: &MigrateArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot migrate {}", args.repo.display());
+    let backend =
+        store::open_backend(&args.repo, &backend_settings(config)?).with_context(context)?;
+    let report = migrate::migrate(&backend).with_context(context)?;
+
+    if report.applied.is_empty() {
+        status!(
+            "Repository {} is already at format version {}",
+            args.repo.display(),
+            report.to
+        );
+        return Ok(());
+    }
+    for step in &report.applied {
+        status!("Applied: {}", step);
+    }
+    status!(
+        "Repository {} migrated from format version {} to {}",
+        args.repo.display(),
+        report.from,
+        report.to
+    );
+    Ok(())
+}
+
+/// Clear a stale repository lock, as long as all its holders are old enough to be
+/// presumed dead. A lock a live process still holds is never broken, see
+/// [`RepoLock::break_lock`](rbckp::backup::store::lock::RepoLock::break_lock).
+fn unlock(args: &UnlockArgs, config: Option<&Path>) -> Result<()> {
+    let context = || format!("cannot unlock {}", args.repo.display());
+    let backend = open_repo(&args.repo, config).with_context(context)?;
+    let locks = RepoLocks::of(&backend);
+
+    match locks.acquire(LockKind::Exclusive, Duration::ZERO) {
+        Ok(Some(_)) => {
+            status!("Repository {} is not locked", args.repo.display());
+            return Ok(());
+        }
+        Ok(None) => bail!("{} does not support locking", args.repo.display()),
+        Err(StoreError::Locked { .. }) => {}
+        Err(err) => return Err(err).with_context(context),
+    }
+
+    let min_age = Duration::from_secs(args.older_than * 60);
+    let holders = locks.holders().with_context(context)?;
+    let recent: Vec<_> = holders
+        .iter()
+        .filter(|holder| holder.age() < min_age)
+        .collect();
+    if !recent.is_empty() {
+        for holder in &recent {
+            log::error!("locked by {}", holder);
+        }
+        bail!(
+            "repository {} was locked less than {} minutes ago; make sure the holders are gone and use --older-than",
+            args.repo.display(),
+            args.older_than
+        );
+    }
+
+    locks.break_lock().with_context(context)?;
+    for holder in &holders {
+        status!("Removed stale lock of {}", holder);
+    }
+    status!("Repository {} unlocked", args.repo.display());
+    Ok(())
+}
+
+/// Compile `--include` / `--exclude` style patterns, see [`filter::path_glob`].
+fn path_globs(patterns: &[String]) -> io::Result<Vec<Glob>> {
+    patterns
+        .iter()
+        .map(|pattern| filter::path_glob(pattern))
+        .collect()
+}
+
+/// Backend settings for repository commands, which also work without a settings file.
+fn backend_settings(config: Option<&Path>) -> Result<BackendSettings> {
+    Ok(optional_settings(config)?
+        .map(|settings| settings.backend)
+        .unwrap_or_default())
+}
+
+/// The settings file given with `--config`, or `./settings.ini`.
+fn load_settings(config: Option<&Path>) -> Result<Settings> {
+    let path = config.unwrap_or(Path::new(DEFAULT_SETTINGS_PATH));
+    Settings::from_path(path).with_context(|| format!("cannot load settings {}", path.display()))
+}
+
+/// Like [`load_settings`], but without a `--config`, a missing `./settings.ini` is fine.
+fn optional_settings(config: Option<&Path>) -> Result<Option<Settings>> {
+    if config.is_none() && !Path::new(DEFAULT_SETTINGS_PATH).exists() {
+        return Ok(None);
+    }
+    load_settings(config).map(Some)
+}
+
+/// A snapshot time to the second, e.g. `2026-01-31T12:00:00Z`.
+fn format_time(time: OffsetDateTime) -> String {
+    time.replace_nanosecond(0)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+/// The first 12 characters of a snapshot id, or all of a shorter one: ids come from
+/// object names, which may be anything.
+fn short_id(id: &str) -> &str {
+    id.get(..12).unwrap_or(id)
+}
+
+/// Ask a yes/no question on the terminal; anything but "y"/"yes" means no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Ask a yes/no question on the terminal; anything but "n"/"no" means yes.
+fn confirm_or_yes(question: &str) -> Result<bool> {
+    print!("{} [Y/n] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(!matches!(answer.trim().to_lowercase().as_str(), "n" | "no"))
+}
+
+/// Load the file to back up, with the path in every error message.
+fn read_target_file(path: &Path, prefer_mmap: bool) -> Result<FileData> {
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("cannot read target file {}", path.display()))?;
+    if !metadata.is_file() {
+        bail!(
+            "cannot read target file {}: not a regular file",
+            path.display()
+        );
+    }
+
+    rbckp::backup::io::read_file(path, prefer_mmap)
+        .with_context(|| format!("cannot read target file {}", path.display()))
+}
+
+fn write_chunk_preview(
+    out: &mut impl Write,
+    idx: usize,
+    chunk: &[u8],
+    preview_len: usize,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "chunk {:>4}: {:>6} bytes | preview: \"{}\"",
+        idx,
+        chunk.len(),
+        preview::preview(chunk, preview_len)
+    )
+}
@@ -0,0 +1,157 @@
+//! `ConcurrentUploader`: every chunk is uploaded, never more than the limit at once.
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use common::SETTINGS;
+use rbckp::{
+    backup::{
+        hash::ChunkId,
+        store::{
+            StoreError,
+            async_pool::ConcurrentUploader,
+            async_store::{self, AsyncChunkStore},
+        },
+    },
+    config::{DEFAULT_MAX_CONCURRENT_UPLOADS, Settings},
+};
+
+/// Keeps chunks in memory; every upload takes a little while, and the most uploads
+/// ever in flight together is recorded.
+#[derive(Default)]
+struct SlowStore {
+    chunks: Mutex<HashMap<ChunkId, Vec<u8>>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    /// Uploads of this chunk fail.
+    broken: Option<ChunkId>,
+}
+
+impl AsyncChunkStore for SlowStore {
+    async fn put(&self, id: ChunkId, data: Vec<u8>) -> Result<(), StoreError> {
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if self.broken == Some(id) {
+            return Err(StoreError::Io(std::io::Error::other("upload failed")));
+        }
+        self.chunks.lock().unwrap().insert(id, data);
+        Ok(())
+    }
+
+    async fn get(&self, id: ChunkId) -> Result<Vec<u8>, StoreError> {
+        self.chunks
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| StoreError::ChunkNotFound(id.to_string()))
+    }
+
+    async fn has(&self, id: ChunkId) -> Result<bool, StoreError> {
+        Ok(self.chunks.lock().unwrap().contains_key(&id))
+    }
+
+    async fn delete(&self, id: ChunkId) -> Result<(), StoreError> {
+        self.chunks.lock().unwrap().remove(&id);
+        Ok(())
+    }
+}
+
+fn chunks(count: usize) -> Vec<(ChunkId, Vec<u8>)> {
+    (0..count)
+        .map(|i| {
+            let data = format!("chunk {}", i).into_bytes();
+            (ChunkId::of(&data), data)
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn uploads_everything_within_the_limit() {
+    let store = Arc::new(SlowStore::default());
+    let uploader = ConcurrentUploader::new(Arc::clone(&store), 3);
+    let chunks = chunks(20);
+
+    assert_eq!(uploader.upload(chunks.clone()).await.unwrap(), 20);
+    assert_eq!(store.max_in_flight.load(Ordering::SeqCst), 3);
+    assert_eq!(store.in_flight.load(Ordering::SeqCst), 0);
+    for (id, data) in chunks {
+        assert!(store.has(id).await.unwrap());
+        assert_eq!(store.get(id).await.unwrap(), data);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn fewer_chunks_than_the_limit() {
+    let store = Arc::new(SlowStore::default());
+    let uploader = ConcurrentUploader::new(Arc::clone(&store), 8);
+
+    assert_eq!(uploader.upload(chunks(2)).await.unwrap(), 2);
+    assert_eq!(store.max_in_flight.load(Ordering::SeqCst), 2);
+    assert_eq!(uploader.upload(Vec::new()).await.unwrap(), 0);
+    assert_eq!(store.chunks.lock().unwrap().len(), 2);
+
+    // No limit would mean nothing is ever uploaded.
+    assert_eq!(
+        ConcurrentUploader::new(Arc::clone(&store), 0).max_concurrent(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn first_failure_is_returned() {
+    let chunks = chunks(6);
+    let store = Arc::new(SlowStore {
+        broken: Some(chunks[2].0),
+        ..SlowStore::default()
+    });
+    let uploader = ConcurrentUploader::new(Arc::clone(&store), 2);
+
+    assert!(matches!(
+        uploader.upload(chunks).await,
+        Err(StoreError::Io(err)) if err.to_string() == "upload failed"
+    ));
+    assert!(store.chunks.lock().unwrap().len() < 6);
+}
+
+#[test]
+fn limit_comes_from_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    let base = SETTINGS;
+    fs::write(&path, base).unwrap();
+    let store = Arc::new(SlowStore::default());
+
+    let settings = Settings::from_path(&path).unwrap();
+    assert_eq!(
+        ConcurrentUploader::from_settings(Arc::clone(&store), &settings).max_concurrent(),
+        DEFAULT_MAX_CONCURRENT_UPLOADS
+    );
+
+    fs::write(&path, format!("{}\n[upload]\nmax_concurrent = 32\n", base)).unwrap();
+    let settings = Settings::from_path(&path).unwrap();
+    assert_eq!(
+        ConcurrentUploader::from_settings(store, &settings).max_concurrent(),
+        32
+    );
+}
+
+#[test]
+fn chunks_are_spread_over_directories() {
+    let id = ChunkId::of(b"chunk");
+    let name = async_store::chunk_object_name(&id);
+    let hex = id.to_string();
+    assert_eq!(name, format!("chunks/{}/{}", &hex[..2], hex));
+}
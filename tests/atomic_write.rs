@@ -0,0 +1,115 @@
+//! Objects are written to a temporary file and renamed into place, so a write that
+//! fails halfway never leaves a partial object behind.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    time::Duration,
+};
+
+use rbckp::backup::store::{
+    Backend, LocalFsBackend,
+    backend::{self, TEMP_SUFFIX},
+    retry::RetryPolicy,
+};
+
+/// Yields `data`, then fails like a disk running full.
+struct FailingAfter<'a> {
+    data: &'a [u8],
+}
+
+impl Read for FailingAfter<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.data.is_empty() {
+            return Err(io::Error::other("no space left on device"));
+        }
+        let len = buf.len().min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Ok(len)
+    }
+}
+
+/// Names of all files in `dir`.
+fn files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn renamed_objects_are_complete() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = LocalFsBackend::new(dir.path());
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+
+    backend.write("packs/0.pack", b"old").unwrap();
+    backend.write("packs/0.pack", &data).unwrap();
+
+    assert_eq!(backend.read("packs/0.pack").unwrap(), data);
+    assert_eq!(files(&dir.path().join("packs")), ["0.pack"]);
+    assert_eq!(backend.list("").unwrap(), ["packs/0.pack"]);
+}
+
+#[test]
+fn interrupted_writes_leave_no_partial_object() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("index.json");
+    let data = vec![7u8; 100_000];
+
+    let err = backend::write_atomic(
+        &path,
+        FailingAfter {
+            data: &data[..50_000],
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "no space left on device");
+    assert!(!path.exists());
+    assert!(files(dir.path()).is_empty());
+
+    // An object that was already there keeps its old content.
+    backend::write_atomic(&path, &b"old"[..]).unwrap();
+    backend::write_atomic(
+        &path,
+        FailingAfter {
+            data: &data[..50_000],
+        },
+    )
+    .unwrap_err();
+    assert_eq!(fs::read(&path).unwrap(), b"old");
+    assert_eq!(files(dir.path()), ["index.json"]);
+}
+
+#[test]
+fn leftover_temp_files_are_not_listed() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = LocalFsBackend::new(dir.path());
+    backend.write("packs/0.pack", b"pack").unwrap();
+    // As left behind by a crash between writing and renaming.
+    fs::write(
+        dir.path()
+            .join(format!("packs/1.pack.123-0{}", TEMP_SUFFIX)),
+        b"pa",
+    )
+    .unwrap();
+
+    assert_eq!(backend.list("packs/").unwrap(), ["packs/0.pack"]);
+}
+
+#[test]
+fn retries_are_configurable() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = LocalFsBackend::new(dir.path());
+    assert_eq!(backend.retry_policy(), RetryPolicy::none());
+
+    let policy = RetryPolicy::with_retries(3, Duration::from_millis(1));
+    let backend = backend.with_retry(policy);
+    assert_eq!(backend.retry_policy().max_attempts, 4);
+    backend.write("config.json", b"{}").unwrap();
+    assert_eq!(backend.read("config.json").unwrap(), b"{}");
+}
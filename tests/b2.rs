@@ -0,0 +1,454 @@
+//! `B2Backend` against a minimal stand-in for the B2 API, including expired
+//! authorizations and upload URLs; and against a real bucket named by
+//! `RBCKP_B2_TEST_BUCKET` with the `b2-integration-tests` feature.
+#![cfg(feature = "b2")]
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use rbckp::{
+    backup::store::{
+        b2::{self, B2Backend},
+        backend::Backend,
+    },
+    config::B2Settings,
+};
+use serde_json::{Value, json};
+use sha1::{Digest, Sha1};
+
+const BUCKET: &str = "test-bucket";
+const BUCKET_ID: &str = "test-bucket-id";
+
+/// What the stand-in server knows.
+#[derive(Default)]
+struct State {
+    authorizations: usize,
+    account_tokens: HashSet<String>,
+    upload_urls: usize,
+    upload_tokens: HashSet<String>,
+    /// File name and id of every version, oldest first, and its content.
+    versions: Vec<(String, String, Vec<u8>)>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// Serve the B2 API functions the backend uses on a local port, one request per
+/// connection.
+fn start_server() -> (String, Arc<Mutex<State>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let state = Arc::new(Mutex::new(State::default()));
+    let (server_url, server_state) = (url.clone(), Arc::clone(&state));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            if let Some(request) = read_request(&stream) {
+                let (status, headers, body) =
+                    handle(&server_url, &mut server_state.lock().unwrap(), request);
+                respond(&mut stream, status, &headers, &body);
+            }
+        }
+    });
+    (url, state)
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next()?.to_string(), parts.next()?.to_string());
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+            }
+            None => break,
+        }
+    }
+    let len = headers
+        .get("content-length")
+        .map_or(0, |len| len.parse().unwrap());
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: u16, headers: &[(&str, String)], body: &[u8]) {
+    let mut response = format!("HTTP/1.1 {} X\r\nConnection: close\r\n", status);
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !headers.iter().any(|(name, _)| *name == "Content-Length") {
+        response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    response.push_str("\r\n");
+    let _ = stream.write_all(response.as_bytes());
+    let _ = stream.write_all(body);
+}
+
+fn json_response(status: u16, value: Value) -> (u16, Vec<(&'static str, String)>, Vec<u8>) {
+    (
+        status,
+        vec![("Content-Type", "application/json".to_string())],
+        value.to_string().into_bytes(),
+    )
+}
+
+fn expired() -> (u16, Vec<(&'static str, String)>, Vec<u8>) {
+    json_response(
+        401,
+        json!({ "status": 401, "code": "expired_auth_token", "message": "expired" }),
+    )
+}
+
+fn decode(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            decoded.push(u8::from_str_radix(&name[i + 1..i + 3], 16).unwrap());
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+fn handle(
+    url: &str,
+    state: &mut State,
+    request: Request,
+) -> (u16, Vec<(&'static str, String)>, Vec<u8>) {
+    let auth = request
+        .headers
+        .get("authorization")
+        .cloned()
+        .unwrap_or_default();
+
+    if request.path == "/b2api/v2/b2_authorize_account" {
+        if !auth.starts_with("Basic ") {
+            return json_response(401, json!({ "code": "unauthorized", "message": "no key" }));
+        }
+        state.authorizations += 1;
+        let token = format!("account-{}", state.authorizations);
+        state.account_tokens.insert(token.clone());
+        return json_response(
+            200,
+            json!({
+                "accountId": "account",
+                "authorizationToken": token,
+                "apiUrl": url,
+                "downloadUrl": url,
+            }),
+        );
+    }
+
+    if request.path == "/upload" {
+        if !state.upload_tokens.contains(&auth) {
+            return expired();
+        }
+        let name = decode(&request.headers["x-bz-file-name"]);
+        let sha1 = format!("{:x}", Sha1::digest(&request.body));
+        if request.headers["x-bz-content-sha1"] != sha1 {
+            return json_response(400, json!({ "code": "bad_request", "message": "sha1" }));
+        }
+        let id = format!("id-{}", state.versions.len());
+        state
+            .versions
+            .push((name.clone(), id.clone(), request.body));
+        return json_response(200, json!({ "fileName": name, "fileId": id }));
+    }
+
+    if !state.account_tokens.contains(&auth) {
+        return expired();
+    }
+
+    if let Some(name) = request.path.strip_prefix(&format!("/file/{}/", BUCKET)) {
+        let name = decode(name);
+        let Some((_, _, data)) = state.versions.iter().rev().find(|(n, ..)| *n == name) else {
+            return json_response(404, json!({ "code": "not_found", "message": name }));
+        };
+        let data = match request.headers.get("range") {
+            Some(range) => {
+                let (start, end) = range
+                    .strip_prefix("bytes=")
+                    .unwrap()
+                    .split_once('-')
+                    .unwrap();
+                let start: usize = start.parse().unwrap();
+                let end = (end.parse::<usize>().unwrap() + 1).min(data.len());
+                data[start.min(end)..end].to_vec()
+            }
+            None => data.clone(),
+        };
+        if request.method == "HEAD" {
+            return (
+                200,
+                vec![("Content-Length", data.len().to_string())],
+                Vec::new(),
+            );
+        }
+        return (200, Vec::new(), data);
+    }
+
+    let body: Value = serde_json::from_slice(&request.body).unwrap();
+    let function = request.path.strip_prefix("/b2api/v2/").unwrap();
+    if function != "b2_list_buckets" {
+        assert_eq!(body["bucketId"].as_str().unwrap_or(BUCKET_ID), BUCKET_ID);
+    }
+    match function {
+        "b2_list_buckets" => {
+            let buckets: Vec<Value> = if body["bucketName"] == BUCKET {
+                vec![json!({ "bucketId": BUCKET_ID, "bucketName": BUCKET })]
+            } else {
+                Vec::new()
+            };
+            json_response(200, json!({ "buckets": buckets }))
+        }
+        "b2_get_upload_url" => {
+            state.upload_urls += 1;
+            let token = format!("upload-{}", state.upload_urls);
+            state.upload_tokens.insert(token.clone());
+            json_response(
+                200,
+                json!({ "uploadUrl": format!("{}/upload", url), "authorizationToken": token }),
+            )
+        }
+        "b2_list_file_names" => {
+            // Two names per page, to exercise paging.
+            let prefix = body["prefix"].as_str().unwrap_or("");
+            let start = body["startFileName"].as_str().unwrap_or("");
+            let mut names: Vec<&String> = state
+                .versions
+                .iter()
+                .map(|(name, ..)| name)
+                .filter(|name| name.starts_with(prefix) && name.as_str() >= start)
+                .collect();
+            names.sort();
+            names.dedup();
+            let files: Vec<Value> = names
+                .iter()
+                .take(2)
+                .map(|name| json!({ "fileName": name, "fileId": "id" }))
+                .collect();
+            json_response(200, json!({ "files": files, "nextFileName": names.get(2) }))
+        }
+        "b2_list_file_versions" => {
+            let prefix = body["prefix"].as_str().unwrap_or("");
+            let files: Vec<Value> = state
+                .versions
+                .iter()
+                .rev()
+                .filter(|(name, ..)| name.starts_with(prefix))
+                .map(|(name, id, _)| json!({ "fileName": name, "fileId": id }))
+                .collect();
+            json_response(
+                200,
+                json!({ "files": files, "nextFileName": null, "nextFileId": null }),
+            )
+        }
+        "b2_delete_file_version" => {
+            let id = body["fileId"].as_str().unwrap();
+            state.versions.retain(|(_, version, _)| version != id);
+            json_response(200, json!({ "fileId": id }))
+        }
+        _ => json_response(400, json!({ "code": "bad_request", "message": function })),
+    }
+}
+
+fn settings(api_url: &str) -> B2Settings {
+    B2Settings {
+        application_key_id: "key-id".to_string(),
+        application_key: "key".to_string(),
+        bucket_name: BUCKET.to_string(),
+        bucket_id: BUCKET_ID.to_string(),
+        prefix: "repo".to_string(),
+        api_url: Some(api_url.to_string()),
+    }
+}
+
+#[test]
+fn objects_round_trip() {
+    let (url, state) = start_server();
+    let backend = B2Backend::new(&settings(&url)).unwrap();
+
+    backend.write("packs/a", b"hello, bucket").unwrap();
+    backend.write("packs/b", b"second").unwrap();
+    backend.write("packs/c d", b"third").unwrap();
+    backend.write("index", b"index").unwrap();
+    // A second version of the same file.
+    backend.write("packs/b", b"second, again").unwrap();
+
+    assert_eq!(backend.read("packs/a").unwrap(), b"hello, bucket");
+    assert_eq!(backend.read("packs/b").unwrap(), b"second, again");
+    assert_eq!(backend.read_range("packs/a", 7, 6).unwrap(), b"bucket");
+    assert_eq!(backend.size("packs/c d").unwrap(), 5);
+    assert!(backend.exists("index").unwrap());
+    assert_eq!(
+        backend.list("packs/").unwrap(),
+        ["packs/a", "packs/b", "packs/c d"]
+    );
+    assert_eq!(
+        backend.list("").unwrap(),
+        ["index", "packs/a", "packs/b", "packs/c d"]
+    );
+    // One upload URL, used again for every upload.
+    assert_eq!(state.lock().unwrap().upload_urls, 1);
+
+    backend.remove("packs/b").unwrap();
+    assert!(!backend.exists("packs/b").unwrap());
+    assert_eq!(state.lock().unwrap().versions.len(), 3);
+    assert_eq!(
+        backend.read_range("packs/a", 10, 10).unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+    for err in [
+        backend.read("packs/b").unwrap_err(),
+        backend.size("packs/b").unwrap_err(),
+        backend.remove("packs/b").unwrap_err(),
+    ] {
+        assert_eq!(err.kind(), io::ErrorKind::NotFound, "{}", err);
+    }
+}
+
+#[test]
+fn expired_upload_url_is_replaced() {
+    let (url, state) = start_server();
+    let backend = B2Backend::new(&settings(&url)).unwrap();
+    backend.write("a", b"first").unwrap();
+
+    state.lock().unwrap().upload_tokens.clear();
+    backend.write("b", b"second").unwrap();
+
+    assert_eq!(backend.read("b").unwrap(), b"second");
+    let state = state.lock().unwrap();
+    assert_eq!(state.upload_urls, 2);
+    assert_eq!(state.authorizations, 1);
+}
+
+#[test]
+fn expired_authorization_is_renewed() {
+    let (url, state) = start_server();
+    let backend = B2Backend::new(&settings(&url)).unwrap();
+    backend.write("a", b"first").unwrap();
+
+    state.lock().unwrap().account_tokens.clear();
+    assert_eq!(backend.read("a").unwrap(), b"first");
+    assert_eq!(state.lock().unwrap().authorizations, 2);
+
+    // Both expired: the new upload URL needs a new authorization first.
+    {
+        let mut state = state.lock().unwrap();
+        state.account_tokens.clear();
+        state.upload_tokens.clear();
+    }
+    backend.write("b", b"second").unwrap();
+    assert!(backend.exists("b").unwrap());
+    assert_eq!(state.lock().unwrap().authorizations, 3);
+}
+
+#[test]
+fn bucket_id_is_looked_up_by_name() {
+    let (url, _state) = start_server();
+    let mut settings = settings(&url);
+    settings.bucket_id.clear();
+    let backend = B2Backend::new(&settings).unwrap();
+    backend.write("a", b"data").unwrap();
+    assert_eq!(backend.list("").unwrap(), ["a"]);
+
+    settings.bucket_name = "no-such-bucket".to_string();
+    assert_eq!(
+        B2Backend::new(&settings).err().unwrap().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn urls_name_bucket_and_prefix() {
+    let configured = settings("http://localhost");
+
+    let settings = b2::settings_for_url("b2://other/some/repo", Some(&configured)).unwrap();
+    assert_eq!(
+        (settings.bucket_name.as_str(), settings.prefix.as_str()),
+        ("other", "some/repo")
+    );
+    // The configured id is for the configured bucket.
+    assert!(settings.bucket_id.is_empty());
+    assert_eq!(settings.application_key, configured.application_key);
+
+    let settings = b2::settings_for_url("b2://test-bucket/x", Some(&configured)).unwrap();
+    assert_eq!(settings.bucket_id, BUCKET_ID);
+    assert_eq!(
+        b2::settings_for_url("b2://", Some(&configured))
+            .unwrap()
+            .prefix,
+        "repo"
+    );
+    assert!(b2::settings_for_url("b2://", None).is_err());
+
+    assert_eq!(b2::file_name("backups/", "packs/a"), "backups/packs/a");
+    assert_eq!(
+        b2::encode_file_name("packs/a b+ü.pack"),
+        "packs/a%20b%2B%C3%BC.pack"
+    );
+}
+
+#[cfg(feature = "b2-integration-tests")]
+#[test]
+fn sandbox_bucket_round_trip() {
+    use std::{
+        env,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    let Ok(bucket) = env::var("RBCKP_B2_TEST_BUCKET") else {
+        return;
+    };
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let backend = B2Backend::new(&B2Settings {
+        application_key_id: env::var("RBCKP_B2_TEST_KEY_ID").unwrap(),
+        application_key: env::var("RBCKP_B2_TEST_KEY").unwrap(),
+        bucket_name: bucket,
+        bucket_id: String::new(),
+        prefix: format!("rbckp-tests/{}", nanos),
+        api_url: None,
+    })
+    .unwrap();
+
+    backend.write("packs/a", b"hello, bucket").unwrap();
+    backend.write("packs/a", b"hello again").unwrap();
+    assert_eq!(backend.read("packs/a").unwrap(), b"hello again");
+    assert_eq!(backend.read_range("packs/a", 6, 5).unwrap(), b"again");
+    assert_eq!(backend.size("packs/a").unwrap(), 11);
+    assert_eq!(backend.list("").unwrap(), ["packs/a"]);
+
+    backend.remove("packs/a").unwrap();
+    assert!(!backend.exists("packs/a").unwrap());
+    assert!(backend.list("").unwrap().is_empty());
+}
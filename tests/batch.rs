@@ -0,0 +1,132 @@
+//! `BatchingStore`: puts wait in the buffer until a batch is full or flushed, and each
+//! batch is uploaded in parallel.
+#![cfg(feature = "async")]
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use rbckp::backup::{
+    hash::ChunkId,
+    store::{
+        StoreError, async_pool::ConcurrentUploader, async_store::AsyncChunkStore,
+        batch::BatchingStore,
+    },
+};
+
+/// Keeps chunks in memory; every upload takes a little while, and the most uploads
+/// ever in flight together is recorded.
+#[derive(Default)]
+struct SlowStore {
+    chunks: Mutex<HashMap<ChunkId, Vec<u8>>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    uploads: AtomicUsize,
+}
+
+impl SlowStore {
+    fn stored(&self) -> usize {
+        self.chunks.lock().unwrap().len()
+    }
+}
+
+impl AsyncChunkStore for SlowStore {
+    async fn put(&self, id: ChunkId, data: Vec<u8>) -> Result<(), StoreError> {
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.uploads.fetch_add(1, Ordering::SeqCst);
+        self.chunks.lock().unwrap().insert(id, data);
+        Ok(())
+    }
+
+    async fn get(&self, id: ChunkId) -> Result<Vec<u8>, StoreError> {
+        self.chunks
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| StoreError::ChunkNotFound(id.to_string()))
+    }
+
+    async fn has(&self, id: ChunkId) -> Result<bool, StoreError> {
+        Ok(self.chunks.lock().unwrap().contains_key(&id))
+    }
+
+    async fn delete(&self, id: ChunkId) -> Result<(), StoreError> {
+        self.chunks.lock().unwrap().remove(&id);
+        Ok(())
+    }
+}
+
+fn chunk(i: usize) -> (ChunkId, Vec<u8>) {
+    let data = format!("chunk {}", i).into_bytes();
+    (ChunkId::of(&data), data)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn puts_are_uploaded_in_batches() {
+    let store = Arc::new(SlowStore::default());
+    let batching = BatchingStore::new(ConcurrentUploader::new(Arc::clone(&store), 8), 4);
+
+    for i in 0..10 {
+        let (id, data) = chunk(i);
+        batching.put(id, data).await.unwrap();
+        // Nothing reaches the store until a batch is full.
+        assert_eq!(store.stored(), (i + 1) / 4 * 4);
+    }
+    assert_eq!(batching.buffered(), 2);
+    // Each batch went up at once rather than one chunk after another.
+    assert_eq!(store.max_in_flight.load(Ordering::SeqCst), 4);
+
+    // Buffered chunks are visible before they are uploaded.
+    let (id, data) = chunk(9);
+    assert!(!store.has(id).await.unwrap());
+    assert!(batching.has(id).await.unwrap());
+    assert_eq!(batching.get(id).await.unwrap(), data);
+
+    assert_eq!(batching.flush().await.unwrap(), 2);
+    assert_eq!(batching.flush().await.unwrap(), 0);
+    assert_eq!(batching.buffered(), 0);
+    assert_eq!(store.stored(), 10);
+    assert_eq!(store.uploads.load(Ordering::SeqCst), 10);
+    for i in 0..10 {
+        let (id, data) = chunk(i);
+        assert_eq!(batching.get(id).await.unwrap(), data);
+    }
+}
+
+#[tokio::test]
+async fn repeated_and_deleted_chunks_are_uploaded_at_most_once() {
+    let store = Arc::new(SlowStore::default());
+    let batching = BatchingStore::new(ConcurrentUploader::new(Arc::clone(&store), 2), 8);
+    let (a, a_data) = chunk(1);
+    let (b, b_data) = chunk(2);
+
+    batching.put(a, a_data.clone()).await.unwrap();
+    batching.put(a, a_data.clone()).await.unwrap();
+    batching.put(b, b_data).await.unwrap();
+    assert_eq!(batching.buffered(), 2);
+    batching.delete(b).await.unwrap();
+    assert!(!batching.has(b).await.unwrap());
+    assert!(matches!(
+        batching.get(b).await,
+        Err(StoreError::ChunkNotFound(_))
+    ));
+
+    assert_eq!(batching.flush().await.unwrap(), 1);
+    assert_eq!(store.uploads.load(Ordering::SeqCst), 1);
+    assert_eq!(store.get(a).await.unwrap(), a_data);
+
+    // No threshold would mean every put waits forever.
+    assert_eq!(
+        BatchingStore::new(ConcurrentUploader::new(store, 2), 0).flush_threshold(),
+        1
+    );
+}
@@ -0,0 +1,70 @@
+//! `rbckp bench` times every stage with the chunk settings of the config.
+
+mod common;
+
+use std::{fs, process::Command};
+
+use common::noise;
+use rbckp::backup::cdc_chunker::{self, CdcParams};
+
+#[test]
+fn bench_reports_a_row_per_stage_with_the_configured_chunking() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("settings.ini"),
+        "debug=false\n[chunk_settings]\nmin=1024\navg=4096\nmax=16384\ngear_shift=2\nboundary_bits=10\n",
+    )
+    .unwrap();
+    let data = noise(300_000, 1);
+    fs::write(dir.path().join("data.bin"), &data).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir.path())
+        .args(["bench", "-F", "data.bin", "--iterations", "2"])
+        .args(["--quiet", "--config", "settings.ini"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+
+    let params = CdcParams::new(1024, 4096, 16384)
+        .with_gear_shift(2)
+        .with_boundary_bits(10);
+    let expected = cdc_chunker::count_chunks_cdc(&data, &params);
+    // Only the full parameters give this count; min/avg/max alone cut elsewhere.
+    assert_ne!(
+        expected,
+        cdc_chunker::estimate_chunk_count(&data, 1024, 4096, 16384)
+    );
+
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(
+        lines[0].starts_with("File: data.bin (300000 bytes), 2 iterations"),
+        "{}",
+        stdout
+    );
+    let header: Vec<&str> = lines[2].split_whitespace().collect();
+    assert_eq!(header, ["stage", "MB/s", "std", "dev", "chunks"]);
+    let rows: Vec<(&str, usize)> = lines[3..]
+        .iter()
+        .map(|line| {
+            let (name, rest) = line.split_at(20);
+            let columns: Vec<&str> = rest.split_whitespace().collect();
+            assert_eq!(columns.len(), 3, "{}", line);
+            for column in &columns[..2] {
+                column.parse::<f64>().unwrap();
+            }
+            (name.trim_end(), columns[2].parse().unwrap())
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            ("gear boundaries", expected),
+            (
+                "gear chunk + hash",
+                cdc_chunker::chunk_refs_cdc(&data, &params).len()
+            ),
+        ]
+    );
+}
@@ -0,0 +1,59 @@
+//! `[chunk_settings] boundary_bits`: an explicit cut probability instead of one
+//! derived from `avg`.
+
+mod common;
+
+use std::fs;
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::cdc_chunker::{self, CdcParams},
+    config::Settings,
+};
+
+#[test]
+fn setting_overrides_the_derived_bits() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(&path, format!("{}boundary_bits=14\n", SETTINGS)).unwrap();
+    let params = Settings::from_path(&path)
+        .unwrap()
+        .chunk_settings
+        .cdc_params();
+    assert_eq!(params.boundary_bits, Some(14));
+
+    let data = noise(1 << 20, 21);
+    let chunks = cdc_chunker::chunk_refs_cdc(&data, &params);
+    assert_eq!(
+        chunks,
+        cdc_chunker::chunk_refs_cdc(
+            &data,
+            &CdcParams::new(1024, 4096, 16384).with_boundary_bits(14)
+        )
+    );
+    // Rarer cuts than the 12 bits `avg = 4096` stands for.
+    let derived = cdc_chunker::chunk_refs_cdc(&data, &CdcParams::new(1024, 4096, 16384));
+    assert!(chunks.len() < derived.len());
+}
+
+#[test]
+fn out_of_range_bits_are_a_config_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    for bits in [
+        "boundary_bits=0\n",
+        "boundary_bits=32\n",
+        "boundary_bits=24\nmax_boundary_bits=20\n",
+        "boundary_bits=8\nmin_boundary_bits=10\n",
+    ] {
+        fs::write(&path, format!("{}{}", SETTINGS, bits)).unwrap();
+        let err = Settings::from_path(&path).unwrap_err();
+        assert!(err.to_string().contains("boundary_bits = "), "{}", err);
+    }
+
+    // The ends of the range are fine.
+    for bits in ["boundary_bits=1\n", "boundary_bits=31\n"] {
+        fs::write(&path, format!("{}{}", SETTINGS, bits)).unwrap();
+        Settings::from_path(&path).unwrap();
+    }
+}
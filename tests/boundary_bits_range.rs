@@ -0,0 +1,118 @@
+//! `min_boundary_bits` / `max_boundary_bits`: the range boundary bits are clamped to.
+
+mod common;
+
+use std::fs;
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::cdc_chunker::{self, CdcParams, ParamWarning},
+    config::Settings,
+};
+
+#[test]
+fn small_average_is_clamped_up_to_min_bits() {
+    let data = noise(4 << 20, 7);
+    let params = CdcParams::new(64, 256, 1 << 20).with_boundary_bits_range(12, 31);
+    let chunks = cdc_chunker::chunk_refs_cdc(&data, &params);
+    assert_eq!(
+        chunks,
+        cdc_chunker::chunk_refs_cdc(
+            &data,
+            &CdcParams::new(64, 256, 1 << 20).with_boundary_bits(12)
+        )
+    );
+    // About `min + 2^12` rather than `min + 256`.
+    let mean = data.len() as f64 / chunks.len() as f64;
+    assert!(mean > 3000.0, "mean chunk length {:.0}", mean);
+
+    // Fractional bits saturate at the same clamp.
+    assert_eq!(
+        cdc_chunker::chunk_refs_cdc(&data, &params.with_fractional_bits()),
+        chunks
+    );
+}
+
+#[test]
+fn derived_and_explicit_bits_respect_max_bits() {
+    let data = noise(2 << 20, 8);
+    let capped = CdcParams::new(1024, 1 << 20, 4 << 20).with_boundary_bits_range(1, 13);
+    let expected = cdc_chunker::chunk_refs_cdc(
+        &data,
+        &CdcParams::new(1024, 1 << 20, 4 << 20).with_boundary_bits(13),
+    );
+    assert_eq!(cdc_chunker::chunk_refs_cdc(&data, &capped), expected);
+    assert_eq!(
+        cdc_chunker::chunk_refs_cdc(&data, &capped.with_boundary_bits(20)),
+        expected
+    );
+
+    // Validation sees the clamped average too.
+    assert_eq!(
+        CdcParams::new(1024, 4096, 16384)
+            .with_boundary_bits(20)
+            .with_boundary_bits_range(1, 12)
+            .validate(),
+        []
+    );
+    assert_eq!(
+        CdcParams::new(1024, 4096, 16384)
+            .with_boundary_bits(20)
+            .validate(),
+        [ParamWarning::MaxClipsBoundaries {
+            avg: 1 << 20,
+            max: 16384
+        }]
+    );
+}
+
+#[test]
+#[should_panic(expected = "boundary bits")]
+fn range_beyond_the_hash_width_is_rejected() {
+    let params = CdcParams::new(64, 256, 1024).with_boundary_bits_range(1, 32);
+    cdc_chunker::chunk_refs_cdc(&noise(4096, 9), &params);
+}
+
+#[test]
+fn chunk_settings_carry_the_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(&path, SETTINGS).unwrap();
+    let params = Settings::from_path(&path)
+        .unwrap()
+        .chunk_settings
+        .cdc_params();
+    assert_eq!(
+        (params.min_boundary_bits, params.max_boundary_bits),
+        (1, 31)
+    );
+
+    fs::write(
+        &path,
+        format!("{}min_boundary_bits=10\nmax_boundary_bits=20\n", SETTINGS),
+    )
+    .unwrap();
+    let params = Settings::from_path(&path)
+        .unwrap()
+        .chunk_settings
+        .cdc_params();
+    assert_eq!(
+        (params.min_boundary_bits, params.max_boundary_bits),
+        (10, 20)
+    );
+}
+
+#[test]
+fn out_of_range_settings_are_a_config_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    for range in [
+        "min_boundary_bits=0\n",
+        "max_boundary_bits=32\n",
+        "min_boundary_bits=20\nmax_boundary_bits=10\n",
+    ] {
+        fs::write(&path, format!("{}{}", SETTINGS, range)).unwrap();
+        let err = Settings::from_path(&path).unwrap_err();
+        assert!(err.to_string().contains("min_boundary_bits"), "{}", err);
+    }
+}
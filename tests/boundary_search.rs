@@ -0,0 +1,50 @@
+//! The boundary search the chunker uses (strided with the `simd` feature) cuts at
+//! exactly the same places as the scalar reference.
+
+use proptest::prelude::*;
+use rbckp::backup::cdc_chunker::{self, CdcParams};
+
+fn ends(data: &[u8], params: &CdcParams) -> Vec<usize> {
+    cdc_chunker::chunk_refs_cdc(data, params)
+        .iter()
+        .map(|chunk| chunk.offset + chunk.len)
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn same_boundaries_as_scalar(
+        data in proptest::collection::vec(any::<u8>(), 0..200_000),
+        min in 1usize..=2048,
+        gear_shift in 1u32..=2,
+        fractional in any::<bool>(),
+    ) {
+        let mut params = CdcParams::new(min, min * 3, min * 9).with_gear_shift(gear_shift);
+        if fractional {
+            params = params.with_fractional_bits();
+        }
+        prop_assert_eq!(ends(&data, &params), cdc_chunker::chunk_boundaries_scalar(&data, &params));
+    }
+}
+
+#[test]
+fn boundaries_at_every_stride_position() {
+    // One-bit boundaries cut almost everywhere, so boundaries land at every offset
+    // within a stride, as well as in the tail after the last full stride.
+    let mut state = 0x9e37_79b9_u32;
+    let data: Vec<u8> = (0..100_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        })
+        .collect();
+    for min in 1..=17 {
+        let params = CdcParams::new(min, min, min + 13).with_boundary_bits(1);
+        assert_eq!(
+            ends(&data, &params),
+            cdc_chunker::chunk_boundaries_scalar(&data, &params)
+        );
+    }
+}
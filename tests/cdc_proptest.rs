@@ -0,0 +1,46 @@
+//! Invariants of `chunk_bytes_cdc` that must hold for any input and sizes, and the
+//! empty input.
+
+use proptest::prelude::*;
+use rbckp::backup::cdc_chunker;
+
+/// `(min, avg, max)` with `0 < min <= avg <= max`.
+fn chunk_sizes() -> impl Strategy<Value = (usize, usize, usize)> {
+    (1usize..=4096)
+        .prop_flat_map(|min| (Just(min), min..=min * 8))
+        .prop_flat_map(|(min, avg)| (Just(min), Just(avg), avg..=avg * 8))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn chunks_cover_input_within_size_limits(
+        data in proptest::collection::vec(any::<u8>(), 0..100_000),
+        (min, avg, max) in chunk_sizes(),
+    ) {
+        let (chunks, _) = cdc_chunker::chunk_bytes_cdc(&data, min, avg, max);
+
+        prop_assert_eq!(chunks.concat(), data);
+        if let Some((_, rest)) = chunks.split_last() {
+            for chunk in rest {
+                prop_assert!(chunk.len() >= min, "chunk of {} bytes, min {}", chunk.len(), min);
+            }
+        }
+        for chunk in &chunks {
+            prop_assert!(!chunk.is_empty());
+            prop_assert!(chunk.len() <= max, "chunk of {} bytes, max {}", chunk.len(), max);
+        }
+    }
+}
+
+#[test]
+fn test_empty_input() {
+    let (chunks, chunk_map) = cdc_chunker::chunk_bytes_cdc(&[], 1024, 4096, 16384);
+    assert!(chunks.is_empty());
+    assert!(chunk_map.is_empty());
+
+    let (chunks, chunk_map) = cdc_chunker::chunk_bytes_cdc_ref(&[], 1024, 4096, 16384);
+    assert!(chunks.is_empty());
+    assert!(chunk_map.is_empty());
+}
@@ -0,0 +1,157 @@
+//! `check_chunks` and `rbckp check`: every chunk against its id, and `--repair` from
+//! the secondary side of a tee store.
+
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::{
+        check::{self, CORRUPT_LIST_NAME},
+        session::BackupSession,
+        store::{Backend, ChunkStore, LocalFsBackend, lock::LockKind, tee::TeeBackend},
+    },
+    config::Settings,
+};
+
+fn repo(path: &Path) -> ChunkStore<LocalFsBackend> {
+    ChunkStore::open(LocalFsBackend::new(path), 1 << 18, LockKind::Shared).unwrap()
+}
+
+/// A backup of about 1 MiB into `primary`, and into `secondary` as well if given.
+fn backed_up(dir: &Path, primary: &Path, secondary: Option<&Path>) {
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let settings = Settings::from_path(&dir.join("settings.ini")).unwrap();
+    let mut backend: Box<dyn Backend> = Box::new(LocalFsBackend::new(primary));
+    if let Some(secondary) = secondary {
+        backend = Box::new(TeeBackend::new(
+            backend,
+            Box::new(LocalFsBackend::new(secondary)),
+        ));
+    }
+    ChunkStore::init(&backend).unwrap();
+    let store = ChunkStore::open(backend, 1 << 18, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings, store);
+    session.add_bytes("a", &noise(1 << 20, 31)).unwrap();
+    session.commit(vec![], &[]).unwrap();
+}
+
+/// Flip a byte of the first indexed chunk in the repository at `path`; returns its
+/// hash and the pack it is in.
+fn damage(path: &Path) -> (String, String) {
+    let store = repo(path);
+    let (hash, location) = store.chunks().next().map(|(hash, l)| (hash, *l)).unwrap();
+    let pack = store.pack_name(location.pack_id);
+    let file = path.join(&pack);
+    let mut data = fs::read(&file).unwrap();
+    data[location.offset as usize] ^= 0xff;
+    fs::write(&file, data).unwrap();
+    (hash, pack)
+}
+
+#[test]
+fn corrupt_chunks_are_found_without_snapshots() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("repo");
+    backed_up(dir.path(), &path, None);
+
+    let report = check::check_chunks(&repo(&path)).unwrap();
+    assert!(report.corrupt.is_empty());
+    assert!(report.chunks > 100);
+    assert_eq!(report.bytes, 1 << 20);
+
+    // Snapshots do not matter.
+    for snapshot in fs::read_dir(path.join("snapshots")).unwrap() {
+        fs::remove_file(snapshot.unwrap().path()).unwrap();
+    }
+    let (hash, pack) = damage(&path);
+    let report = check::check_chunks(&repo(&path)).unwrap();
+    let corrupt: Vec<(&str, &str)> = report
+        .corrupt
+        .iter()
+        .map(|chunk| (chunk.hash.as_str(), chunk.pack.as_str()))
+        .collect();
+    assert_eq!(corrupt, [(hash.as_str(), pack.as_str())]);
+}
+
+#[test]
+fn repair_restores_packs_from_the_secondary() {
+    let dir = tempfile::tempdir().unwrap();
+    let (primary, secondary) = (dir.path().join("primary"), dir.path().join("secondary"));
+    backed_up(dir.path(), &primary, Some(&secondary));
+    let (hash, _) = damage(&primary);
+
+    let store = repo(&primary);
+    let corrupt = check::check_chunks(&store).unwrap().corrupt;
+    let report =
+        check::repair_chunks(&store, Some(&LocalFsBackend::new(&secondary)), &corrupt).unwrap();
+    assert_eq!(report.repaired, [hash]);
+    assert!(report.marked.is_empty());
+    assert!(check::check_chunks(&store).unwrap().corrupt.is_empty());
+    assert!(!primary.join(CORRUPT_LIST_NAME).exists());
+}
+
+#[test]
+fn damaged_secondary_copies_are_not_used() {
+    let dir = tempfile::tempdir().unwrap();
+    let (primary, secondary) = (dir.path().join("primary"), dir.path().join("secondary"));
+    backed_up(dir.path(), &primary, Some(&secondary));
+    let (hash, pack) = damage(&primary);
+    let before = fs::read(primary.join(&pack)).unwrap();
+    // Another chunk of the same pack is damaged on the other side.
+    let store = repo(&primary);
+    let other = store
+        .chunks()
+        .find(|(other, location)| *other != hash && store.pack_name(location.pack_id) == pack)
+        .map(|(_, location)| *location)
+        .unwrap();
+    let mut copy = fs::read(secondary.join(&pack)).unwrap();
+    copy[other.offset as usize] ^= 0xff;
+    fs::write(secondary.join(&pack), copy).unwrap();
+
+    let store = repo(&primary);
+    let corrupt = check::check_chunks(&store).unwrap().corrupt;
+    let report =
+        check::repair_chunks(&store, Some(&LocalFsBackend::new(&secondary)), &corrupt).unwrap();
+    assert!(report.repaired.is_empty());
+    assert_eq!(report.marked, std::slice::from_ref(&hash));
+    assert_eq!(fs::read(primary.join(&pack)).unwrap(), before);
+    assert_eq!(
+        check::read_corrupt_list(store.backend())
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [format!("{} {}", hash, pack)]
+    );
+}
+
+#[test]
+fn check_command_fails_on_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("repo");
+    backed_up(dir.path(), &path, None);
+    let check = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rbckp"))
+            .current_dir(dir.path())
+            .args(["check", "--repo", "repo"])
+            .args(args)
+            .args(["--quiet", "--config", "settings.ini"])
+            .output()
+            .unwrap()
+    };
+    assert!(check(&[]).status.success());
+
+    let (hash, pack) = damage(&path);
+    let output = check(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains(&hash));
+    assert!(!path.join(CORRUPT_LIST_NAME).exists());
+
+    // Nothing to repair from: the chunk is listed, and the check still fails.
+    assert!(!check(&["--repair"]).status.success());
+    assert_eq!(
+        fs::read_to_string(path.join(CORRUPT_LIST_NAME)).unwrap(),
+        format!("{} {}\n", hash, pack)
+    );
+}
@@ -0,0 +1,171 @@
+//! `ChunkCache`: chunks of a remote repository are read once, kept locally, and the
+//! least recently used ones are evicted to stay within the size limit.
+
+mod common;
+
+use std::{
+    fs, io,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use common::SETTINGS;
+use rbckp::{
+    backup::store::{Backend, ChunkStore, InMemoryBackend, cache::ChunkCache, lock::LockKind},
+    config::Settings,
+};
+
+/// Counts reads, standing in for a remote backend where each one is a round trip.
+#[derive(Clone, Default)]
+struct Remote {
+    objects: Arc<InMemoryBackend>,
+    reads: Arc<AtomicUsize>,
+}
+
+impl Remote {
+    fn reads(&self) -> usize {
+        self.reads.load(Ordering::SeqCst)
+    }
+}
+
+impl Backend for Remote {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.objects.read(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.objects.write(name, data)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.objects.list(prefix)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.objects.remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.objects.exists(name)
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.objects.read_range(name, offset, len)
+    }
+}
+
+/// Chunk `i`: 1000 bytes.
+fn chunk(i: u8) -> (String, Vec<u8>) {
+    let data = vec![i; 1000];
+    (blake3::hash(&data).to_hex().to_string(), data)
+}
+
+/// A repository holding chunks `0..count`, written without a cache.
+fn remote(count: u8) -> Remote {
+    let remote = Remote::default();
+    ChunkStore::init(&remote).unwrap();
+    let mut store = ChunkStore::open(remote.clone(), 1 << 20, LockKind::Shared).unwrap();
+    for i in 0..count {
+        let (hash, data) = chunk(i);
+        store.put(&hash, &data).unwrap();
+    }
+    store.flush().unwrap();
+    remote
+}
+
+fn open(remote: &Remote, cache: ChunkCache) -> ChunkStore<Remote> {
+    ChunkStore::open(remote.clone(), 1 << 20, LockKind::Shared)
+        .unwrap()
+        .with_chunk_cache(cache)
+}
+
+#[test]
+fn chunks_are_fetched_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let remote = remote(3);
+    let store = open(&remote, ChunkCache::open(dir.path(), 1 << 20).unwrap());
+
+    let reads = remote.reads();
+    let (hash, data) = chunk(1);
+    assert_eq!(store.get(&hash).unwrap(), data);
+    assert_eq!(remote.reads(), reads + 1);
+    assert_eq!(store.get(&hash).unwrap(), data);
+    assert_eq!(remote.reads(), reads + 1);
+    assert_eq!(store.chunk_cache().unwrap().len(), 1);
+
+    // The cache outlives the process.
+    drop(store);
+    let store = open(&remote, ChunkCache::open(dir.path(), 1 << 20).unwrap());
+    let reads = remote.reads();
+    assert_eq!(store.get(&hash).unwrap(), data);
+    assert_eq!(remote.reads(), reads);
+
+    // A damaged copy is dropped and fetched again.
+    fs::write(dir.path().join(&hash[..2]).join(&hash), b"short").unwrap();
+    assert_eq!(store.get(&hash).unwrap(), data);
+    assert_eq!(remote.reads(), reads + 1);
+    assert_eq!(store.get(&hash).unwrap(), data);
+    assert_eq!(remote.reads(), reads + 1);
+}
+
+#[test]
+fn least_recently_used_chunks_are_evicted() {
+    let dir = tempfile::tempdir().unwrap();
+    let remote = remote(4);
+    // Room for three chunks.
+    let store = open(&remote, ChunkCache::open(dir.path(), 3000).unwrap());
+    for i in 0..3 {
+        store.get(&chunk(i).0).unwrap();
+    }
+    // Chunk 0 is used again, so chunk 1 is now the least recently used one.
+    store.get(&chunk(0).0).unwrap();
+    store.get(&chunk(3).0).unwrap();
+
+    let cache = store.chunk_cache().unwrap();
+    assert_eq!((cache.len(), cache.size()), (3, 3000));
+    let cached = |i| cache.get(&chunk(i).0).is_some();
+    assert!(cached(0) && !cached(1) && cached(2) && cached(3));
+    assert!(!dir.path().join(&chunk(1).0[..2]).join(chunk(1).0).exists());
+
+    // Reopening with a smaller limit evicts down to it.
+    let cache = ChunkCache::open(dir.path(), 1500).unwrap();
+    assert_eq!((cache.len(), cache.size()), (1, 1000));
+}
+
+#[test]
+fn put_fills_the_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let remote = remote(0);
+    let mut store = open(&remote, ChunkCache::open(dir.path(), 1 << 20).unwrap());
+    let (hash, data) = chunk(7);
+    store.put(&hash, &data).unwrap();
+    store.flush().unwrap();
+
+    let reads = remote.reads();
+    assert_eq!(store.get(&hash).unwrap(), data);
+    assert_eq!(remote.reads(), reads);
+}
+
+#[test]
+fn cache_section_of_the_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    let base = SETTINGS;
+    fs::write(&path, base).unwrap();
+    let settings = Settings::from_path(&path).unwrap();
+    assert!(!settings.cache.enabled);
+    assert_eq!(settings.cache.max_size(), 10_000_000_000);
+
+    fs::write(
+        &path,
+        format!("{}[cache]\nenabled=true\nmax_size_gb=0.5\n", base),
+    )
+    .unwrap();
+    let settings = Settings::from_path(&path).unwrap();
+    assert!(settings.backend.chunk_cache.enabled);
+    assert_eq!(settings.backend.chunk_cache.max_size(), 500_000_000);
+}
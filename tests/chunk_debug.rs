@@ -0,0 +1,81 @@
+//! `chunk_debug_info` and `--debug-boundaries`: where chunks were cut and why.
+
+mod common;
+
+use std::{fs, process::Command};
+
+use common::{SETTINGS, nonzero_noise};
+use rbckp::backup::cdc_chunker::{self, CdcParams, CutReason};
+
+#[test]
+fn constant_data_is_cut_at_max_size() {
+    // The gear hash of a repeated byte settles on one value, which is not a boundary,
+    // so every chunk runs to the maximum size.
+    let params = CdcParams::new(1024, 4096, 16384);
+    let data = vec![0xab; 3 * 16384 + 100];
+    let chunks = cdc_chunker::chunk_debug_info(&data, &params);
+
+    let reasons: Vec<CutReason> = chunks.iter().map(|chunk| chunk.reason).collect();
+    assert_eq!(
+        reasons,
+        [
+            CutReason::Forced,
+            CutReason::Forced,
+            CutReason::Forced,
+            CutReason::EndOfInput
+        ]
+    );
+    assert!(chunks[..3].iter().all(|chunk| chunk.len == 16384));
+    // The last 100 bytes are too short to be scanned.
+    assert_eq!(chunks[3].rolling_hash, None);
+}
+
+#[test]
+fn debug_info_matches_the_chunks() {
+    let params = CdcParams::new(1024, 4096, 16384);
+    let mut data = nonzero_noise(200_000, 0x5eed);
+    data.splice(100_000..100_000, vec![0; 100_000]);
+
+    let chunks = cdc_chunker::chunk_debug_info(&data, &params);
+    let refs = cdc_chunker::chunk_refs_cdc(&data, &params);
+    assert_eq!(chunks.len(), refs.len());
+    for (chunk, chunk_ref) in chunks.iter().zip(&refs) {
+        assert_eq!((chunk.offset, chunk.len), (chunk_ref.offset, chunk_ref.len));
+        match chunk.reason {
+            CutReason::Boundary => assert_eq!(chunk.rolling_hash.unwrap() & 4095, 0),
+            CutReason::ZeroRun => assert!(chunk_ref.hash.starts_with("zero:")),
+            CutReason::Forced => assert_eq!(chunk.len, 16384),
+            CutReason::EndOfInput => {}
+        }
+    }
+    let count = |reason| chunks.iter().filter(|chunk| chunk.reason == reason).count();
+    assert!(count(CutReason::Boundary) > 10);
+    assert!(count(CutReason::ZeroRun) > 0);
+    // Before the zero run and at the very end.
+    assert_eq!(count(CutReason::EndOfInput), 2);
+}
+
+#[test]
+fn debug_boundaries_flag_prints_every_cut() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    fs::write(dir.path().join("data.bin"), vec![0xab; 2 * 16384 + 10]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir.path())
+        .args(["-F", "data.bin", "--debug-boundaries"])
+        .args(["--config", "settings.ini"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout
+        .lines()
+        .skip_while(|line| *line != "offset len reason hash")
+        .skip(1)
+        .collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("0 16384 forced "));
+    assert!(lines[1].starts_with("16384 16384 forced "));
+    assert_eq!(lines[2], "32768 10 end -");
+}
@@ -0,0 +1,34 @@
+//! `ChunkId`: raw BLAKE3 hashes with a hex text form.
+
+use std::collections::HashMap;
+
+use rbckp::backup::{cdc_chunker, hash::ChunkId};
+
+#[test]
+fn hex_form_round_trips() {
+    let id = ChunkId::of(b"chunk");
+    let hex = id.to_string();
+    assert_eq!(hex, blake3::hash(b"chunk").to_hex().as_str());
+    assert_eq!(hex.parse::<ChunkId>().unwrap(), id);
+    assert_eq!(hex.to_uppercase().parse::<ChunkId>().unwrap(), id);
+    assert_eq!(id.as_bytes(), &cdc_chunker::chunk_id_hash_bytes(b"chunk"));
+
+    assert!("".parse::<ChunkId>().is_err());
+    assert!(hex[1..].parse::<ChunkId>().is_err());
+    assert!(cdc_chunker::zero_chunk_id(4096).parse::<ChunkId>().is_err());
+
+    // Serialized as the hex string, also as a map key.
+    let json = serde_json::to_string(&HashMap::from([(id, 1)])).unwrap();
+    assert_eq!(json, format!("{{\"{}\":1}}", hex));
+    let back: HashMap<ChunkId, i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back[&id], 1);
+}
+
+#[test]
+fn chunk_map_is_keyed_by_chunk_id() {
+    let data: Vec<u8> = b"abcdefgh".repeat(4096);
+    let (chunks, chunk_map) = cdc_chunker::chunk_bytes_cdc(&data, 256, 1024, 4096);
+    for chunk in &chunks {
+        assert!(chunk_map[&ChunkId::of(chunk)].contains(chunk));
+    }
+}
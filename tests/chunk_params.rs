@@ -0,0 +1,34 @@
+//! `CdcParams::validate`: warnings for chunk sizes that work but chunk poorly.
+
+use rbckp::backup::cdc_chunker::{CdcParams, ParamWarning};
+
+#[test]
+fn max_close_to_avg_is_reported() {
+    let warnings = CdcParams::new(1024, 4096, 5000).validate();
+    assert_eq!(
+        warnings,
+        [ParamWarning::MaxClipsBoundaries {
+            avg: 4096,
+            max: 5000
+        }]
+    );
+    assert!(warnings[0].to_string().contains("5000"));
+}
+
+#[test]
+fn roomy_max_is_fine() {
+    assert_eq!(CdcParams::new(1024, 4096, 65536).validate(), []);
+    assert_eq!(CdcParams::new(1024, 4096, 8192).validate(), []);
+}
+
+#[test]
+fn explicit_boundary_bits_set_the_average() {
+    let params = CdcParams::new(1024, 4096, 65536).with_boundary_bits(16);
+    assert_eq!(
+        params.validate(),
+        [ParamWarning::MaxClipsBoundaries {
+            avg: 65536,
+            max: 65536
+        }]
+    );
+}
@@ -0,0 +1,151 @@
+//! Helpers shared by the integration tests; each test crate uses some of them.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use rbckp::backup::{snapshot::Snapshot, store::LocalFsBackend};
+
+/// `settings.ini` for tests: small chunks, so a few hundred KB already make many.
+pub const SETTINGS: &str = "debug=false\n[chunk_settings]\nmin=1024\navg=4096\nmax=16384\n";
+
+/// [`SETTINGS`] with `top_level` (whole lines) added before its first section.
+pub fn settings_with(top_level: &str) -> String {
+    SETTINGS.replacen(
+        "[chunk_settings]",
+        &format!("{}[chunk_settings]", top_level),
+        1,
+    )
+}
+
+/// Pseudo-random bytes from a xorshift generator started at `seed`.
+pub fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+/// [`noise`] with the lowest bit of every byte set, so it has no zero bytes at all.
+pub fn nonzero_noise(len: usize, seed: u64) -> Vec<u8> {
+    noise(len, seed).into_iter().map(|byte| byte | 1).collect()
+}
+
+/// The `rbckp` binary, to run in `dir` with the `settings.ini` there.
+pub fn rbckp_command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rbckp"));
+    command
+        .current_dir(dir)
+        .args(args)
+        .args(["--config", "settings.ini"]);
+    command
+}
+
+/// Run [`rbckp_command`], failing unless it succeeds.
+pub fn rbckp(dir: &Path, args: &[&str]) -> Output {
+    succeeded(args, rbckp_command(dir, args).output().unwrap())
+}
+
+/// What [`rbckp`] writes to stdout, as text.
+pub fn rbckp_stdout(dir: &Path, args: &[&str]) -> String {
+    String::from_utf8(rbckp(dir, args).stdout).unwrap()
+}
+
+/// [`rbckp`], writing `input` to its stdin.
+pub fn rbckp_with_input(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = rbckp_command(dir, args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input).unwrap();
+    drop(stdin);
+    succeeded(args, child.wait_with_output().unwrap())
+}
+
+fn succeeded(args: &[&str], output: Output) -> Output {
+    assert!(
+        output.status.success(),
+        "rbckp {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Back up `args` (paths and options) to the repository `repo` in `dir`, returning
+/// the id of the new snapshot.
+pub fn back_up(dir: &Path, args: &[&str]) -> String {
+    snapshot_id(&rbckp(dir, &[&["backup", "--repo", "repo"], args].concat()))
+}
+
+/// Snapshot `id`, or the one it is a unique prefix of, from the repository `repo` in
+/// `dir`.
+pub fn load_snapshot(dir: &Path, id: &str) -> Snapshot {
+    let backend = LocalFsBackend::new(&dir.join("repo"));
+    let id = Snapshot::resolve_id(&backend, id).unwrap();
+    Snapshot::load(&backend, &id).unwrap()
+}
+
+/// The id in the "Snapshot <id> saved" status line.
+pub fn snapshot_id(output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout
+        .lines()
+        .find(|line| line.starts_with("Snapshot "))
+        .unwrap();
+    line.split_whitespace().nth(1).unwrap().to_string()
+}
+
+/// Something [`make_tree`] creates, at a path relative to the root of the tree.
+pub enum TreeItem<'a> {
+    File(&'a str, &'a [u8]),
+    Dir(&'a str),
+    /// A symlink and its target.
+    Symlink(&'a str, &'a str),
+    /// A hard link and the file it links to, created before.
+    HardLink(&'a str, &'a str),
+    /// Permissions of a file or directory created before.
+    Mode(&'a str, u32),
+}
+
+/// Create `nodes` below `root` in order, with any missing parent directories, and
+/// return `root`.
+pub fn make_tree(root: &Path, nodes: &[TreeItem]) -> PathBuf {
+    fs::create_dir_all(root).unwrap();
+    for node in nodes {
+        let (TreeItem::File(path, _)
+        | TreeItem::Dir(path)
+        | TreeItem::Symlink(path, _)
+        | TreeItem::HardLink(path, _)
+        | TreeItem::Mode(path, _)) = node;
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        match node {
+            TreeItem::File(_, data) => fs::write(&path, data).unwrap(),
+            TreeItem::Dir(_) => fs::create_dir_all(&path).unwrap(),
+            #[cfg(unix)]
+            TreeItem::Symlink(_, target) => std::os::unix::fs::symlink(target, &path).unwrap(),
+            TreeItem::HardLink(_, existing) => fs::hard_link(root.join(existing), &path).unwrap(),
+            #[cfg(unix)]
+            TreeItem::Mode(_, mode) => {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&path, fs::Permissions::from_mode(*mode)).unwrap()
+            }
+            #[cfg(not(unix))]
+            TreeItem::Symlink(..) | TreeItem::Mode(..) => panic!("symlinks and modes need unix"),
+        }
+    }
+    root.to_path_buf()
+}
@@ -0,0 +1,101 @@
+//! `shared_chunks` and `rbckp compare`: an insertion near the front of a file only
+//! changes the chunks around it.
+
+mod common;
+
+use std::fs;
+
+use common::{SETTINGS, noise, rbckp_command, rbckp_stdout};
+use rbckp::backup::{
+    cdc_chunker::{self, CdcParams, ChunkRef},
+    compare::{self, SharedChunks},
+};
+
+/// `data` with 100 bytes inserted near the front.
+fn with_insertion(data: &[u8]) -> Vec<u8> {
+    let mut edited = data.to_vec();
+    edited.splice(5_000..5_000, noise(100, 0xfeed));
+    edited
+}
+
+fn chunk_ref(hash: &str, len: usize) -> ChunkRef {
+    ChunkRef {
+        hash: hash.to_string(),
+        offset: 0,
+        len,
+    }
+}
+
+#[test]
+fn insertion_near_the_front_keeps_most_chunks() {
+    let params = CdcParams::new(1024, 4096, 16384);
+    let data = noise(1_000_000, 0x5eed);
+    let edited = with_insertion(&data);
+
+    let shared = compare::shared_chunks(
+        &cdc_chunker::chunk_refs_cdc(&data, &params),
+        &cdc_chunker::chunk_refs_cdc(&edited, &params),
+    );
+    assert_eq!(shared.bytes, edited.len() as u64);
+    assert!(shared.shared_chunk_percent() > 90.0, "{:?}", shared);
+    assert!(shared.shared_byte_percent() > 90.0, "{:?}", shared);
+    assert_eq!(shared.new_bytes, shared.bytes - shared.shared_bytes);
+    // The inserted bytes, and at most a few chunks around them.
+    assert!(shared.new_bytes >= 100 && shared.new_bytes < 4 * 16384);
+}
+
+#[test]
+fn counts_every_chunk_of_b_but_new_chunks_once() {
+    let a = [chunk_ref("x", 10), chunk_ref("y", 20)];
+    let b = [
+        chunk_ref("y", 20),
+        chunk_ref("z", 30),
+        chunk_ref("y", 20),
+        chunk_ref("z", 30),
+    ];
+
+    assert_eq!(
+        compare::shared_chunks(&a, &b),
+        SharedChunks {
+            chunks: 4,
+            bytes: 100,
+            shared_chunks: 2,
+            shared_bytes: 40,
+            new_bytes: 30,
+        }
+    );
+    let nothing = compare::shared_chunks(&a, &[]);
+    assert_eq!(nothing.shared_chunk_percent(), 100.0);
+    assert_eq!(compare::shared_chunks(&[], &b).shared_byte_percent(), 0.0);
+}
+
+#[test]
+fn compare_command_reports_counts_and_percentages() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let data = noise(300_000, 0x5eed);
+    fs::write(dir.path().join("a.bin"), &data).unwrap();
+    fs::write(dir.path().join("b.bin"), with_insertion(&data)).unwrap();
+
+    let compare = ["compare", "-F", "a.bin", "-F", "b.bin"];
+    let text = rbckp_stdout(dir.path(), &compare);
+    assert!(text.contains("b.bin: "), "{}", text);
+    assert!(text.contains(", 300100 bytes"), "{}", text);
+    assert!(text.contains("Shared: "), "{}", text);
+
+    let json: serde_json::Value = serde_json::from_str(&rbckp_stdout(
+        dir.path(),
+        &[&compare[..], &["--json"]].concat(),
+    ))
+    .unwrap();
+    assert_eq!(json["b"]["bytes"], 300_100);
+    assert!(json["shared_byte_percent"].as_f64().unwrap() > 90.0);
+    assert_eq!(
+        json["shared_bytes"].as_u64().unwrap() + json["new_bytes"].as_u64().unwrap(),
+        300_100
+    );
+
+    // Exactly two files.
+    let output = rbckp_command(dir.path(), &compare[..3]).output().unwrap();
+    assert!(!output.status.success());
+}
@@ -0,0 +1,175 @@
+//! `ConcurrentUploader` and `ChunkStore::with_concurrent_uploads`: the packs of an
+//! upload batch are written several at a time, and a failed write fails the flush.
+
+mod common;
+
+use std::{
+    fs, io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::store::{
+        Backend, ChunkStore, InMemoryBackend, StoreError, lock::LockKind,
+        upload::ConcurrentUploader,
+    },
+    config::{DEFAULT_MAX_CONCURRENT_UPLOADS, Settings},
+};
+
+/// An in-memory backend whose writes take a while, recording how many overlap, and
+/// fail for names containing `fail_on`.
+#[derive(Clone, Default)]
+struct Slow {
+    objects: Arc<InMemoryBackend>,
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: Arc<AtomicUsize>,
+    fail_on: Arc<Mutex<Option<String>>>,
+}
+
+impl Slow {
+    fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    fn fail_on(&self, name: &str) {
+        *self.fail_on.lock().unwrap() = Some(name.to_string());
+    }
+}
+
+impl Backend for Slow {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.objects.read(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let fail_on = self.fail_on.lock().unwrap().clone();
+        if fail_on.is_some_and(|fail_on| name.contains(&fail_on)) {
+            return Err(io::Error::other(format!("cannot write {}", name)));
+        }
+        self.objects.write(name, data)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.objects.list(prefix)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.objects.remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.objects.exists(name)
+    }
+}
+
+fn objects(count: usize) -> Vec<(String, Vec<u8>)> {
+    (0..count)
+        .map(|i| (format!("objects/{}", i), vec![i as u8; 10]))
+        .collect()
+}
+
+#[test]
+fn up_to_the_limit_is_written_at_once() {
+    let backend = Slow::default();
+    ConcurrentUploader::new(3)
+        .upload(&backend, &objects(8))
+        .unwrap();
+    assert_eq!(backend.max_in_flight(), 3);
+    assert_eq!(backend.list("objects/").unwrap().len(), 8);
+    assert_eq!(backend.read("objects/5").unwrap(), [5; 10]);
+
+    // Fewer objects than the limit: one thread each.
+    let backend = Slow::default();
+    ConcurrentUploader::new(8)
+        .upload(&backend, &objects(2))
+        .unwrap();
+    assert_eq!(backend.max_in_flight(), 2);
+    assert_eq!(backend.list("objects/").unwrap().len(), 2);
+}
+
+#[test]
+fn first_failure_is_returned() {
+    let backend = Slow::default();
+    backend.fail_on("objects/1");
+    let err = ConcurrentUploader::new(2)
+        .upload(&backend, &objects(6))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "cannot write objects/1");
+    // Writes already started finish, but no new ones start after the failure.
+    assert!(backend.list("objects/").unwrap().len() < 5);
+}
+
+/// Chunk store over `backend` where every chunk fills a pack, four packs make a batch
+/// and two are written at once.
+fn open(backend: &Slow) -> ChunkStore<Slow> {
+    ChunkStore::init(backend).unwrap();
+    ChunkStore::open(backend.clone(), 1000, LockKind::Shared)
+        .unwrap()
+        .with_upload_batch(4)
+        .with_concurrent_uploads(2)
+}
+
+fn put_chunks(store: &mut ChunkStore<Slow>, count: u64) -> Result<(), StoreError> {
+    for seed in 0..count {
+        let chunk = noise(1000, seed + 1);
+        store.put(&blake3::hash(&chunk).to_hex(), &chunk)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn packs_of_a_batch_are_written_concurrently() {
+    let backend = Slow::default();
+    let mut store = open(&backend);
+    put_chunks(&mut store, 4).unwrap();
+
+    assert_eq!(backend.max_in_flight(), 2);
+    assert_eq!(backend.list("packs/").unwrap().len(), 4);
+    drop(store);
+    let store = ChunkStore::open(backend.clone(), 1000, LockKind::Shared).unwrap();
+    assert_eq!(store.chunks().count(), 4);
+}
+
+#[test]
+fn failed_pack_write_fails_the_flush() {
+    let backend = Slow::default();
+    let mut store = open(&backend);
+    backend.fail_on(".pack");
+    put_chunks(&mut store, 2).unwrap();
+
+    let err = store.flush().unwrap_err();
+    assert!(err.to_string().contains("cannot write packs/"), "{}", err);
+}
+
+#[test]
+fn upload_section_sets_the_concurrency() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    let settings = |upload: &str| {
+        fs::write(&path, format!("{}{}", SETTINGS, upload)).unwrap();
+        Settings::from_path(&path).unwrap()
+    };
+
+    assert_eq!(
+        settings("").backend.upload.max_concurrent,
+        DEFAULT_MAX_CONCURRENT_UPLOADS
+    );
+    assert_eq!(
+        settings("[upload]\nmax_concurrent=16\n")
+            .backend
+            .upload
+            .max_concurrent,
+        16
+    );
+}
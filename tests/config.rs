@@ -0,0 +1,249 @@
+//! Loading settings from a path other than `./settings.ini`.
+
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use common::SETTINGS;
+use rbckp::{
+    backup::store,
+    config::{Settings, StoreKind},
+};
+
+#[test]
+fn settings_load_from_custom_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("backup.conf");
+    fs::write(
+        &path,
+        "debug=false\npack_size=1048576\n[chunk_settings]\nmin=1024\navg=8192\nmax=32768\ngear_shift=2\n",
+    )
+    .unwrap();
+
+    let settings = Settings::from_path(&path).unwrap();
+
+    assert_eq!(settings.chunk_settings.min, 1024);
+    assert_eq!(settings.chunk_settings.avg, 8192);
+    assert_eq!(settings.chunk_settings.max, 32768);
+    assert_eq!(settings.chunk_settings.gear_shift, 2);
+    assert_eq!(settings.pack_size, 1048576);
+}
+
+#[test]
+fn missing_settings_file_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(Settings::from_path(&dir.path().join("missing.ini")).is_err());
+}
+
+#[test]
+fn invalid_gear_shift_is_a_config_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(&path, format!("{}gear_shift=3\n", SETTINGS)).unwrap();
+
+    let err = Settings::from_path(&path).unwrap_err();
+    assert!(
+        err.to_string().contains("gear_shift must be 1 or 2"),
+        "{}",
+        err
+    );
+
+    // Reported as an error by the command line, not a panic of the chunker.
+    fs::write(dir.path().join("data.bin"), [7u8; 10_000]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir.path())
+        .args(["-F", "data.bin", "--config", "settings.ini"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("gear_shift must be 1 or 2"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn chunk_sizes_out_of_order_are_a_config_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    for (min, avg, max) in [
+        (0, 4096, 16384),
+        (8192, 4096, 16384),
+        (1024, 32768, 16384),
+        (1024, 4096, 2048),
+    ] {
+        fs::write(
+            &path,
+            format!(
+                "debug=false\n[chunk_settings]\nmin={}\navg={}\nmax={}\n",
+                min, avg, max
+            ),
+        )
+        .unwrap();
+        let err = Settings::from_path(&path).unwrap_err();
+        assert!(
+            err.to_string().contains("needs 0 < min <= avg <= max"),
+            "{} {} {}: {}",
+            min,
+            avg,
+            max,
+            err
+        );
+    }
+
+    // Equal sizes are fine: every chunk is then cut at the same length.
+    fs::write(
+        &path,
+        "debug=false\n[chunk_settings]\nmin=4096\navg=4096\nmax=4096\n",
+    )
+    .unwrap();
+    Settings::from_path(&path).unwrap();
+}
+
+#[test]
+fn store_section_puts_plain_locations_in_s3() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(
+        &path,
+        format!(
+            "{}[store]\ntype=s3\nbucket=my-backups\nprefix=rbckp/\n",
+            SETTINGS
+        ),
+    )
+    .unwrap();
+
+    let settings = Settings::from_path(&path).unwrap();
+    assert_eq!(settings.backend.default_kind, StoreKind::S3);
+    let s3 = settings.backend.s3.as_ref().unwrap();
+    assert_eq!(
+        (s3.bucket.as_str(), s3.prefix.as_str()),
+        ("my-backups", "rbckp/")
+    );
+    assert_eq!(s3.region, "us-east-1");
+
+    let location = |repo: &str| {
+        store::repo_location(Path::new(repo), &settings.backend)
+            .to_string_lossy()
+            .into_owned()
+    };
+    assert_eq!(location("laptop"), "s3://my-backups/rbckp/laptop");
+    assert_eq!(location("./laptop/"), "s3://my-backups/rbckp/laptop");
+    assert_eq!(location("s3://other/x"), "s3://other/x");
+    assert_eq!(location("sftp://host/repo"), "sftp://host/repo");
+}
+
+#[test]
+fn store_section_puts_plain_locations_in_gcs() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(
+        &path,
+        format!(
+            "{}[store]\ntype=gcs\nbucket=my-gcs-bucket\nprefix=rbckp\n\
+         credentials_file=/etc/rbckp/sa.json\n",
+            SETTINGS
+        ),
+    )
+    .unwrap();
+
+    let settings = Settings::from_path(&path).unwrap();
+    assert_eq!(settings.backend.default_kind, StoreKind::Gcs);
+    assert!(settings.backend.s3.is_none());
+    let gcs = settings.backend.gcs.as_ref().unwrap();
+    assert_eq!(
+        (gcs.bucket.as_str(), gcs.prefix.as_str()),
+        ("my-gcs-bucket", "rbckp")
+    );
+    assert_eq!(
+        gcs.credentials_file.as_deref(),
+        Some(Path::new("/etc/rbckp/sa.json"))
+    );
+
+    let location = |repo: &str| {
+        store::repo_location(Path::new(repo), &settings.backend)
+            .to_string_lossy()
+            .into_owned()
+    };
+    assert_eq!(location("laptop"), "gs://my-gcs-bucket/rbckp/laptop");
+    assert_eq!(location("s3://other/x"), "s3://other/x");
+}
+
+#[test]
+fn store_section_puts_plain_locations_on_sftp() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(
+        &path,
+        format!(
+            "{}[store]\ntype=sftp\nhost=nas.local\nport=2222\nuser=backup\n\
+         key_path=/etc/rbckp/id_ed25519\nremote_root=/srv/backups\n",
+            SETTINGS
+        ),
+    )
+    .unwrap();
+
+    let settings = Settings::from_path(&path).unwrap();
+    assert_eq!(settings.backend.default_kind, StoreKind::Sftp);
+    let sftp = settings.backend.sftp.as_ref().unwrap();
+    assert_eq!((sftp.host.as_str(), sftp.port), ("nas.local", 2222));
+    assert_eq!(
+        sftp.key_path.as_deref(),
+        Some(Path::new("/etc/rbckp/id_ed25519"))
+    );
+
+    let location = |repo: &str| {
+        store::repo_location(Path::new(repo), &settings.backend)
+            .to_string_lossy()
+            .into_owned()
+    };
+    assert_eq!(
+        location("laptop"),
+        "sftp://backup@nas.local:2222/srv/backups/laptop"
+    );
+    assert_eq!(location("sftp://other/x"), "sftp://other/x");
+}
+
+#[test]
+fn b2_section_holds_bucket_and_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(
+        &path,
+        format!(
+            "{}[backend.b2]\napplication_key_id=0012ab\napplication_key=K001secret\n\
+         bucket_name=my-b2-bucket\nbucket_id=4a48fe8875c6214145260818\n",
+            SETTINGS
+        ),
+    )
+    .unwrap();
+
+    let settings = Settings::from_path(&path).unwrap();
+    let b2 = settings.backend.b2.as_ref().unwrap();
+    assert_eq!(
+        (b2.application_key_id.as_str(), b2.application_key.as_str()),
+        ("0012ab", "K001secret")
+    );
+    assert_eq!(
+        (b2.bucket_name.as_str(), b2.bucket_id.as_str()),
+        ("my-b2-bucket", "4a48fe8875c6214145260818")
+    );
+    assert!(b2.prefix.is_empty() && b2.api_url.is_none());
+}
+
+#[test]
+fn plain_locations_are_local_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(
+        &path,
+        format!("{}[backend.s3]\nbucket=my-backups\n", SETTINGS),
+    )
+    .unwrap();
+
+    let settings = Settings::from_path(&path).unwrap();
+    assert_eq!(settings.backend.default_kind, StoreKind::Local);
+    assert_eq!(
+        store::repo_location(Path::new("laptop"), &settings.backend),
+        Path::new("laptop")
+    );
+}
@@ -0,0 +1,157 @@
+//! `ChunkStore::contains_many`: a whole chunk list is checked against the index loaded
+//! when the store was opened, without a backend call per chunk.
+
+mod common;
+
+use std::{
+    fs, io,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::{
+        session::BackupSession,
+        store::{Backend, ChunkStore, InMemoryBackend, lock::LockKind},
+    },
+    config::Settings,
+};
+
+const PACK_SIZE: u64 = 64 << 20;
+
+/// Counts the calls that go to the wrapped backend, by kind.
+#[derive(Clone, Default)]
+struct CountingBackend {
+    inner: Arc<InMemoryBackend>,
+    lists: Arc<AtomicUsize>,
+    reads: Arc<AtomicUsize>,
+    others: Arc<AtomicUsize>,
+}
+
+impl CountingBackend {
+    fn calls(&self) -> usize {
+        self.lists.load(Ordering::SeqCst)
+            + self.reads.load(Ordering::SeqCst)
+            + self.others.load(Ordering::SeqCst)
+    }
+
+    fn reset(&self) {
+        for counter in [&self.lists, &self.reads, &self.others] {
+            counter.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Backend for CountingBackend {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.read(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.others.fetch_add(1, Ordering::SeqCst);
+        self.inner.write(name, data)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.lists.fetch_add(1, Ordering::SeqCst);
+        self.inner.list(prefix)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.others.fetch_add(1, Ordering::SeqCst);
+        self.inner.remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.others.fetch_add(1, Ordering::SeqCst);
+        self.inner.exists(name)
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        self.others.fetch_add(1, Ordering::SeqCst);
+        self.inner.size(name)
+    }
+
+    fn read_range(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.read_range(name, offset, len)
+    }
+}
+
+fn chunk(i: usize) -> (String, Vec<u8>) {
+    let data = format!("chunk number {}", i).into_bytes();
+    (blake3::hash(&data).to_hex().to_string(), data)
+}
+
+/// A repository with chunks `0..count` in a single pack.
+fn repository(count: usize) -> CountingBackend {
+    let backend = CountingBackend::default();
+    ChunkStore::init(&backend).unwrap();
+    let mut store = ChunkStore::open(backend.clone(), PACK_SIZE, LockKind::Shared).unwrap();
+    for i in 0..count {
+        let (hash, data) = chunk(i);
+        assert!(store.put(&hash, &data).unwrap());
+    }
+    store.flush().unwrap();
+    backend
+}
+
+/// Backend calls it takes to open a repository of `count` chunks.
+fn calls_to_open(count: usize) -> usize {
+    let backend = repository(count);
+    backend.reset();
+    ChunkStore::open(backend.clone(), PACK_SIZE, LockKind::Shared).unwrap();
+    backend.calls()
+}
+
+#[test]
+fn ten_thousand_lookups_need_no_backend_calls() {
+    let backend = repository(10_000);
+    backend.reset();
+
+    // Opening lists the packs once and reads the one pack's index, however many
+    // chunks it has.
+    let store = ChunkStore::open(backend.clone(), PACK_SIZE, LockKind::Shared).unwrap();
+    assert_eq!(backend.lists.load(Ordering::SeqCst), 1);
+    let opening = backend.calls();
+    assert_eq!(opening, calls_to_open(10));
+
+    let mut hashes: Vec<String> = (0..10_000).map(|i| chunk(i).0).collect();
+    hashes.extend((10_000..10_010).map(|i| chunk(i).0));
+    let stored = store.contains_many(&hashes);
+
+    assert_eq!(backend.calls(), opening);
+    assert_eq!(stored.len(), 10_010);
+    assert!(stored[..10_000].iter().all(|&stored| stored));
+    assert!(stored[10_000..].iter().all(|&stored| !stored));
+    assert!(store.contains_many::<&str>(&[]).is_empty());
+}
+
+#[test]
+fn backup_of_stored_content_only_looks_up_the_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(&path, SETTINGS).unwrap();
+    let settings = Settings::from_path(&path).unwrap();
+    let data = noise(1_000_000, 0x5eed);
+
+    let backend = repository(0);
+    let store = ChunkStore::open(backend.clone(), PACK_SIZE, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings.clone(), store);
+    session.add_bytes("first", &data).unwrap();
+    let chunks = session.stats().new_chunks;
+    assert!(chunks > 100);
+    session.finish().unwrap();
+
+    let store = ChunkStore::open(backend.clone(), PACK_SIZE, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings, store);
+    backend.reset();
+    session.add_bytes("again", &data).unwrap();
+    assert_eq!(backend.calls(), 0);
+    assert_eq!(session.stats().new_chunks, 0);
+    assert_eq!(session.stats().chunks, chunks);
+}
@@ -0,0 +1,133 @@
+//! `copy_snapshot` and `rbckp copy`: snapshots move between repositories with only
+//! the chunks the destination lacks.
+
+mod common;
+
+use std::{collections::HashSet, fs, path::Path};
+
+use common::{SETTINGS, noise, rbckp_stdout};
+use rbckp::{
+    backup::{
+        copy::{self, CopyStats},
+        session::BackupSession,
+        snapshot::Snapshot,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+    },
+    config::Settings,
+};
+
+fn repo(path: &Path) -> ChunkStore<LocalFsBackend> {
+    if !path.exists() {
+        fs::create_dir(path).unwrap();
+        ChunkStore::init(&LocalFsBackend::new(path)).unwrap();
+    }
+    ChunkStore::open(LocalFsBackend::new(path), 1 << 20, LockKind::Shared).unwrap()
+}
+
+fn commit_files(dir: &Path, repo_path: &Path, files: &[(&str, &[u8])]) -> (String, Snapshot) {
+    let path = dir.join("settings.ini");
+    fs::write(&path, SETTINGS).unwrap();
+    let mut session = BackupSession::new(Settings::from_path(&path).unwrap(), repo(repo_path));
+    for (name, data) in files {
+        session.add_bytes(name, data).unwrap();
+    }
+    session.commit(vec![], &[]).unwrap()
+}
+
+fn chunks(snapshot: &Snapshot) -> HashSet<String> {
+    snapshot
+        .manifest
+        .entries
+        .iter()
+        .flat_map(|entry| entry.chunks.iter().cloned())
+        .collect()
+}
+
+#[test]
+fn second_overlapping_snapshot_transfers_only_the_delta() {
+    let dir = tempfile::tempdir().unwrap();
+    let (local, offsite) = (dir.path().join("local"), dir.path().join("offsite"));
+    let (a, b, c) = (noise(200_000, 1), noise(150_000, 2), noise(100_000, 3));
+    let (first_id, first) = commit_files(dir.path(), &local, &[("a", &a), ("b", &b)]);
+    let (second_id, second) = commit_files(dir.path(), &local, &[("a", &a), ("c", &c)]);
+
+    let from = repo(&local);
+    let mut to = repo(&offsite);
+    let stats = copy::copy_snapshot(&from, &mut to, &first_id).unwrap();
+    assert_eq!(stats.chunks, chunks(&first).len());
+    assert_eq!(stats.chunks_copied, stats.chunks);
+    assert_eq!(stats.bytes_copied, 350_000);
+
+    // Only the chunks of `c` are new to the destination.
+    let delta = chunks(&second).difference(&chunks(&first)).count();
+    assert!(delta > 0 && delta < chunks(&second).len());
+    let stats = copy::copy_snapshot(&from, &mut to, &second_id).unwrap();
+    assert_eq!(
+        stats,
+        CopyStats {
+            chunks: chunks(&second).len(),
+            chunks_copied: delta,
+            bytes_copied: 100_000,
+        }
+    );
+
+    // The copies are complete snapshots with the same ids.
+    let to = repo(&offsite);
+    assert_eq!(Snapshot::list(to.backend()).unwrap().len(), 2);
+    assert_eq!(Snapshot::load(to.backend(), &second_id).unwrap(), second);
+    let restored: Vec<u8> = second.manifest.entries[1]
+        .chunks
+        .iter()
+        .flat_map(|hash| to.get(hash).unwrap())
+        .collect();
+    assert_eq!(restored, c);
+    assert!(copy::missing_snapshots(&from, &to).unwrap().is_empty());
+}
+
+#[test]
+fn copy_command_with_all_copies_what_is_missing() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let data = dir.join("data");
+    fs::create_dir(&data).unwrap();
+    fs::write(data.join("one.bin"), noise(50_000, 4)).unwrap();
+
+    rbckp_stdout(dir, &["init", "local", "--quiet"]);
+    rbckp_stdout(dir, &["init", "offsite", "--quiet"]);
+    rbckp_stdout(dir, &["backup", "--repo", "local", "data", "--quiet"]);
+    let list = rbckp_stdout(dir, &["list-snapshots", "--repo", "local", "--quiet"]);
+    let first = list.split_whitespace().next().unwrap().to_string();
+    rbckp_stdout(
+        dir,
+        &[
+            "copy",
+            "--from",
+            "local",
+            "--to",
+            "offsite",
+            "--snapshot",
+            &first[..8],
+            "--quiet",
+        ],
+    );
+
+    fs::write(data.join("two.bin"), noise(50_000, 5)).unwrap();
+    rbckp_stdout(dir, &["backup", "--repo", "local", "data", "--quiet"]);
+    let output = rbckp_stdout(
+        dir,
+        &["copy", "--from", "local", "--to", "offsite", "--all"],
+    );
+    assert_eq!(output.lines().count(), 1, "{}", output);
+    assert!(output.contains("(50000 bytes)"), "{}", output);
+
+    let output = rbckp_stdout(
+        dir,
+        &["copy", "--from", "local", "--to", "offsite", "--all"],
+    );
+    assert!(output.contains("has every snapshot"), "{}", output);
+    assert_eq!(
+        rbckp_stdout(dir, &["list-snapshots", "--repo", "offsite", "--quiet"]),
+        rbckp_stdout(dir, &["list-snapshots", "--repo", "local", "--quiet"])
+    );
+}
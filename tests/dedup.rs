@@ -0,0 +1,236 @@
+//! Deduplication across files and backups through the repository's persistent index,
+//! and of whole files within a backup.
+
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::{
+        hash::ChunkId,
+        index::{CHUNK_INDEX_NAME, ChunkIndex, ChunkLocation},
+        session::BackupSession,
+        store::{Backend, ChunkStore, InMemoryBackend, LocalFsBackend, lock::LockKind},
+    },
+    config::Settings,
+};
+
+fn settings(dir: &std::path::Path) -> Settings {
+    let path = dir.join("settings.ini");
+    fs::write(&path, SETTINGS).unwrap();
+    Settings::from_path(&path).unwrap()
+}
+
+fn packed_bytes(backend: &LocalFsBackend) -> u64 {
+    backend
+        .list("packs/")
+        .unwrap()
+        .iter()
+        .map(|name| backend.size(name).unwrap())
+        .sum()
+}
+
+#[test]
+fn same_file_backed_up_twice_is_stored_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+
+    let file = dir.path().join("data.bin");
+    fs::write(&file, noise(300_000, 7)).unwrap();
+    let copy = dir.path().join("copy.bin");
+    fs::copy(&file, &copy).unwrap();
+
+    // First backup: the file and an identical copy of it.
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings(dir.path()), store);
+    session.add_file(&file).unwrap();
+    session.add_file(&copy).unwrap();
+    let first = *session.stats();
+    session.commit(vec![], &[]).unwrap();
+
+    assert_eq!(first.chunks % 2, 0);
+    assert_eq!(first.new_chunks, first.chunks / 2);
+    assert_eq!(first.new_bytes, 300_000);
+    let packed = packed_bytes(&LocalFsBackend::new(&repo));
+
+    // Second backup, in a new process as far as the store is concerned.
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings(dir.path()), store);
+    session.add_file(&file).unwrap();
+    let second = *session.stats();
+    session.commit(vec![], &[]).unwrap();
+
+    assert_eq!(second.chunks, first.chunks / 2);
+    assert_eq!(second.new_chunks, 0);
+    assert_eq!(second.new_bytes, 0);
+    assert_eq!(packed_bytes(&LocalFsBackend::new(&repo)), packed);
+
+    // chunks.idx has each unique chunk once, in a pack that is there.
+    let backend = LocalFsBackend::new(&repo);
+    let index = ChunkIndex::load(&backend).unwrap();
+    assert_eq!(index.len(), first.new_chunks);
+    for hash in &backed_up_chunks(dir.path(), &repo, &file) {
+        let locations = index.locations(&hash.parse().unwrap());
+        assert_eq!(locations.len(), 1, "{}", hash);
+        assert!(
+            backend
+                .exists(&locations[0].pack_file.to_string_lossy())
+                .unwrap()
+        );
+    }
+}
+
+/// The chunk ids of `file`, as a backup of it to `repo` records them.
+fn backed_up_chunks(dir: &Path, repo: &Path, file: &Path) -> Vec<String> {
+    let store = ChunkStore::open(LocalFsBackend::new(repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings(dir), store);
+    session.add_file(file).unwrap();
+    let manifest = session.finish().unwrap();
+    manifest.entries[0].chunks.clone()
+}
+
+#[test]
+fn repository_without_chunk_index_gets_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let file = dir.path().join("data.bin");
+    fs::write(&file, noise(100_000, 3)).unwrap();
+    let chunks = backed_up_chunks(dir.path(), &repo, &file);
+    let packed = packed_bytes(&LocalFsBackend::new(&repo));
+
+    // As left by a version that kept only index.json.
+    fs::remove_file(repo.join(CHUNK_INDEX_NAME)).unwrap();
+    assert_eq!(backed_up_chunks(dir.path(), &repo, &file), chunks);
+    assert_eq!(packed_bytes(&LocalFsBackend::new(&repo)), packed);
+    let index = ChunkIndex::load(&LocalFsBackend::new(&repo)).unwrap();
+    assert!(chunks.iter().all(|hash| index.has(&hash.parse().unwrap())));
+}
+
+#[test]
+fn chunk_index_keeps_every_location() {
+    let location = |pack: &str, offset| ChunkLocation {
+        pack_file: pack.into(),
+        offset,
+        length: 100,
+    };
+    let (a, b) = (ChunkId::of(b"a"), ChunkId::of(b"b"));
+    let mut index = ChunkIndex::default();
+    assert!(!index.has(&a));
+    assert!(index.insert(a, location("packs/0.pack", 0)));
+    assert!(!index.insert(a, location("packs/0.pack", 0)));
+    assert!(index.insert(a, location("packs/1.pack", 40)));
+    assert!(index.insert(b, location("packs/1.pack", 140)));
+    assert_eq!(index.locations(&a).len(), 2);
+
+    let backend = InMemoryBackend::default();
+    index.save(&backend).unwrap();
+    let mut loaded = ChunkIndex::load(&backend).unwrap();
+    assert_eq!(loaded, index);
+
+    // Without pack 1, `a` is still stored in pack 0 and `b` nowhere.
+    assert_eq!(
+        loaded.retain_packs(|pack| pack != Path::new("packs/1.pack")),
+        2
+    );
+    assert_eq!(loaded.locations(&a), [location("packs/0.pack", 0)]);
+    assert!(!loaded.has(&b));
+    assert_eq!(loaded.len(), 1);
+}
+
+#[test]
+fn identical_file_is_not_chunked_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+
+    let file = dir.path().join("data.bin");
+    fs::write(&file, noise(200_000, 11)).unwrap();
+    let copy = dir.path().join("copy.bin");
+    fs::copy(&file, &copy).unwrap();
+
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings(dir.path()), store);
+    session.add_file(&file).unwrap();
+    let first = *session.stats();
+    assert_eq!(first.duplicate_files, 0);
+    session.add_file(&copy).unwrap();
+    let second = *session.stats();
+    let manifest = session.finish().unwrap();
+
+    assert_eq!(second.duplicate_files, 1);
+    assert_eq!(second.new_chunks, first.new_chunks);
+    assert_eq!(second.new_bytes, first.new_bytes);
+    let [original, duplicate] = &manifest.entries[..] else {
+        panic!("expected two entries");
+    };
+    assert_eq!(duplicate.chunks, original.chunks);
+    assert!(duplicate.content_hash.is_some());
+    assert_eq!(duplicate.content_hash, original.content_hash);
+}
+
+/// `base` with a few bytes overwritten and a few inserted, differently per `variant`.
+fn variant(base: &[u8], variant: usize) -> Vec<u8> {
+    let mut data = base.to_vec();
+    for edit in 0..3 {
+        let at = (variant * 7919 + edit * 104_729) * 3 % (data.len() - 64);
+        data[at..at + 16].copy_from_slice(&[variant as u8 + 1; 16]);
+    }
+    let at = variant * 65_537 % data.len();
+    data.splice(at..at, [0xa5; 5]);
+    data
+}
+
+/// Back up `data` to the new repository `repo` with the CLI, returning its packed size.
+fn backup_dir(dir: &Path, data: &str, repo: &str) -> u64 {
+    let rbckp = |args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+            .current_dir(dir)
+            .args(args)
+            .args(["--quiet", "--config", "settings.ini"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    rbckp(&["init", repo]);
+    rbckp(&["backup", data, "--repo", repo]);
+    packed_bytes(&LocalFsBackend::new(&dir.join(repo)))
+}
+
+/// Five near-identical files, e.g. variants of one disk image, in one directory: the
+/// chunks of the first file deduplicate the others within the same run.
+///
+/// Scaled down from the 100 MB images this matters for.
+#[test]
+fn near_identical_files_in_one_run_share_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    settings(dir.path());
+    let base = noise(1_000_000, 13);
+    fs::create_dir(dir.path().join("images")).unwrap();
+    for i in 0..5 {
+        let image = variant(&base, i);
+        fs::write(dir.path().join(format!("images/{}.img", i)), &image).unwrap();
+        fs::create_dir(dir.path().join(format!("single{}", i))).unwrap();
+        fs::write(dir.path().join(format!("single{}/{}.img", i, i)), &image).unwrap();
+    }
+
+    let together = backup_dir(dir.path(), "images", "repo");
+    let separately: u64 = (0..5)
+        .map(|i| backup_dir(dir.path(), &format!("single{}", i), &format!("repo{}", i)))
+        .sum();
+
+    // Each variant adds only the chunks around its edits.
+    assert!(together > 1_000_000);
+    assert!(together < 1_200_000, "stored {} bytes", together);
+    assert!(
+        separately > 4 * together,
+        "{} bytes in one run, {} in separate repositories",
+        together,
+        separately
+    );
+}
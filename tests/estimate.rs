@@ -0,0 +1,188 @@
+//! `estimate_chunk_count` counts exactly the chunks `chunk_bytes_cdc` cuts, and
+//! `rbckp estimate` predicts what a backup writes.
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{SETTINGS, noise, rbckp, rbckp_stdout};
+use rbckp::{
+    backup::{
+        cdc_chunker,
+        estimate::Estimator,
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+    },
+    config::Settings,
+};
+
+#[test]
+fn estimate_matches_chunking() {
+    let inputs: Vec<(&str, Vec<u8>)> = vec![
+        ("empty", Vec::new()),
+        ("shorter than min", noise(100, 1)),
+        ("noise", noise(500_000, 2)),
+        ("zeros", vec![0; 100_000]),
+        ("text", b"the quick brown fox ".repeat(10_000)),
+        (
+            "mixed",
+            [noise(50_000, 3), vec![0; 70_000], noise(30_000, 4)].concat(),
+        ),
+    ];
+    let sizes = [(256, 1024, 4096), (1024, 4096, 16384), (4096, 16384, 65536)];
+
+    for (name, data) in &inputs {
+        for (min, avg, max) in sizes {
+            let (chunks, _) = cdc_chunker::chunk_bytes_cdc(data, min, avg, max);
+            assert_eq!(
+                cdc_chunker::estimate_chunk_count(data, min, avg, max),
+                chunks.len(),
+                "{} with sizes {}/{}/{}",
+                name,
+                min,
+                avg,
+                max
+            );
+        }
+    }
+}
+
+/// The number before ` bytes` on the line of `rbckp estimate` output starting with `label`.
+fn bytes_on(stdout: &str, label: &str) -> u64 {
+    let line = stdout
+        .lines()
+        .find(|line| line.starts_with(label))
+        .unwrap_or_else(|| panic!("no {} line in {}", label, stdout));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let at = words.iter().position(|word| *word == "bytes").unwrap();
+    words[at - 1].parse().unwrap()
+}
+
+/// Every file of the repository and its size, leaving out the lock, which every
+/// command takes.
+fn repo_files(repo: &Path) -> Vec<(String, u64)> {
+    let mut files = Vec::new();
+    let mut dirs = vec![repo.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_name() == "lock" || entry.file_name() == "locks" {
+                continue;
+            }
+            if entry.file_type().unwrap().is_dir() {
+                dirs.push(entry.path());
+            } else {
+                let name = entry.path().to_string_lossy().into_owned();
+                files.push((name, entry.metadata().unwrap().len()));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn estimate_predicts_the_backup_and_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    fs::create_dir_all(dir.path().join("data/sub")).unwrap();
+    fs::write(dir.path().join("data/noise.bin"), noise(200_000, 7)).unwrap();
+    fs::write(
+        dir.path().join("data/sub/text.txt"),
+        b"all work and no play ".repeat(5_000),
+    )
+    .unwrap();
+    fs::write(dir.path().join("data/copy.bin"), noise(200_000, 7)).unwrap();
+    fs::write(dir.path().join("data/skip.log"), noise(100_000, 8)).unwrap();
+    rbckp(dir.path(), &["init", "repo"]);
+    let repo = dir.path().join("repo");
+    let before = repo_files(&repo);
+
+    let args = [
+        "estimate",
+        "--repo",
+        "repo",
+        "-F",
+        "data",
+        "--exclude",
+        "*.log",
+    ];
+    let first = rbckp_stdout(dir.path(), &args);
+    assert_eq!(repo_files(&repo), before);
+    // The copy is deduplicated, the excluded file not read.
+    assert_eq!(bytes_on(&first, "Total:"), 505_000);
+    let new_bytes = bytes_on(&first, "New:");
+    assert_eq!(new_bytes, 305_000);
+    // The text compresses well, the noise not at all.
+    let compressed = bytes_on(&first, "New, compressed:");
+    assert!(compressed < new_bytes && compressed > 200_000, "{}", first);
+
+    let backup = rbckp(
+        dir.path(),
+        &[
+            "backup",
+            "--repo",
+            "repo",
+            "-F",
+            "data",
+            "--exclude",
+            "*.log",
+        ],
+    );
+    let backup = String::from_utf8(backup.stdout).unwrap();
+    assert!(
+        backup.contains(&format!("({} bytes)", new_bytes)),
+        "{}",
+        backup
+    );
+
+    let second = rbckp_stdout(dir.path(), &args);
+    assert_eq!(bytes_on(&second, "Total:"), 505_000);
+    assert_eq!(bytes_on(&second, "New:"), 0);
+    assert_eq!(bytes_on(&second, "New, compressed:"), 0);
+
+    // Without the exclude, only the log file is new.
+    let all = ["estimate", "--repo", "repo", "data"];
+    let third = rbckp_stdout(dir.path(), &all);
+    assert_eq!(bytes_on(&third, "New:"), 100_000);
+}
+
+#[test]
+fn estimator_counts_what_a_session_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let settings = Settings::from_path(&dir.path().join("settings.ini")).unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let inputs = [noise(300_000, 1), noise(100_000, 2), noise(300_000, 1)];
+
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut estimator = Estimator::new(&settings, &store);
+    for data in &inputs {
+        estimator.add_bytes(data).unwrap();
+    }
+    let estimate = *estimator.estimate();
+
+    let mut session = BackupSession::new(settings.clone(), store);
+    for (i, data) in inputs.iter().enumerate() {
+        session.add_bytes(&i.to_string(), data).unwrap();
+    }
+    let stats = *session.stats();
+    assert_eq!(estimate.files, stats.files);
+    assert_eq!(estimate.bytes, stats.bytes);
+    assert_eq!(estimate.chunks, stats.chunks);
+    assert_eq!(estimate.new_chunks, stats.new_chunks);
+    assert_eq!(estimate.new_bytes, stats.new_bytes);
+    // Random data does not compress.
+    assert_eq!(estimate.sampled_bytes, estimate.new_bytes);
+    assert!(estimate.compressed_new_bytes() >= estimate.new_bytes);
+    session.finish().unwrap();
+
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut estimator = Estimator::new(&settings, &store);
+    for data in &inputs {
+        estimator.add_bytes(data).unwrap();
+    }
+    assert_eq!(estimator.estimate().new_bytes, 0);
+}
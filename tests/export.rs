@@ -0,0 +1,123 @@
+//! `rbckp export`: the archive unpacks to the same tree as a direct restore.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use common::{SETTINGS, TreeItem, back_up, make_tree, rbckp};
+
+/// Back up a tree with a large file, a private file, a hard link and a symlink, and
+/// return the snapshot id.
+fn back_up_tree(dir: &Path) -> String {
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let big: Vec<u8> = (0..500_000u32).map(|i| (i * 13 % 251) as u8).collect();
+    make_tree(
+        &dir.join("data"),
+        &[
+            TreeItem::File("sub/big.bin", &big),
+            TreeItem::File("private.txt", b"secret"),
+            TreeItem::Mode("private.txt", 0o600),
+            TreeItem::HardLink("linked.txt", "private.txt"),
+            TreeItem::Symlink("shortcut", "sub/big.bin"),
+        ],
+    );
+    rbckp(dir, &["init", "repo"]);
+    back_up(dir, &["data"])
+}
+
+/// Everything below `root`: relative path, permission bits, and content or link target.
+fn describe(root: &Path) -> Vec<(PathBuf, u32, Vec<u8>)> {
+    let mut found = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        let content = if metadata.is_symlink() {
+            fs::read_link(&path)
+                .unwrap()
+                .into_os_string()
+                .into_encoded_bytes()
+        } else if metadata.is_dir() {
+            for entry in fs::read_dir(&path).unwrap() {
+                pending.push(entry.unwrap().path());
+            }
+            Vec::new()
+        } else {
+            fs::read(&path).unwrap()
+        };
+        let relative = path.strip_prefix(root).unwrap().to_path_buf();
+        found.push((relative, metadata.mode() & 0o7777, content));
+    }
+    found.sort();
+    found
+}
+
+fn inode(path: &Path) -> u64 {
+    fs::metadata(path).unwrap().ino()
+}
+
+#[test]
+fn tar_export_matches_restore() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = back_up_tree(dir.path());
+    rbckp(
+        dir.path(),
+        &["restore", "--repo", "repo", "--target", "restored", &id],
+    );
+    rbckp(
+        dir.path(),
+        &[
+            "export",
+            "--repo",
+            "repo",
+            "--snapshot",
+            &id,
+            "--output",
+            "snapshot.tar",
+        ],
+    );
+
+    let unpacked = dir.path().join("unpacked");
+    tar::Archive::new(File::open(dir.path().join("snapshot.tar")).unwrap())
+        .unpack(&unpacked)
+        .unwrap();
+    assert_eq!(describe(&unpacked), describe(&dir.path().join("restored")));
+    assert_eq!(
+        inode(&unpacked.join("data/private.txt")),
+        inode(&unpacked.join("data/linked.txt"))
+    );
+}
+
+#[test]
+fn zstd_export_matches_restore() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = back_up_tree(dir.path());
+    rbckp(
+        dir.path(),
+        &["restore", "--repo", "repo", "--target", "restored", &id],
+    );
+    rbckp(
+        dir.path(),
+        &[
+            "export",
+            "--repo",
+            "repo",
+            "--snapshot",
+            &id,
+            "--format",
+            "tar.zst",
+            "--output",
+            "snapshot.tar.zst",
+        ],
+    );
+
+    let unpacked = dir.path().join("unpacked");
+    let decoder =
+        zstd::Decoder::new(File::open(dir.path().join("snapshot.tar.zst")).unwrap()).unwrap();
+    tar::Archive::new(decoder).unpack(&unpacked).unwrap();
+    assert_eq!(describe(&unpacked), describe(&dir.path().join("restored")));
+}
@@ -0,0 +1,107 @@
+//! Pack fan-out: where packs go for each depth, and that they are found there again.
+
+use rbckp::backup::store::{
+    Backend, ChunkStore, LocalFsBackend, StoreError, chunk_store::pack_name, lock::LockKind,
+    repo_config::REPO_CONFIG_NAME,
+};
+
+#[test]
+fn pack_name_per_depth() {
+    let id = 0x0123_4567_89ab_cdef;
+    assert_eq!(pack_name(id, 0), "packs/0123456789abcdef.pack");
+    assert_eq!(pack_name(id, 1), "packs/ef/0123456789abcdef.pack");
+    assert_eq!(pack_name(id, 2), "packs/ef/cd/0123456789abcdef.pack");
+}
+
+/// Pack ids count up from 0, so consecutive packs must not share a directory.
+#[test]
+fn consecutive_packs_spread_over_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = LocalFsBackend::new(dir.path());
+    ChunkStore::init_with_fanout(&backend, 2).unwrap();
+
+    let mut store = ChunkStore::open(LocalFsBackend::new(dir.path()), 1, LockKind::Shared).unwrap();
+    for i in 0..4u8 {
+        let chunk = vec![i; 100];
+        store
+            .put(blake3::hash(&chunk).to_hex().as_str(), &chunk)
+            .unwrap();
+    }
+    store.flush().unwrap();
+
+    let packs = backend.list("packs/").unwrap();
+    assert_eq!(
+        packs,
+        [
+            "packs/00/00/0000000000000000.pack",
+            "packs/01/00/0000000000000001.pack",
+            "packs/02/00/0000000000000002.pack",
+            "packs/03/00/0000000000000003.pack",
+        ]
+    );
+}
+
+/// Store a few chunks in a new repository with `fanout_depth`, then reopen it (and
+/// rebuild its index) and read them back.
+fn round_trip(fanout_depth: u32) {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = LocalFsBackend::new(dir.path());
+    let config = ChunkStore::init_with_fanout(&backend, fanout_depth).unwrap();
+    assert_eq!(config.fanout_depth, fanout_depth);
+
+    let chunks: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i; 1000 + i as usize]).collect();
+    let hashes: Vec<String> = chunks
+        .iter()
+        .map(|chunk| blake3::hash(chunk).to_hex().to_string())
+        .collect();
+
+    let mut store = ChunkStore::open(LocalFsBackend::new(dir.path()), 1, LockKind::Shared).unwrap();
+    for (hash, chunk) in hashes.iter().zip(&chunks) {
+        store.put(hash, chunk).unwrap();
+    }
+    store.flush().unwrap();
+    drop(store);
+
+    let packs = backend.list("packs/").unwrap();
+    assert_eq!(packs.len(), 3);
+    for name in &packs {
+        let id = name
+            .rsplit('/')
+            .next()
+            .unwrap()
+            .strip_suffix(".pack")
+            .unwrap();
+        let id = u64::from_str_radix(id, 16).unwrap();
+        assert_eq!(name, &pack_name(id, fanout_depth));
+    }
+
+    let store = ChunkStore::open(LocalFsBackend::new(dir.path()), 1, LockKind::Shared).unwrap();
+    for (hash, chunk) in hashes.iter().zip(&chunks) {
+        assert_eq!(&store.get(hash).unwrap(), chunk);
+    }
+    drop(store);
+
+    let report = ChunkStore::rebuild_index(&backend, true).unwrap();
+    assert_eq!((report.packs, report.chunks), (3, 3));
+}
+
+#[test]
+fn depth_1_round_trip() {
+    round_trip(1);
+}
+
+#[test]
+fn depth_2_round_trip() {
+    round_trip(2);
+}
+
+#[test]
+fn depth_beyond_maximum_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = LocalFsBackend::new(dir.path());
+    assert!(matches!(
+        ChunkStore::init_with_fanout(&backend, 3),
+        Err(StoreError::Io(_))
+    ));
+    assert!(!backend.exists(REPO_CONFIG_NAME).unwrap());
+}
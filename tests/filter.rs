@@ -0,0 +1,245 @@
+//! Exclude patterns (`--exclude` globs, the `exclude` setting and `.rbckpignore` files)
+//! and `--include` globs.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use common::settings_with;
+use rbckp::backup::{
+    filter::{self, ExcludeFilter, FileFilter},
+    snapshot::Snapshot,
+    store::LocalFsBackend,
+    walk,
+};
+
+fn excluded(filter: &ExcludeFilter, path: &str) -> bool {
+    filter.is_excluded(Path::new(path), false)
+}
+
+#[test]
+fn extension_glob_matches_at_any_depth() {
+    let filter = ExcludeFilter::new(&["*.log"]).unwrap();
+    assert!(excluded(&filter, "app.log"));
+    assert!(excluded(&filter, "var/log/app.log"));
+    assert!(!excluded(&filter, "app.log.gz"));
+    assert!(!excluded(&filter, "logs/app.txt"));
+}
+
+#[test]
+fn double_star_matches_directory_contents() {
+    let filter = ExcludeFilter::new(&["**/__pycache__/**"]).unwrap();
+    assert!(excluded(&filter, "__pycache__/mod.cpython-312.pyc"));
+    assert!(excluded(&filter, "src/pkg/__pycache__/mod.pyc"));
+    assert!(!excluded(&filter, "src/pkg/mod.py"));
+}
+
+#[test]
+fn negation_re_includes_and_last_match_wins() {
+    let filter = ExcludeFilter::new(&["*.log", "!important.log"]).unwrap();
+    assert!(excluded(&filter, "debug.log"));
+    assert!(!excluded(&filter, "important.log"));
+    assert!(!excluded(&filter, "logs/important.log"));
+
+    let filter = ExcludeFilter::new(&["!important.log", "*.log"]).unwrap();
+    assert!(excluded(&filter, "important.log"));
+}
+
+#[test]
+fn anchored_and_directory_patterns() {
+    let filter = ExcludeFilter::new(&["/build", "target/"]).unwrap();
+    assert!(filter.is_excluded(Path::new("build"), true));
+    assert!(!filter.is_excluded(Path::new("src/build"), true));
+    assert!(filter.is_excluded(Path::new("crates/x/target"), true));
+    assert!(!filter.is_excluded(Path::new("target"), false));
+}
+
+#[test]
+fn walk_honors_excludes_and_ignore_files() {
+    let root = tempfile::tempdir().unwrap();
+    let files = [
+        "a.txt",
+        "a.log",
+        "important.log",
+        "src/main.py",
+        "src/__pycache__/main.pyc",
+        "sub/keep.tmp",
+        "sub/deeper/drop.tmp",
+        "sub/deeper/drop.log",
+        "other/drop.tmp",
+    ];
+    for file in files {
+        let path = root.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, file).unwrap();
+    }
+    // Only applies below `sub/deeper`, and not to the log exclusion from the command line.
+    fs::write(
+        root.path().join("sub/deeper/.rbckpignore"),
+        "# temp files\n*.tmp\n",
+    )
+    .unwrap();
+    fs::write(root.path().join("other/.rbckpignore"), "/drop.tmp\n").unwrap();
+
+    let filter = ExcludeFilter::new(&["*.log", "!important.log", "**/__pycache__/**"]).unwrap();
+    let mut collected = Vec::new();
+    walk::collect_files(
+        root.path(),
+        &filter,
+        &FileFilter::default(),
+        false,
+        &mut collected,
+    )
+    .unwrap();
+
+    let relative: Vec<PathBuf> = collected
+        .iter()
+        .map(|path| path.strip_prefix(root.path()).unwrap().to_path_buf())
+        .collect();
+    let expected: Vec<PathBuf> = [
+        "a.txt",
+        "important.log",
+        "other/.rbckpignore",
+        "src/main.py",
+        "sub/deeper/.rbckpignore",
+        "sub/keep.tmp",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(relative, expected);
+}
+
+fn file_filter(includes: &[&str], excludes: &[&str]) -> FileFilter {
+    let globs = |patterns: &[&str]| -> Vec<_> {
+        patterns
+            .iter()
+            .map(|pattern| filter::path_glob(pattern).unwrap())
+            .collect()
+    };
+    FileFilter::new(&globs(includes), &globs(excludes))
+}
+
+#[test]
+fn includes_whitelist_files_and_directories() {
+    let filter = file_filter(&["*.rs", "/docs"], &[]);
+    assert!(filter.matches(Path::new("main.rs")));
+    assert!(filter.matches(Path::new("src/lib.rs")));
+    assert!(filter.matches(Path::new("docs/guide/intro.md")));
+    assert!(!filter.matches(Path::new("README.md")));
+    assert!(!filter.matches(Path::new("src/docs/notes.md")));
+
+    assert!(file_filter(&[], &[]).matches(Path::new("anything")));
+}
+
+#[test]
+fn excludes_narrow_the_included_set() {
+    let filter = file_filter(&["src"], &["*_test.rs", "src/gen"]);
+    assert!(filter.matches(Path::new("src/lib.rs")));
+    assert!(!filter.matches(Path::new("src/lib_test.rs")));
+    assert!(!filter.matches(Path::new("src/gen/tables.rs")));
+    // An exclude cannot add back what the includes left out.
+    assert!(!filter.matches(Path::new("README.md")));
+}
+
+#[test]
+fn walk_only_collects_included_files() {
+    let root = tempfile::tempdir().unwrap();
+    for file in ["a.txt", "b.md", "notes/c.md", "notes/d.txt"] {
+        let path = root.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, file).unwrap();
+    }
+
+    let mut collected = Vec::new();
+    walk::collect_files(
+        root.path(),
+        &ExcludeFilter::new(&["notes/d.txt"]).unwrap(),
+        &file_filter(&["*.md", "notes"], &[]),
+        false,
+        &mut collected,
+    )
+    .unwrap();
+
+    let relative: Vec<PathBuf> = collected
+        .iter()
+        .map(|path| path.strip_prefix(root.path()).unwrap().to_path_buf())
+        .collect();
+    assert_eq!(
+        relative,
+        [PathBuf::from("b.md"), PathBuf::from("notes/c.md")]
+    );
+}
+
+#[test]
+fn backup_leaves_out_excluded_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("settings.ini"),
+        settings_with("exclude = target/ .git/ *.tmp\n"),
+    )
+    .unwrap();
+    for file in [
+        "src/main.rs",
+        "src/scratch.tmp",
+        "keep.tmp",
+        "target/debug/app",
+        "target/debug/deps/lib.rlib",
+        ".git/HEAD",
+        "notes.txt",
+    ] {
+        let path = dir.path().join("project").join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, file).unwrap();
+    }
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+            .current_dir(dir.path())
+            .args(args)
+            .args(["--config", "settings.ini"])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    };
+    run(&["init", "repo"]);
+    // `--exclude` patterns come after the configured ones and can re-include.
+    run(&[
+        "backup",
+        "--repo",
+        "repo",
+        "project",
+        "--exclude",
+        "notes.txt",
+        "--exclude",
+        "!keep.tmp",
+    ]);
+
+    let backend = LocalFsBackend::new(&dir.path().join("repo"));
+    let ids = Snapshot::list(&backend).unwrap();
+    let snapshot = Snapshot::load(&backend, &ids[0]).unwrap();
+    let mut names: Vec<&str> = snapshot
+        .manifest
+        .entries
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["project/keep.tmp", "project/src/main.rs"]);
+    assert!(
+        snapshot
+            .manifest
+            .directories
+            .iter()
+            .all(|dir| !dir.name.contains("target") && !dir.name.contains(".git"))
+    );
+}
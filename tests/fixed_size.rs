@@ -0,0 +1,105 @@
+//! `ChunkingStrategy::FixedSize`: the baseline that shows why chunks are cut by content.
+
+mod common;
+
+use std::{fs, process::Command};
+
+use common::{SETTINGS, noise};
+use rbckp::backup::{
+    cdc_chunker::{self, CdcParams, ChunkingStrategy, CutReason, StreamChunker},
+    compare,
+};
+
+fn params() -> CdcParams {
+    CdcParams::new(1024, 4096, 16384)
+}
+
+#[test]
+fn an_inserted_byte_shifts_every_fixed_size_chunk() {
+    let original = noise(1 << 20, 21);
+    let edited = [&[0x42][..], &original[..]].concat();
+
+    let fixed = params().with_strategy(ChunkingStrategy::FixedSize);
+    let shared = compare::shared_chunks(
+        &cdc_chunker::chunk_refs_cdc(&original, &fixed),
+        &cdc_chunker::chunk_refs_cdc(&edited, &fixed),
+    );
+    assert_eq!(shared.shared_chunks, 0);
+
+    // Content-defined cuts come back after the first chunk.
+    let shared = compare::shared_chunks(
+        &cdc_chunker::chunk_refs_cdc(&original, &params()),
+        &cdc_chunker::chunk_refs_cdc(&edited, &params()),
+    );
+    assert!(
+        shared.shared_byte_percent() > 95.0,
+        "{:.1}% shared",
+        shared.shared_byte_percent()
+    );
+}
+
+#[test]
+fn fixed_size_cuts_every_avg_bytes() {
+    let data = noise(10_000, 22);
+    let fixed = params().with_strategy(ChunkingStrategy::FixedSize);
+
+    let chunks = cdc_chunker::chunk_refs_cdc(&data, &fixed);
+    let lens: Vec<usize> = chunks.iter().map(|chunk| chunk.len).collect();
+    assert_eq!(lens, [4096, 4096, 1808]);
+    assert_eq!(cdc_chunker::chunk_refs_cdc_parallel(&data, &fixed), chunks);
+    let streamed: Vec<_> = StreamChunker::new(&data[..], &fixed)
+        .map(|chunk| chunk.unwrap().0)
+        .collect();
+    assert_eq!(streamed, chunks);
+
+    let reasons: Vec<CutReason> = cdc_chunker::chunk_debug_info(&data, &fixed)
+        .iter()
+        .map(|chunk| chunk.reason)
+        .collect();
+    assert_eq!(
+        reasons,
+        [CutReason::Forced, CutReason::Forced, CutReason::EndOfInput]
+    );
+    // Nothing to warn about: the maximum never comes into play.
+    assert_eq!(
+        CdcParams::new(1024, 4096, 4096)
+            .with_strategy(ChunkingStrategy::FixedSize)
+            .validate(),
+        []
+    );
+}
+
+#[test]
+fn strategy_flag_and_setting() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = SETTINGS;
+    fs::write(dir.path().join("data.bin"), noise(100_000, 23)).unwrap();
+    let summary = |settings: &str, args: &[&str]| {
+        fs::write(dir.path().join("settings.ini"), settings).unwrap();
+        let _ = fs::remove_file(dir.path().join("output.txt"));
+        let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+            .current_dir(dir.path())
+            .args(["-F", "data.bin", "--json"])
+            .args(args)
+            .args(["--config", "settings.ini"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let cdc = summary(settings, &[]);
+    assert_eq!(cdc["strategy"], "cdc");
+    let fixed = summary(settings, &["--strategy", "fixed-size"]);
+    assert_eq!(fixed["strategy"], "fixed-size");
+    assert_eq!(fixed["chunks"], 25);
+    assert_eq!(fixed["chunk_sizes"]["max"], 4096);
+
+    let configured = summary(&format!("{}strategy=fixed-size\n", settings), &[]);
+    assert_eq!(configured["chunks"], 25);
+    let overridden = summary(
+        &format!("{}strategy=fixed-size\n", settings),
+        &["--strategy", "cdc"],
+    );
+    assert_eq!(overridden["chunks"], cdc["chunks"]);
+}
@@ -0,0 +1,46 @@
+//! Fractional boundary bits hit averages that are not a power of two.
+
+mod common;
+
+use common::noise;
+use rbckp::backup::cdc_chunker::{self, CdcParams};
+
+fn mean_chunk_len(data: &[u8], params: &CdcParams) -> f64 {
+    let chunks = cdc_chunker::chunk_refs_cdc(data, params);
+    data.len() as f64 / chunks.len() as f64
+}
+
+#[test]
+fn fractional_bits_hit_the_average_more_closely() {
+    let data = noise(20 << 20, 5);
+    // A small minimum and a large maximum, so the mean is about `min + avg`.
+    let (min, avg, max) = (64, 3000, 1 << 20);
+    let expected = (min + avg) as f64;
+
+    let rounded = mean_chunk_len(&data, &CdcParams::new(min, avg, max));
+    let fractional = mean_chunk_len(&data, &CdcParams::new(min, avg, max).with_fractional_bits());
+
+    assert!(
+        (fractional - expected).abs() < (rounded - expected).abs(),
+        "fractional mean {:.0}, rounded mean {:.0}, expected {:.0}",
+        fractional,
+        rounded,
+        expected
+    );
+    assert!(
+        (fractional - expected).abs() < expected * 0.05,
+        "fractional mean {:.0}, expected {:.0}",
+        fractional,
+        expected
+    );
+}
+
+#[test]
+fn power_of_two_average_is_unchanged() {
+    let data = noise(2 << 20, 6);
+    let params = CdcParams::new(1024, 4096, 16384);
+    assert_eq!(
+        cdc_chunker::chunk_refs_cdc(&data, &params.with_fractional_bits()),
+        cdc_chunker::chunk_refs_cdc(&data, &params)
+    );
+}
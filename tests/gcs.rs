@@ -0,0 +1,92 @@
+//! `GcsBackend` against a real bucket. The bucket is named by `RBCKP_GCS_TEST_BUCKET`
+//! and credentials come from `RBCKP_GCS_TEST_CREDENTIALS` or the usual Google chain;
+//! without a bucket the tests that need one pass without doing anything.
+#![cfg(feature = "gcs-integration-tests")]
+
+use std::{
+    env, io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rbckp::{
+    backup::store::{
+        backend::Backend,
+        gcs::{self, GcsBackend},
+    },
+    config::GcsSettings,
+};
+
+/// A backend below a prefix of its own, so runs do not see each other's objects.
+fn test_backend(name: &str) -> Option<GcsBackend> {
+    let bucket = env::var("RBCKP_GCS_TEST_BUCKET").ok()?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let settings = GcsSettings {
+        bucket,
+        prefix: format!("rbckp-tests/{}-{}", name, nanos),
+        credentials_file: env::var_os("RBCKP_GCS_TEST_CREDENTIALS").map(Into::into),
+    };
+    Some(GcsBackend::new(&settings).unwrap())
+}
+
+#[test]
+fn objects_round_trip() {
+    let Some(backend) = test_backend("round-trip") else {
+        return;
+    };
+
+    backend.write("packs/a", b"hello, bucket").unwrap();
+    backend.write("packs/b", b"second").unwrap();
+    backend.write("index", b"index").unwrap();
+
+    assert_eq!(backend.read("packs/a").unwrap(), b"hello, bucket");
+    assert_eq!(backend.read_range("packs/a", 7, 6).unwrap(), b"bucket");
+    assert_eq!(backend.size("packs/a").unwrap(), 13);
+    assert!(backend.exists("packs/b").unwrap());
+    assert_eq!(backend.list("packs/").unwrap(), ["packs/a", "packs/b"]);
+
+    for name in ["packs/a", "packs/b", "index"] {
+        backend.remove(name).unwrap();
+    }
+    assert!(backend.list("").unwrap().is_empty());
+}
+
+#[test]
+fn missing_objects_are_not_found() {
+    let Some(backend) = test_backend("missing") else {
+        return;
+    };
+
+    assert!(!backend.exists("nothing").unwrap());
+    for err in [
+        backend.read("nothing").unwrap_err(),
+        backend.size("nothing").unwrap_err(),
+        backend.remove("nothing").unwrap_err(),
+    ] {
+        assert_eq!(err.kind(), io::ErrorKind::NotFound, "{}", err);
+    }
+}
+
+#[test]
+fn urls_name_bucket_and_prefix() {
+    let defaults = GcsSettings {
+        bucket: "configured".to_string(),
+        prefix: "backups".to_string(),
+        credentials_file: Some("/etc/rbckp/sa.json".into()),
+    };
+
+    let settings = gcs::settings_for_url("gs://other/some/repo", Some(&defaults)).unwrap();
+    assert_eq!(
+        (settings.bucket.as_str(), settings.prefix.as_str()),
+        ("other", "some/repo")
+    );
+    assert_eq!(settings.credentials_file, defaults.credentials_file);
+
+    let settings = gcs::settings_for_url("gs://", Some(&defaults)).unwrap();
+    assert_eq!(settings.bucket, "configured");
+    assert!(gcs::settings_for_url("gs://", None).is_err());
+    assert_eq!(gcs::object_name("backups/", "packs/a"), "backups/packs/a");
+    assert_eq!(gcs::object_name("", "packs/a"), "packs/a");
+}
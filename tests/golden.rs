@@ -0,0 +1,160 @@
+//! Chunk boundaries must not move between releases: existing stores deduplicate
+//! against chunks cut by older versions.
+//!
+//! Chunks a fixed input and compares the result with `golden_chunks.txt`. After an
+//! intentional change, bump `CHUNKER_FORMAT_VERSION` and regenerate the list with
+//! `RBCKP_UPDATE_GOLDEN=1 cargo test --test golden`.
+
+use std::{env, fs, path::PathBuf};
+
+use rbckp::backup::cdc_chunker::{
+    self, CHUNKER_FORMAT_VERSION, CdcParams, DEFAULT_GEAR_SEED, StreamChunker,
+};
+
+/// Fixed input: a text part (with repeats, like real files) followed by
+/// pseudo-random bytes from a xorshift generator with a fixed seed.
+fn golden_input() -> Vec<u8> {
+    let mut data = Vec::new();
+    for line in 0..400 {
+        data.extend_from_slice(
+            format!("line {:04}: the quick brown fox jumps\n", line % 97).as_bytes(),
+        );
+    }
+
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    while data.len() < 96 * 1024 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        data.push((state >> 32) as u8);
+    }
+    data
+}
+
+/// One line per chunk: `<offset> <len> <hash>`, under a version header.
+fn describe_chunks(params: &CdcParams) -> String {
+    let data = golden_input();
+    let mut lines = vec![format!("# chunker version {}", CHUNKER_FORMAT_VERSION)];
+    for chunk_ref in cdc_chunker::chunk_refs_cdc(&data, params) {
+        lines.push(format!(
+            "{} {} {}",
+            chunk_ref.offset, chunk_ref.len, chunk_ref.hash
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+fn golden_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden_chunks.txt")
+}
+
+#[test]
+fn chunk_boundaries_match_golden_list() {
+    let params = CdcParams::new(256, 1024, 4096);
+    let actual = describe_chunks(&params);
+
+    if env::var_os("RBCKP_UPDATE_GOLDEN").is_some() {
+        fs::write(golden_path(), &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(golden_path()).unwrap();
+    if actual == expected {
+        return;
+    }
+
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    let mut diff = String::new();
+    for i in 0..expected_lines.len().max(actual_lines.len()) {
+        match (expected_lines.get(i), actual_lines.get(i)) {
+            (Some(e), Some(a)) if e == a => {}
+            (e, a) => {
+                diff += &format!(
+                    "- {}\n+ {}\n",
+                    e.unwrap_or(&"<none>"),
+                    a.unwrap_or(&"<none>")
+                );
+            }
+        }
+    }
+    panic!(
+        "chunk boundaries changed (chunker version {}); this breaks dedup against existing \
+         stores.\nIf intended, bump CHUNKER_FORMAT_VERSION and regenerate the golden list.\n{}",
+        CHUNKER_FORMAT_VERSION, diff
+    );
+}
+
+#[test]
+fn stream_chunker_matches_golden_list() {
+    let params = CdcParams::new(256, 1024, 4096);
+    let data = golden_input();
+
+    let streamed: Vec<_> = StreamChunker::new(data.as_slice(), &params)
+        .map(|chunk| chunk.unwrap().0)
+        .collect();
+    assert_eq!(streamed, cdc_chunker::chunk_refs_cdc(&data, &params));
+}
+
+#[test]
+fn default_seed_is_the_built_in_table() {
+    let data = golden_input();
+    assert_eq!(
+        cdc_chunker::chunk_bytes_cdc_seeded(&data, 256, 1024, 4096, DEFAULT_GEAR_SEED).0,
+        cdc_chunker::chunk_bytes_cdc(&data, 256, 1024, 4096).0
+    );
+}
+
+/// Pinned like the golden list: a seed must give the same table and boundaries on
+/// every platform and in every release.
+#[test]
+fn seeded_chunking_is_pinned() {
+    let table = cdc_chunker::make_gear_table_seeded(42);
+    assert_eq!(table[..4], [1083830552, 378489443, 2479366291, 955869191]);
+
+    let data = golden_input();
+    let (chunks, _) = cdc_chunker::chunk_bytes_cdc_seeded(&data, 256, 1024, 4096, 42);
+    let lens: Vec<usize> = chunks.iter().take(8).map(Vec::len).collect();
+    assert_eq!(lens, [382, 3589, 3589, 3589, 3589, 452, 317, 771]);
+
+    let (default_chunks, _) = cdc_chunker::chunk_bytes_cdc(&data, 256, 1024, 4096);
+    assert_ne!(chunks, default_chunks);
+}
+
+/// The public table and step reproduce the hash every boundary was cut at.
+#[test]
+fn gear_hash_step_reproduces_boundaries() {
+    let table = cdc_chunker::make_gear_table();
+    assert_eq!(
+        table,
+        cdc_chunker::make_gear_table_seeded(DEFAULT_GEAR_SEED)
+    );
+    assert_eq!(table[..4], [1967346228, 3442512730, 635166490, 1264377456]);
+
+    let data = golden_input();
+    let (chunks, _) = cdc_chunker::chunk_bytes_cdc(&data, 256, 1024, 4096);
+    // An average of 1024 bytes cuts where the low 10 bits are zero. The last chunk is
+    // the tail and chunks of the maximum size are forced cuts.
+    for chunk in &chunks[..chunks.len() - 1] {
+        if chunk.len() == 4096 {
+            continue;
+        }
+        let hash = chunk.iter().fold(0, |rolling, &byte| {
+            cdc_chunker::gear_hash_step(rolling, byte, &table)
+        });
+        assert_eq!(hash & 1023, 0, "chunk of {} bytes", chunk.len());
+    }
+}
+
+#[test]
+fn raw_chunk_ids_match_hex_ids() {
+    let data = golden_input();
+    let (chunks, _) = cdc_chunker::chunk_bytes_cdc(&data, 256, 1024, 4096);
+    for chunk in chunks.iter().map(Vec::as_slice).chain([&b""[..]]) {
+        let hex: String = cdc_chunker::chunk_id_hash_bytes(chunk)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(hex, cdc_chunker::chunk_id_hash(chunk).to_string());
+    }
+}
@@ -0,0 +1,128 @@
+//! Hard links: read once on backup, recreated as links on restore.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use common::{SETTINGS, TreeItem, make_tree};
+use rbckp::{
+    backup::{
+        restore::{self, HardLinks},
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+    },
+    config::Settings,
+};
+
+fn open_session(dir: &Path) -> BackupSession<LocalFsBackend> {
+    let repo = dir.join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let settings_path = dir.join("settings.ini");
+    fs::write(&settings_path, SETTINGS).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    BackupSession::new(Settings::from_path(&settings_path).unwrap(), store)
+}
+
+/// `tree/a` and `tree/b` are links to one file, `tree/c` is a separate copy of it.
+fn make_links(dir: &Path) -> Vec<PathBuf> {
+    let content: Vec<u8> = (0..50_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let root = make_tree(
+        &dir.join("tree"),
+        &[
+            TreeItem::File("a", &content),
+            TreeItem::HardLink("b", "a"),
+            TreeItem::File("c", &content),
+        ],
+    );
+    ["a", "b", "c"].iter().map(|name| root.join(name)).collect()
+}
+
+#[test]
+fn links_are_chunked_once_and_grouped() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut session = open_session(dir.path());
+    let files = make_links(dir.path());
+
+    session.add_file(&files[0]).unwrap();
+    let after_first = *session.stats();
+    session.add_file(&files[1]).unwrap();
+    let after_second = *session.stats();
+    // The second link is not read, so nothing about it is new.
+    assert_eq!(after_second.new_chunks, after_first.new_chunks);
+    assert_eq!(after_second.files, 2);
+    assert_eq!(after_second.hard_links, 1);
+    session.add_file(&files[2]).unwrap();
+
+    let manifest = session.finish().unwrap();
+    let [a, b, c] = &manifest.entries[..] else {
+        panic!("expected three entries");
+    };
+    assert!(a.link_group.is_some());
+    assert_eq!(a.link_group, b.link_group);
+    assert_eq!(a.chunks, b.chunks);
+    assert_eq!(b.size, 50_000);
+    assert_eq!(c.link_group, None);
+}
+
+#[test]
+fn links_to_resumed_entries_join_their_group() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut session = open_session(dir.path());
+    let files = make_links(dir.path());
+    let first = session.add_file(&files[0]).unwrap().clone();
+    session.finish().unwrap();
+
+    // A second run picking up the entry of the first, as `backup --resume` does.
+    let repo = dir.path().join("repo");
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let settings = Settings::from_path(&dir.path().join("settings.ini")).unwrap();
+    let mut session = BackupSession::new(settings, store);
+    session.add_entry(first.clone()).unwrap();
+    let link = session.add_file(&files[1]).unwrap().clone();
+    assert_eq!(link.link_group, first.link_group);
+    assert_eq!(link.chunks, first.chunks);
+    assert_eq!(session.stats().hard_links, 1);
+
+    let copy = session.add_file(&files[2]).unwrap();
+    assert_eq!(copy.link_group, None);
+}
+
+#[test]
+fn links_are_restored_as_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut session = open_session(dir.path());
+    for file in make_links(dir.path()) {
+        session.add_file(&file).unwrap();
+    }
+    let manifest = session.finish().unwrap();
+    let repo = dir.path().join("repo");
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+
+    let out = dir.path().join("out");
+    let mut hard_links = HardLinks::new();
+    let mut restored = Vec::new();
+    for entry in &manifest.entries {
+        let path = restore::restore_path(&out, &entry.name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        hard_links.restore_file(entry, &store, &path).unwrap();
+        restored.push(path);
+    }
+
+    let metadata: Vec<_> = restored
+        .iter()
+        .map(|path| fs::metadata(path).unwrap())
+        .collect();
+    assert_eq!(metadata[0].ino(), metadata[1].ino());
+    assert_eq!(metadata[0].nlink(), 2);
+    assert_ne!(metadata[0].ino(), metadata[2].ino());
+    assert_eq!(
+        fs::read(&restored[1]).unwrap(),
+        fs::read(&restored[2]).unwrap()
+    );
+}
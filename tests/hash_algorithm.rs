@@ -0,0 +1,132 @@
+//! `hash_algorithm = "xxhash3"` / `"siphash"`: chunk ids from xxHash3 or keyed SipHash
+//! instead of BLAKE3, fixed per repository at `init`.
+
+mod common;
+
+use std::fs;
+
+use common::{rbckp, settings_with};
+use rbckp::backup::{
+    cdc_chunker::{self, CdcParams},
+    hash::{ChunkHasher, ChunkId, HashAlgorithm, SipHasher13},
+    store::{LocalFsBackend, repo_config::RepoConfig},
+};
+
+#[test]
+fn xxhash3_changes_ids_but_not_boundaries() {
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i * 31 % 241) as u8).collect();
+    let params = CdcParams::new(1024, 4096, 16384);
+    let blake3 = cdc_chunker::chunk_refs_cdc(&data, &params);
+    let xxhash3 =
+        cdc_chunker::chunk_refs_cdc(&data, &params.with_hash_algorithm(HashAlgorithm::XxHash3));
+
+    assert_eq!(blake3.len(), xxhash3.len());
+    for (blake3, xxhash3) in blake3.iter().zip(&xxhash3) {
+        assert_eq!((blake3.offset, blake3.len), (xxhash3.offset, xxhash3.len));
+        assert_ne!(blake3.hash, xxhash3.hash);
+        let chunk = &data[xxhash3.offset..xxhash3.offset + xxhash3.len];
+        let id: ChunkId = xxhash3.hash.parse().unwrap();
+        assert_eq!(id, HashAlgorithm::XxHash3.hasher().hash(chunk));
+        assert_eq!(id.as_bytes()[16..], [0; 16]);
+    }
+}
+
+#[test]
+fn repository_keeps_the_algorithm_it_was_created_with() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = |algorithm: &str| {
+        fs::write(
+            dir.path().join("settings.ini"),
+            settings_with(&format!("hash_algorithm={}\n", algorithm)),
+        )
+        .unwrap()
+    };
+    settings("xxhash3");
+    fs::create_dir(dir.path().join("data")).unwrap();
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(dir.path().join("data/file.bin"), &content).unwrap();
+
+    rbckp(dir.path(), &["init", "repo"]);
+    let config = RepoConfig::load(&LocalFsBackend::new(&dir.path().join("repo"))).unwrap();
+    assert_eq!(config.hash_algorithm, HashAlgorithm::XxHash3);
+
+    // Backups use the repository's algorithm whatever the settings say now.
+    settings("blake3");
+    let backup = rbckp(dir.path(), &["backup", "--repo", "repo", "data"]);
+    assert!(String::from_utf8_lossy(&backup.stderr).contains("xxhash3"));
+    rbckp(
+        dir.path(),
+        &["rebuild-index", "--repo", "repo", "--read-data"],
+    );
+
+    let list = rbckp(dir.path(), &["list-snapshots", "--repo", "repo"]);
+    let id = String::from_utf8(list.stdout).unwrap()[..12].to_string();
+    rbckp(
+        dir.path(),
+        &["restore", "--repo", "repo", "--target", "out", &id],
+    );
+    assert_eq!(
+        fs::read(dir.path().join("out/data/file.bin")).unwrap(),
+        content
+    );
+}
+
+#[test]
+fn siphash_keys_change_ids() {
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i * 31 % 241) as u8).collect();
+    let params = CdcParams::new(1024, 4096, 16384).with_hash_algorithm(HashAlgorithm::SipHash);
+    let first = cdc_chunker::chunk_refs_cdc(&data, &params.with_hash_key(Some([1; 16])));
+    let second = cdc_chunker::chunk_refs_cdc(&data, &params.with_hash_key(Some([2; 16])));
+    let again = cdc_chunker::chunk_refs_cdc_parallel(&data, &params.with_hash_key(Some([1; 16])));
+
+    assert_eq!(first, again);
+    assert_eq!(first.len(), second.len());
+    for (first, second) in first.iter().zip(&second) {
+        assert_eq!((first.offset, first.len), (second.offset, second.len));
+        assert_ne!(first.hash, second.hash);
+        let chunk = &data[first.offset..first.offset + first.len];
+        let id: ChunkId = first.hash.parse().unwrap();
+        assert_eq!(id, SipHasher13 { key: [1; 16] }.hash(chunk));
+        assert_eq!(id.as_bytes()[16..], [0; 16]);
+    }
+}
+
+#[test]
+fn siphash_repository_keeps_its_key() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("settings.ini"),
+        settings_with("hash_algorithm=siphash\n"),
+    )
+    .unwrap();
+    fs::create_dir(dir.path().join("data")).unwrap();
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(dir.path().join("data/file.bin"), &content).unwrap();
+
+    rbckp(dir.path(), &["init", "repo"]);
+    rbckp(dir.path(), &["init", "other"]);
+    let key = |repo: &str| {
+        let config = RepoConfig::load(&LocalFsBackend::new(&dir.path().join(repo))).unwrap();
+        assert_eq!(config.hash_algorithm, HashAlgorithm::SipHash);
+        config.hash_key.unwrap()
+    };
+    assert_ne!(key("repo"), key("other"));
+
+    // Chunks are stored under ids with the repository's key, so re-hashing them all
+    // finds nothing wrong.
+    rbckp(dir.path(), &["backup", "--repo", "repo", "data"]);
+    rbckp(
+        dir.path(),
+        &["rebuild-index", "--repo", "repo", "--read-data"],
+    );
+    let list = rbckp(dir.path(), &["list-snapshots", "--repo", "repo"]);
+    let id = String::from_utf8(list.stdout).unwrap()[..12].to_string();
+    rbckp(
+        dir.path(),
+        &["restore", "--repo", "repo", "--target", "out", &id],
+    );
+    assert_eq!(
+        fs::read(dir.path().join("out/data/file.bin")).unwrap(),
+        content
+    );
+}
@@ -0,0 +1,63 @@
+//! `ChunkStore::with_length_check`: a stored chunk with the same hash but another
+//! length is reported as a collision instead of being deduplicated.
+
+use std::path::Path;
+
+use rbckp::backup::{
+    hash::ChunkId,
+    store::{ChunkStore, LocalFsBackend, StoreError, lock::LockKind},
+};
+
+/// A hasher so bad that every chunk collides.
+fn stub_hash(_chunk: &[u8]) -> String {
+    ChunkId::of(b"every chunk").to_string()
+}
+
+fn open_store(repo: &Path) -> ChunkStore<LocalFsBackend> {
+    ChunkStore::open(LocalFsBackend::new(repo), 1 << 20, LockKind::Shared).unwrap()
+}
+
+#[test]
+fn same_hash_with_another_length_is_a_collision() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    ChunkStore::init(&LocalFsBackend::new(repo)).unwrap();
+    let first = b"the first chunk".as_slice();
+    let longer = b"a longer, different chunk".as_slice();
+    let hash = stub_hash(first);
+    assert_eq!(stub_hash(longer), hash);
+
+    let mut store = open_store(repo).with_length_check(true);
+    assert!(store.put(&stub_hash(first), first).unwrap());
+    // Still in the open pack.
+    match store.put(&stub_hash(longer), longer) {
+        Err(StoreError::HashCollision {
+            hash: collided,
+            stored_len,
+            len,
+        }) => {
+            assert_eq!(collided, hash);
+            assert_eq!((stored_len, len), (first.len() as u64, longer.len() as u64));
+        }
+        other => panic!("expected a hash collision, got {:?}", other),
+    }
+    store.flush().unwrap();
+
+    // Once in the index, after reopening.
+    let mut store = open_store(repo).with_length_check(true);
+    let err = store.put(&stub_hash(longer), longer).unwrap_err();
+    assert!(matches!(err, StoreError::HashCollision { .. }));
+    assert!(err.to_string().contains("hash collision"));
+    assert!(matches!(
+        store.check_length(&hash, longer.len() as u64),
+        Err(StoreError::HashCollision { .. })
+    ));
+    // A chunk of the same length cannot be told apart, and is deduplicated.
+    assert!(!store.put(&hash, b"same length too").unwrap());
+    assert_eq!(store.get(&hash).unwrap(), first);
+
+    // Without the check, the hash alone decides.
+    let mut store = open_store(repo);
+    assert!(!store.put(&stub_hash(longer), longer).unwrap());
+    assert!(store.check_length(&hash, longer.len() as u64).is_ok());
+}
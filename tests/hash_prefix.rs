@@ -0,0 +1,171 @@
+//! `hash_prefix_len`: chunk ids cut to a prefix as keys, with collisions detected
+//! rather than merged.
+
+mod common;
+
+use std::{collections::HashSet, fs, process::Command};
+
+use common::{noise, settings_with};
+use rbckp::{
+    backup::{
+        cdc_chunker::{self, CdcParams},
+        hash::{CHUNK_ID_HEX_LEN, ChunkId, PrefixKeys},
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, StoreError, lock::LockKind},
+    },
+    config::Settings,
+};
+
+#[test]
+fn short_prefixes_tell_small_data_apart() {
+    let block = noise(500_000, 41);
+    let data = [&block[..], &block[..]].concat();
+    let chunks = cdc_chunker::chunk_refs_cdc(&data, &CdcParams::new(1024, 4096, 16384));
+    let unique: HashSet<&str> = chunks.iter().map(|chunk| chunk.hash.as_str()).collect();
+
+    let mut keys = PrefixKeys::new(8);
+    let mut distinct = HashSet::new();
+    for chunk in &chunks {
+        let key = keys.key(&chunk.hash).unwrap();
+        assert_eq!(key, chunk.hash[..8]);
+        distinct.insert(key);
+    }
+    assert_eq!(distinct.len(), unique.len());
+    assert_eq!(keys.tracked(), unique.len());
+
+    // Whole ids stay whole; zero chunk ids are not cut.
+    let mut whole = PrefixKeys::new(CHUNK_ID_HEX_LEN);
+    for chunk in &chunks {
+        assert_eq!(whole.key(&chunk.hash).unwrap(), chunk.hash);
+    }
+    // Whole ids cannot collide, so none are kept for the check.
+    assert_eq!(whole.tracked(), 0);
+    assert_eq!(keys.key("zero:65536").unwrap(), "zero:65536");
+}
+
+#[test]
+fn shared_prefixes_are_collisions() {
+    // Far more distinct chunks than one hex digit has values.
+    let data = noise(200_000, 42);
+    let chunks = cdc_chunker::chunk_refs_cdc(&data, &CdcParams::new(1024, 4096, 16384));
+    assert!(chunks.len() > 16);
+
+    let mut keys = PrefixKeys::new(1);
+    let collision = chunks
+        .iter()
+        .find_map(|chunk| keys.key(&chunk.hash).err())
+        .expect("17 chunks cannot have distinct one-digit prefixes");
+    assert_ne!(collision.first, collision.second);
+    assert!(collision.first.starts_with(&collision.prefix));
+    assert!(collision.second.starts_with(&collision.prefix));
+    assert!(collision.to_string().contains("hash_prefix_len"));
+}
+
+#[test]
+#[should_panic(expected = "hash prefix length")]
+fn empty_prefixes_are_rejected() {
+    PrefixKeys::new(0);
+}
+
+#[test]
+fn setting_applies_to_the_chunking_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let block = noise(100_000, 43);
+    fs::write(
+        dir.path().join("data.bin"),
+        [&block[..], &block[..]].concat(),
+    )
+    .unwrap();
+    let run = |prefix_len: &str| {
+        fs::write(
+            dir.path().join("settings.ini"),
+            settings_with(&format!("hash_prefix_len={}\n", prefix_len)),
+        )
+        .unwrap();
+        let _ = fs::remove_file(dir.path().join("output.txt"));
+        Command::new(env!("CARGO_BIN_EXE_rbckp"))
+            .current_dir(dir.path())
+            .args(["-F", "data.bin", "--json"])
+            .args(["--config", "settings.ini"])
+            .output()
+            .unwrap()
+    };
+    let unique = |prefix_len: &str| {
+        let output = run(prefix_len);
+        assert!(output.status.success(), "{:?}", output);
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()["unique_chunks"]
+            .as_u64()
+            .unwrap()
+    };
+
+    assert_eq!(unique("10"), unique("64"));
+    let output = run("1");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("share the id prefix")
+    );
+    assert!(!run("0").status.success());
+    assert!(!run("65").status.success());
+}
+
+#[test]
+fn backups_look_chunks_up_by_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let path = dir.path().join("settings.ini");
+    fs::write(&path, settings_with("hash_prefix_len=10\n")).unwrap();
+    let file = dir.path().join("data.bin");
+    fs::write(&file, noise(200_000, 44)).unwrap();
+
+    let back_up = || {
+        let store =
+            ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+        let mut session = BackupSession::new(Settings::from_path(&path).unwrap(), store);
+        session.add_file(&file).unwrap();
+        let stats = *session.stats();
+        session.commit(vec![], &[]).unwrap();
+        stats
+    };
+    let first = back_up();
+    assert!(first.new_chunks > 0);
+    // The second run finds every chunk under its prefix.
+    assert_eq!(back_up().new_chunks, 0);
+}
+
+#[test]
+fn store_refuses_a_chunk_whose_prefix_is_taken() {
+    let dir = tempfile::tempdir().unwrap();
+    ChunkStore::init(&LocalFsBackend::new(dir.path())).unwrap();
+    let open = || ChunkStore::open(LocalFsBackend::new(dir.path()), 1 << 20, LockKind::Shared);
+
+    // More distinct chunks than one hex digit has values.
+    let chunks: Vec<(String, Vec<u8>)> = (0..17u8)
+        .map(|i| (ChunkId::of(&[i]).to_string(), vec![i]))
+        .collect();
+    let mut store = open().unwrap().with_hash_prefix_len(1);
+    let (hash, data) = chunks
+        .iter()
+        .find(|(hash, data)| store.put(hash, data).is_err())
+        .expect("17 chunks cannot have distinct one-digit prefixes");
+    assert!(matches!(
+        store.put(hash, data),
+        Err(StoreError::PrefixCollision(collision)) if collision.second == *hash
+    ));
+    assert!(!store.contains(hash));
+    store.flush().unwrap();
+    drop(store);
+
+    // Prefixes of stored chunks are taken in the next run too; whole ids tell the
+    // chunks apart.
+    let mut store = open().unwrap().with_hash_prefix_len(1);
+    assert!(matches!(
+        store.put(hash, data),
+        Err(StoreError::PrefixCollision(_))
+    ));
+    let mut store = open().unwrap();
+    assert!(store.put(hash, data).unwrap());
+}
@@ -0,0 +1,138 @@
+//! `rbckp import`: a tar archive becomes the same snapshot as a backup of its tree.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    os::unix::fs::symlink,
+    process::Output,
+};
+
+use common::{SETTINGS, rbckp, rbckp_with_input, snapshot_id};
+use rbckp::{
+    backup::{
+        import,
+        manifest::{Manifest, ManifestEntry},
+        session::BackupSession,
+        snapshot::Snapshot,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+    },
+    config::Settings,
+};
+use tar::{Builder, EntryType, Header};
+
+/// `manifest` in name order, with modification times cut to whole seconds (all a tar
+/// header holds).
+fn normalized(manifest: &Manifest) -> (Vec<ManifestEntry>, Vec<ManifestEntry>) {
+    let normalize = |entries: &[ManifestEntry]| {
+        let mut entries: Vec<ManifestEntry> = entries
+            .iter()
+            .cloned()
+            .map(|mut entry| {
+                entry.mtime = entry
+                    .mtime
+                    .map(|mtime| mtime.replace_nanosecond(0).unwrap());
+                entry
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    };
+    (
+        normalize(&manifest.entries),
+        normalize(&manifest.directories),
+    )
+}
+
+#[test]
+fn import_matches_direct_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let data = dir.path().join("data");
+    fs::create_dir_all(data.join("sub/deeper")).unwrap();
+    let big: Vec<u8> = (0..400_000u32).map(|i| (i * 7 % 253) as u8).collect();
+    fs::write(data.join("sub/big.bin"), big).unwrap();
+    fs::write(data.join("sub/deeper/note.txt"), "note").unwrap();
+    fs::write(data.join("copy.txt"), "note").unwrap();
+    fs::write(data.join("empty"), "").unwrap();
+    symlink("sub/big.bin", data.join("shortcut")).unwrap();
+
+    rbckp(dir.path(), &["init", "repo"]);
+    let backup = rbckp(dir.path(), &["backup", "--repo", "repo", "data"]);
+
+    let archive = dir.path().join("data.tar");
+    let mut builder = Builder::new(File::create(&archive).unwrap());
+    builder.follow_symlinks(false);
+    builder.append_dir_all("data", &data).unwrap();
+    builder.into_inner().unwrap().sync_all().unwrap();
+    let import = rbckp_with_input(
+        dir.path(),
+        &["import", "--repo", "repo"],
+        &fs::read(&archive).unwrap(),
+    );
+
+    let backend = LocalFsBackend::new(&dir.path().join("repo"));
+    let load = |output: &Output| {
+        let id = Snapshot::resolve_id(&backend, &snapshot_id(output)).unwrap();
+        Snapshot::load(&backend, &id).unwrap()
+    };
+    let (backed_up, imported) = (load(&backup), load(&import));
+    assert_eq!(imported.paths, backed_up.paths);
+    assert_eq!(
+        normalized(&imported.manifest),
+        normalized(&backed_up.manifest)
+    );
+    // Every chunk was already stored by the backup.
+    assert!(
+        String::from_utf8_lossy(&import.stdout).contains(" 0 of "),
+        "{}",
+        String::from_utf8_lossy(&import.stdout)
+    );
+}
+
+#[test]
+fn skips_special_files_and_links_hard_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut builder = Builder::new(Vec::new());
+    let mut append = |name: &str, entry_type: EntryType, content: &[u8], link: Option<&str>| {
+        let mut header = Header::new_ustar();
+        header.set_entry_type(entry_type);
+        header.set_size(content.len() as u64);
+        header.set_mode(0o640);
+        header.set_mtime(1_700_000_000);
+        match link {
+            Some(target) => builder.append_link(&mut header, name, target).unwrap(),
+            None => builder.append_data(&mut header, name, content).unwrap(),
+        }
+    };
+    append("./top/", EntryType::Directory, b"", None);
+    append("./top/file", EntryType::Regular, b"content", None);
+    append("./top/fifo", EntryType::Fifo, b"", None);
+    append("./top/link", EntryType::Link, b"", Some("top/file"));
+    append("./top/dangling", EntryType::Link, b"", Some("top/missing"));
+    let archive = builder.into_inner().unwrap();
+
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let settings = Settings::from_path(&dir.path().join("settings.ini")).unwrap();
+    let mut session = BackupSession::new(settings, store);
+    let stats = import::import_tar(&mut session, archive.as_slice()).unwrap();
+    let manifest = session.finish().unwrap();
+
+    assert_eq!(stats.paths, ["top"]);
+    assert_eq!((stats.entries, stats.directories, stats.skipped), (2, 1, 2));
+    let names: Vec<&str> = manifest.entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["top/file", "top/link"]);
+    assert_eq!(manifest.directories[0].name, "top");
+    assert_eq!(manifest.directories[0].mode, Some(0o640));
+
+    let (file, link) = (&manifest.entries[0], &manifest.entries[1]);
+    assert!(file.link_group.is_some());
+    assert_eq!(file.link_group, link.link_group);
+    assert_eq!(file.chunks, link.chunks);
+    assert_eq!(file.size, 7);
+}
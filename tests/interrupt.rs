@@ -0,0 +1,199 @@
+//! Interrupted backups: a cancelled session stops before its next chunk, keeps what it
+//! stored, and `rbckp backup --resume` continues from the journal.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::{
+        cancel::CancelToken,
+        journal::{BackupJournal, JOURNAL_NAME},
+        session::BackupSession,
+        snapshot::Snapshot,
+        store::{ChunkStore, LocalFsBackend, StoreError, lock::LockKind, repo_config::RepoConfig},
+    },
+    config::Settings,
+};
+
+fn session(dir: &Path, repo_name: &str) -> BackupSession<LocalFsBackend> {
+    let repo = dir.join(repo_name);
+    if !repo.exists() {
+        fs::create_dir(&repo).unwrap();
+        ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    }
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let settings = Settings::from_path(&dir.join("settings.ini")).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    BackupSession::new(settings, store)
+}
+
+/// Chunk ids of `data` as a session of the test settings cuts them.
+fn chunk_ids(data: &[u8], dir: &Path) -> Vec<String> {
+    let mut scratch = session(dir, "scratch");
+    scratch.add_bytes("x", data).unwrap().chunks.clone()
+}
+
+#[test]
+fn resumed_backup_matches_an_uninterrupted_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b) = (noise(300_000, 1), noise(600_000, 2));
+
+    // Cancelled after 40 chunks of `b`, as a signal handler would.
+    let cancel = CancelToken::new();
+    let mut first = session(dir.path(), "repo").with_cancel(cancel.clone());
+    first.add_bytes("a", &a).unwrap();
+    let mut chunks = 0;
+    let err = first
+        .add_reader("b", &b[..], |_| {
+            chunks += 1;
+            if chunks == 40 {
+                cancel.cancel();
+            }
+        })
+        .unwrap_err();
+    assert!(matches!(err, StoreError::Interrupted));
+    assert_eq!(first.stats().files, 1);
+    let stored_before = first.stats().new_chunks;
+    first.checkpoint().unwrap();
+    drop(first);
+
+    // The resumed run stores only the chunks that did not land before.
+    let mut second = session(dir.path(), "repo");
+    for hash in chunk_ids(&a, dir.path()) {
+        assert!(second.store().contains(&hash));
+    }
+    second.add_bytes("a", &a).unwrap();
+    second.add_bytes("b", &b).unwrap();
+    let resumed_new = second.stats().new_chunks;
+    let (_, resumed) = second.commit(vec!["a".into(), "b".into()], &[]).unwrap();
+
+    let mut fresh = session(dir.path(), "fresh");
+    fresh.add_bytes("a", &a).unwrap();
+    fresh.add_bytes("b", &b).unwrap();
+    let fresh_new = fresh.stats().new_chunks;
+    let (_, expected) = fresh.commit(vec!["a".into(), "b".into()], &[]).unwrap();
+
+    assert_eq!(resumed.manifest, expected.manifest);
+    assert_eq!(stored_before + resumed_new, fresh_new);
+    assert!(stored_before > 40);
+}
+
+/// `rbckp backup data --repo repo` in `dir` with `extra` arguments, keeping its cache
+/// (and so its journal) in `dir/cache`.
+fn backup(dir: &Path, extra: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir)
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .args(["backup", "data", "--repo", "repo"])
+        .args(extra)
+        .args(["--config", "settings.ini"])
+        .output()
+        .unwrap()
+}
+
+/// Set up `data/a` and `data/b` and the journal of an interrupted backup of `data`
+/// that finished `data/a`, in the local cache of the repository, where `rbckp backup`
+/// keeps it. Returns the finished entry and the journal path.
+fn interrupted_backup(dir: &Path) -> (rbckp::backup::manifest::ManifestEntry, PathBuf) {
+    fs::create_dir(dir.join("data")).unwrap();
+    let (a, b) = (noise(100_000, 3), noise(100_000, 4));
+    fs::write(dir.join("data/a"), &a).unwrap();
+    fs::write(dir.join("data/b"), &b).unwrap();
+
+    let mut interrupted = session(dir, "repo");
+    let finished = interrupted.add_bytes("data/a", &a).unwrap().clone();
+    interrupted.checkpoint().unwrap();
+    drop(interrupted);
+
+    let id = RepoConfig::load(&LocalFsBackend::new(&dir.join("repo")))
+        .unwrap()
+        .id;
+    let journal_path = dir.join("cache/rbckp").join(id).join(JOURNAL_NAME);
+    let mut journal = BackupJournal::create(&journal_path, &["data".to_string()]).unwrap();
+    journal.add_file(&finished).unwrap();
+    drop(journal);
+    (finished, journal_path)
+}
+
+fn last_snapshot(dir: &Path) -> Snapshot {
+    let backend = LocalFsBackend::new(&dir.join("repo"));
+    let ids = Snapshot::list(&backend).unwrap();
+    Snapshot::load(&backend, ids.last().unwrap()).unwrap()
+}
+
+#[test]
+fn resume_flag_continues_from_the_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let (finished, journal_path) = interrupted_backup(dir.path());
+
+    let output = backup(dir.path(), &["--resume"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("Resuming")
+    );
+    assert!(!journal_path.exists());
+    assert!(!dir.path().join("repo").join(JOURNAL_NAME).exists());
+
+    let snapshot = last_snapshot(dir.path());
+    let names: Vec<&str> = snapshot
+        .manifest
+        .entries
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    assert_eq!(names, ["data/a", "data/b"]);
+    // Taken over from the journal rather than read again, which would record its
+    // modification time.
+    assert_eq!(snapshot.manifest.entries[0], finished);
+    assert!(snapshot.manifest.entries[1].mtime.is_some());
+
+    // Nothing to resume is fine.
+    let output = backup(dir.path(), &["--resume", "--quiet"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn backups_without_a_terminal_resume_without_asking() {
+    let dir = tempfile::tempdir().unwrap();
+    let (finished, journal_path) = interrupted_backup(dir.path());
+
+    // stdin is not a terminal here, as under cron: no question, and no waiting for
+    // an answer.
+    let output = backup(dir.path(), &[]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Resuming"), "{}", stdout);
+    assert!(!stdout.contains("[Y/n]"), "{}", stdout);
+    assert!(!journal_path.exists());
+    assert_eq!(last_snapshot(dir.path()).manifest.entries[0], finished);
+}
+
+#[test]
+fn no_resume_starts_over() {
+    let dir = tempfile::tempdir().unwrap();
+    let (finished, journal_path) = interrupted_backup(dir.path());
+
+    let output = backup(dir.path(), &["--no-resume"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        !String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("Resuming")
+    );
+    assert!(!journal_path.exists());
+    // Read again, so this time with its modification time.
+    let first = &last_snapshot(dir.path()).manifest.entries[0];
+    assert_eq!(first.chunks, finished.chunks);
+    assert!(first.mtime.is_some());
+
+    let output = backup(dir.path(), &["--no-resume", "--resume"]);
+    assert!(!output.status.success());
+}
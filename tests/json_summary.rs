@@ -0,0 +1,51 @@
+//! `rbckp -F <file> --json`: the run summary as the only thing on stdout.
+
+mod common;
+
+use std::{fs, process::Command};
+
+use common::{SETTINGS, noise};
+
+#[test]
+fn json_summary_is_all_of_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    // The same block twice, so most chunks repeat.
+    let block = noise(200_000, 11);
+    fs::write(
+        dir.path().join("data.bin"),
+        [&block[..], &block[..]].concat(),
+    )
+    .unwrap();
+
+    // Debug logging goes to stderr and must not end up in the JSON.
+    let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir.path())
+        .args(["-F", "data.bin", "--json", "--verbose"])
+        .args(["--config", "settings.ini"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(summary["file"], "data.bin");
+    assert_eq!(summary["total_bytes"], 400_000);
+    assert_eq!(
+        summary["params"],
+        serde_json::json!({"min": 1024, "avg": 4096, "max": 16384})
+    );
+    let chunks = summary["chunks"].as_u64().unwrap();
+    let unique = summary["unique_chunks"].as_u64().unwrap();
+    assert!(unique < chunks, "{} unique of {} chunks", unique, chunks);
+    let ratio = summary["dedup_ratio"].as_f64().unwrap();
+    assert!(ratio > 1.5 && ratio <= 2.0, "dedup ratio {}", ratio);
+    assert!(summary["chunk_sizes"]["max"].as_u64().unwrap() <= 16384);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir.path())
+        .args(["-F", "data.bin", "--json", "--debug-boundaries"])
+        .args(["--config", "settings.ini"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
@@ -0,0 +1,242 @@
+//! Shared and exclusive repository locks, taken from several threads against a
+//! temporary local repository.
+
+mod common;
+
+use std::{
+    fs,
+    sync::{Arc, Barrier, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::{
+        restore,
+        session::BackupSession,
+        store::{
+            ChunkStore, LocalFsBackend, StoreError,
+            index::ChunkIndex,
+            lock::{LOCK_RECORDS_DIR, LockKind, RepoLock, RepoLocks},
+        },
+    },
+    config::Settings,
+};
+
+fn temp_repo() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    ChunkStore::init(&LocalFsBackend::new(dir.path())).unwrap();
+    dir
+}
+
+#[test]
+fn shared_locks_coexist() {
+    let repo = temp_repo();
+    let root = repo.path().to_path_buf();
+
+    let first = RepoLock::acquire(&root, LockKind::Shared, Duration::ZERO).unwrap();
+    let second = thread::spawn(move || {
+        RepoLock::acquire(&root, LockKind::Shared, Duration::ZERO).map(|_| ())
+    })
+    .join()
+    .unwrap();
+
+    assert!(second.is_ok());
+    drop(first);
+}
+
+#[test]
+fn exclusive_lock_conflicts_with_shared_lock() {
+    let repo = temp_repo();
+    let root = repo.path().to_path_buf();
+
+    let _shared = RepoLock::acquire(&root, LockKind::Shared, Duration::ZERO).unwrap();
+    let result = thread::spawn(move || {
+        RepoLock::acquire(&root, LockKind::Exclusive, Duration::from_millis(200)).map(|_| ())
+    })
+    .join()
+    .unwrap();
+
+    match result {
+        Err(StoreError::Locked {
+            holder: Some(holder),
+        }) => {
+            assert_eq!(holder.pid, std::process::id());
+            assert_eq!(holder.kind, LockKind::Shared);
+            assert!(
+                StoreError::Locked {
+                    holder: Some(holder)
+                }
+                .to_string()
+                .contains("on host")
+            );
+        }
+        other => panic!("expected a lock conflict, got {:?}", other),
+    }
+}
+
+#[test]
+fn shared_lock_conflicts_with_exclusive_lock() {
+    let repo = temp_repo();
+    let root = repo.path().to_path_buf();
+
+    let _exclusive = RepoLock::acquire(&root, LockKind::Exclusive, Duration::ZERO).unwrap();
+    let result = thread::spawn(move || {
+        RepoLock::acquire(&root, LockKind::Shared, Duration::ZERO).map(|_| ())
+    })
+    .join()
+    .unwrap();
+
+    assert!(matches!(
+        result,
+        Err(StoreError::Locked { holder: Some(ref holder) }) if holder.kind == LockKind::Exclusive
+    ));
+}
+
+#[test]
+fn waiting_lock_is_granted_once_released() {
+    let repo = temp_repo();
+    let root = repo.path().to_path_buf();
+
+    let exclusive = RepoLock::acquire(&root, LockKind::Exclusive, Duration::ZERO).unwrap();
+    let (started, wait_started) = mpsc::channel();
+    let waiter = thread::spawn(move || {
+        started.send(()).unwrap();
+        let start = Instant::now();
+        RepoLock::acquire(&root, LockKind::Shared, Duration::from_secs(10)).map(|_| start.elapsed())
+    });
+
+    wait_started.recv().unwrap();
+    thread::sleep(Duration::from_millis(300));
+    drop(exclusive);
+
+    let waited = waiter.join().unwrap().unwrap();
+    assert!(
+        waited >= Duration::from_millis(200),
+        "waited only {:?}",
+        waited
+    );
+}
+
+#[test]
+fn holder_records_follow_the_lock() {
+    let repo = temp_repo();
+    let root = repo.path();
+
+    let first = RepoLock::acquire(root, LockKind::Shared, Duration::ZERO).unwrap();
+    let second = RepoLock::acquire(root, LockKind::Shared, Duration::ZERO).unwrap();
+    assert_eq!(RepoLock::holders(root).unwrap().len(), 2);
+
+    drop(first);
+    assert_eq!(RepoLock::holders(root).unwrap().len(), 1);
+    drop(second);
+    assert!(RepoLock::holders(root).unwrap().is_empty());
+}
+
+#[test]
+fn held_lock_survives_break_lock() {
+    let repo = temp_repo();
+    let root = repo.path();
+    let locks = RepoLocks::of(&LocalFsBackend::new(root));
+
+    let _shared = locks.acquire(LockKind::Shared, Duration::ZERO).unwrap();
+    match locks.break_lock() {
+        Err(StoreError::Locked {
+            holder: Some(holder),
+        }) => {
+            assert_eq!(holder.pid, std::process::id());
+        }
+        other => panic!("expected the lock to be held, got {:?}", other),
+    }
+
+    assert_eq!(locks.holders().unwrap().len(), 1);
+    assert!(matches!(
+        locks.acquire(LockKind::Exclusive, Duration::ZERO),
+        Err(StoreError::Locked { .. })
+    ));
+}
+
+#[test]
+fn break_lock_clears_records_left_by_crashes() {
+    let repo = temp_repo();
+    let root = repo.path();
+    let records = root.join(LOCK_RECORDS_DIR);
+
+    // Keep the record of a lock that is no longer held, as a crash would.
+    let lock = RepoLock::acquire(root, LockKind::Shared, Duration::ZERO).unwrap();
+    let record = fs::read_dir(&records)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let contents = fs::read(&record).unwrap();
+    drop(lock);
+    fs::write(&record, contents).unwrap();
+    assert_eq!(RepoLock::holders(root).unwrap().len(), 1);
+
+    RepoLock::break_lock(root).unwrap();
+    assert!(RepoLock::holders(root).unwrap().is_empty());
+    assert!(RepoLock::acquire(root, LockKind::Exclusive, Duration::ZERO).is_ok());
+}
+
+#[test]
+fn open_stores_share_the_lock() {
+    let repo = temp_repo();
+    let root = repo.path().to_path_buf();
+
+    let _store = ChunkStore::open(LocalFsBackend::new(&root), 1 << 20, LockKind::Shared).unwrap();
+    let holder = RepoLock::holders(&root).unwrap();
+    assert_eq!(holder.len(), 1);
+    assert_eq!(holder[0].kind, LockKind::Shared);
+
+    // A second store can be open at the same time.
+    assert!(ChunkStore::open(LocalFsBackend::new(&root), 1 << 20, LockKind::Shared).is_ok());
+}
+
+#[test]
+fn parallel_backups_keep_each_others_chunks() {
+    let repo = temp_repo();
+    let root = repo.path().to_path_buf();
+    let settings_dir = tempfile::tempdir().unwrap();
+    fs::write(settings_dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let settings = Settings::from_path(&settings_dir.path().join("settings.ini")).unwrap();
+
+    // Small packs, so the two sessions commit the index many times each while the
+    // other one is running.
+    let start = Arc::new(Barrier::new(2));
+    let sessions: Vec<_> = (0..2u64)
+        .map(|seed| {
+            let (root, settings, start) = (root.clone(), settings.clone(), Arc::clone(&start));
+            thread::spawn(move || {
+                let store =
+                    ChunkStore::open(LocalFsBackend::new(&root), 64 * 1024, LockKind::Shared)
+                        .unwrap();
+                let mut session = BackupSession::new(settings, store);
+                let data = noise(2_000_000, seed + 1);
+                start.wait();
+                session.add_bytes("data", &data).unwrap();
+                let (_, snapshot) = session.commit(vec![seed.to_string()], &[]).unwrap();
+                (data, snapshot.manifest)
+            })
+        })
+        .collect();
+    let sessions: Vec<_> = sessions
+        .into_iter()
+        .map(|session| session.join().unwrap())
+        .collect();
+
+    // The index as committed, without the repair an open would do.
+    let index = ChunkIndex::load(&LocalFsBackend::new(&root), "index.json").unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&root), 1 << 20, LockKind::Shared).unwrap();
+    for (data, manifest) in &sessions {
+        let entry = &manifest.entries[0];
+        for hash in &entry.chunks {
+            assert!(index.contains(hash), "chunk {} is not indexed", hash);
+        }
+        let mut restored = Vec::new();
+        restore::write_entry(entry, &store, &mut restored).unwrap();
+        assert_eq!(&restored, data);
+    }
+}
@@ -0,0 +1,205 @@
+//! `diff`, `diff_snapshots` and `rbckp diff`: added, removed and changed files between
+//! two manifests or snapshots.
+
+mod common;
+
+use std::fs;
+
+use common::{SETTINGS, back_up, rbckp, rbckp_stdout};
+use rbckp::backup::{
+    diff::{self, ChangedEntry},
+    manifest::{EntryKind, Manifest, ManifestEntry},
+    snapshot::Snapshot,
+};
+
+fn entry(name: &str, chunks: &[&str]) -> ManifestEntry {
+    ManifestEntry {
+        name: name.to_string(),
+        kind: EntryKind::File,
+        size: 100 * chunks.len() as u64,
+        chunks: chunks.iter().map(|hash| hash.to_string()).collect(),
+        content_hash: None,
+        mtime: None,
+        mode: None,
+        uid: None,
+        gid: None,
+        link_group: None,
+    }
+}
+
+fn manifest(entries: Vec<ManifestEntry>) -> Manifest {
+    Manifest {
+        entries,
+        directories: Vec::new(),
+    }
+}
+
+#[test]
+fn file_that_gained_a_chunk_is_the_only_change() {
+    let old = manifest(vec![
+        entry("docs/report.txt", &["a", "b"]),
+        entry("notes.txt", &["c"]),
+        entry("photo.jpg", &["d", "e", "f"]),
+    ]);
+    let mut gained = entry("docs/report.txt", &["a", "b", "g"]);
+    // Metadata alone is not a change.
+    gained.mode = Some(0o600);
+    let new = manifest(vec![
+        entry("photo.jpg", &["d", "e", "f"]),
+        gained,
+        entry("notes.txt", &["c"]),
+    ]);
+
+    let diff = diff::diff(&old, &new);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    assert_eq!(
+        diff.changed,
+        [ChangedEntry {
+            name: "docs/report.txt".to_string(),
+            chunks_added: 1,
+            chunks_removed: 0,
+            chunks_shared: 2,
+            old_size: 200,
+            new_size: 300,
+        }]
+    );
+    assert_eq!(diff.changed[0].chunk_delta(), 1);
+    assert_eq!(diff.unchanged, 2);
+    assert_eq!(
+        diff.to_string(),
+        "changed  docs/report.txt: +1 -0 chunks (2 shared), 200 -> 300 bytes\n\
+         0 added, 0 removed, 1 changed, 2 unchanged"
+    );
+}
+
+#[test]
+fn added_removed_and_rewritten_files() {
+    let old = manifest(vec![
+        entry("a", &["x", "x", "y"]),
+        entry("gone", &["z"]),
+        entry("same", &[]),
+    ]);
+    let mut link = entry("a-link", &[]);
+    link.kind = EntryKind::Symlink {
+        target: b"a".to_vec(),
+    };
+    let new = manifest(vec![
+        entry("a", &["x", "w", "w", "v"]),
+        entry("same", &[]),
+        entry("new", &["z"]),
+        link,
+    ]);
+
+    let diff = diff::diff(&old, &new);
+    assert_eq!(diff.added, ["a-link", "new"]);
+    assert_eq!(diff.removed, ["gone"]);
+    assert_eq!(diff.changed.len(), 1);
+    // One `x` is kept; the other and `y` are replaced by `w`, `w` and `v`.
+    assert_eq!(
+        (diff.changed[0].chunks_added, diff.changed[0].chunks_removed),
+        (3, 2)
+    );
+    assert_eq!(diff.changed[0].chunks_shared, 1);
+    assert_eq!(diff.changed[0].chunk_delta(), 1);
+    assert!(!diff.is_empty());
+    assert!(diff::diff(&new, &new).is_empty());
+
+    // A symlink pointing elsewhere has no chunks either way, but did change.
+    let mut retargeted = new.clone();
+    retargeted.entries[3].kind = EntryKind::Symlink {
+        target: b"same".to_vec(),
+    };
+    let diff = diff::diff(&new, &retargeted);
+    assert_eq!(diff.changed.len(), 1);
+    assert_eq!(diff.changed[0].name, "a-link");
+    assert_eq!(diff.changed[0].chunk_delta(), 0);
+}
+
+fn snapshot(entries: Vec<ManifestEntry>) -> Snapshot {
+    Snapshot::new(vec![".".to_string()], manifest(entries))
+}
+
+#[test]
+fn snapshots_are_matched_by_path_whatever_the_order() {
+    let old = snapshot(vec![
+        entry("/home/me/b.txt", &["b"]),
+        entry("/home/me/a.txt", &["a1", "a2"]),
+        entry("/home/me/gone.txt", &["g"]),
+    ]);
+    let new = snapshot(vec![
+        entry("home/me/new.txt", &["n", "a1"]),
+        entry("home/me/./a.txt", &["a1", "a3"]),
+        entry("home//me/b.txt", &["b"]),
+    ]);
+
+    let diff = diff::diff_snapshots(&old, &new);
+    assert_eq!(diff.files.added, ["home/me/new.txt"]);
+    assert_eq!(diff.files.removed, ["/home/me/gone.txt"]);
+    assert_eq!(
+        diff.files.changed,
+        [ChangedEntry {
+            name: "home/me/./a.txt".to_string(),
+            chunks_added: 1,
+            chunks_removed: 1,
+            chunks_shared: 1,
+            old_size: 200,
+            new_size: 200,
+        }]
+    );
+    assert_eq!(diff.files.unchanged, 1);
+    // `n`, `a1`, `a3` and `b`, of which `a1` and `b` were there before.
+    assert_eq!((diff.chunks, diff.shared_chunks), (4, 2));
+    assert!(diff.to_string().ends_with(
+        "1 added, 1 removed, 1 changed, 1 unchanged\n\
+         2 of 4 chunks shared with the older snapshot"
+    ));
+}
+
+#[test]
+fn rewritten_file_shares_no_chunks() {
+    let old = snapshot(vec![entry("log", &["x", "y"])]);
+    let new = snapshot(vec![entry("log", &["z"])]);
+
+    let diff = diff::diff_snapshots(&old, &new);
+    assert_eq!(diff.files.changed[0].chunks_shared, 0);
+    assert_eq!((diff.chunks, diff.shared_chunks), (1, 0));
+    assert!(diff::diff_snapshots(&new, &new).files.is_empty());
+}
+
+#[test]
+fn diff_command_compares_two_snapshots() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let data = dir.join("data");
+    fs::create_dir(&data).unwrap();
+    fs::write(data.join("kept.txt"), b"kept").unwrap();
+    fs::write(data.join("edited.txt"), b"before").unwrap();
+    fs::write(data.join("deleted.txt"), b"deleted").unwrap();
+
+    rbckp(dir, &["init", "repo"]);
+    let old = back_up(dir, &["data"]);
+    fs::write(data.join("edited.txt"), b"after!").unwrap();
+    fs::remove_file(data.join("deleted.txt")).unwrap();
+    fs::write(data.join("created.txt"), b"created").unwrap();
+    let new = back_up(dir, &["data"]);
+
+    let text = rbckp_stdout(dir, &["diff", "--repo", "repo", &old, &new]);
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines[0].starts_with("added    ") && lines[0].ends_with("created.txt"));
+    assert!(lines[1].starts_with("removed  ") && lines[1].ends_with("deleted.txt"));
+    assert!(lines[2].contains("edited.txt: +1 -1 chunks (0 shared), 6 -> 6 bytes"));
+    assert_eq!(lines[3], "1 added, 1 removed, 1 changed, 1 unchanged");
+    assert_eq!(lines[4], "1 of 3 chunks shared with the older snapshot");
+
+    let json: serde_json::Value = serde_json::from_str(&rbckp_stdout(
+        dir,
+        &["diff", "--repo", "repo", &old, &new, "--json"],
+    ))
+    .unwrap();
+    assert_eq!(json["unchanged"], 1);
+    assert_eq!(json["changed"][0]["chunks_added"], 1);
+    assert_eq!(json["changed"][0]["chunks_shared"], 0);
+    assert_eq!(json["shared_chunks"], 1);
+    assert_eq!(json["removed"].as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,206 @@
+//! File metadata recorded in manifest entries and reapplied on restore.
+
+mod common;
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use common::SETTINGS;
+use rbckp::{
+    backup::{
+        restore,
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+        walk,
+    },
+    config::Settings,
+};
+
+fn settings(dir: &std::path::Path) -> Settings {
+    let path = dir.join("settings.ini");
+    fs::write(&path, SETTINGS).unwrap();
+    Settings::from_path(&path).unwrap()
+}
+
+#[test]
+fn metadata_is_recorded_and_restored() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings(dir.path()), store);
+
+    let file = dir.path().join("data.bin");
+    fs::write(&file, vec![7u8; 50_000]).unwrap();
+    let mtime = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    let entry = session.add_file(&file).unwrap().clone();
+    let metadata = fs::metadata(&file).unwrap();
+    assert_eq!(entry.size, metadata.len());
+    assert_eq!(entry.mtime.map(std::time::SystemTime::from), Some(mtime));
+    #[cfg(unix)]
+    assert_eq!(entry.mode, Some(0o640));
+
+    let manifest = session.finish().unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let out = dir.path().join("restored.bin");
+    restore::restore_file(&manifest.entries[0], &store, &out).unwrap();
+    restore::apply_metadata(&manifest.entries[0], &out).unwrap();
+
+    let restored = fs::metadata(&out).unwrap();
+    assert_eq!(restored.len(), metadata.len());
+    assert_eq!(restored.modified().unwrap(), mtime);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(restored.permissions().mode() & 0o7777, 0o640);
+    }
+}
+
+#[test]
+fn entries_without_metadata_still_load() {
+    let json = r#"{"name":"a","size":3,"chunks":["00"]}"#;
+    let entry: rbckp::backup::manifest::ManifestEntry = serde_json::from_str(json).unwrap();
+    assert_eq!(entry.mtime, None);
+    assert_eq!(entry.mode, None);
+    assert_eq!(entry.uid, None);
+}
+
+/// Whether two times are equal within the precision of the filesystem (one second
+/// covers the coarsest ones the tests might run on).
+fn same_time(a: SystemTime, b: SystemTime) -> bool {
+    let difference = a
+        .duration_since(b)
+        .or_else(|_| b.duration_since(a))
+        .unwrap();
+    difference < Duration::from_secs(1)
+}
+
+fn set_mtime(path: &Path, mtime: SystemTime) {
+    let file = if path.is_dir() {
+        fs::File::open(path)
+    } else {
+        fs::File::options().write(true).open(path)
+    };
+    file.unwrap().set_modified(mtime).unwrap();
+}
+
+#[test]
+fn directory_metadata_is_restored_after_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(settings(dir.path()), store);
+
+    let root = dir.path().join("data");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("sub/a.txt"), b"a").unwrap();
+    fs::write(root.join("b.txt"), b"b").unwrap();
+    let base = SystemTime::UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789);
+    set_mtime(&root.join("sub/a.txt"), base);
+    set_mtime(&root.join("b.txt"), base + Duration::from_secs(60));
+    set_mtime(&root.join("sub"), base + Duration::from_secs(120));
+    set_mtime(&root, base + Duration::from_secs(180));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(root.join("sub"), fs::Permissions::from_mode(0o750)).unwrap();
+    }
+
+    let mut files = Vec::new();
+    walk::collect_files(
+        &root,
+        &Default::default(),
+        &Default::default(),
+        false,
+        &mut files,
+    )
+    .unwrap();
+    let dirs = walk::parent_dirs(&root, &files);
+    assert_eq!(dirs, [root.clone(), root.join("sub")]);
+    for dir in &dirs {
+        session.add_directory(dir).unwrap();
+    }
+    for file in &files {
+        session.add_file(file).unwrap();
+    }
+    let manifest = session.finish().unwrap();
+
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let target = dir.path().join("restored");
+    for entry in &manifest.entries {
+        let out = restore::restore_path(&target, &entry.name);
+        fs::create_dir_all(out.parent().unwrap()).unwrap();
+        restore::restore_file(entry, &store, &out).unwrap();
+        restore::apply_metadata(entry, &out).unwrap();
+    }
+    for entry in manifest.directories.iter().rev() {
+        restore::apply_metadata(entry, &restore::restore_path(&target, &entry.name)).unwrap();
+    }
+
+    for path in [
+        root.clone(),
+        root.join("sub"),
+        root.join("sub/a.txt"),
+        root.join("b.txt"),
+    ] {
+        let original = fs::metadata(&path).unwrap();
+        let restored =
+            fs::metadata(restore::restore_path(&target, &path.to_string_lossy())).unwrap();
+        assert!(
+            same_time(original.modified().unwrap(), restored.modified().unwrap()),
+            "mtime of {}",
+            path.display()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                original.permissions().mode(),
+                restored.permissions().mode(),
+                "mode of {}",
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn ownership_is_recorded_and_reapplied() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("owned.txt");
+    fs::write(&file, b"owned").unwrap();
+    let metadata = fs::metadata(&file).unwrap();
+
+    let mut entry: rbckp::backup::manifest::ManifestEntry =
+        serde_json::from_str(r#"{"name":"owned.txt","size":5,"chunks":[]}"#).unwrap();
+    entry.set_metadata(&metadata);
+    assert_eq!(entry.uid, Some(metadata.uid()));
+    assert_eq!(entry.gid, Some(metadata.gid()));
+
+    // Giving a file to its current owner works without privileges.
+    restore::apply_ownership(&entry, &file).unwrap();
+    let restored = fs::metadata(&file).unwrap();
+    assert_eq!(restored.uid(), metadata.uid());
+    assert_eq!(restored.gid(), metadata.gid());
+}
@@ -0,0 +1,111 @@
+//! Repository format versions: newer repositories are refused, older ones are brought
+//! up to date step by step by `migrate`.
+
+mod common;
+
+use std::fs;
+
+use common::{SETTINGS, rbckp, rbckp_command};
+use rbckp::backup::store::{
+    Backend, ChunkStore, LocalFsBackend, StoreError,
+    lock::LockKind,
+    migrate::{self, Migration},
+    repo_config::{REPO_VERSION, RepoConfig},
+};
+
+fn set_version(backend: &LocalFsBackend, version: u32) {
+    let mut config = RepoConfig::read(backend).unwrap();
+    config.version = version;
+    config.save(backend).unwrap();
+}
+
+#[test]
+fn newer_repository_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    let backend = LocalFsBackend::new(&repo);
+    let config = ChunkStore::init(&backend).unwrap();
+    assert_eq!(config.version, REPO_VERSION);
+    set_version(&backend, REPO_VERSION + 1);
+
+    match ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared) {
+        Err(err @ StoreError::UnsupportedVersion { .. }) => {
+            assert!(err.to_string().contains("please upgrade rbckp"))
+        }
+        Err(err) => panic!("expected a version error, got {}", err),
+        Ok(_) => panic!("a newer repository was opened"),
+    }
+    assert!(matches!(
+        migrate::migrate(&backend),
+        Err(StoreError::UnsupportedVersion { .. })
+    ));
+
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let output = rbckp_command(dir.path(), &["list-snapshots", "--repo", "repo"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("please upgrade rbckp"));
+
+    // Older ones are not worked on before they are migrated.
+    set_version(&backend, REPO_VERSION - 1);
+    assert!(matches!(
+        RepoConfig::load(&backend),
+        Err(StoreError::NeedsMigration { .. })
+    ));
+}
+
+fn add_marker(backend: &dyn Backend, config: &mut RepoConfig) -> Result<(), StoreError> {
+    backend.write("marker", config.id.as_bytes())?;
+    Ok(())
+}
+
+#[test]
+fn dummy_migration_runs_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    let backend = LocalFsBackend::new(&repo);
+    let config = ChunkStore::init(&backend).unwrap();
+
+    // The current format needs nothing.
+    assert_eq!(
+        migrate::migrate(&backend).unwrap(),
+        migrate::MigrateReport {
+            from: REPO_VERSION,
+            to: REPO_VERSION,
+            applied: Vec::new(),
+        }
+    );
+
+    let steps = [Migration {
+        from: REPO_VERSION,
+        description: "add a marker",
+        run: add_marker,
+    }];
+    let target = REPO_VERSION + 1;
+    let report = migrate::migrate_to(&backend, &steps, target).unwrap();
+    assert_eq!((report.from, report.to), (REPO_VERSION, target));
+    assert_eq!(report.applied, ["add a marker"]);
+    assert_eq!(backend.read("marker").unwrap(), config.id.as_bytes());
+    assert_eq!(RepoConfig::read(&backend).unwrap().version, target);
+
+    // Running it again finds nothing to do.
+    backend.remove("marker").unwrap();
+    let again = migrate::migrate_to(&backend, &steps, target).unwrap();
+    assert!(again.applied.is_empty());
+    assert!(!backend.exists("marker").unwrap());
+
+    // A missing step fails before anything is changed.
+    assert!(migrate::migrate_to(&backend, &steps, target + 1).is_err());
+    assert_eq!(RepoConfig::read(&backend).unwrap().version, target);
+}
+
+#[test]
+fn migrate_command_on_a_current_repository() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    rbckp(dir.path(), &["init", "repo"]);
+    rbckp(dir.path(), &["migrate", "--repo", "repo"]);
+}
@@ -0,0 +1,157 @@
+//! The pack layer of `ChunkStore`: chunks found through the index after reopening,
+//! packs spilling over at the target size, and a pack cut short by a crash.
+
+mod common;
+
+use std::fs::{self, OpenOptions};
+
+use common::noise;
+use rbckp::backup::store::{
+    Backend, ChunkStore, LocalFsBackend, StoreError, index::ChunkIndex, lock::LockKind,
+};
+
+fn hash(chunk: &[u8]) -> String {
+    blake3::hash(chunk).to_hex().to_string()
+}
+
+/// `count` distinct chunks of `len` bytes each, with their hashes.
+fn chunks(count: u64, len: usize) -> Vec<(String, Vec<u8>)> {
+    (0..count)
+        .map(|seed| {
+            let chunk = noise(len, seed + 1);
+            (hash(&chunk), chunk)
+        })
+        .collect()
+}
+
+fn temp_repo() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    ChunkStore::init(&LocalFsBackend::new(dir.path())).unwrap();
+    dir
+}
+
+fn open(dir: &tempfile::TempDir, pack_size: u64) -> ChunkStore<LocalFsBackend> {
+    ChunkStore::open(LocalFsBackend::new(dir.path()), pack_size, LockKind::Shared).unwrap()
+}
+
+#[test]
+fn lookups_after_reopening() {
+    let repo = temp_repo();
+    let stored = chunks(5, 10_000);
+
+    let mut store = open(&repo, 1 << 20);
+    for (hash, chunk) in &stored {
+        assert!(store.put(hash, chunk).unwrap());
+    }
+    store.flush().unwrap();
+    drop(store);
+
+    let store = open(&repo, 1 << 20);
+    for (hash, chunk) in &stored {
+        assert!(store.contains(hash));
+        assert_eq!(&store.get(hash).unwrap(), chunk);
+    }
+
+    let missing = hash(b"never stored");
+    assert!(!store.contains(&missing));
+    assert!(matches!(
+        store.get(&missing),
+        Err(StoreError::ChunkNotFound(name)) if name == missing
+    ));
+}
+
+#[test]
+fn full_packs_spill_before_a_flush() {
+    let repo = temp_repo();
+    let backend = LocalFsBackend::new(repo.path());
+    let stored = chunks(10, 10_000);
+
+    // Room for about three chunks per pack.
+    let mut store = open(&repo, 25_000);
+    for (hash, chunk) in &stored {
+        store.put(hash, chunk).unwrap();
+    }
+    // Packs that filled up are written and indexed already.
+    let spilled = backend.list("packs/").unwrap().len();
+    assert!(spilled >= 3, "{} packs written before the flush", spilled);
+    for (hash, chunk) in &stored {
+        assert_eq!(&store.get(hash).unwrap(), chunk);
+    }
+
+    store.flush().unwrap();
+    drop(store);
+    assert!(backend.list("packs/").unwrap().len() > spilled);
+
+    let store = open(&repo, 25_000);
+    let mut packs: Vec<u64> = stored
+        .iter()
+        .map(|(hash, _)| {
+            store
+                .chunks()
+                .find(|(stored, _)| stored == hash)
+                .unwrap()
+                .1
+                .pack_id
+        })
+        .collect();
+    packs.sort_unstable();
+    packs.dedup();
+    assert!(packs.len() > 3);
+    for (hash, chunk) in &stored {
+        assert_eq!(&store.get(hash).unwrap(), chunk);
+    }
+}
+
+#[test]
+fn truncated_pack_is_dropped_and_its_chunks_stored_again() {
+    let repo = temp_repo();
+    let backend = LocalFsBackend::new(repo.path());
+    let first = chunks(2, 10_000);
+    let last = chunks(4, 10_000).split_off(2);
+
+    let mut store = open(&repo, 1 << 20);
+    for (hash, chunk) in &first {
+        store.put(hash, chunk).unwrap();
+    }
+    store.flush().unwrap();
+    for (hash, chunk) in &last {
+        store.put(hash, chunk).unwrap();
+    }
+    store.flush().unwrap();
+    let last_pack = store.pack_name(store.chunks().map(|(_, at)| at.pack_id).max().unwrap());
+    drop(store);
+
+    // A crash while the last pack was written leaves it without its footer.
+    let path = repo.path().join(&last_pack);
+    let len = fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len / 2)
+        .unwrap();
+
+    let mut store = open(&repo, 1 << 20);
+    for (hash, chunk) in &first {
+        assert_eq!(&store.get(hash).unwrap(), chunk);
+    }
+    for (hash, _) in &last {
+        assert!(!store.contains(hash));
+        assert!(matches!(store.get(hash), Err(StoreError::ChunkNotFound(_))));
+    }
+    // The stored index no longer names the truncated pack's chunks either.
+    let index = ChunkIndex::load(&backend, "index.json").unwrap();
+    assert!(last.iter().all(|(hash, _)| index.get(hash).is_none()));
+
+    // The next backup stores them again.
+    for (hash, chunk) in &last {
+        assert!(store.put(hash, chunk).unwrap());
+    }
+    store.flush().unwrap();
+    drop(store);
+
+    let store = open(&repo, 1 << 20);
+    for (hash, chunk) in first.iter().chain(&last) {
+        assert_eq!(&store.get(hash).unwrap(), chunk);
+    }
+}
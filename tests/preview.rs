@@ -0,0 +1,58 @@
+//! Chunk previews: truncation, escaping, and the ellipsis only on truncated chunks.
+
+mod common;
+
+use std::{fs, process::Command};
+
+use common::SETTINGS;
+use rbckp::backup::preview::{self, DEFAULT_PREVIEW_LEN, ELLIPSIS};
+
+#[test]
+fn short_chunks_are_shown_whole() {
+    assert_eq!(preview::preview(b"hello", 60), "hello");
+    assert_eq!(preview::preview(b"exactly 10", 10), "exactly 10");
+    assert_eq!(preview::preview(b"", 10), "");
+}
+
+#[test]
+fn long_chunks_are_truncated_with_an_ellipsis() {
+    assert_eq!(
+        preview::preview(b"hello world", 5),
+        format!("hello{}", ELLIPSIS)
+    );
+    assert_eq!(preview::preview(b"abc", 0), ELLIPSIS);
+
+    let chunk = vec![b'x'; 1000];
+    assert_eq!(
+        preview::preview(&chunk, DEFAULT_PREVIEW_LEN),
+        format!("{}...", "x".repeat(60))
+    );
+}
+
+#[test]
+fn control_characters_are_escaped() {
+    assert_eq!(preview::preview(b"a\nb\rc\td", 60), "a\\nb\\rc\\td");
+    // The limit counts bytes of the chunk, not of the escaped text.
+    assert_eq!(preview::preview(b"\n\n\nabc", 3), "\\n\\n\\n...");
+    assert_eq!(preview::preview(&[0xff, b'a'], 60), "\u{fffd}a");
+}
+
+#[test]
+fn preview_len_flag_sets_the_length() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    fs::write(dir.path().join("data.txt"), "line\n".repeat(100)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir.path())
+        .args(["-F", "data.txt", "--preview-len", "12"])
+        .args(["--config", "settings.ini"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let previews = fs::read_to_string(dir.path().join("output.txt")).unwrap();
+    assert_eq!(
+        previews,
+        "chunk    0:    500 bytes | preview: \"line\\nline\\nli...\"\n"
+    );
+}
@@ -0,0 +1,54 @@
+//! The library never prints: all output lives in `main`, where `--quiet` can control it.
+//!
+//! Test output is captured in-process, so the chunking runs in a child test process
+//! whose real stdout is checked instead.
+
+use std::{env, io::Cursor, process::Command};
+
+use rbckp::backup::cdc_chunker::{self, CdcParams, StreamChunker};
+
+const CHILD_ENV: &str = "RBCKP_QUIET_TEST_CHILD";
+const BEGIN: &str = "--- chunking starts ---";
+const END: &str = "--- chunking ends ---";
+
+fn run_chunkers() {
+    let data: Vec<u8> = (0..200_000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let params = CdcParams::new(1024, 4096, 16384);
+
+    cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384);
+    cdc_chunker::chunk_refs_cdc(&data, &params);
+    cdc_chunker::chunk_refs_cdc_parallel(&data, &params);
+    cdc_chunker::chunk_boundaries_cdc(&data, 1024, 4096, 16384);
+    for chunk in StreamChunker::new(Cursor::new(&data), &params) {
+        chunk.unwrap();
+    }
+}
+
+#[test]
+fn chunking_prints_nothing_to_stdout() {
+    if env::var_os(CHILD_ENV).is_some() {
+        println!("{}", BEGIN);
+        run_chunkers();
+        println!("{}", END);
+        return;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args([
+            "chunking_prints_nothing_to_stdout",
+            "--exact",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let start = stdout.find(BEGIN).expect("child did not run the chunkers") + BEGIN.len();
+    let end = stdout.find(END).expect("child did not finish chunking");
+    assert_eq!(stdout[start..end].trim(), "", "chunking printed to stdout");
+}
@@ -0,0 +1,129 @@
+//! Partial restores with `--include` / `--exclude`, and `rbckp cat`.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{SETTINGS, TreeItem, back_up, make_tree, rbckp, rbckp_command};
+
+/// A backed-up tree, returning the snapshot id:
+///
+/// ```text
+/// data/notes.txt
+/// data/docs/report.txt
+/// data/docs/draft.md
+/// data/photos/cat.jpg
+/// ```
+fn back_up_tree(dir: &Path) -> String {
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let photo: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 253) as u8).collect();
+    make_tree(
+        &dir.join("data"),
+        &[
+            TreeItem::File("notes.txt", b"notes"),
+            TreeItem::File("docs/report.txt", b"report"),
+            TreeItem::File("docs/draft.md", b"draft"),
+            TreeItem::File("photos/cat.jpg", &photo),
+        ],
+    );
+    rbckp(dir, &["init", "repo"]);
+    back_up(dir, &["data"])
+}
+
+/// Regular files below `root`, relative to it, sorted.
+fn files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path.strip_prefix(root).unwrap().to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn restore_only_included_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = back_up_tree(dir.path());
+
+    rbckp(
+        dir.path(),
+        &[
+            "restore",
+            "--repo",
+            "repo",
+            "--target",
+            "out",
+            "--include",
+            "*.txt",
+            &id,
+        ],
+    );
+    assert_eq!(
+        files(&dir.path().join("out")),
+        ["data/docs/report.txt", "data/notes.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn restore_excludes_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = back_up_tree(dir.path());
+
+    rbckp(
+        dir.path(),
+        &[
+            "restore",
+            "--repo",
+            "repo",
+            "--target",
+            "out",
+            "--include",
+            "/data/docs",
+            "--exclude",
+            "*.md",
+            &id,
+        ],
+    );
+    assert_eq!(
+        files(&dir.path().join("out")),
+        [PathBuf::from("data/docs/report.txt")]
+    );
+}
+
+#[test]
+fn cat_writes_one_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = back_up_tree(dir.path());
+
+    let output = rbckp(
+        dir.path(),
+        &["cat", "--repo", "repo", &id, "data/photos/cat.jpg"],
+    );
+    assert_eq!(
+        output.stdout,
+        fs::read(dir.path().join("data/photos/cat.jpg")).unwrap()
+    );
+
+    let missing = rbckp_command(
+        dir.path(),
+        &["cat", "--repo", "repo", &id, "data/missing.txt"],
+    )
+    .output()
+    .unwrap();
+    assert!(!missing.status.success());
+    assert!(missing.stdout.is_empty());
+}
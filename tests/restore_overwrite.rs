@@ -0,0 +1,152 @@
+//! Restoring into a target that already has some of the files: `--overwrite` policies
+//! and `--dry-run`.
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use common::{SETTINGS, back_up, rbckp, rbckp_command};
+
+/// A year before any snapshot in these tests, to tell untouched files apart.
+fn long_ago() -> SystemTime {
+    SystemTime::now() - Duration::from_secs(365 * 24 * 3600)
+}
+
+/// Back up `data/{same,changed,resized}.txt`, then prepare `out` (the restore target)
+/// with an identical `same.txt` dated `long_ago()`, a `changed.txt` with other content
+/// of the same size, and a `resized.txt` of another size. `new.txt` is only in the
+/// snapshot. Returns the snapshot id.
+fn prepare(dir: &Path) -> String {
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let data = dir.join("data");
+    fs::create_dir(&data).unwrap();
+    for (name, content) in [
+        ("same.txt", "same"),
+        ("changed.txt", "new content"),
+        ("resized.txt", "resized"),
+        ("new.txt", "new"),
+    ] {
+        fs::write(data.join(name), content).unwrap();
+    }
+
+    rbckp(dir, &["init", "repo"]);
+    let id = back_up(dir, &["data"]);
+
+    let out = dir.join("out/data");
+    fs::create_dir_all(&out).unwrap();
+    fs::write(out.join("same.txt"), "same").unwrap();
+    fs::write(out.join("changed.txt"), "old content").unwrap();
+    fs::write(out.join("resized.txt"), "much longer than before").unwrap();
+    File::options()
+        .write(true)
+        .open(out.join("same.txt"))
+        .unwrap()
+        .set_modified(long_ago())
+        .unwrap();
+    id
+}
+
+fn restore_args<'a>(id: &'a str, extra: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec!["restore", "--repo", "repo", "--target", "out"];
+    args.extend(extra);
+    args.push(id);
+    args
+}
+
+fn read(dir: &Path, name: &str) -> String {
+    fs::read_to_string(dir.join("out/data").join(name)).unwrap()
+}
+
+fn untouched(dir: &Path, name: &str) -> bool {
+    let modified = fs::metadata(dir.join("out/data").join(name))
+        .unwrap()
+        .modified()
+        .unwrap();
+    modified < long_ago() + Duration::from_secs(60)
+}
+
+#[test]
+fn never_refuses_and_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = prepare(dir.path());
+
+    let output = rbckp_command(dir.path(), &restore_args(&id, &[]))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--overwrite"));
+    assert_eq!(read(dir.path(), "changed.txt"), "old content");
+    assert!(!dir.path().join("out/data/new.txt").exists());
+}
+
+#[test]
+fn always_replaces_everything() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = prepare(dir.path());
+
+    rbckp(dir.path(), &restore_args(&id, &["--overwrite", "always"]));
+    assert_eq!(read(dir.path(), "same.txt"), "same");
+    assert!(!untouched(dir.path(), "same.txt"));
+    assert_eq!(read(dir.path(), "changed.txt"), "new content");
+    assert_eq!(read(dir.path(), "resized.txt"), "resized");
+    assert_eq!(read(dir.path(), "new.txt"), "new");
+}
+
+#[test]
+fn if_changed_skips_identical_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = prepare(dir.path());
+
+    let output = rbckp(
+        dir.path(),
+        &restore_args(&id, &["--overwrite", "if-changed"]),
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("2 overwritten, 1 unchanged"),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(untouched(dir.path(), "same.txt"));
+    assert_eq!(read(dir.path(), "changed.txt"), "new content");
+    assert_eq!(read(dir.path(), "resized.txt"), "resized");
+    assert_eq!(read(dir.path(), "new.txt"), "new");
+}
+
+#[test]
+fn dry_run_reports_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = prepare(dir.path());
+
+    let output = rbckp(
+        dir.path(),
+        &restore_args(&id, &["--overwrite", "if-changed", "--dry-run"]),
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    let summary = lines.pop().unwrap();
+    lines.sort();
+    let expected = [
+        "create    out/data/new.txt",
+        "overwrite out/data/changed.txt",
+        "overwrite out/data/resized.txt",
+        "skip      out/data/same.txt",
+    ];
+    assert_eq!(lines, expected);
+    assert_eq!(
+        summary,
+        "Would create 1, overwrite 2 and skip 1 files; 0 conflicts"
+    );
+    assert_eq!(read(dir.path(), "changed.txt"), "old content");
+    assert!(!dir.path().join("out/data/new.txt").exists());
+
+    // Under the default policy, the same dry run lists the conflicts and fails.
+    let output = rbckp_command(dir.path(), &restore_args(&id, &["--dry-run"]))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("conflict  out/data/same.txt"));
+}
@@ -0,0 +1,191 @@
+//! Retention policies against a synthetic set of snapshots at known times.
+
+mod common;
+
+use std::{collections::HashSet, fs};
+
+use common::{SETTINGS, back_up, noise, rbckp, rbckp_stdout};
+
+use rbckp::backup::{
+    retention::{KeepReason, RetentionPolicy},
+    store::{ChunkStore, LocalFsBackend, lock::LockKind},
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+/// 2026-01-01 is a Thursday, so ISO week 1 of 2026 starts on 2025-12-29.
+fn snapshots() -> Vec<(String, OffsetDateTime)> {
+    [
+        ("s1", "2026-01-01T10:00:00Z"),
+        ("s2", "2026-01-01T18:00:00Z"),
+        ("s3", "2026-01-02T09:00:00Z"),
+        ("s4", "2026-01-05T12:00:00Z"), // Monday, week 2
+        ("s5", "2026-01-20T08:00:00Z"), // week 4
+        ("s6", "2026-02-03T10:00:00Z"), // week 6
+        ("s7", "2026-02-03T20:00:00Z"),
+    ]
+    .into_iter()
+    .map(|(id, time)| {
+        (
+            id.to_string(),
+            OffsetDateTime::parse(time, &Rfc3339).unwrap(),
+        )
+    })
+    .collect()
+}
+
+fn kept(policy: &RetentionPolicy) -> Vec<String> {
+    let mut kept: Vec<String> = policy
+        .apply(&snapshots())
+        .into_iter()
+        .filter(|decision| decision.keep())
+        .map(|decision| decision.id)
+        .collect();
+    kept.sort();
+    kept
+}
+
+#[test]
+fn keep_last() {
+    let policy = RetentionPolicy {
+        keep_last: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(kept(&policy), ["s6", "s7"]);
+}
+
+#[test]
+fn keep_daily_keeps_newest_of_each_day() {
+    let policy = RetentionPolicy {
+        keep_daily: Some(5),
+        ..Default::default()
+    };
+    assert_eq!(kept(&policy), ["s2", "s3", "s4", "s5", "s7"]);
+}
+
+#[test]
+fn keep_weekly_uses_iso_weeks() {
+    let policy = RetentionPolicy {
+        keep_weekly: Some(4),
+        ..Default::default()
+    };
+    // Weeks 6, 4, 2 and 1 (s3 is the newest of 2026-W01).
+    assert_eq!(kept(&policy), ["s3", "s4", "s5", "s7"]);
+}
+
+#[test]
+fn keep_monthly_stops_when_months_run_out() {
+    let policy = RetentionPolicy {
+        keep_monthly: Some(12),
+        ..Default::default()
+    };
+    assert_eq!(kept(&policy), ["s5", "s7"]);
+}
+
+#[test]
+fn rules_combine_and_report_every_reason() {
+    let policy = RetentionPolicy {
+        keep_last: Some(1),
+        keep_daily: Some(2),
+        keep_monthly: Some(3),
+        ..Default::default()
+    };
+    let decisions = policy.apply(&snapshots());
+
+    // Newest first.
+    let ids: Vec<&str> = decisions.iter().map(|d| d.id.as_str()).collect();
+    assert_eq!(ids, ["s7", "s6", "s5", "s4", "s3", "s2", "s1"]);
+
+    assert_eq!(
+        decisions[0].reasons,
+        [KeepReason::Last, KeepReason::Daily, KeepReason::Monthly]
+    );
+    assert_eq!(
+        decisions[2].reasons,
+        [KeepReason::Daily, KeepReason::Monthly]
+    );
+    assert!(decisions.iter().skip(3).all(|d| !d.keep()));
+    assert!(!decisions[1].keep());
+}
+
+#[test]
+fn empty_policy_keeps_nothing() {
+    let policy = RetentionPolicy::default();
+    assert!(policy.is_empty());
+    assert!(kept(&policy).is_empty());
+}
+
+#[test]
+fn prune_removes_only_unreferenced_chunks() {
+    let repo = tempfile::tempdir().unwrap();
+    ChunkStore::init(&LocalFsBackend::new(repo.path())).unwrap();
+    let mut store =
+        ChunkStore::open(LocalFsBackend::new(repo.path()), 1024, LockKind::Exclusive).unwrap();
+
+    let chunks: Vec<(String, Vec<u8>)> = (0..20u8)
+        .map(|i| {
+            let data = vec![i; 300];
+            (blake3::hash(&data).to_hex().to_string(), data)
+        })
+        .collect();
+    for (hash, data) in &chunks {
+        store.put(hash, data).unwrap();
+    }
+    store.flush().unwrap();
+
+    // Keep every third chunk.
+    let referenced: HashSet<String> = chunks
+        .iter()
+        .step_by(3)
+        .map(|(hash, _)| hash.clone())
+        .collect();
+    let report = store.prune(&referenced).unwrap();
+    assert_eq!(report.removed_chunks, 20 - referenced.len());
+
+    for (hash, data) in &chunks {
+        if referenced.contains(hash) {
+            assert_eq!(&store.get(hash).unwrap(), data);
+        } else {
+            assert!(!store.contains(hash));
+        }
+    }
+
+    // The packs on disk agree with the index after reopening.
+    drop(store);
+    let store = ChunkStore::open(LocalFsBackend::new(repo.path()), 1024, LockKind::Shared).unwrap();
+    for (hash, _) in &chunks {
+        assert_eq!(store.contains(hash), referenced.contains(hash));
+    }
+}
+
+#[test]
+fn forget_lists_snapshots_with_short_ids() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    fs::create_dir(dir.join("data")).unwrap();
+    fs::write(dir.join("data/f"), noise(10_000, 1)).unwrap();
+    rbckp(dir, &["init", "repo"]);
+    let id = back_up(dir, &["data"]);
+
+    // Snapshot ids are object names, so a copied-in snapshot may have any.
+    let snapshots = dir.join("repo/snapshots");
+    fs::copy(
+        snapshots.join(format!("{}.json", id)),
+        snapshots.join("abc.json"),
+    )
+    .unwrap();
+    let output = rbckp_stdout(
+        dir,
+        &[
+            "forget",
+            "--repo",
+            "repo",
+            "--keep-last",
+            "1",
+            "--dry-run",
+            "--quiet",
+        ],
+    );
+    assert!(output.contains("  abc  "), "{}", output);
+    assert!(output.contains(&format!("  {}  ", &id[..12])), "{}", output);
+}
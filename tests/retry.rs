@@ -0,0 +1,180 @@
+//! `RetryBackend`: transient store errors are retried with backoff, permanent ones are not.
+
+mod common;
+
+use std::{
+    fs, io,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use common::SETTINGS;
+use rbckp::{
+    backup::store::{
+        Backend, ChunkStore, InMemoryBackend, StoreError,
+        lock::LockKind,
+        retry::{self, MAX_RETRY_DELAY, RetryBackend, RetryPolicy},
+    },
+    config::{RetrySettings, Settings},
+};
+
+/// An in-memory backend whose next `failures` operations fail with `kind`.
+#[derive(Clone)]
+struct Flaky {
+    objects: Arc<InMemoryBackend>,
+    failures: Arc<AtomicU32>,
+    calls: Arc<AtomicU32>,
+    kind: io::ErrorKind,
+}
+
+impl Flaky {
+    fn new(kind: io::ErrorKind) -> Self {
+        Flaky {
+            objects: Arc::default(),
+            failures: Arc::default(),
+            calls: Arc::default(),
+            kind,
+        }
+    }
+
+    /// Fail the next `failures` calls, and count calls from zero again.
+    fn fail_next(&self, failures: u32) {
+        self.failures.store(failures, Ordering::SeqCst);
+        self.calls.store(0, Ordering::SeqCst);
+    }
+
+    fn calls(&self) -> u32 {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    fn check(&self) -> io::Result<()> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let left = self.failures.load(Ordering::SeqCst);
+        if left > 0 {
+            self.failures.store(left - 1, Ordering::SeqCst);
+            return Err(io::Error::new(self.kind, "flaky"));
+        }
+        Ok(())
+    }
+}
+
+impl Backend for Flaky {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.check()?;
+        self.objects.read(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.check()?;
+        self.objects.write(name, data)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.check()?;
+        self.objects.list(prefix)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.check()?;
+        self.objects.remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.check()?;
+        self.objects.exists(name)
+    }
+}
+
+fn retrying(flaky: &Flaky, retries: u32) -> RetryBackend {
+    RetryBackend::new(
+        Box::new(flaky.clone()),
+        RetryPolicy::with_retries(retries, Duration::from_millis(1)),
+    )
+}
+
+#[test]
+fn transient_errors_are_retried() {
+    let flaky = Flaky::new(retry::http_status_kind(503));
+    let backend = retrying(&flaky, 3);
+
+    flaky.fail_next(3);
+    backend.write("a", b"data").unwrap();
+    assert_eq!(flaky.calls(), 4);
+    flaky.fail_next(2);
+    assert_eq!(backend.read("a").unwrap(), b"data");
+    flaky.fail_next(1);
+    assert!(backend.exists("a").unwrap());
+
+    // Out of retries: the error is still transient.
+    flaky.fail_next(4);
+    let err = StoreError::from(backend.read("a").unwrap_err());
+    assert!(err.is_transient(), "{:?}", err);
+    assert_eq!(flaky.calls(), 4);
+
+    // Chunks go through the store as usual.
+    flaky.fail_next(0);
+    ChunkStore::init(&backend).unwrap();
+    let mut store = ChunkStore::open(retrying(&flaky, 3), 1 << 20, LockKind::Shared).unwrap();
+    let data = b"a chunk that gets through eventually".to_vec();
+    let hash = blake3::hash(&data).to_hex().to_string();
+    store.put(&hash, &data).unwrap();
+    flaky.fail_next(2);
+    store.flush().unwrap();
+    flaky.fail_next(2);
+    assert_eq!(store.get(&hash).unwrap(), data);
+}
+
+#[test]
+fn permanent_errors_fail_at_once() {
+    for status in [401, 403, 404] {
+        let flaky = Flaky::new(retry::http_status_kind(status));
+        let backend = retrying(&flaky, 3);
+        flaky.fail_next(1);
+        let err = StoreError::from(backend.read("a").unwrap_err());
+        assert!(!err.is_transient(), "HTTP {}: {:?}", status, err);
+        assert_eq!(flaky.calls(), 1);
+    }
+
+    // A lost answer to a removal would make its retry fail, so it is not retried.
+    let flaky = Flaky::new(io::ErrorKind::TimedOut);
+    let backend = retrying(&flaky, 3);
+    flaky.fail_next(1);
+    assert!(backend.remove("a").is_err());
+    assert_eq!(flaky.calls(), 1);
+}
+
+#[test]
+fn delays_double_up_to_a_minute() {
+    let policy = RetryPolicy::with_retries(20, Duration::from_millis(500));
+    assert_eq!(policy.max_attempts, 21);
+    assert_eq!(policy.delay(0), Duration::from_millis(500));
+    assert_eq!(policy.delay(3), Duration::from_secs(4));
+    assert_eq!(policy.delay(19), MAX_RETRY_DELAY);
+
+    for status in [408, 429, 500, 502, 503, 504] {
+        let err = io::Error::new(retry::http_status_kind(status), "x");
+        assert!(retry::is_transient(&err), "HTTP {}", status);
+    }
+}
+
+#[test]
+fn retry_section_configures_the_backends() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    let settings = |retry: &str| {
+        fs::write(&path, format!("{}{}", SETTINGS, retry)).unwrap();
+        Settings::from_path(&path).unwrap()
+    };
+
+    assert_eq!(settings("").backend.retry, RetrySettings::default());
+    let retry = settings("[retry]\nmax_retries=2\nbase_delay_ms=50\n")
+        .backend
+        .retry;
+    assert_eq!(
+        retry.policy(),
+        RetryPolicy::with_retries(2, Duration::from_millis(50))
+    );
+}
@@ -0,0 +1,98 @@
+//! End to end through the binary: back up a tree, delete it, restore the snapshot into
+//! a fresh directory and compare the result with the original byte for byte, modes and
+//! symlinks included.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use common::{SETTINGS, TreeItem, make_tree, noise, rbckp};
+
+/// What a tree holds, by path relative to its root.
+#[derive(Debug, PartialEq, Eq)]
+enum Node {
+    Dir { mode: u32 },
+    File { mode: u32, data: Vec<u8> },
+    Link { target: PathBuf },
+}
+
+fn snapshot_tree(root: &Path) -> BTreeMap<PathBuf, Node> {
+    fn walk(root: &Path, dir: &Path, nodes: &mut BTreeMap<PathBuf, Node>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let meta = fs::symlink_metadata(&path).unwrap();
+            let mode = meta.permissions().mode() & 0o7777;
+            let node = if meta.file_type().is_symlink() {
+                Node::Link {
+                    target: fs::read_link(&path).unwrap(),
+                }
+            } else if meta.is_dir() {
+                walk(root, &path, nodes);
+                Node::Dir { mode }
+            } else {
+                Node::File {
+                    mode,
+                    data: fs::read(&path).unwrap(),
+                }
+            };
+            nodes.insert(path.strip_prefix(root).unwrap().to_path_buf(), node);
+        }
+    }
+
+    let mut nodes = BTreeMap::new();
+    walk(root, root, &mut nodes);
+    nodes
+}
+
+/// `data/` with files of several sizes and modes, a nested directory with its own
+/// mode, and symlinks that resolve and that dangle.
+fn make_data(dir: &Path) -> PathBuf {
+    make_tree(
+        &dir.join("data"),
+        &[
+            TreeItem::File("empty", b""),
+            TreeItem::File("small.txt", b"small"),
+            TreeItem::File("nested/large.bin", &noise(3_000_000, 7)),
+            TreeItem::File("nested/deeper/script.sh", b"#!/bin/sh\ntrue\n"),
+            TreeItem::File("private", b"secret"),
+            TreeItem::Symlink("link", "small.txt"),
+            TreeItem::Symlink("nested/up", "../small.txt"),
+            TreeItem::Symlink("dangling", "missing"),
+            TreeItem::Mode("nested/deeper/script.sh", 0o755),
+            TreeItem::Mode("private", 0o600),
+            TreeItem::Mode("small.txt", 0o444),
+            TreeItem::Mode("nested/deeper", 0o750),
+        ],
+    )
+}
+
+#[test]
+fn restored_tree_matches_the_deleted_original() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let data = make_data(dir);
+    let original = snapshot_tree(&data);
+
+    rbckp(dir, &["init", "repo"]);
+    rbckp(dir, &["backup", "--repo", "repo", "data", "--quiet"]);
+    let list = rbckp(dir, &["list-snapshots", "--repo", "repo"]);
+    let list = String::from_utf8(list.stdout).unwrap();
+    let id = list.split_whitespace().next().unwrap();
+
+    fs::remove_dir_all(&data).unwrap();
+
+    rbckp(dir, &["restore", "--repo", "repo", "--target", "out", id]);
+    let restored = snapshot_tree(&dir.join("out/data"));
+
+    assert_eq!(restored.len(), original.len());
+    for (path, node) in &original {
+        assert_eq!(restored.get(path), Some(node), "{} differs", path.display());
+    }
+}
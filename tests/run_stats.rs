@@ -0,0 +1,126 @@
+//! Run statistics: counted by the backup session, kept in the snapshot, and shown by
+//! `rbckp list-snapshots --long` and `--json`.
+
+mod common;
+
+use std::fs;
+
+use common::{SETTINGS, back_up, noise, rbckp, rbckp_stdout};
+use rbckp::{
+    backup::{
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+    },
+    config::Settings,
+};
+
+#[test]
+fn second_backup_reports_the_one_changed_file() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    fs::create_dir(dir.path().join("data")).unwrap();
+    for (i, name) in ["a", "b", "c"].iter().enumerate() {
+        fs::write(
+            dir.path().join("data").join(name),
+            noise(50_000, i as u64 + 1),
+        )
+        .unwrap();
+    }
+    rbckp(dir.path(), &["init", "repo"]);
+    back_up(dir.path(), &["data"]);
+
+    let mut b = noise(50_000, 2);
+    b[25_000] ^= 1;
+    fs::write(dir.path().join("data/b"), b).unwrap();
+    back_up(dir.path(), &["data"]);
+
+    let output = rbckp(
+        dir.path(),
+        &["list-snapshots", "--repo", "repo", "--json", "--quiet"],
+    );
+    let snapshots: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let stats = |i: usize, field: &str| snapshots[i]["stats"][field].as_u64().unwrap();
+
+    assert_eq!(snapshots.as_array().unwrap().len(), 2);
+    assert_eq!((stats(0, "files_new"), stats(0, "files_changed")), (3, 0));
+    assert_eq!(
+        (
+            stats(1, "files_new"),
+            stats(1, "files_changed"),
+            stats(1, "files_unchanged")
+        ),
+        (0, 1, 2)
+    );
+    assert_eq!(stats(1, "bytes_read"), 150_000);
+    // Only the chunks around the flipped byte are new.
+    assert!(stats(1, "new_chunks") > 0 && stats(1, "new_chunks") < stats(1, "chunks"));
+    assert!(stats(1, "bytes_stored") < 50_000);
+    assert!(snapshots[1]["throughput_mb_s"].as_f64().is_some());
+
+    let text = rbckp_stdout(dir.path(), &["list", "--repo", "repo", "--long", "--quiet"]);
+    assert!(text.contains("0 new, 1 changed, 2 unchanged"), "{}", text);
+}
+
+#[test]
+fn backup_of_other_paths_is_no_parent() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    for name in ["x", "y"] {
+        fs::create_dir(dir.path().join(name)).unwrap();
+        fs::write(dir.path().join(name).join("f"), noise(10_000, 7)).unwrap();
+    }
+    rbckp(dir.path(), &["init", "repo"]);
+    back_up(dir.path(), &["x"]);
+    back_up(dir.path(), &["y"]);
+
+    let output = rbckp(
+        dir.path(),
+        &["list-snapshots", "--repo", "repo", "--json", "--quiet"],
+    );
+    let snapshots: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    for snapshot in snapshots.as_array().unwrap() {
+        assert_eq!(snapshot["stats"]["files_new"], 1);
+    }
+}
+
+#[test]
+fn session_counts_against_its_parent() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let session = || {
+        let settings = Settings::from_path(&dir.path().join("settings.ini")).unwrap();
+        let store =
+            ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+        BackupSession::new(settings, store)
+    };
+
+    let mut first = session();
+    first.add_bytes("same", b"unchanged content").unwrap();
+    first.add_bytes("edited", b"old content").unwrap();
+    let (_, parent) = first.commit(vec![], &[]).unwrap();
+    assert_eq!(parent.stats.unwrap().files_new, 2);
+
+    let mut second = session().with_parent(&parent.manifest);
+    second.add_bytes("same", b"unchanged content").unwrap();
+    second.add_bytes("edited", b"new content").unwrap();
+    second.add_bytes("added", b"more content").unwrap();
+    let stats = *second.stats();
+    assert_eq!(
+        (stats.new_files, stats.changed_files, stats.unchanged_files),
+        (1, 1, 1)
+    );
+    let (_, snapshot) = second.commit(vec![], &[]).unwrap();
+    let recorded = snapshot.stats.unwrap();
+    assert_eq!(
+        (
+            recorded.files_new,
+            recorded.files_changed,
+            recorded.files_unchanged
+        ),
+        (1, 1, 1)
+    );
+    assert_eq!(recorded.bytes_read, stats.bytes);
+}
@@ -0,0 +1,95 @@
+//! Key layout, multipart threshold and URL parsing of the S3 backend; none of these
+//! need a bucket.
+#![cfg(feature = "s3")]
+
+use std::io;
+
+use rbckp::{
+    backup::store::s3::{
+        MULTIPART_THRESHOLD, READ_AFTER_WRITE_RETRY, object_key, settings_for_url, uses_multipart,
+    },
+    config::S3Settings,
+};
+
+#[test]
+fn keys_are_prefixed() {
+    assert_eq!(object_key("", "index.json"), "index.json");
+    assert_eq!(object_key("/", "index.json"), "index.json");
+    assert_eq!(object_key("laptop", "index.json"), "laptop/index.json");
+    assert_eq!(object_key("/laptop/", "index.json"), "laptop/index.json");
+    assert_eq!(
+        object_key("backups/laptop/", "packs/0000000000000000.pack"),
+        "backups/laptop/packs/0000000000000000.pack"
+    );
+    // Listing below the prefix itself.
+    assert_eq!(object_key("backups/laptop", ""), "backups/laptop/");
+}
+
+#[test]
+fn multipart_starts_at_the_threshold() {
+    assert!(!uses_multipart(0));
+    assert!(!uses_multipart(MULTIPART_THRESHOLD - 1));
+    assert!(uses_multipart(MULTIPART_THRESHOLD));
+    assert!(uses_multipart(MULTIPART_THRESHOLD + 1));
+}
+
+#[test]
+fn read_after_write_retries_are_short() {
+    let waited: std::time::Duration = (0..READ_AFTER_WRITE_RETRY.max_attempts - 1)
+        .map(|retry| READ_AFTER_WRITE_RETRY.delay(retry))
+        .sum();
+    const { assert!(READ_AFTER_WRITE_RETRY.max_attempts > 1) };
+    assert!(waited.as_secs() < 5, "{:?}", waited);
+}
+
+fn configured() -> S3Settings {
+    S3Settings {
+        bucket: "configured".to_string(),
+        prefix: "configured/prefix".to_string(),
+        region: "eu-west-1".to_string(),
+        endpoint: Some("http://localhost:9000".to_string()),
+    }
+}
+
+#[test]
+fn bare_url_uses_the_settings() {
+    let settings = settings_for_url("s3://", Some(&configured())).unwrap();
+    assert_eq!(settings.bucket, "configured");
+    assert_eq!(settings.prefix, "configured/prefix");
+}
+
+#[test]
+fn bucket_only_url_has_no_prefix() {
+    let settings = settings_for_url("s3://my-backups", Some(&configured())).unwrap();
+    assert_eq!(settings.bucket, "my-backups");
+    assert_eq!(settings.prefix, "");
+    // Region and endpoint still come from the settings.
+    assert_eq!(settings.region, "eu-west-1");
+    assert_eq!(settings.endpoint.as_deref(), Some("http://localhost:9000"));
+}
+
+#[test]
+fn url_with_prefix() {
+    let settings = settings_for_url("s3://my-backups/hosts/laptop", None).unwrap();
+    assert_eq!(settings.bucket, "my-backups");
+    assert_eq!(settings.prefix, "hosts/laptop");
+}
+
+#[test]
+fn url_without_bucket_is_refused() {
+    let err = settings_for_url("s3://", None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("no bucket"), "{}", err);
+
+    let err = settings_for_url("s3://", Some(&S3Settings::default())).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn other_schemes_are_refused() {
+    for url in ["gs://my-backups", "/srv/backups", "s3:/my-backups"] {
+        let err = settings_for_url(url, Some(&configured())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", url);
+        assert!(err.to_string().contains("not an s3:// URL"), "{}", err);
+    }
+}
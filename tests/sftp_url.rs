@@ -0,0 +1,101 @@
+//! Parsing `sftp://` repository locations, and the error for each way one can be
+//! wrong.
+#![cfg(feature = "sftp")]
+
+use rbckp::backup::store::sftp::{SftpLocation, SftpUrlError};
+
+#[test]
+fn user_host_port_and_path() {
+    assert_eq!(
+        SftpLocation::parse("sftp://backup@example.org:2222/srv/repo").unwrap(),
+        SftpLocation {
+            user: "backup".into(),
+            host: "example.org".into(),
+            port: 2222,
+            path: "/srv/repo".into(),
+        }
+    );
+}
+
+#[test]
+fn port_defaults_to_22() {
+    let location = SftpLocation::parse("sftp://backup@example.org/srv/repo").unwrap();
+    assert_eq!(location.port, 22);
+    assert_eq!(location.path, "/srv/repo");
+}
+
+#[test]
+fn ipv6_hosts_in_brackets() {
+    let location = SftpLocation::parse("sftp://backup@[::1]:2222/repo").unwrap();
+    assert_eq!(location.host, "::1");
+    assert_eq!(location.port, 2222);
+
+    assert_eq!(
+        SftpLocation::parse("sftp://backup@[::1/repo"),
+        Err(SftpUrlError::BadHost("[::1".into()))
+    );
+    assert_eq!(
+        SftpLocation::parse("sftp://backup@[::1]x/repo"),
+        Err(SftpUrlError::BadHost("[::1]x".into()))
+    );
+}
+
+#[test]
+fn other_schemes() {
+    assert_eq!(
+        SftpLocation::parse("ssh://backup@example.org/repo"),
+        Err(SftpUrlError::NotSftp)
+    );
+}
+
+#[test]
+fn missing_user() {
+    assert_eq!(
+        SftpLocation::parse("sftp://@example.org/repo"),
+        Err(SftpUrlError::MissingUser)
+    );
+}
+
+#[test]
+fn missing_host() {
+    for url in [
+        "sftp://backup@/repo",
+        "sftp://backup@:22/repo",
+        "sftp://backup@[]/repo",
+    ] {
+        assert_eq!(
+            SftpLocation::parse(url),
+            Err(SftpUrlError::MissingHost),
+            "{}",
+            url
+        );
+    }
+}
+
+#[test]
+fn bad_port() {
+    for (url, port) in [
+        ("sftp://backup@example.org:ssh/repo", "ssh"),
+        ("sftp://backup@example.org:70000/repo", "70000"),
+        ("sftp://backup@example.org:/repo", ""),
+    ] {
+        assert_eq!(
+            SftpLocation::parse(url),
+            Err(SftpUrlError::BadPort(port.into())),
+            "{}",
+            url
+        );
+    }
+}
+
+#[test]
+fn bad_path() {
+    for url in ["sftp://backup@example.org", "sftp://backup@example.org:22/"] {
+        assert_eq!(
+            SftpLocation::parse(url),
+            Err(SftpUrlError::BadPath),
+            "{}",
+            url
+        );
+    }
+}
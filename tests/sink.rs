@@ -0,0 +1,140 @@
+//! `chunk_to_sink`: every chunk reaches the sink once, in order.
+//! `DedupSink`: only the first occurrence of a chunk is passed on.
+
+mod common;
+
+use std::fs;
+
+use common::noise;
+use rbckp::backup::{
+    cdc_chunker::{self, CdcParams},
+    hash::ChunkId,
+    sink::{ChunkSink, DedupSink, FsSink, MemorySink},
+    store::{ChunkStore, InMemoryBackend, StoreError, lock::LockKind},
+};
+
+/// Remembers what it was given.
+#[derive(Default)]
+struct CountingSink {
+    hashes: Vec<String>,
+    data: Vec<u8>,
+}
+
+impl ChunkSink for CountingSink {
+    fn accept(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError> {
+        self.hashes.push(hash.to_string());
+        self.data.extend_from_slice(chunk);
+        Ok(())
+    }
+}
+
+/// Pseudo-random data with its first 50 KB repeated at the end, so some chunks repeat.
+fn data() -> Vec<u8> {
+    let mut data = noise(250_000, 0x1234_5678_9abc_def0);
+    data.extend_from_within(..50_000);
+    data
+}
+
+#[test]
+fn sink_receives_every_chunk_once() {
+    let data = data();
+    let params = CdcParams::new(1024, 4096, 16384);
+    let mut sink = CountingSink::default();
+    cdc_chunker::chunk_to_sink(&data, &params, &mut sink).unwrap();
+
+    let (chunks, _) = cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384);
+    assert_eq!(sink.data, data);
+    assert_eq!(sink.hashes.len(), chunks.len());
+    for (hash, chunk) in sink.hashes.iter().zip(&chunks) {
+        assert_eq!(*hash, ChunkId::of(chunk).to_string());
+    }
+    // Repeated chunks are delivered every time they occur.
+    let mut distinct = sink.hashes.clone();
+    distinct.sort();
+    distinct.dedup();
+    assert!(distinct.len() < sink.hashes.len());
+}
+
+#[test]
+fn memory_sink_matches_chunk_bytes() {
+    let data = data();
+    let params = CdcParams::new(1024, 4096, 16384);
+    let mut sink = MemorySink::default();
+    cdc_chunker::chunk_to_sink(&data, &params, &mut sink).unwrap();
+
+    assert_eq!(
+        sink.into_parts(),
+        cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384)
+    );
+
+    // Zero chunks are grouped under the hash of their bytes, other ids must be hashes.
+    let mut sink = MemorySink::default();
+    sink.accept(&cdc_chunker::zero_chunk_id(10), &[0; 10])
+        .unwrap();
+    assert_eq!(sink.chunk_map[&ChunkId::of(&[0; 10])], [vec![0; 10]]);
+    assert!(sink.accept("not a hash", b"data").is_err());
+}
+
+#[test]
+fn fs_sink_writes_each_distinct_chunk_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = data();
+    let params = CdcParams::new(1024, 4096, 16384);
+    let mut sink = FsSink::new(dir.path());
+    cdc_chunker::chunk_to_sink(&data, &params, &mut sink).unwrap();
+
+    let (_, chunk_map) = cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384);
+    assert_eq!(sink.written, chunk_map.len());
+    for (id, chunks) in &chunk_map {
+        let hash = id.to_string();
+        let stored = fs::read(dir.path().join(&hash[..2]).join(&hash)).unwrap();
+        assert_eq!(stored, chunks[0]);
+    }
+}
+
+#[test]
+fn chunk_store_is_a_sink() {
+    let backend = InMemoryBackend::default();
+    ChunkStore::init(&backend).unwrap();
+    let mut store = ChunkStore::open(backend, 1 << 20, LockKind::Shared).unwrap();
+    let data = data();
+    cdc_chunker::chunk_to_sink(&data, &CdcParams::new(1024, 4096, 16384), &mut store).unwrap();
+
+    let (_, chunk_map) = cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384);
+    for (id, chunks) in &chunk_map {
+        assert_eq!(store.get(&id.to_string()).unwrap(), chunks[0]);
+    }
+}
+
+#[test]
+fn dedup_sink_decides_like_the_chunk_map() {
+    let data = data();
+    let params = CdcParams::new(1024, 4096, 16384);
+    let mut sink = DedupSink::new(CountingSink::default());
+    cdc_chunker::stream_to_sink(&data[..], &params, &mut sink).unwrap();
+
+    // The full version: every chunk's bytes, grouped by id.
+    let (chunks, chunk_map) = cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384);
+    let mut first = std::collections::HashSet::new();
+    let expected: Vec<String> = chunks
+        .iter()
+        .map(|chunk| ChunkId::of(chunk).to_string())
+        .filter(|hash| first.insert(hash.clone()))
+        .collect();
+
+    assert_eq!(sink.inner().hashes, expected);
+    assert_eq!(sink.seen().len(), chunk_map.len());
+    assert_eq!(sink.duplicates, chunks.len() - chunk_map.len());
+    let repeated: usize = chunk_map
+        .values()
+        .flat_map(|copies| &copies[1..])
+        .map(Vec::len)
+        .sum();
+    assert!(sink.duplicates > 0);
+    assert_eq!(sink.duplicate_bytes, repeated as u64);
+
+    // Only ids are kept; the bytes went to the inner sink once per distinct chunk.
+    let distinct_bytes: usize = chunk_map.values().map(|copies| copies[0].len()).sum();
+    assert_eq!(sink.inner().data.len(), distinct_bytes);
+    assert!(sink.seen().iter().all(|hash| hash.len() == 64));
+}
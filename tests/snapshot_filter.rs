@@ -0,0 +1,164 @@
+//! Host and tag filters, and retention applied per (host, tags) group.
+
+use rbckp::backup::{
+    manifest::Manifest,
+    retention::{RetentionPolicy, SnapshotGroup},
+    snapshot::{Snapshot, SnapshotFilter},
+};
+use time::{Duration, OffsetDateTime, format_description::well_known::Rfc3339};
+
+fn snapshot(host: &str, tags: &[&str], time: OffsetDateTime) -> Snapshot {
+    let mut snapshot = Snapshot::new(vec!["/data".to_string()], Manifest::new());
+    snapshot.hostname = host.to_string();
+    snapshot.time = time;
+    for tag in tags {
+        snapshot.add_tag(tag);
+    }
+    snapshot
+}
+
+/// Three days of daily snapshots from two hosts, one of which has two tag sets.
+fn snapshots() -> Vec<(String, Snapshot)> {
+    let start = OffsetDateTime::parse("2026-03-01T12:00:00Z", &Rfc3339).unwrap();
+    let mut snapshots = Vec::new();
+    for day in 0..3 {
+        let time = start + Duration::days(day);
+        snapshots.push((
+            format!("laptop-work-{}", day),
+            snapshot("laptop", &["work"], time),
+        ));
+        snapshots.push((
+            format!("laptop-home-{}", day),
+            snapshot("laptop", &["home", "photos"], time + Duration::hours(1)),
+        ));
+        snapshots.push((format!("server-{}", day), snapshot("server", &[], time)));
+    }
+    snapshots
+}
+
+fn matching(filter: &SnapshotFilter) -> Vec<String> {
+    let mut ids: Vec<String> = snapshots()
+        .into_iter()
+        .filter(|(_, snapshot)| filter.matches(snapshot))
+        .map(|(id, _)| id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn snapshot_records_hostname() {
+    let snapshot = Snapshot::new(Vec::new(), Manifest::new());
+    assert!(!snapshot.hostname.is_empty());
+}
+
+#[test]
+fn filter_by_tag_requires_every_tag() {
+    let filter = SnapshotFilter {
+        tags: vec!["home".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        matching(&filter),
+        ["laptop-home-0", "laptop-home-1", "laptop-home-2"]
+    );
+
+    let filter = SnapshotFilter {
+        tags: vec!["home".to_string(), "work".to_string()],
+        ..Default::default()
+    };
+    assert!(matching(&filter).is_empty());
+}
+
+#[test]
+fn filter_by_host_accepts_any_listed_host() {
+    let filter = SnapshotFilter {
+        hosts: vec!["server".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(matching(&filter), ["server-0", "server-1", "server-2"]);
+
+    let filter = SnapshotFilter {
+        hosts: vec!["server".to_string(), "laptop".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(matching(&filter).len(), 9);
+}
+
+#[test]
+fn empty_filter_matches_everything() {
+    assert_eq!(matching(&SnapshotFilter::default()).len(), 9);
+}
+
+#[test]
+fn forget_keeps_last_of_each_group() {
+    let snapshots = snapshots();
+    let refs: Vec<_> = snapshots
+        .iter()
+        .map(|(id, snapshot)| (id.clone(), snapshot))
+        .collect();
+    let policy = RetentionPolicy {
+        keep_last: Some(1),
+        ..Default::default()
+    };
+
+    let groups = policy.apply_grouped(&refs);
+    let summary: Vec<(SnapshotGroup, Vec<String>)> = groups
+        .into_iter()
+        .map(|(group, decisions)| {
+            let kept = decisions
+                .into_iter()
+                .filter(|decision| decision.keep())
+                .map(|decision| decision.id)
+                .collect();
+            (group, kept)
+        })
+        .collect();
+
+    let group = |host: &str, tags: &[&str]| SnapshotGroup {
+        host: host.to_string(),
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+    };
+    assert_eq!(
+        summary,
+        [
+            (
+                group("laptop", &["home", "photos"]),
+                vec!["laptop-home-2".to_string()]
+            ),
+            (
+                group("laptop", &["work"]),
+                vec!["laptop-work-2".to_string()]
+            ),
+            (group("server", &[]), vec!["server-2".to_string()]),
+        ]
+    );
+}
+
+#[test]
+fn forget_groups_only_filtered_snapshots() {
+    let snapshots = snapshots();
+    let filter = SnapshotFilter {
+        hosts: vec!["laptop".to_string()],
+        tags: vec!["work".to_string()],
+    };
+    let refs: Vec<_> = snapshots
+        .iter()
+        .filter(|(_, snapshot)| filter.matches(snapshot))
+        .map(|(id, snapshot)| (id.clone(), snapshot))
+        .collect();
+    let policy = RetentionPolicy {
+        keep_daily: Some(2),
+        ..Default::default()
+    };
+
+    let groups = policy.apply_grouped(&refs);
+    assert_eq!(groups.len(), 1);
+    let forgotten: Vec<_> = groups[0]
+        .1
+        .iter()
+        .filter(|decision| !decision.keep())
+        .map(|decision| decision.id.as_str())
+        .collect();
+    assert_eq!(forgotten, ["laptop-work-0"]);
+}
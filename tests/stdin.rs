@@ -0,0 +1,61 @@
+//! `rbckp backup --stdin`: piped data gives the same entry as backing up a file.
+
+mod common;
+
+use std::{fs, process::Output};
+
+use common::{SETTINGS, noise, rbckp, rbckp_with_input, snapshot_id};
+use rbckp::backup::{manifest::ManifestEntry, snapshot::Snapshot, store::LocalFsBackend};
+
+/// A few MB of pseudo-random bytes, without the zero runs that would become zero chunks.
+fn dump() -> Vec<u8> {
+    noise(3_000_000, 0x2545_f491_4f6c_dd1d)
+}
+
+#[test]
+fn stdin_backup_matches_file_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let data = dump();
+    fs::write(dir.path().join("db.sql"), &data).unwrap();
+
+    rbckp(dir.path(), &["init", "repo"]);
+    let piped = rbckp_with_input(
+        dir.path(),
+        &[
+            "backup",
+            "--repo",
+            "repo",
+            "--stdin",
+            "--stdin-name",
+            "db.sql",
+        ],
+        &data,
+    );
+    let from_file = rbckp(dir.path(), &["backup", "--repo", "repo", "db.sql"]);
+
+    let backend = LocalFsBackend::new(&dir.path().join("repo"));
+    let load = |output: &Output| {
+        let id = Snapshot::resolve_id(&backend, &snapshot_id(output)).unwrap();
+        Snapshot::load(&backend, &id).unwrap()
+    };
+    let (piped, from_file) = (load(&piped), load(&from_file));
+    assert_eq!(piped.paths, ["db.sql"]);
+    assert_eq!(piped.paths, from_file.paths);
+
+    let content = |entry: &ManifestEntry| {
+        (
+            entry.name.clone(),
+            entry.size,
+            entry.chunks.clone(),
+            entry.content_hash.clone(),
+        )
+    };
+    assert_eq!(piped.manifest.entries.len(), 1);
+    assert_eq!(piped.manifest.entries[0].size, data.len() as u64);
+    assert_eq!(
+        content(&piped.manifest.entries[0]),
+        content(&from_file.manifest.entries[0])
+    );
+    assert!(piped.manifest.entries[0].chunks.len() > 1);
+}
@@ -0,0 +1,186 @@
+//! Symlinks: recorded as links by default, followed on request, restored as links,
+//! and never written through on restore.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use common::{SETTINGS, TreeItem, make_tree};
+use rbckp::{
+    backup::{
+        filter::{ExcludeFilter, FileFilter},
+        manifest::Manifest,
+        restore,
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+        walk,
+    },
+    config::Settings,
+};
+
+/// A tree with a regular file and one link of each interesting kind:
+///
+/// ```text
+/// tree/file.txt
+/// tree/relative -> file.txt
+/// tree/absolute -> <outside>/elsewhere.txt
+/// tree/dangling -> missing.txt
+/// tree/dir/loop -> ..
+/// ```
+fn make_links(dir: &Path) -> PathBuf {
+    fs::write(dir.join("elsewhere.txt"), b"elsewhere").unwrap();
+    let elsewhere = dir.join("elsewhere.txt").to_string_lossy().into_owned();
+    make_tree(
+        &dir.join("tree"),
+        &[
+            TreeItem::File("file.txt", b"file"),
+            TreeItem::Symlink("relative", "file.txt"),
+            TreeItem::Symlink("absolute", &elsewhere),
+            TreeItem::Symlink("dangling", "missing.txt"),
+            TreeItem::Dir("dir"),
+            TreeItem::Symlink("dir/loop", ".."),
+        ],
+    )
+}
+
+fn collect(root: &Path, follow_symlinks: bool) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    walk::collect_files(
+        root,
+        &ExcludeFilter::default(),
+        &FileFilter::default(),
+        follow_symlinks,
+        &mut files,
+    )
+    .unwrap();
+    files
+        .into_iter()
+        .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+        .collect()
+}
+
+/// Back up everything `collect_files` finds below `root` and return the manifest.
+fn back_up(dir: &Path, root: &Path, follow_symlinks: bool) -> Manifest {
+    let repo = dir.join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let settings_path = dir.join("settings.ini");
+    fs::write(&settings_path, SETTINGS).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(Settings::from_path(&settings_path).unwrap(), store);
+
+    for relative in collect(root, follow_symlinks) {
+        let path = root.join(relative);
+        if fs::symlink_metadata(&path).unwrap().is_symlink() && !follow_symlinks {
+            session.add_symlink(&path).unwrap();
+        } else {
+            session.add_file(&path).unwrap();
+        }
+    }
+    session.finish().unwrap()
+}
+
+#[test]
+fn links_are_recorded_without_reading_them() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = make_links(dir.path());
+    let manifest = back_up(dir.path(), &root, false);
+
+    let target = |name: &str| {
+        let entry = manifest
+            .get(&root.join(name).to_string_lossy())
+            .unwrap_or_else(|| panic!("{} not in manifest", name));
+        assert!(entry.chunks.is_empty());
+        entry.symlink_target().map(<[u8]>::to_vec)
+    };
+    assert_eq!(target("relative"), Some(b"file.txt".to_vec()));
+    assert_eq!(
+        target("absolute"),
+        Some(
+            dir.path()
+                .join("elsewhere.txt")
+                .to_string_lossy()
+                .into_owned()
+                .into_bytes()
+        )
+    );
+    assert_eq!(target("dangling"), Some(b"missing.txt".to_vec()));
+    assert_eq!(target("dir/loop"), Some(b"..".to_vec()));
+    assert_eq!(
+        manifest
+            .get(&root.join("file.txt").to_string_lossy())
+            .unwrap()
+            .size,
+        4
+    );
+}
+
+#[test]
+fn links_are_restored_as_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = make_links(dir.path());
+    let manifest = back_up(dir.path(), &root, false);
+
+    let out = dir.path().join("out");
+    for entry in &manifest.entries {
+        let Some(target) = entry.symlink_target() else {
+            continue;
+        };
+        let path = restore::restore_path(&out, &entry.name);
+        restore::check_no_symlinks(&out, &path).unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        restore::restore_symlink(entry, &path).unwrap();
+        assert_eq!(
+            fs::read_link(&path).unwrap().as_os_str().as_encoded_bytes(),
+            target
+        );
+    }
+
+    let restored = restore::restore_path(&out, &root.join("dangling").to_string_lossy());
+    assert!(fs::symlink_metadata(&restored).unwrap().is_symlink());
+    assert!(!restored.exists());
+}
+
+#[test]
+fn following_reads_targets_and_stops_at_loops() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = make_links(dir.path());
+
+    // `dir/loop` leads back to the root, which is not walked a second time, and the
+    // dangling link is skipped.
+    let files = collect(&root, true);
+    assert_eq!(
+        files,
+        ["absolute", "file.txt", "relative"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+
+    let manifest = back_up(dir.path(), &root, true);
+    let absolute = manifest
+        .get(&root.join("absolute").to_string_lossy())
+        .unwrap();
+    assert!(absolute.symlink_target().is_none());
+    assert_eq!(absolute.size, b"elsewhere".len() as u64);
+}
+
+#[test]
+fn restore_refuses_to_write_through_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let outside = dir.path().join("outside");
+    fs::create_dir(&outside).unwrap();
+    let out = dir.path().join("out");
+    fs::create_dir(&out).unwrap();
+    symlink(&outside, out.join("escape")).unwrap();
+
+    assert!(restore::check_no_symlinks(&out, &out.join("escape/passwd")).is_err());
+    assert!(restore::check_no_symlinks(&out, &out.join("escape")).is_err());
+    assert!(restore::check_no_symlinks(&out, &out.join("fine/file")).is_ok());
+    assert!(restore::check_no_symlinks(&out, &outside.join("file")).is_err());
+}
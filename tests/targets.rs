@@ -0,0 +1,117 @@
+//! Several backup targets in one snapshot: overlapping targets are backed up once,
+//! relative paths are kept relative to the recorded working directory.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{SETTINGS, back_up, load_snapshot, rbckp};
+use rbckp::backup::{snapshot::Snapshot, walk};
+
+/// A repository next to `src/a.txt`, `src/sub/b.txt` and `notes.txt`.
+fn setup(dir: &Path) {
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    fs::create_dir_all(dir.join("src/sub")).unwrap();
+    fs::write(dir.join("src/a.txt"), "a").unwrap();
+    fs::write(dir.join("src/sub/b.txt"), "b").unwrap();
+    fs::write(dir.join("notes.txt"), "notes").unwrap();
+    rbckp(dir, &["init", "repo"]);
+}
+
+fn names(snapshot: &Snapshot) -> Vec<&str> {
+    let mut names: Vec<&str> = snapshot
+        .manifest
+        .entries
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn overlapping_targets_are_backed_up_once() {
+    let dir = tempfile::tempdir().unwrap();
+    setup(dir.path());
+
+    let id = back_up(
+        dir.path(),
+        &[
+            "-F",
+            "src/sub",
+            "-F",
+            "./src",
+            "-F",
+            "src/sub/b.txt",
+            "-F",
+            "src/",
+            "notes.txt",
+            "./notes.txt",
+        ],
+    );
+    let snapshot = load_snapshot(dir.path(), &id);
+    // Positional paths come first.
+    assert_eq!(snapshot.paths, ["notes.txt", "src"]);
+    assert_eq!(
+        names(&snapshot),
+        ["notes.txt", "src/a.txt", "src/sub/b.txt"]
+    );
+}
+
+#[test]
+fn absolute_paths_are_kept_and_relative_ones_follow_the_cwd() {
+    let dir = tempfile::tempdir().unwrap();
+    setup(dir.path());
+    let elsewhere = tempfile::tempdir().unwrap();
+    let hosts = elsewhere.path().join("hosts");
+    fs::write(&hosts, "127.0.0.1 localhost\n").unwrap();
+    let hosts = hosts.to_str().unwrap();
+
+    let id = back_up(dir.path(), &["-F", "./src", "-F", hosts, "-F", "notes.txt"]);
+    let snapshot = load_snapshot(dir.path(), &id);
+    assert_eq!(snapshot.paths, ["src", hosts, "notes.txt"]);
+    let mut expected = vec!["notes.txt", "src/a.txt", "src/sub/b.txt", hosts];
+    expected.sort();
+    assert_eq!(names(&snapshot), expected);
+    assert_eq!(
+        fs::canonicalize(&snapshot.cwd).unwrap(),
+        fs::canonicalize(dir.path()).unwrap()
+    );
+}
+
+#[test]
+fn same_place_spelled_differently_is_one_root() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src/sub")).unwrap();
+    let absolute = dir.path().join("src");
+    let dotted = dir.path().join("src/sub/../sub");
+
+    for roots in [
+        walk::backup_roots([absolute.as_path(), dotted.as_path()]),
+        walk::backup_roots([dotted.as_path(), absolute.as_path()]),
+    ] {
+        assert_eq!(roots, [absolute.as_path()]);
+    }
+    assert_eq!(
+        walk::backup_roots([Path::new("./"), Path::new("missing")]),
+        [PathBuf::from("."), PathBuf::from("missing")]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_targets_are_not_merged_with_what_they_point_to() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    std::os::unix::fs::symlink("src", dir.path().join("link")).unwrap();
+
+    let src = dir.path().join("src");
+    let link = dir.path().join("link");
+    assert_eq!(
+        walk::backup_roots([src.as_path(), link.as_path()]),
+        [src, link]
+    );
+}
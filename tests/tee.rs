@@ -0,0 +1,183 @@
+//! `TeeBackend`: a repository kept on two backends at once.
+
+mod common;
+
+use std::{
+    fs, io,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use common::SETTINGS;
+use rbckp::{
+    backup::store::{
+        self, Backend, ChunkStore, InMemoryBackend, StoreError, lock::LockKind, tee::TeeBackend,
+    },
+    config::{Settings, StoreKind, TeeSettings},
+};
+
+/// An in-memory backend that can be told to fail writes, and is shared with the test.
+#[derive(Clone, Default)]
+struct Side {
+    objects: Arc<InMemoryBackend>,
+    broken: Arc<AtomicBool>,
+}
+
+impl Backend for Side {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.objects.read(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        if self.broken.load(Ordering::SeqCst) {
+            return Err(io::Error::other("disk on fire"));
+        }
+        self.objects.write(name, data)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.objects.list(prefix)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.objects.remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.objects.exists(name)
+    }
+}
+
+fn tee(primary: &Side, secondary: &Side) -> TeeBackend {
+    TeeBackend::new(Box::new(primary.clone()), Box::new(secondary.clone()))
+}
+
+fn chunk(i: usize) -> (String, Vec<u8>) {
+    let data = format!("chunk number {}", i).into_bytes();
+    (blake3::hash(&data).to_hex().to_string(), data)
+}
+
+#[test]
+fn both_sides_get_every_object_and_reads_fall_back() {
+    let (primary, secondary) = (Side::default(), Side::default());
+    ChunkStore::init(&tee(&primary, &secondary)).unwrap();
+    let mut store = ChunkStore::open(tee(&primary, &secondary), 1 << 20, LockKind::Shared).unwrap();
+    for i in 0..100 {
+        let (hash, data) = chunk(i);
+        assert!(store.put(&hash, &data).unwrap());
+    }
+    store.flush().unwrap();
+
+    let names = primary.list("").unwrap();
+    assert!(names.iter().any(|name| name.starts_with("packs/")));
+    assert_eq!(secondary.list("").unwrap(), names);
+    for name in &names {
+        assert_eq!(primary.read(name).unwrap(), secondary.read(name).unwrap());
+    }
+
+    // The primary loses its packs; chunks are still read from the secondary.
+    for name in names.iter().filter(|name| name.starts_with("packs/")) {
+        primary.remove(name).unwrap();
+    }
+    let store = ChunkStore::open(tee(&primary, &secondary), 1 << 20, LockKind::Shared).unwrap();
+    let (hash, data) = chunk(42);
+    assert!(store.contains(&hash));
+    assert_eq!(store.get(&hash).unwrap(), data);
+
+    // Either side is a repository of its own.
+    let store = ChunkStore::open(secondary.clone(), 1 << 20, LockKind::Shared).unwrap();
+    assert_eq!(store.get(&hash).unwrap(), data);
+
+    let both = tee(&primary, &secondary);
+    assert!(both.exists(&names[0]).unwrap());
+    both.remove(&names[0]).unwrap();
+    assert!(!both.exists(&names[0]).unwrap());
+    assert_eq!(
+        both.remove(&names[0]).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn failed_write_on_one_side_says_which() {
+    let (primary, secondary) = (Side::default(), Side::default());
+    ChunkStore::init(&tee(&primary, &secondary)).unwrap();
+    let mut store = ChunkStore::open(tee(&primary, &secondary), 1 << 20, LockKind::Shared).unwrap();
+    let (hash, data) = chunk(0);
+    store.put(&hash, &data).unwrap();
+
+    secondary.broken.store(true, Ordering::SeqCst);
+    match store.flush() {
+        Err(StoreError::TeeWritePartial {
+            primary_ok,
+            secondary_ok,
+            error,
+        }) => {
+            assert!(primary_ok && !secondary_ok);
+            assert_eq!(error.to_string(), "disk on fire");
+        }
+        other => panic!("expected a partial write, got {:?}", other),
+    }
+    assert!(
+        primary
+            .list("packs/")
+            .unwrap()
+            .iter()
+            .any(|name| name.ends_with(".pack"))
+    );
+    assert!(secondary.list("packs/").unwrap().is_empty());
+
+    primary.broken.store(true, Ordering::SeqCst);
+    let err = StoreError::from(tee(&primary, &secondary).write("x", b"x").unwrap_err());
+    assert!(matches!(
+        err,
+        StoreError::TeeWritePartial {
+            primary_ok: false,
+            secondary_ok: false,
+            ..
+        }
+    ));
+    assert!(err.to_string().contains("both sides"));
+}
+
+fn load_settings(dir: &Path, store: &str) -> Result<Settings, config::ConfigError> {
+    let path = dir.join("settings.ini");
+    fs::write(&path, format!("{}[store]\n{}", SETTINGS, store)).unwrap();
+    Settings::from_path(&path)
+}
+
+#[test]
+fn store_section_sets_up_both_sides() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = load_settings(
+        dir.path(),
+        "type=tee\nprimary.type=local\nsecondary.type=s3\nbucket=my-backups\nprefix=rbckp\n",
+    )
+    .unwrap();
+    assert_eq!(settings.backend.default_kind, StoreKind::Tee);
+    assert_eq!(
+        settings.backend.tee,
+        Some(TeeSettings {
+            primary: StoreKind::Local,
+            secondary: StoreKind::S3,
+        })
+    );
+    assert_eq!(settings.backend.s3.as_ref().unwrap().bucket, "my-backups");
+    // Each side resolves the name on its own.
+    assert_eq!(
+        store::repo_location(Path::new("laptop"), &settings.backend),
+        Path::new("laptop")
+    );
+
+    assert!(load_settings(dir.path(), "type=tee\nprimary.type=local\n").is_err());
+    assert!(
+        load_settings(
+            dir.path(),
+            "type=tee\nprimary.type=tee\nsecondary.type=local\n"
+        )
+        .is_err()
+    );
+}
@@ -0,0 +1,171 @@
+//! The canonical chunk-boundary test vectors, and the chunker format version
+//! repositories record.
+//!
+//! A failure here means the gear table, the shift or the boundary mask changed: stores
+//! would no longer deduplicate against their existing chunks. If that is intended,
+//! bump `CHUNKER_FORMAT_VERSION` and update the vectors.
+
+use rbckp::backup::{
+    cdc_chunker::{
+        self, CHUNKER_FORMAT_VERSION, CdcParams, StreamChunker,
+        test_vectors::{Input, REF_VECTORS, TEST_VECTORS, TestVector},
+    },
+    store::{
+        Backend, ChunkStore, LocalFsBackend,
+        repo_config::{REPO_CONFIG_NAME, RepoConfig},
+    },
+};
+
+#[test]
+fn boundaries_match_the_vectors() {
+    let mut failures = Vec::new();
+    for vector in TEST_VECTORS {
+        let ends = vector.cut();
+        if ends != vector.ends {
+            failures.push(format!(
+                "{}: expected {:?}\n  got {:?}",
+                vector.name, vector.ends, ends
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "chunk boundaries moved for chunker format {}:\n{}",
+        CHUNKER_FORMAT_VERSION,
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn vectors_are_well_formed() {
+    for vector in TEST_VECTORS {
+        let len = vector.input.bytes().len();
+        assert_eq!(vector.ends.last(), Some(&len), "{}", vector.name);
+        let mut start = 0;
+        for &end in vector.ends {
+            assert!(end - start <= vector.max_chunk_size, "{}", vector.name);
+            if end < len {
+                assert!(end - start >= vector.min_chunk_size, "{}", vector.name);
+            }
+            start = end;
+        }
+    }
+
+    // Every kind of input is covered, and at least one non-default shift.
+    assert!(
+        TEST_VECTORS
+            .iter()
+            .any(|v| matches!(v.input, Input::Zeros(_)))
+    );
+    assert!(
+        TEST_VECTORS
+            .iter()
+            .any(|v| matches!(v.input, Input::Noise { .. }))
+    );
+    assert!(
+        TEST_VECTORS
+            .iter()
+            .any(|v| matches!(v.input, Input::Text(_)))
+    );
+    assert!(TEST_VECTORS.iter().any(|v| v.gear_shift != 1));
+}
+
+/// Whether the public chunkers make a zero chunk of some of `vector`'s input, which
+/// its ends therefore do not describe.
+fn has_zero_run(vector: &TestVector) -> bool {
+    cdc_chunker::chunk_refs_cdc(&vector.input.bytes(), &vector.params())
+        .iter()
+        .any(|chunk| cdc_chunker::zero_chunk_len(&chunk.hash).is_some())
+}
+
+#[test]
+fn vectors_pin_the_public_chunkers() {
+    // Inputs with zero runs are pinned through the public chunkers by REF_VECTORS.
+    let vectors: Vec<&TestVector> = TEST_VECTORS.iter().filter(|v| !has_zero_run(v)).collect();
+    assert!(vectors.len() < TEST_VECTORS.len());
+
+    // The default gear table and parameters are what the vectors are cut with.
+    for vector in vectors.iter().filter(|v| v.gear_shift == 1) {
+        let data = vector.input.bytes();
+        let ends = cdc_chunker::chunk_boundaries_cdc(
+            &data,
+            vector.min_chunk_size,
+            vector.target_avg_chunk_size,
+            vector.max_chunk_size,
+        );
+        assert_eq!(ends, vector.ends, "{}", vector.name);
+    }
+    for vector in vectors {
+        let data = vector.input.bytes();
+        assert_eq!(
+            cdc_chunker::chunk_boundaries_scalar(&data, &vector.params()),
+            vector.ends,
+            "{}",
+            vector.name
+        );
+    }
+    assert_eq!(
+        TEST_VECTORS[0].params(),
+        CdcParams::new(1024, 4096, 16384).with_gear_shift(1)
+    );
+}
+
+#[test]
+fn chunk_refs_match_the_vectors() {
+    for vector in REF_VECTORS {
+        assert_eq!(vector.cut(), vector.chunks, "{}", vector.name);
+        assert!(
+            vector.chunks.iter().any(|&(_, zero)| zero),
+            "{}",
+            vector.name
+        );
+
+        // Zero chunks are named by their length, and every other chunk is cut and
+        // named the same by the parallel and the streaming chunker.
+        let data = vector.input.bytes();
+        let refs = cdc_chunker::chunk_refs_cdc(&data, &vector.params());
+        for chunk in &refs {
+            if let Some(len) = cdc_chunker::zero_chunk_len(&chunk.hash) {
+                assert_eq!(len, chunk.len, "{}", vector.name);
+                assert_eq!(chunk.hash, cdc_chunker::zero_chunk_id(len));
+            }
+        }
+        assert_eq!(
+            cdc_chunker::chunk_refs_cdc_parallel(&data, &vector.params()),
+            refs,
+            "{}",
+            vector.name
+        );
+        let streamed: Vec<_> = StreamChunker::new(data.as_slice(), &vector.params())
+            .map(|chunk| chunk.unwrap().0)
+            .collect();
+        assert_eq!(streamed, refs, "{}", vector.name);
+    }
+}
+
+#[test]
+fn repositories_record_the_chunker_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = LocalFsBackend::new(dir.path());
+    let config = ChunkStore::init(&backend).unwrap();
+    assert_eq!(config.chunker_version, CHUNKER_FORMAT_VERSION);
+    assert!(config.chunker_compatible());
+
+    let stored: serde_json::Value =
+        serde_json::from_slice(&backend.read(REPO_CONFIG_NAME).unwrap()).unwrap();
+    assert_eq!(stored["chunker_version"], CHUNKER_FORMAT_VERSION);
+
+    // Repositories from before the version was recorded read as unknown.
+    let mut old = stored.clone();
+    old.as_object_mut().unwrap().remove("chunker_version");
+    let old: RepoConfig = serde_json::from_value(old).unwrap();
+    assert_eq!(old.chunker_version, 0);
+    assert!(old.chunker_compatible());
+
+    // A different format is detected rather than silently deduplicated against.
+    let newer = RepoConfig {
+        chunker_version: CHUNKER_FORMAT_VERSION + 1,
+        ..config
+    };
+    assert!(!newer.chunker_compatible());
+}
@@ -0,0 +1,68 @@
+//! Phase timings add up across phases, and a backup session fills them in.
+
+mod common;
+
+use std::{fs, time::Duration};
+
+use common::SETTINGS;
+use rbckp::{
+    backup::{
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+        timing::{self, Phase, PhaseTimings},
+    },
+    config::Settings,
+};
+
+#[test]
+fn timings_accumulate_per_phase() {
+    let mut timings = PhaseTimings::default();
+    timings.add(Phase::Read, Duration::from_millis(30));
+    timings.add(Phase::Hash, Duration::from_millis(5));
+    timings.add(Phase::Read, Duration::from_millis(20));
+    timings.add(Phase::Chunk, Duration::from_millis(7));
+
+    assert_eq!(timings.get(Phase::Read), Duration::from_millis(50));
+    assert_eq!(timings.get(Phase::Chunk), Duration::from_millis(7));
+    assert_eq!(timings.get(Phase::Hash), Duration::from_millis(5));
+    assert_eq!(timings.total(), Duration::from_millis(62));
+
+    let value = timings.time(Phase::Chunk, || {
+        std::thread::sleep(Duration::from_millis(10));
+        42
+    });
+    assert_eq!(value, 42);
+    assert!(timings.chunk >= Duration::from_millis(17));
+    assert_eq!(timings.read, Duration::from_millis(50));
+    assert_eq!(timings.hash, Duration::from_millis(5));
+}
+
+#[test]
+fn throughput_in_mb_per_second() {
+    assert_eq!(
+        timing::throughput_mb_s(5_000_000, Duration::from_secs(2)),
+        2.5
+    );
+    assert_eq!(timing::throughput_mb_s(1_000, Duration::ZERO), 0.0);
+}
+
+#[test]
+fn session_times_every_phase() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    fs::write(dir.path().join("settings.ini"), SETTINGS).unwrap();
+    let settings = Settings::from_path(&dir.path().join("settings.ini")).unwrap();
+    let data: Vec<u8> = (0..1_000_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    fs::write(dir.path().join("data.bin"), &data).unwrap();
+
+    let mut session = BackupSession::new(settings, store);
+    assert_eq!(*session.timings(), PhaseTimings::default());
+    session.add_file(&dir.path().join("data.bin")).unwrap();
+    let timings = *session.timings();
+    assert!(timings.read > Duration::ZERO);
+    assert!(timings.chunk > Duration::ZERO);
+    assert!(timings.hash > Duration::ZERO);
+}
@@ -0,0 +1,146 @@
+//! `ChunkStore::with_upload_batch`: full packs are held back and written together,
+//! with one index update per batch.
+
+mod common;
+
+use std::{
+    fs, io,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::store::{Backend, ChunkStore, InMemoryBackend, lock::LockKind},
+    config::{Settings, UploadSettings},
+};
+
+/// An in-memory backend that counts the packs and indexes written to it.
+#[derive(Clone, Default)]
+struct Counting {
+    objects: Arc<InMemoryBackend>,
+    packs: Arc<AtomicUsize>,
+    indexes: Arc<AtomicUsize>,
+}
+
+impl Counting {
+    fn packs(&self) -> usize {
+        self.packs.load(Ordering::SeqCst)
+    }
+
+    fn indexes(&self) -> usize {
+        self.indexes.load(Ordering::SeqCst)
+    }
+}
+
+impl Backend for Counting {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.objects.read(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        if name.ends_with(".pack") {
+            self.packs.fetch_add(1, Ordering::SeqCst);
+        } else if name == "index.json" {
+            self.indexes.fetch_add(1, Ordering::SeqCst);
+        }
+        self.objects.write(name, data)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.objects.list(prefix)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.objects.remove(name)
+    }
+
+    fn exists(&self, name: &str) -> io::Result<bool> {
+        self.objects.exists(name)
+    }
+}
+
+/// `count` distinct chunks of 1000 bytes, with their hashes.
+fn chunks(count: u64) -> Vec<(String, Vec<u8>)> {
+    (0..count)
+        .map(|seed| {
+            let chunk = noise(1000, seed + 1);
+            (blake3::hash(&chunk).to_hex().to_string(), chunk)
+        })
+        .collect()
+}
+
+fn open(backend: &Counting, batch: usize) -> ChunkStore<Counting> {
+    // Every chunk fills a pack.
+    ChunkStore::open(backend.clone(), 1000, LockKind::Shared)
+        .unwrap()
+        .with_upload_batch(batch)
+}
+
+#[test]
+fn packs_are_written_a_batch_at_a_time() {
+    let backend = Counting::default();
+    ChunkStore::init(&backend).unwrap();
+    let mut store = open(&backend, 3);
+    let indexes = backend.indexes();
+    let chunks = chunks(7);
+
+    for (count, (hash, chunk)) in chunks.iter().enumerate() {
+        assert!(store.put(hash, chunk).unwrap());
+        // Packs wait until three are full.
+        assert_eq!(backend.packs(), (count + 1) / 3 * 3);
+    }
+    assert_eq!(backend.indexes(), indexes + 2);
+
+    // Held back chunks are found, and deduplicated, before they are written.
+    let (hash, chunk) = &chunks[6];
+    assert!(store.contains(hash));
+    assert_eq!(store.chunk_len(hash), Some(1000));
+    assert_eq!(&store.get(hash).unwrap(), chunk);
+    assert!(!store.put(hash, chunk).unwrap());
+
+    store.flush().unwrap();
+    assert_eq!(backend.packs(), 7);
+    assert_eq!(backend.indexes(), indexes + 3);
+    drop(store);
+
+    let store = open(&backend, 1);
+    for (hash, chunk) in &chunks {
+        assert_eq!(&store.get(hash).unwrap(), chunk);
+    }
+}
+
+#[test]
+fn without_a_batch_every_pack_is_written_at_once() {
+    let backend = Counting::default();
+    ChunkStore::init(&backend).unwrap();
+    let mut store = open(&backend, 1);
+    let indexes = backend.indexes();
+
+    for (hash, chunk) in chunks(4) {
+        store.put(&hash, &chunk).unwrap();
+    }
+    assert_eq!(backend.packs(), 4);
+    assert_eq!(backend.indexes(), indexes + 4);
+}
+
+#[test]
+fn upload_section_sets_the_batch() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.ini");
+    let settings = |upload: &str| {
+        fs::write(&path, format!("{}{}", SETTINGS, upload)).unwrap();
+        Settings::from_path(&path).unwrap()
+    };
+
+    assert_eq!(settings("").backend.upload, UploadSettings::default());
+    assert_eq!(
+        settings("[upload]\nbatch_packs=8\n")
+            .backend
+            .upload
+            .batch_packs,
+        8
+    );
+}
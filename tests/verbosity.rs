@@ -0,0 +1,96 @@
+//! `--quiet` / `--verbose` and the `debug` setting: status lines go to stdout and log
+//! messages to stderr, each only at the levels that ask for them.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+const SETTINGS: &str = "[chunk_settings]\nmin=1024\navg=4096\nmax=16384\n";
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_rbckp"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "rbckp {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn quiet_suppresses_status_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("settings.ini"),
+        format!("debug=false\n{}", SETTINGS),
+    )
+    .unwrap();
+    fs::write(dir.path().join("file.txt"), "content").unwrap();
+    run(dir.path(), &["init", "repo"]);
+
+    let backup = |extra: &[&str]| {
+        let mut args = vec!["backup", "--repo", "repo", "file.txt"];
+        args.extend(extra);
+        run(dir.path(), &args)
+    };
+    let normal = backup(&[]);
+    assert!(
+        stdout(&normal).starts_with("Snapshot "),
+        "{}",
+        stdout(&normal)
+    );
+    assert_eq!(stderr(&normal), "");
+
+    let quiet = backup(&["-q"]);
+    assert_eq!(stdout(&quiet), "");
+    assert_eq!(stderr(&quiet), "");
+
+    // Data a command exists to show is printed even when quiet.
+    let list = run(dir.path(), &["list-snapshots", "--repo", "repo", "--quiet"]);
+    assert_eq!(stdout(&list).lines().count(), 2);
+}
+
+#[test]
+fn verbose_and_debug_setting_add_debug_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("data.txt"), "some text to chunk").unwrap();
+    let chunk = |extra: &[&str]| {
+        let mut args = vec!["-F", "data.txt"];
+        args.extend(extra);
+        // Chunking writes a preview and will not overwrite an old one.
+        let _ = fs::remove_file(dir.path().join("output.txt"));
+        stderr(&run(dir.path(), &args))
+    };
+
+    fs::write(
+        dir.path().join("settings.ini"),
+        format!("debug=false\n{}", SETTINGS),
+    )
+    .unwrap();
+    assert!(!chunk(&[]).contains("Current settings"));
+    assert!(chunk(&["-v"]).contains("Current settings"));
+    assert!(!chunk(&["-q"]).contains("Current settings"));
+
+    fs::write(
+        dir.path().join("settings.ini"),
+        format!("debug=true\n{}", SETTINGS),
+    )
+    .unwrap();
+    assert!(chunk(&[]).contains("Current settings"));
+    assert!(!chunk(&["-q"]).contains("Current settings"));
+}
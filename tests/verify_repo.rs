@@ -0,0 +1,174 @@
+//! `verify_repo` and `rbckp verify`: structural checks always, data re-reads for all
+//! chunks or a stable subset of them.
+
+mod common;
+
+use std::{collections::HashSet, fs, path::Path, process::Command};
+
+use common::{SETTINGS, noise};
+use rbckp::{
+    backup::{
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+        verify::{self, DataSubset, RepoProblem},
+    },
+    config::Settings,
+};
+
+fn repo(path: &Path) -> ChunkStore<LocalFsBackend> {
+    ChunkStore::open(LocalFsBackend::new(path), 1 << 18, LockKind::Shared).unwrap()
+}
+
+/// A repository with one snapshot of about 250 chunks in several packs.
+fn backed_up(dir: &Path) -> ChunkStore<LocalFsBackend> {
+    let repo_path = dir.join("repo");
+    fs::create_dir(&repo_path).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo_path)).unwrap();
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    let settings = Settings::from_path(&dir.join("settings.ini")).unwrap();
+    let mut session = BackupSession::new(settings, repo(&repo_path));
+    session.add_bytes("a", &noise(1 << 20, 1)).unwrap();
+    session.commit(vec![], &[]).unwrap();
+    repo(&repo_path)
+}
+
+#[test]
+fn buckets_cover_every_chunk_exactly_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = backed_up(dir.path());
+    let ids: Vec<String> = store.chunks().map(|(hash, _)| hash).collect();
+    assert!(ids.len() > 100);
+
+    let mut covered = HashSet::new();
+    let mut reads = 0;
+    for bucket in 1..=7 {
+        let subset = DataSubset::Bucket { bucket, buckets: 7 };
+        let report = verify::verify_repo(&store, Some(&subset)).unwrap();
+        assert!(report.is_clean(), "{:?}", report.problems);
+        assert_eq!(report.chunks, ids.len());
+        assert!(report.chunks_read > 0 && report.chunks_read < ids.len());
+        // The same chunks every time.
+        assert_eq!(verify::verify_repo(&store, Some(&subset)).unwrap(), report);
+        reads += report.chunks_read;
+
+        for hash in ids.iter().filter(|hash| subset.contains(hash)) {
+            assert!(covered.insert(hash.clone()), "{} in two buckets", hash);
+        }
+    }
+    assert_eq!(reads, ids.len());
+    assert_eq!(covered.len(), ids.len());
+
+    let report = verify::verify_repo(&store, None).unwrap();
+    assert_eq!((report.snapshots, report.chunks_read), (1, 0));
+    let report = verify::verify_repo(&store, Some(&DataSubset::ALL)).unwrap();
+    assert_eq!(report.chunks_read, ids.len());
+    assert_eq!(report.bytes_read, 1 << 20);
+    let some = verify::verify_repo(&store, Some(&DataSubset::Percent(20.0))).unwrap();
+    assert!(some.chunks_read > ids.len() / 10 && some.chunks_read < ids.len() * 3 / 10);
+}
+
+#[test]
+fn damaged_chunks_and_packs_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = backed_up(dir.path());
+    let (hash, location) = store.chunks().next().map(|(hash, l)| (hash, *l)).unwrap();
+    let pack = dir
+        .path()
+        .join("repo")
+        .join(store.pack_name(location.pack_id));
+    let mut data = fs::read(&pack).unwrap();
+    data[location.offset as usize] ^= 0xff;
+    fs::write(&pack, data).unwrap();
+
+    // The structure is intact; only reading the chunk finds the damage.
+    assert!(verify::verify_repo(&store, None).unwrap().is_clean());
+    let corrupt = RepoProblem::CorruptChunk {
+        hash: hash.clone(),
+        pack: store.pack_name(location.pack_id),
+    };
+    let report = verify::verify_repo(&store, Some(&DataSubset::ALL)).unwrap();
+    assert_eq!(report.problems, std::slice::from_ref(&corrupt));
+    let found: Vec<RepoProblem> = (1..=5)
+        .flat_map(|bucket| {
+            let subset = DataSubset::Bucket { bucket, buckets: 5 };
+            verify::verify_repo(&store, Some(&subset)).unwrap().problems
+        })
+        .collect();
+    assert_eq!(found, [corrupt]);
+
+    // A lost pack takes its chunks out of the index on the next open, so the
+    // snapshot refers to missing chunks.
+    fs::remove_file(&pack).unwrap();
+    let report = verify::verify_repo(&repo(&dir.path().join("repo")), None).unwrap();
+    assert!(report.problems.iter().any(|problem| matches!(
+        problem,
+        RepoProblem::MissingChunk { hash: missing, .. } if *missing == hash
+    )));
+    // While the index still points into it, the pack itself is reported.
+    let report = verify::verify_repo(&store, None).unwrap();
+    assert!(report.problems.iter().any(|problem| matches!(
+        problem,
+        RepoProblem::BadPack { name, .. } if *name == store.pack_name(location.pack_id)
+    )));
+}
+
+#[test]
+fn subsets_parse() {
+    assert_eq!("10%".parse(), Ok(DataSubset::Percent(10.0)));
+    assert_eq!("2.5%".parse(), Ok(DataSubset::Percent(2.5)));
+    assert_eq!(
+        "3/7".parse(),
+        Ok(DataSubset::Bucket {
+            bucket: 3,
+            buckets: 7
+        })
+    );
+    for bad in ["101%", "-1%", "x%", "0/7", "8/7", "3/0", "3", "1/2/3"] {
+        assert!(bad.parse::<DataSubset>().is_err(), "{}", bad);
+    }
+    assert_eq!(
+        DataSubset::Bucket {
+            bucket: 3,
+            buckets: 7
+        }
+        .to_string(),
+        "3/7"
+    );
+}
+
+#[test]
+fn verify_command_fails_on_damage() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = backed_up(dir.path());
+    let verify = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rbckp"))
+            .current_dir(dir.path())
+            .args(["verify", "--repo", "repo"])
+            .args(args)
+            .args(["--quiet", "--config", "settings.ini"])
+            .output()
+            .unwrap()
+    };
+    assert!(verify(&["--read-data-subset", "1/3"]).status.success());
+    assert!(!verify(&["--read-data-subset", "4/3"]).status.success());
+    assert!(
+        !verify(&["--read-data", "--read-data-subset", "10%"])
+            .status
+            .success()
+    );
+
+    let (hash, location) = store.chunks().next().map(|(hash, l)| (hash, *l)).unwrap();
+    let pack = dir
+        .path()
+        .join("repo")
+        .join(store.pack_name(location.pack_id));
+    let mut data = fs::read(&pack).unwrap();
+    data[location.offset as usize] ^= 0xff;
+    fs::write(&pack, data).unwrap();
+    drop(store);
+
+    assert!(verify(&[]).status.success());
+    let output = verify(&["--read-data"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains(&hash));
+}
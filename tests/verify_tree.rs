@@ -0,0 +1,113 @@
+//! `rbckp verify-tree`: comparing a live directory with a snapshot.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use common::{SETTINGS, TreeItem, make_tree, noise, rbckp, rbckp_command};
+use rbckp::{
+    backup::{
+        restore,
+        session::BackupSession,
+        store::{ChunkStore, LocalFsBackend, lock::LockKind},
+        verify,
+    },
+    config::Settings,
+};
+
+/// `data/big.bin` (3 MB), `data/small.txt` and `data/sub/other.txt`.
+fn make_data(dir: &Path) -> PathBuf {
+    fs::write(dir.join("settings.ini"), SETTINGS).unwrap();
+    make_tree(
+        &dir.join("data"),
+        &[
+            TreeItem::File("big.bin", &noise(3 << 20, 9)),
+            TreeItem::File("small.txt", b"small"),
+            TreeItem::File("sub/other.txt", b"other"),
+        ],
+    )
+}
+
+#[test]
+fn byte_change_is_only_found_by_reading_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = make_data(dir.path());
+    rbckp(dir.path(), &["init", "repo"]);
+    rbckp(dir.path(), &["backup", "--repo", "repo", "data"]);
+    let list = rbckp(dir.path(), &["list-snapshots", "--repo", "repo"]);
+    let id = String::from_utf8(list.stdout).unwrap()[..12].to_string();
+    let verify = |extra: &[&str]| {
+        let mut args = vec![
+            "verify-tree",
+            "--repo",
+            "repo",
+            "--snapshot",
+            &id,
+            "--path",
+            "data",
+        ];
+        args.extend(extra);
+        rbckp_command(dir.path(), &args).output().unwrap()
+    };
+    assert!(verify(&[]).status.success());
+    assert!(verify(&["--read-data"]).status.success());
+
+    // Flip one byte in the middle, keeping size and modification time.
+    let big = data.join("big.bin");
+    let mtime = fs::metadata(&big).unwrap().modified().unwrap();
+    let file = File::options().read(true).write(true).open(&big).unwrap();
+    let mut byte = [0];
+    file.read_exact_at(&mut byte, 3 << 19).unwrap();
+    file.write_all_at(&[!byte[0]], 3 << 19).unwrap();
+    file.set_modified(mtime).unwrap();
+    drop(file);
+
+    assert!(verify(&[]).status.success());
+    let output = verify(&["--read-data"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "modified data/big.bin\n"
+    );
+}
+
+#[test]
+fn reports_added_missing_and_modified() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = make_data(dir.path());
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let settings = Settings::from_path(&dir.path().join("settings.ini")).unwrap();
+    let mut session = BackupSession::new(settings, store);
+    for name in ["big.bin", "small.txt", "sub/other.txt"] {
+        session.add_file(&data.join(name)).unwrap();
+    }
+    let manifest = session.finish().unwrap();
+
+    let report = verify::verify_tree(&manifest, &data, None).unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.checked, 3);
+
+    fs::remove_file(data.join("sub/other.txt")).unwrap();
+    fs::write(data.join("new.txt"), "new").unwrap();
+    fs::write(data.join("small.txt"), "changed").unwrap();
+
+    let report = verify::verify_tree(&manifest, &data, None).unwrap();
+    let key = |name: &str| restore::entry_path(&data.join(name).to_string_lossy());
+    assert_eq!(report.added, [key("new.txt")]);
+    assert_eq!(report.missing, [key("sub/other.txt")]);
+    assert_eq!(report.modified, [key("small.txt")]);
+    assert!(!report.is_clean());
+
+    // Only the part of the snapshot below the checked path counts.
+    let report = verify::verify_tree(&manifest, &data.join("sub"), None).unwrap();
+    assert_eq!(report.missing, [key("sub/other.txt")]);
+    assert!(report.added.is_empty());
+}
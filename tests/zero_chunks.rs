@@ -0,0 +1,240 @@
+//! Long runs of zero bytes become zero chunks, recorded by length only.
+
+mod common;
+
+use common::{SETTINGS, nonzero_noise};
+use std::io::{self, Read};
+
+use bytes::Bytes;
+use rbckp::backup::{
+    cdc_chunker::{self, CdcParams, StreamChunker, ZERO_CHUNK_MAX, ZERO_RUN_MIN},
+    restore,
+    session::BackupSession,
+    sink::ChunkSink,
+    store::{ChunkStore, LocalFsBackend, StoreError, lock::LockKind},
+};
+use rbckp::config::Settings;
+
+fn params() -> CdcParams {
+    CdcParams::new(1024, 4096, 16384)
+}
+
+#[test]
+fn zero_region_becomes_one_zero_chunk() {
+    let mut data = nonzero_noise(100_000, 1);
+    data.extend(vec![0u8; 1 << 20]);
+    data.extend(nonzero_noise(100_000, 2));
+
+    let refs = cdc_chunker::chunk_refs_cdc(&data, &params());
+    let zero: Vec<_> = refs
+        .iter()
+        .filter(|chunk_ref| cdc_chunker::zero_chunk_len(&chunk_ref.hash).is_some())
+        .collect();
+    assert_eq!(zero.len(), 1);
+    assert_eq!(zero[0].offset, 100_000);
+    assert_eq!(zero[0].len, 1 << 20);
+    assert_eq!(zero[0].hash, cdc_chunker::zero_chunk_id(1 << 20));
+
+    // The chunks still tile the input, in order.
+    let mut offset = 0;
+    for chunk_ref in &refs {
+        assert_eq!(chunk_ref.offset, offset);
+        offset += chunk_ref.len;
+    }
+    assert_eq!(offset, data.len());
+    assert_eq!(refs, cdc_chunker::chunk_refs_cdc_parallel(&data, &params()));
+}
+
+#[test]
+fn short_zero_runs_are_chunked_normally() {
+    let mut data = nonzero_noise(50_000, 3);
+    data.extend(vec![0u8; ZERO_RUN_MIN - 1]);
+    data.extend(nonzero_noise(50_000, 4));
+
+    let refs = cdc_chunker::chunk_refs_cdc(&data, &params());
+    assert!(
+        refs.iter()
+            .all(|chunk_ref| cdc_chunker::zero_chunk_len(&chunk_ref.hash).is_none())
+    );
+}
+
+#[test]
+fn zero_chunks_are_not_stored_and_restore_as_zeros() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    ChunkStore::init(&LocalFsBackend::new(&repo)).unwrap();
+    let settings_path = dir.path().join("settings.ini");
+    std::fs::write(&settings_path, SETTINGS).unwrap();
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut session = BackupSession::new(Settings::from_path(&settings_path).unwrap(), store);
+
+    let mut data = vec![0u8; 300_000];
+    data.extend(nonzero_noise(20_000, 5));
+    data.extend(vec![0u8; 200_000]);
+    session.add_bytes("image", &data).unwrap();
+    let stats = *session.stats();
+    let manifest = session.finish().unwrap();
+
+    let stored: u64 = stats.new_bytes;
+    assert!(stored <= 20_000 + 16384, "stored {} bytes", stored);
+
+    let store = ChunkStore::open(LocalFsBackend::new(&repo), 1 << 20, LockKind::Shared).unwrap();
+    let mut restored = Vec::new();
+    restore::write_entry(&manifest.entries[0], &store, &mut restored).unwrap();
+    assert_eq!(restored, data);
+}
+
+/// Hands out at most `step` bytes per read, so runs straddle many refills.
+struct Trickle<'a> {
+    data: &'a [u8],
+    step: usize,
+}
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.step).min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Ok(len)
+    }
+}
+
+fn streamed(data: &[u8], step: usize) -> Vec<cdc_chunker::ChunkRef> {
+    StreamChunker::new(Trickle { data, step }, &params())
+        .map(|chunk| {
+            let (chunk_ref, chunk) = chunk.unwrap();
+            assert_eq!(
+                chunk,
+                data[chunk_ref.offset..chunk_ref.offset + chunk_ref.len]
+            );
+            chunk_ref
+        })
+        .collect()
+}
+
+#[test]
+fn stream_chunker_cuts_zero_runs_like_slices() {
+    let mut data = vec![0u8; ZERO_RUN_MIN + 10];
+    data.extend(nonzero_noise(70_000, 6));
+    data.extend(vec![0u8; ZERO_RUN_MIN - 1]);
+    data.extend(nonzero_noise(30_000, 7));
+    data.extend(vec![0u8; 3 * ZERO_RUN_MIN + 123]);
+    data.extend(nonzero_noise(500, 8));
+    data.extend(vec![0u8; ZERO_RUN_MIN]);
+    data.extend(nonzero_noise(100_000, 9));
+    data.extend(vec![0u8; 2 * ZERO_RUN_MIN]);
+
+    let refs = cdc_chunker::chunk_refs_cdc(&data, &params());
+    assert_eq!(
+        refs.iter()
+            .filter(|chunk_ref| cdc_chunker::zero_chunk_len(&chunk_ref.hash).is_some())
+            .count(),
+        4
+    );
+    for step in [1000, 4096, 1 << 20] {
+        assert_eq!(streamed(&data, step), refs, "reads of {} bytes", step);
+    }
+}
+
+#[test]
+fn stream_chunker_splits_long_zero_runs_like_slices() {
+    let mut data = nonzero_noise(10_000, 10);
+    data.extend(vec![0u8; ZERO_CHUNK_MAX + 5]);
+    data.extend(nonzero_noise(10_000, 11));
+    data.extend(vec![0u8; ZERO_CHUNK_MAX]);
+    data.extend(vec![0u8; ZERO_RUN_MIN]);
+
+    let refs = cdc_chunker::chunk_refs_cdc(&data, &params());
+    let zero_lens: Vec<_> = refs
+        .iter()
+        .filter_map(|chunk_ref| cdc_chunker::zero_chunk_len(&chunk_ref.hash))
+        .collect();
+    assert_eq!(zero_lens, [ZERO_CHUNK_MAX, 5, ZERO_CHUNK_MAX, ZERO_RUN_MIN]);
+    assert_eq!(streamed(&data, 1 << 20), refs);
+}
+
+#[test]
+fn oversized_zero_ids_are_not_zero_chunks() {
+    assert_eq!(
+        cdc_chunker::zero_chunk_len(&cdc_chunker::zero_chunk_id(ZERO_CHUNK_MAX)),
+        Some(ZERO_CHUNK_MAX)
+    );
+    let oversized = cdc_chunker::zero_chunk_id(ZERO_CHUNK_MAX + 1);
+    assert_eq!(cdc_chunker::zero_chunk_len(&oversized), None);
+    assert_eq!(cdc_chunker::zero_chunk_len("zero:0"), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    ChunkStore::init(&LocalFsBackend::new(dir.path())).unwrap();
+    let store =
+        ChunkStore::open(LocalFsBackend::new(dir.path()), 1 << 20, LockKind::Shared).unwrap();
+    assert!(!store.contains(&oversized));
+    assert!(matches!(
+        store.get(&oversized),
+        Err(StoreError::ChunkNotFound(_))
+    ));
+    assert!(matches!(
+        store.get("zero:18446744073709551615"),
+        Err(StoreError::ChunkNotFound(_))
+    ));
+}
+
+/// Records the id and length of every chunk it is given.
+#[derive(Default)]
+struct SpanSink(Vec<(String, usize)>);
+
+impl ChunkSink for SpanSink {
+    fn accept(&mut self, hash: &str, chunk: &[u8]) -> Result<(), StoreError> {
+        self.0.push((hash.to_string(), chunk.len()));
+        Ok(())
+    }
+}
+
+/// The streaming and the in-memory entry points cut zero runs alike, so their chunks
+/// deduplicate against each other.
+#[test]
+fn every_entry_point_cuts_zero_runs_alike() {
+    let mut data = nonzero_noise(70_000, 12);
+    data.extend(vec![0u8; 3 * ZERO_RUN_MIN]);
+    data.extend(nonzero_noise(50_000, 13));
+    data.extend(vec![0u8; ZERO_RUN_MIN + 7]);
+
+    let refs = cdc_chunker::chunk_refs_cdc(&data, &params());
+    let ends: Vec<usize> = refs
+        .iter()
+        .map(|chunk_ref| chunk_ref.offset + chunk_ref.len)
+        .collect();
+    let spans: Vec<(String, usize)> = refs
+        .iter()
+        .map(|chunk_ref| (chunk_ref.hash.clone(), chunk_ref.len))
+        .collect();
+    assert!(
+        spans
+            .iter()
+            .any(|(hash, _)| cdc_chunker::zero_chunk_len(hash).is_some())
+    );
+
+    let mut sink = SpanSink::default();
+    cdc_chunker::chunk_to_sink(&data, &params(), &mut sink).unwrap();
+    assert_eq!(sink.0, spans);
+
+    let chunks = cdc_chunker::chunks_cdc(&Bytes::from(data.clone()), &params());
+    let chunk_spans: Vec<(String, usize)> = chunks
+        .iter()
+        .map(|chunk| (chunk.hash.clone(), chunk.data.len()))
+        .collect();
+    assert_eq!(chunk_spans, spans);
+
+    let (copies, _) = cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384);
+    let copy_lens: Vec<usize> = copies.iter().map(Vec::len).collect();
+    let lens: Vec<usize> = refs.iter().map(|chunk_ref| chunk_ref.len).collect();
+    assert_eq!(copy_lens, lens);
+
+    assert_eq!(
+        cdc_chunker::chunk_boundaries_cdc(&data, 1024, 4096, 16384),
+        ends
+    );
+    assert_eq!(cdc_chunker::chunk_boundaries_scalar(&data, &params()), ends);
+    assert_eq!(cdc_chunker::count_chunks_cdc(&data, &params()), refs.len());
+    assert_eq!(streamed(&data, 1 << 20), refs);
+}
@@ -0,0 +1,63 @@
+//! `chunks_cdc`: chunks are slices of the input buffer, not copies of it.
+
+mod common;
+
+use bytes::Bytes;
+use common::noise;
+use rbckp::backup::{
+    cdc_chunker::{self, CdcParams},
+    hash::ChunkId,
+};
+
+#[test]
+fn chunks_point_into_the_input_buffer() {
+    let data = noise(500_000, 0x0123_4567_89ab_cdef);
+    let params = CdcParams::new(1024, 4096, 16384);
+    let buffer = Bytes::from(data.clone());
+    let chunks = cdc_chunker::chunks_cdc(&buffer, &params);
+
+    let (copies, _) = cdc_chunker::chunk_bytes_cdc(&data, 1024, 4096, 16384);
+    assert!(chunks.len() > 50);
+    assert_eq!(chunks.len(), copies.len());
+
+    let start = buffer.as_ptr() as usize;
+    let mut offset = 0;
+    for (chunk, copy) in chunks.iter().zip(&copies) {
+        assert_eq!(chunk.data, copy[..]);
+        assert_eq!(chunk.hash, ChunkId::of(copy).to_string());
+        // No allocation per chunk: each one is the next stretch of the buffer itself.
+        assert_eq!(chunk.data.as_ptr() as usize, start + offset);
+        offset += chunk.data.len();
+    }
+    assert_eq!(offset, buffer.len());
+
+    // Clones share the bytes too.
+    let clone = chunks[1].clone();
+    assert_eq!(clone.data.as_ptr(), chunks[1].data.as_ptr());
+    assert!(cdc_chunker::chunks_cdc(&Bytes::new(), &params).is_empty());
+}
+
+#[test]
+fn chunks_match_chunk_refs_across_zero_runs() {
+    let mut data = noise(200_000, 5);
+    data.extend_from_slice(&[0; 300_000]);
+    data.extend_from_slice(&noise(100_000, 6));
+    let params = CdcParams::new(1024, 4096, 16384);
+    let buffer = Bytes::from(data.clone());
+
+    let chunks = cdc_chunker::chunks_cdc(&buffer, &params);
+    let refs = cdc_chunker::chunk_refs_cdc(&data, &params);
+    assert_eq!(chunks.len(), refs.len());
+    for (chunk, chunk_ref) in chunks.iter().zip(&refs) {
+        assert_eq!(chunk.hash, chunk_ref.hash);
+        assert_eq!(
+            chunk.data,
+            data[chunk_ref.offset..chunk_ref.offset + chunk_ref.len]
+        );
+    }
+    assert!(
+        chunks
+            .iter()
+            .any(|chunk| cdc_chunker::zero_chunk_len(&chunk.hash).is_some())
+    );
+}